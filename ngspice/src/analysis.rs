@@ -0,0 +1,1276 @@
+// Copyright 2022 Andrew Morrow.
+// analysis.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Typed analysis commands and results.
+
+use crate::control::vector_name;
+use crate::{DataType, Error, NgSpice, Simulation, VectorValues};
+use num_complex::Complex64;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Formatter};
+
+/// A transient analysis, formatted as ngSPICE's `tran` command.
+///
+/// ```
+/// use ngspice::analysis::TranCommand;
+/// let cmd = TranCommand::new(1e-6, 2e-3).max_step(1e-7).uic();
+/// assert_eq!(cmd.to_string(), "tran 1e-6 2e-3 0e0 1e-7 uic");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TranCommand {
+    /// The suggested output step, in seconds.
+    pub step: f64,
+    /// The time the analysis ends, in seconds.
+    pub stop: f64,
+    /// The time output starts, in seconds. The circuit is still simulated from zero.
+    pub start: f64,
+    /// The largest internal step ngSPICE may take, if limited.
+    pub max_step: Option<f64>,
+    /// Skips the operating point and starts from the `ic` values in the netlist.
+    pub uic: bool,
+}
+
+impl TranCommand {
+    /// # Panics
+    ///
+    /// Panics unless `step` and `stop` are positive.
+    pub fn new(step: f64, stop: f64) -> Self {
+        assert!(step > 0.0, "transient step must be positive");
+        assert!(stop > 0.0, "transient stop time must be positive");
+        TranCommand {
+            step,
+            stop,
+            start: 0.0,
+            max_step: None,
+            uic: false,
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Panics unless `start` is at least zero and before the stop time.
+    pub fn start(mut self, start: f64) -> Self {
+        assert!(
+            (0.0..self.stop).contains(&start),
+            "transient start time must be within [0, stop)"
+        );
+        self.start = start;
+        self
+    }
+
+    /// # Panics
+    ///
+    /// Panics unless `max_step` is positive.
+    pub fn max_step(mut self, max_step: f64) -> Self {
+        assert!(max_step > 0.0, "transient maximum step must be positive");
+        self.max_step = Some(max_step);
+        self
+    }
+
+    pub fn uic(mut self) -> Self {
+        self.uic = true;
+        self
+    }
+
+    /// Runs the analysis on `circuit`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`NgSpice::simulate`], or [`Error::MissingScale`] if the results
+    /// have no time vector.
+    pub fn run(&self, circuit: &str) -> Result<TranResult, Error> {
+        TranResult::new(NgSpice::simulate(circuit, &self.to_string())?)
+    }
+}
+
+impl Display for TranCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "tran {:e} {:e}", self.step, self.stop)?;
+        // the maximum step is positional, so it needs the start time before it
+        if self.start != 0.0 || self.max_step.is_some() {
+            write!(f, " {:e}", self.start)?;
+        }
+        if let Some(max_step) = self.max_step {
+            write!(f, " {:e}", max_step)?;
+        }
+        if self.uic {
+            f.write_str(" uic")?;
+        }
+        Ok(())
+    }
+}
+
+/// The results of a transient analysis, with every waveform sampled at [`TranResult::time`].
+#[derive(Clone, Debug)]
+pub struct TranResult {
+    simulation: Simulation,
+    time: String,
+}
+
+impl TranResult {
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingScale`] if the scale of `simulation` is not a real time vector.
+    pub fn new(simulation: Simulation) -> Result<Self, Error> {
+        let time = simulation
+            .scale_vector()
+            .filter(|(k, v)| {
+                simulation.vectors[*k].datatype == DataType::Time && v.real().is_some()
+            })
+            .map(|(k, _)| k.to_owned())
+            .ok_or(Error::MissingScale)?;
+        Ok(TranResult { simulation, time })
+    }
+
+    /// The time of every sample, in seconds.
+    pub fn time(&self) -> &[f64] {
+        self.simulation
+            .real_vector(&self.time)
+            .expect("time vector was checked on construction")
+    }
+
+    /// The waveform of a vector expression such as `v(out)` or `i(vdd)`, or of a vector by its
+    /// name in the results.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingVector`] if there is no such real vector, or if it is not
+    /// sampled at every time point.
+    pub fn waveform(&self, expr: &str) -> Result<&[f64], Error> {
+        let values = self
+            .simulation
+            .real_vector(expr)
+            .or_else(|_| self.simulation.real_vector(&vector_name(expr)))?;
+        if values.len() == self.time().len() {
+            Ok(values)
+        } else {
+            Err(Error::MissingVector(expr.to_owned()))
+        }
+    }
+
+    /// Every waveform other than time, by vector name.
+    pub fn waveforms(&self) -> impl Iterator<Item = (&str, &[f64])> {
+        let len = self.time().len();
+        self.simulation
+            .vectors
+            .iter()
+            .filter(move |(k, _)| **k != self.time)
+            .filter_map(move |(k, v)| Some((k.as_str(), v.values.real()?)))
+            .filter(move |(_, v)| v.len() == len)
+    }
+
+    pub fn simulation(&self) -> &Simulation {
+        &self.simulation
+    }
+
+    pub fn into_simulation(self) -> Simulation {
+        self.simulation
+    }
+}
+
+/// The DC operating point of a circuit.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OpResult {
+    /// Node voltages by node name.
+    pub node_voltages: HashMap<String, f64>,
+    /// Branch currents by vector name, e.g. `v1#branch`.
+    pub branch_currents: HashMap<String, f64>,
+}
+
+impl OpResult {
+    /// Collects the voltage and current vectors of an `op` analysis. Vectors of other types,
+    /// such as saved device parameters, are left out.
+    pub fn new(simulation: &Simulation) -> Self {
+        let mut op = OpResult::default();
+        for (name, info) in &simulation.vectors {
+            let value = match info.values.real() {
+                Some(&[x]) => x,
+                _ => continue,
+            };
+            let target = match info.datatype {
+                DataType::Voltage => &mut op.node_voltages,
+                DataType::Current => &mut op.branch_currents,
+                _ => continue,
+            };
+            target.insert(name.clone(), value);
+        }
+        op
+    }
+
+    /// The voltage of a node, given by name or as `v(node)`.
+    pub fn voltage(&self, node: &str) -> Option<f64> {
+        self.node_voltages
+            .get(node)
+            .or_else(|| self.node_voltages.get(&vector_name(node)))
+            .copied()
+    }
+
+    /// The current through a voltage source or inductor, given by name or as `i(name)`.
+    pub fn current(&self, element: &str) -> Option<f64> {
+        let lower = element.to_ascii_lowercase();
+        self.branch_currents
+            .get(&vector_name(element))
+            .or_else(|| self.branch_currents.get(&format!("{}#branch", lower)))
+            .copied()
+    }
+}
+
+impl NgSpice {
+    /// Runs an `op` analysis on `circuit`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`NgSpice::simulate`].
+    pub fn operating_point(circuit: &str) -> Result<OpResult, Error> {
+        Ok(OpResult::new(&NgSpice::simulate(circuit, "op")?))
+    }
+}
+
+/// How an AC analysis spaces its frequencies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variation {
+    /// A number of points per decade.
+    Decade,
+    /// A number of points per octave.
+    Octave,
+    /// A total number of points, linearly spaced.
+    Linear,
+}
+
+impl Variation {
+    fn keyword(&self) -> &'static str {
+        match self {
+            Variation::Decade => "dec",
+            Variation::Octave => "oct",
+            Variation::Linear => "lin",
+        }
+    }
+}
+
+/// Panics unless a frequency sweep has points and `0 < start <= stop`.
+fn check_frequency_sweep(points: u32, start: f64, stop: f64) {
+    assert!(points > 0, "frequency sweep needs at least one point");
+    assert!(start > 0.0, "start frequency must be positive");
+    assert!(stop >= start, "stop frequency must not be below the start");
+}
+
+/// A small-signal AC analysis, formatted as ngSPICE's `ac` command.
+///
+/// ```
+/// use ngspice::analysis::AcCommand;
+/// assert_eq!(AcCommand::dec(20, 10.0, 1e6).to_string(), "ac dec 20 1e1 1e6");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AcCommand {
+    pub variation: Variation,
+    /// Points per decade or octave, or the total number of points for [`Variation::Linear`].
+    pub points: u32,
+    /// The first frequency, in hertz.
+    pub start: f64,
+    /// The last frequency, in hertz.
+    pub stop: f64,
+}
+
+impl AcCommand {
+    /// # Panics
+    ///
+    /// Panics if `points` is zero, or unless `0 < start <= stop`.
+    pub fn new(variation: Variation, points: u32, start: f64, stop: f64) -> Self {
+        check_frequency_sweep(points, start, stop);
+        AcCommand {
+            variation,
+            points,
+            start,
+            stop,
+        }
+    }
+
+    /// # Panics
+    ///
+    /// See [`AcCommand::new`].
+    pub fn dec(points_per_decade: u32, start: f64, stop: f64) -> Self {
+        AcCommand::new(Variation::Decade, points_per_decade, start, stop)
+    }
+
+    /// # Panics
+    ///
+    /// See [`AcCommand::new`].
+    pub fn oct(points_per_octave: u32, start: f64, stop: f64) -> Self {
+        AcCommand::new(Variation::Octave, points_per_octave, start, stop)
+    }
+
+    /// # Panics
+    ///
+    /// See [`AcCommand::new`].
+    pub fn lin(points: u32, start: f64, stop: f64) -> Self {
+        AcCommand::new(Variation::Linear, points, start, stop)
+    }
+
+    /// Runs the analysis on `circuit`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`NgSpice::simulate`], or [`Error::MissingScale`] if the results
+    /// have no frequency vector.
+    pub fn run(&self, circuit: &str) -> Result<AcResult, Error> {
+        AcResult::new(NgSpice::simulate(circuit, &self.to_string())?)
+    }
+}
+
+impl Display for AcCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ac {} {} {:e} {:e}",
+            self.variation.keyword(),
+            self.points,
+            self.start,
+            self.stop
+        )
+    }
+}
+
+/// The results of an AC analysis. Every response is complex and sampled at
+/// [`AcResult::frequency`].
+#[derive(Clone, Debug)]
+pub struct AcResult {
+    simulation: Simulation,
+    frequency_name: String,
+    frequency: Vec<f64>,
+}
+
+impl AcResult {
+    /// Converts any real vectors in `simulation` to complex ones, so that every response can
+    /// be read the same way.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingScale`] if the scale of `simulation` is not a frequency vector.
+    pub fn new(mut simulation: Simulation) -> Result<Self, Error> {
+        let frequency_name = simulation
+            .scale_vector()
+            .filter(|(k, _)| simulation.vectors[*k].datatype == DataType::Frequency)
+            .map(|(k, _)| k.to_owned())
+            .ok_or(Error::MissingScale)?;
+        let frequency = simulation.scale_values().ok_or(Error::MissingScale)?;
+        for (name, info) in &mut simulation.vectors {
+            if *name == frequency_name {
+                continue;
+            }
+            if let VectorValues::Real(x) = &info.values {
+                let values: Vec<Complex64> = x.iter().map(|&re| Complex64::new(re, 0.0)).collect();
+                info.values = values.into();
+            }
+        }
+        Ok(AcResult {
+            simulation,
+            frequency_name,
+            frequency,
+        })
+    }
+
+    /// The frequency of every sample, in hertz.
+    pub fn frequency(&self) -> &[f64] {
+        &self.frequency
+    }
+
+    /// The response of a vector expression such as `v(out)` or `i(vdd)`, or of a vector by its
+    /// name in the results.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingVector`] if there is no such vector, or if it is not sampled at
+    /// every frequency.
+    pub fn response(&self, expr: &str) -> Result<&[Complex64], Error> {
+        let vectors = &self.simulation.vectors;
+        vectors
+            .get(expr)
+            .or_else(|| vectors.get(&vector_name(expr)))
+            .filter(|_| expr != self.frequency_name)
+            .and_then(|v| v.values.complex())
+            .filter(|v| v.len() == self.frequency.len())
+            .ok_or_else(|| Error::MissingVector(expr.to_owned()))
+    }
+
+    /// Every response, by vector name.
+    pub fn responses(&self) -> impl Iterator<Item = (&str, &[Complex64])> {
+        let len = self.frequency.len();
+        self.simulation
+            .vectors
+            .iter()
+            .filter(move |(k, _)| **k != self.frequency_name)
+            .filter_map(move |(k, v)| Some((k.as_str(), v.values.complex()?)))
+            .filter(move |(_, v)| v.len() == len)
+    }
+
+    pub fn simulation(&self) -> &Simulation {
+        &self.simulation
+    }
+
+    pub fn into_simulation(self) -> Simulation {
+        self.simulation
+    }
+}
+
+/// A distortion product computed by a `disto` analysis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Product {
+    /// The second harmonic, at 2·f1.
+    Hd2,
+    /// The third harmonic, at 3·f1.
+    Hd3,
+    /// The sum intermodulation product, at f1 + f2.
+    Sim2,
+    /// The difference intermodulation product, at f1 − f2.
+    Dim2,
+    /// The third-order intermodulation product, at 2·f1 − f2.
+    Dim3,
+}
+
+/// A small-signal distortion analysis, formatted as ngSPICE's `disto` command.
+///
+/// Sources give their distortion inputs with `DISTOF1` and, for intermodulation, `DISTOF2`.
+///
+/// ```
+/// use ngspice::analysis::DistoCommand;
+/// let cmd = DistoCommand::dec(10, 1e3, 1e6).intermodulation(0.9);
+/// assert_eq!(cmd.to_string(), "disto dec 10 1e3 1e6 9e-1");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DistoCommand {
+    /// The sweep of f1.
+    pub sweep: AcCommand,
+    /// f2 as a fraction of f1, for intermodulation. `None` computes harmonics instead.
+    pub f2_over_f1: Option<f64>,
+}
+
+impl DistoCommand {
+    pub fn new(sweep: AcCommand) -> Self {
+        DistoCommand {
+            sweep,
+            f2_over_f1: None,
+        }
+    }
+
+    /// # Panics
+    ///
+    /// See [`AcCommand::new`].
+    pub fn dec(points_per_decade: u32, start: f64, stop: f64) -> Self {
+        DistoCommand::new(AcCommand::dec(points_per_decade, start, stop))
+    }
+
+    /// # Panics
+    ///
+    /// See [`AcCommand::new`].
+    pub fn oct(points_per_octave: u32, start: f64, stop: f64) -> Self {
+        DistoCommand::new(AcCommand::oct(points_per_octave, start, stop))
+    }
+
+    /// # Panics
+    ///
+    /// See [`AcCommand::new`].
+    pub fn lin(points: u32, start: f64, stop: f64) -> Self {
+        DistoCommand::new(AcCommand::lin(points, start, stop))
+    }
+
+    /// Computes intermodulation between f1 and a second tone at `f2_over_f1 · f1`.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless `0 < f2_over_f1 < 1`, as ngSPICE requires.
+    pub fn intermodulation(mut self, f2_over_f1: f64) -> Self {
+        assert!(
+            f2_over_f1 > 0.0 && f2_over_f1 < 1.0,
+            "f2/f1 must be between 0 and 1"
+        );
+        self.f2_over_f1 = Some(f2_over_f1);
+        self
+    }
+
+    /// The products the analysis computes, in the order ngSPICE creates their plots.
+    pub fn products(&self) -> &'static [Product] {
+        match self.f2_over_f1 {
+            Some(_) => &[Product::Sim2, Product::Dim2, Product::Dim3],
+            None => &[Product::Hd2, Product::Hd3],
+        }
+    }
+
+    /// Runs the analysis on `circuit`, collecting the plot of every product.
+    ///
+    /// Hooks do not run, because the analysis uses a [`crate::session::Session`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`NgSpice::simulate`], or [`Error::MissingVector`] if ngSPICE
+    /// did not create a plot for every product.
+    pub fn run(&self, circuit: &str) -> Result<DistoResult, Error> {
+        let mut session = NgSpice::session();
+        session.load_circuit(circuit)?;
+        let before = session.plots();
+        session.run(&self.to_string())?;
+        // plots are listed newest first
+        let mut created: Vec<String> = session
+            .plots()
+            .into_iter()
+            .filter(|p| !before.contains(p))
+            .collect();
+        created.reverse();
+        let mut collect = || -> Result<DistoResult, Error> {
+            let mut result = DistoResult::default();
+            for (k, &product) in self.products().iter().enumerate() {
+                let plot = created
+                    .get(k)
+                    .ok_or_else(|| Error::MissingVector(format!("{:?}", product)))?;
+                let sim = session.plot(plot)?;
+                result.products.insert(product, AcResult::new(sim)?);
+            }
+            Ok(result)
+        };
+        let result = collect();
+        for plot in &created {
+            session.destroy(plot)?;
+        }
+        result
+    }
+}
+
+impl Display for DistoCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // the sweep formats as `ac ...`, and disto takes the same arguments
+        let sweep = self.sweep.to_string();
+        write!(f, "disto{}", &sweep[2..])?;
+        if let Some(ratio) = self.f2_over_f1 {
+            write!(f, " {:e}", ratio)?;
+        }
+        Ok(())
+    }
+}
+
+/// The results of a distortion analysis: one AC-style result per product, against f1.
+#[derive(Clone, Debug, Default)]
+pub struct DistoResult {
+    pub products: BTreeMap<Product, AcResult>,
+}
+
+impl DistoResult {
+    pub fn get(&self, product: Product) -> Option<&AcResult> {
+        self.products.get(&product)
+    }
+}
+
+/// A noise analysis, formatted as ngSPICE's `noise` command.
+///
+/// ```
+/// use ngspice::analysis::{NoiseCommand, Variation};
+/// let cmd = NoiseCommand::new("out", "vin", Variation::Decade, 10, 1.0, 1e6);
+/// assert_eq!(cmd.to_string(), "noise v(out) vin dec 10 1e0 1e6");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoiseCommand {
+    /// The node whose noise is measured.
+    pub output: String,
+    /// The node the output is measured against, if not ground.
+    pub reference: Option<String>,
+    /// The independent source the noise is referred back to for the input-referred noise.
+    pub source: String,
+    pub variation: Variation,
+    /// Points per decade or octave, or the total number of points for [`Variation::Linear`].
+    pub points: u32,
+    /// The first frequency, in hertz.
+    pub start: f64,
+    /// The last frequency, in hertz.
+    pub stop: f64,
+}
+
+impl NoiseCommand {
+    /// # Panics
+    ///
+    /// Panics if `points` is zero, or unless `0 < start <= stop`.
+    pub fn new(
+        output: &str,
+        source: &str,
+        variation: Variation,
+        points: u32,
+        start: f64,
+        stop: f64,
+    ) -> Self {
+        check_frequency_sweep(points, start, stop);
+        NoiseCommand {
+            output: output.to_owned(),
+            reference: None,
+            source: source.to_owned(),
+            variation,
+            points,
+            start,
+            stop,
+        }
+    }
+
+    pub fn reference(mut self, node: &str) -> Self {
+        self.reference = Some(node.to_owned());
+        self
+    }
+
+    /// Runs the analysis on `circuit`, collecting both plots it produces.
+    ///
+    /// Hooks do not run, because the analysis uses a [`crate::session::Session`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`NgSpice::simulate`], or [`Error::MissingVector`] if either
+    /// plot is missing.
+    pub fn run(&self, circuit: &str) -> Result<NoiseResult, Error> {
+        let mut session = NgSpice::session();
+        session.load_circuit(circuit)?;
+        let totals_plot = session.run(&self.to_string())?;
+        // plots are listed newest first, so the spectrum is the one before the totals
+        let spectrum_plot = session
+            .plots()
+            .into_iter()
+            .skip_while(|p| *p != totals_plot)
+            .nth(1)
+            .ok_or_else(|| Error::MissingVector(totals_plot.clone()))?;
+        let totals = session.plot(&totals_plot)?;
+        let spectrum = session.plot(&spectrum_plot)?;
+        session.destroy(&totals_plot)?;
+        session.destroy(&spectrum_plot)?;
+        NoiseResult::new(spectrum, &totals)
+    }
+}
+
+impl Display for NoiseCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.reference {
+            Some(reference) => write!(f, "noise v({},{})", self.output, reference)?,
+            None => write!(f, "noise v({})", self.output)?,
+        }
+        write!(
+            f,
+            " {} {} {} {:e} {:e}",
+            self.source,
+            self.variation.keyword(),
+            self.points,
+            self.start,
+            self.stop
+        )
+    }
+}
+
+/// The results of a noise analysis: spectral densities from its first plot, and the noise
+/// integrated over the whole sweep from its second.
+#[derive(Clone, Debug)]
+pub struct NoiseResult {
+    spectrum: Simulation,
+    frequency: Vec<f64>,
+    /// The total output noise over the sweep, in volts RMS.
+    pub onoise_total: f64,
+    /// The total input-referred noise over the sweep, in volts or amps RMS depending on the
+    /// reference source.
+    pub inoise_total: f64,
+}
+
+impl NoiseResult {
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingScale`] if `spectrum` has no frequency vector, or
+    /// [`Error::MissingVector`] if `totals` lacks `onoise_total` or `inoise_total`.
+    pub fn new(spectrum: Simulation, totals: &Simulation) -> Result<Self, Error> {
+        let frequency = spectrum.scale_values().ok_or(Error::MissingScale)?;
+        let total = |name: &str| match totals.real_vector(name)? {
+            &[x, ..] => Ok(x),
+            [] => Err(Error::MissingVector(name.to_owned())),
+        };
+        Ok(NoiseResult {
+            onoise_total: total("onoise_total")?,
+            inoise_total: total("inoise_total")?,
+            spectrum,
+            frequency,
+        })
+    }
+
+    /// The frequency of every sample, in hertz.
+    pub fn frequency(&self) -> &[f64] {
+        &self.frequency
+    }
+
+    /// The output noise spectral density, in V/√Hz.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingVector`] if the results have no `onoise_spectrum`.
+    pub fn output_density(&self) -> Result<&[f64], Error> {
+        self.spectrum.real_vector("onoise_spectrum")
+    }
+
+    /// The input-referred noise spectral density, in V/√Hz or A/√Hz.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingVector`] if the results have no `inoise_spectrum`.
+    pub fn input_density(&self) -> Result<&[f64], Error> {
+        self.spectrum.real_vector("inoise_spectrum")
+    }
+
+    /// The spectral density plot, including per-device contributions if ngSPICE was asked to
+    /// keep them.
+    pub fn spectrum(&self) -> &Simulation {
+        &self.spectrum
+    }
+}
+
+/// A DC sensitivity analysis, formatted as ngSPICE's `sens` command.
+///
+/// ```
+/// use ngspice::analysis::SensCommand;
+/// assert_eq!(SensCommand::voltage("out").to_string(), "sens v(out)");
+/// assert_eq!(SensCommand::current("vdd").to_string(), "sens i(vdd)");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SensCommand {
+    /// The output as a vector expression, e.g. `v(out)`.
+    pub output: String,
+}
+
+impl SensCommand {
+    /// The sensitivity of a node voltage, optionally relative to a second node given as
+    /// `out,ref`.
+    pub fn voltage(node: &str) -> Self {
+        SensCommand {
+            output: format!("v({})", node),
+        }
+    }
+
+    /// The sensitivity of the current through a voltage source.
+    pub fn current(source: &str) -> Self {
+        SensCommand {
+            output: format!("i({})", source),
+        }
+    }
+
+    /// Runs the analysis on `circuit`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`NgSpice::simulate`].
+    pub fn run(&self, circuit: &str) -> Result<SensitivityResult, Error> {
+        let sim = NgSpice::simulate(circuit, &self.to_string())?;
+        Ok(SensitivityResult::new(&self.output, &sim))
+    }
+}
+
+impl Display for SensCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "sens {}", self.output)
+    }
+}
+
+/// The sensitivity of the output to one circuit parameter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sensitivity {
+    /// The vector name ngSPICE gave the parameter, e.g. `r1` or `q1:bf`.
+    pub name: String,
+    /// The device the parameter belongs to, e.g. `r1` or `q1`.
+    pub device: String,
+    /// The device parameter, or `None` for an element's own value.
+    pub parameter: Option<String>,
+    /// The change in output per unit change of the parameter.
+    pub value: f64,
+}
+
+/// The results of a DC sensitivity analysis, largest magnitude first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SensitivityResult {
+    /// The output the sensitivities are of, e.g. `v(out)`.
+    pub output: String,
+    pub entries: Vec<Sensitivity>,
+}
+
+impl SensitivityResult {
+    /// Collects the real, single-valued vectors of a `sens` run.
+    pub fn new(output: &str, simulation: &Simulation) -> Self {
+        let mut entries: Vec<Sensitivity> = simulation
+            .vectors
+            .iter()
+            .filter_map(|(name, info)| match info.values.real() {
+                Some(&[value]) => Some((name, value)),
+                _ => None,
+            })
+            .map(|(name, value)| {
+                let (device, parameter) = match name.split_once(':') {
+                    Some((device, parameter)) => (device, Some(parameter.to_owned())),
+                    None => (name.as_str(), None),
+                };
+                Sensitivity {
+                    name: name.clone(),
+                    device: device.to_owned(),
+                    parameter,
+                    value,
+                }
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            b.value
+                .abs()
+                .total_cmp(&a.value.abs())
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        SensitivityResult {
+            output: output.to_owned(),
+            entries,
+        }
+    }
+
+    /// The sensitivity to the named parameter, e.g. `r1` or `q1:bf`.
+    pub fn get(&self, name: &str) -> Option<f64> {
+        let name = name.to_ascii_lowercase();
+        self.entries
+            .iter()
+            .find(|e| e.name == name)
+            .map(|e| e.value)
+    }
+
+    /// Every sensitivity to parameters of the named device.
+    pub fn device(&self, device: &str) -> Vec<&Sensitivity> {
+        let device = device.to_ascii_lowercase();
+        self.entries.iter().filter(|e| e.device == device).collect()
+    }
+}
+
+/// One swept source of a DC analysis. The source may also be a resistor, or `temp` for the
+/// circuit temperature.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DcSweep {
+    pub source: String,
+    pub start: f64,
+    pub stop: f64,
+    pub step: f64,
+}
+
+impl DcSweep {
+    /// # Panics
+    ///
+    /// Panics if `step` is zero or steps away from `stop`.
+    pub fn new(source: &str, start: f64, stop: f64, step: f64) -> Self {
+        assert!(
+            step != 0.0 && (stop - start) * step >= 0.0,
+            "DC sweep step must move from start towards stop"
+        );
+        DcSweep {
+            source: source.to_owned(),
+            start,
+            stop,
+            step,
+        }
+    }
+
+    /// The values ngSPICE visits, from `start` up to and including `stop`.
+    pub fn values(&self) -> Vec<f64> {
+        // tolerate rounding so that e.g. 0 to 1 by 0.1 includes 1
+        let count = ((self.stop - self.start) / self.step + 1e-9).floor() as usize + 1;
+        (0..count)
+            .map(|k| self.start + k as f64 * self.step)
+            .collect()
+    }
+}
+
+impl Display for DcSweep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:e} {:e} {:e}",
+            self.source, self.start, self.stop, self.step
+        )
+    }
+}
+
+/// A DC sweep, formatted as ngSPICE's `dc` command, optionally nested inside a sweep of a
+/// second source.
+///
+/// ```
+/// use ngspice::analysis::DcCommand;
+/// let cmd = DcCommand::new("vds", 0.0, 5.0, 0.1).nested("vgs", 1.0, 3.0, 0.5);
+/// assert_eq!(cmd.to_string(), "dc vds 0e0 5e0 1e-1 vgs 1e0 3e0 5e-1");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct DcCommand {
+    /// The inner sweep, which varies fastest.
+    pub sweep: DcSweep,
+    /// The outer sweep, which steps once per pass of the inner one.
+    pub nested: Option<DcSweep>,
+}
+
+impl DcCommand {
+    /// # Panics
+    ///
+    /// See [`DcSweep::new`].
+    pub fn new(source: &str, start: f64, stop: f64, step: f64) -> Self {
+        DcCommand {
+            sweep: DcSweep::new(source, start, stop, step),
+            nested: None,
+        }
+    }
+
+    /// # Panics
+    ///
+    /// See [`DcSweep::new`].
+    pub fn nested(mut self, source: &str, start: f64, stop: f64, step: f64) -> Self {
+        self.nested = Some(DcSweep::new(source, start, stop, step));
+        self
+    }
+
+    /// Runs the analysis on `circuit`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`NgSpice::simulate`], or [`Error::MissingScale`] if the results
+    /// have no sweep vector.
+    pub fn run(&self, circuit: &str) -> Result<DcResult, Error> {
+        DcResult::new(NgSpice::simulate(circuit, &self.to_string())?, self.clone())
+    }
+}
+
+impl Display for DcCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "dc {}", self.sweep)?;
+        if let Some(nested) = &self.nested {
+            write!(f, " {}", nested)?;
+        }
+        Ok(())
+    }
+}
+
+/// The results of a DC sweep. ngSPICE returns a nested sweep as one long vector per output,
+/// which [`DcResult::curves`] splits into one curve per value of the outer source.
+#[derive(Clone, Debug)]
+pub struct DcResult {
+    simulation: Simulation,
+    command: DcCommand,
+    sweep_name: String,
+}
+
+impl DcResult {
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingScale`] if `simulation` has no real sweep vector, such as
+    /// `v-sweep`.
+    pub fn new(simulation: Simulation, command: DcCommand) -> Result<Self, Error> {
+        let sweep_name = simulation
+            .scale_vector()
+            .filter(|(_, v)| v.real().is_some())
+            .map(|(k, _)| k.to_owned())
+            .ok_or(Error::MissingScale)?;
+        Ok(DcResult {
+            simulation,
+            command,
+            sweep_name,
+        })
+    }
+
+    /// The source swept by the inner sweep.
+    pub fn source(&self) -> &str {
+        &self.command.sweep.source
+    }
+
+    /// The source swept by the outer sweep, if nested.
+    pub fn nested_source(&self) -> Option<&str> {
+        self.command.nested.as_ref().map(|s| s.source.as_str())
+    }
+
+    /// The name of the sweep vector in the results, e.g. `v-sweep`.
+    pub fn sweep_name(&self) -> &str {
+        &self.sweep_name
+    }
+
+    /// The values of the inner source for one curve.
+    pub fn sweep(&self) -> &[f64] {
+        let all = self.sweep_values();
+        &all[..self.curve_len().min(all.len())]
+    }
+
+    /// The values of the outer source, one per curve. Empty unless the sweep is nested.
+    pub fn nested_values(&self) -> Vec<f64> {
+        let curves = self.sweep_values().len().div_ceil(self.curve_len().max(1));
+        match &self.command.nested {
+            Some(nested) => nested.values().into_iter().take(curves).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The flattened values of a vector expression such as `v(out)`, in the order ngSPICE
+    /// computed them.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingVector`] if there is no such real vector, or if it is not
+    /// sampled at every sweep point.
+    pub fn values(&self, expr: &str) -> Result<&[f64], Error> {
+        let values = self
+            .simulation
+            .real_vector(expr)
+            .or_else(|_| self.simulation.real_vector(&vector_name(expr)))?;
+        if values.len() == self.sweep_values().len() {
+            Ok(values)
+        } else {
+            Err(Error::MissingVector(expr.to_owned()))
+        }
+    }
+
+    /// The values of a vector expression as one curve against [`DcResult::sweep`] per outer
+    /// source value. A sweep that is not nested has one curve. If the run stopped early, the
+    /// last curve is short.
+    ///
+    /// # Errors
+    ///
+    /// See [`DcResult::values`].
+    pub fn curves(&self, expr: &str) -> Result<Vec<&[f64]>, Error> {
+        Ok(self.values(expr)?.chunks(self.curve_len().max(1)).collect())
+    }
+
+    pub fn simulation(&self) -> &Simulation {
+        &self.simulation
+    }
+
+    pub fn into_simulation(self) -> Simulation {
+        self.simulation
+    }
+
+    fn sweep_values(&self) -> &[f64] {
+        self.simulation
+            .real_vector(&self.sweep_name)
+            .expect("sweep vector was checked on construction")
+    }
+
+    /// The number of points per curve, i.e. per pass of the inner sweep.
+    fn curve_len(&self) -> usize {
+        let all = self.sweep_values();
+        if self.command.nested.is_none() {
+            return all.len();
+        }
+        let expected = self.command.sweep.values().len();
+        // ngSPICE restarts the inner sweep from its start value for each outer step
+        all.iter()
+            .skip(1)
+            .position(|&v| v == all[0])
+            .map_or(all.len(), |k| k + 1)
+            .min(expected.max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VectorInfo;
+
+    #[test]
+    fn formats_and_aligns_transients() -> Result<(), Error> {
+        assert_eq!(TranCommand::new(1e-6, 1e-3).to_string(), "tran 1e-6 1e-3");
+        assert_eq!(
+            TranCommand::new(1e-9, 1e-6).start(5e-7).to_string(),
+            "tran 1e-9 1e-6 5e-7"
+        );
+        let mut sim = Simulation::default();
+        for (name, datatype, values) in [
+            ("time", DataType::Time, vec![0.0, 1.0, 2.0]),
+            ("out", DataType::Voltage, vec![0.0, 0.5, 1.0]),
+            ("v1#branch", DataType::Current, vec![0.0, -1.0, -1.0]),
+        ] {
+            let values = VectorValues::Real(values.into());
+            let scale = Some("time".to_owned()).filter(|_| name != "time");
+            sim.vectors.insert(
+                name.to_owned(),
+                VectorInfo {
+                    datatype,
+                    values,
+                    scale,
+                },
+            );
+        }
+        let tran = TranResult::new(sim)?;
+        assert_eq!(tran.time(), [0.0, 1.0, 2.0]);
+        assert_eq!(tran.waveform("V(out)")?, [0.0, 0.5, 1.0]);
+        assert_eq!(tran.waveform("i(v1)")?, [0.0, -1.0, -1.0]);
+        assert!(tran.waveform("v(missing)").is_err());
+        assert_eq!(tran.waveforms().count(), 2);
+        assert!(TranResult::new(Simulation::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn formats_and_converts_ac() -> Result<(), Error> {
+        assert_eq!(AcCommand::oct(4, 1e3, 8e3).to_string(), "ac oct 4 1e3 8e3");
+        assert_eq!(
+            AcCommand::lin(101, 1.0, 1e2).to_string(),
+            "ac lin 101 1e0 1e2"
+        );
+        let mut sim = Simulation::default();
+        let freq = vec![Complex64::new(10.0, 0.0), Complex64::new(100.0, 0.0)];
+        sim.vectors.insert(
+            "frequency".to_owned(),
+            VectorInfo {
+                datatype: DataType::Frequency,
+                values: VectorValues::Complex(freq.into()),
+                scale: None,
+            },
+        );
+        sim.vectors.insert(
+            "in".to_owned(),
+            VectorInfo {
+                datatype: DataType::Voltage,
+                values: VectorValues::Real(vec![1.0, 1.0].into()),
+                scale: Some("frequency".to_owned()),
+            },
+        );
+        let ac = AcResult::new(sim)?;
+        assert_eq!(ac.frequency(), [10.0, 100.0]);
+        assert_eq!(ac.response("v(in)")?, [Complex64::new(1.0, 0.0); 2]);
+        assert!(ac.response("frequency").is_err());
+        assert_eq!(ac.responses().count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn reshapes_nested_dc_sweeps() -> Result<(), Error> {
+        assert_eq!(DcSweep::new("v1", 0.0, 1.0, 0.1).values().len(), 11);
+        assert_eq!(DcSweep::new("v1", 1.0, 0.0, -0.5).values(), [1.0, 0.5, 0.0]);
+        let cmd = DcCommand::new("vds", 0.0, 2.0, 1.0).nested("vgs", 1.0, 2.0, 1.0);
+        let mut sim = Simulation::default();
+        for (name, values) in [
+            ("v-sweep", vec![0.0, 1.0, 2.0, 0.0, 1.0, 2.0]),
+            ("d", vec![0.0, 1.0, 1.5, 0.0, 2.0, 3.0]),
+        ] {
+            let values = VectorValues::Real(values.into());
+            let datatype = DataType::Voltage;
+            sim.vectors.insert(
+                name.to_owned(),
+                VectorInfo {
+                    datatype,
+                    values,
+                    scale: None,
+                },
+            );
+        }
+        let dc = DcResult::new(sim.clone(), cmd)?;
+        assert_eq!(dc.source(), "vds");
+        assert_eq!(dc.nested_source(), Some("vgs"));
+        assert_eq!(dc.sweep(), [0.0, 1.0, 2.0]);
+        assert_eq!(dc.nested_values(), [1.0, 2.0]);
+        assert_eq!(dc.curves("v(d)")?, [[0.0, 1.0, 1.5], [0.0, 2.0, 3.0]]);
+        let flat = DcResult::new(sim, DcCommand::new("vds", 0.0, 2.0, 1.0))?;
+        assert_eq!(flat.curves("d")?.len(), 1);
+        assert!(flat.nested_values().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn collects_operating_point() {
+        let mut sim = Simulation::default();
+        for (name, datatype, value) in [
+            ("out", DataType::Voltage, 2.5),
+            ("v1#branch", DataType::Current, -1e-3),
+            ("@m1[gm]", DataType::Unknown, 0.1),
+        ] {
+            let values = VectorValues::Real(vec![value].into());
+            sim.vectors.insert(
+                name.to_owned(),
+                VectorInfo {
+                    datatype,
+                    values,
+                    scale: None,
+                },
+            );
+        }
+        let op = OpResult::new(&sim);
+        assert_eq!(op.node_voltages.len(), 1);
+        assert_eq!(op.voltage("V(out)"), Some(2.5));
+        assert_eq!(op.voltage("out"), Some(2.5));
+        assert_eq!(op.current("V1"), Some(-1e-3));
+        assert_eq!(op.current("i(v1)"), Some(-1e-3));
+        assert_eq!(op.current("v2"), None);
+    }
+
+    #[test]
+    fn reads_noise_plots() -> Result<(), Error> {
+        let cmd = NoiseCommand::new("out", "v1", Variation::Octave, 4, 1e3, 1e4).reference("ref");
+        assert_eq!(cmd.to_string(), "noise v(out,ref) v1 oct 4 1e3 1e4");
+        let mut spectrum = Simulation::default();
+        let mut totals = Simulation::default();
+        for (total, name, datatype, values) in [
+            (false, "frequency", DataType::Frequency, vec![1e3, 1e4]),
+            (
+                false,
+                "onoise_spectrum",
+                DataType::VoltageDensity,
+                vec![2e-8, 1e-8],
+            ),
+            (true, "onoise_total", DataType::Voltage, vec![1e-6]),
+            (true, "inoise_total", DataType::Voltage, vec![1e-7]),
+        ] {
+            let values = VectorValues::Real(values.into());
+            let sim = if total { &mut totals } else { &mut spectrum };
+            sim.vectors.insert(
+                name.to_owned(),
+                VectorInfo {
+                    datatype,
+                    values,
+                    scale: None,
+                },
+            );
+        }
+        let noise = NoiseResult::new(spectrum.clone(), &totals)?;
+        assert_eq!(noise.frequency(), [1e3, 1e4]);
+        assert_eq!(noise.output_density()?, [2e-8, 1e-8]);
+        assert!(noise.input_density().is_err());
+        assert_eq!((noise.onoise_total, noise.inoise_total), (1e-6, 1e-7));
+        assert!(NoiseResult::new(spectrum, &Simulation::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sorts_sensitivities() {
+        let mut sim = Simulation::default();
+        for (name, value) in [("r1", -2e-4), ("v1", 0.5), ("q1:bf", 1e-3), ("q1:is", 2e12)] {
+            let values = VectorValues::Real(vec![value].into());
+            let datatype = DataType::Unknown;
+            sim.vectors.insert(
+                name.to_owned(),
+                VectorInfo {
+                    datatype,
+                    values,
+                    scale: None,
+                },
+            );
+        }
+        let sens = SensitivityResult::new("v(out)", &sim);
+        let names: Vec<&str> = sens.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["q1:is", "v1", "q1:bf", "r1"]);
+        assert_eq!(sens.get("R1"), Some(-2e-4));
+        assert_eq!(sens.device("Q1").len(), 2);
+        assert_eq!(sens.entries[0].parameter.as_deref(), Some("is"));
+        assert_eq!(sens.entries[1].parameter, None);
+    }
+
+    #[test]
+    fn formats_disto() {
+        let harmonic = DistoCommand::oct(5, 1e2, 1e4);
+        assert_eq!(harmonic.to_string(), "disto oct 5 1e2 1e4");
+        assert_eq!(harmonic.products(), [Product::Hd2, Product::Hd3]);
+        let im = DistoCommand::lin(3, 1.0, 3.0).intermodulation(0.5);
+        assert_eq!(im.to_string(), "disto lin 3 1e0 3e0 5e-1");
+        assert_eq!(im.products().len(), 3);
+        assert!(DistoResult::default().get(Product::Dim3).is_none());
+    }
+}
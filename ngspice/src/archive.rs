@@ -0,0 +1,219 @@
+// Copyright 2022 Andrew Morrow.
+// archive.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Exports every plot of a run, e.g. its operating point, AC and transient results, into one
+//! archive of rawfiles named after the plots.
+
+use crate::analysis::NoiseResult;
+use crate::bundle::{invalid, now, parse_rawfile, raw_vectors, rawfile, tar, untar};
+use crate::characterize::Characterization;
+use crate::session::Session;
+use crate::{Error, Simulation};
+use std::path::Path;
+
+/// Several named plots, stored as one rawfile each in a tar archive.
+#[derive(Clone, Debug, Default)]
+pub struct PlotArchive {
+    /// Plot names, e.g. `tran1`, and their vectors, in the order they were added.
+    pub plots: Vec<(String, Simulation)>,
+}
+
+impl PlotArchive {
+    pub fn new() -> Self {
+        PlotArchive::default()
+    }
+
+    /// Adds a plot. Its log output is not stored.
+    pub fn add(mut self, name: &str, simulation: Simulation) -> Self {
+        self.plots.push((name.to_owned(), simulation));
+        self
+    }
+
+    /// The named plot, if it is in the archive.
+    pub fn get(&self, name: &str) -> Option<&Simulation> {
+        self.plots.iter().find(|(n, _)| n == name).map(|(_, s)| s)
+    }
+
+    /// The archive as an uncompressed tar file with an entry `<plot>.raw` for each plot and
+    /// an `index.txt` listing the plots in order.
+    ///
+    /// A rawfile plot holds only vectors as long as its scale, so any others are left out and
+    /// listed after their plot's name in the index, as in `tran1 omitted vdd#branch`.
+    pub fn to_tar(&self) -> Vec<u8> {
+        let names: Vec<String> = self
+            .plots
+            .iter()
+            .map(|(n, _)| format!("{}.raw", n))
+            .collect();
+        let mut index = String::new();
+        let mut files = Vec::new();
+        for ((name, simulation), file) in self.plots.iter().zip(&names) {
+            index.push_str(name);
+            let (_, omitted) = raw_vectors(simulation);
+            if !omitted.is_empty() {
+                index.push_str(" omitted ");
+                index.push_str(&omitted.join(" "));
+            }
+            index.push('\n');
+            files.push((file.as_str(), rawfile(simulation, name)));
+        }
+        files.insert(0, ("index.txt", index));
+        tar(&files, now())
+    }
+
+    /// Writes the archive to `path`, conventionally with a `.tar` extension.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the file cannot be written.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        std::fs::write(path, self.to_tar()).map_err(Error::Io)
+    }
+
+    /// Parses an archive written by [`PlotArchive::to_tar`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the archive is malformed or a listed plot is missing.
+    pub fn from_tar(bytes: &[u8]) -> Result<PlotArchive, Error> {
+        let entries = untar(bytes)?;
+        let find = |name: &str| entries.iter().find(|(n, _)| n == name).map(|(_, c)| c);
+        let index = find("index.txt").ok_or_else(|| invalid("archive has no index"))?;
+        let mut archive = PlotArchive::new();
+        for name in index.lines().filter_map(|l| l.split_whitespace().next()) {
+            let raw = find(&format!("{}.raw", name)).ok_or_else(|| invalid("missing plot"))?;
+            let simulation = Simulation {
+                vectors: parse_rawfile(raw)?,
+                ..Simulation::default()
+            };
+            archive.plots.push((name.to_owned(), simulation));
+        }
+        Ok(archive)
+    }
+
+    /// Reads an archive written by [`PlotArchive::write`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the file cannot be read or is malformed.
+    pub fn read(path: impl AsRef<Path>) -> Result<PlotArchive, Error> {
+        PlotArchive::from_tar(&std::fs::read(path)?)
+    }
+}
+
+impl Session {
+    /// Copies every plot the session holds, oldest first, into an archive. ngSPICE's `const`
+    /// plot of constants is left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a plot cannot be extracted.
+    pub fn archive_plots(&mut self) -> Result<PlotArchive, Error> {
+        let mut archive = PlotArchive::new();
+        for name in self.plots().into_iter().rev() {
+            if name != "const" {
+                let plot = self.plot(&name)?;
+                archive = archive.add(&name, plot);
+            }
+        }
+        Ok(archive)
+    }
+}
+
+impl Characterization {
+    /// Collects the analyses that were run into an archive, named `op`, `ac`, `tran` and
+    /// `noise`. Only the noise spectrum is archived, not its integrated totals.
+    pub fn archive(&self) -> PlotArchive {
+        let mut archive = PlotArchive::new();
+        for (name, plot) in [
+            ("op", self.op.as_ref()),
+            ("ac", self.ac.as_ref()),
+            ("tran", self.tran.as_ref()),
+            ("noise", self.noise.as_ref().map(NoiseResult::spectrum)),
+        ] {
+            if let Some(plot) = plot {
+                archive = archive.add(name, plot.clone());
+            }
+        }
+        archive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, VectorInfo, VectorValues};
+    use num_complex::Complex64;
+
+    #[test]
+    fn round_trips_plots() {
+        let mut op = Simulation::default();
+        op.vectors.insert(
+            "out".to_owned(),
+            VectorInfo {
+                datatype: DataType::Voltage,
+                values: VectorValues::Real(vec![2.5].into()),
+                scale: None,
+            },
+        );
+        let mut ac = Simulation::default();
+        for (name, datatype, values) in [
+            (
+                "frequency",
+                DataType::Frequency,
+                vec![Complex64::new(1e3, 0.0)],
+            ),
+            ("out", DataType::Voltage, vec![Complex64::new(0.5, -0.5)]),
+        ] {
+            let info = VectorInfo {
+                datatype,
+                values: VectorValues::Complex(values.into()),
+                scale: None,
+            };
+            ac.vectors.insert(name.to_owned(), info);
+        }
+        // a vector that does not fit the plot is listed in the index instead
+        ac.vectors.insert(
+            "extra".to_owned(),
+            VectorInfo {
+                datatype: DataType::Voltage,
+                values: VectorValues::Real(vec![1.0, 2.0].into()),
+                scale: None,
+            },
+        );
+        let archive = PlotArchive::new().add("op1", op).add("ac1", ac);
+        let tar = archive.to_tar();
+        let index = untar(&tar).unwrap().remove(0);
+        assert_eq!(
+            index,
+            (
+                "index.txt".to_owned(),
+                "op1\nac1 omitted extra\n".to_owned()
+            )
+        );
+        let read = PlotArchive::from_tar(&tar).unwrap();
+        let names: Vec<&str> = read.plots.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["op1", "ac1"]);
+        assert_eq!(read.get("op1").unwrap().real_vector("out").unwrap(), [2.5]);
+        let ac = read.get("ac1").unwrap();
+        assert_eq!(ac.real_vector("frequency").unwrap(), [1e3]);
+        assert_eq!(
+            ac.vectors["out"].values.complex().unwrap(),
+            [Complex64::new(0.5, -0.5)]
+        );
+    }
+}
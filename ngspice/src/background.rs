@@ -0,0 +1,395 @@
+// Copyright 2022 Andrew Morrow.
+// background.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Simulations that run on ngSPICE's background thread.
+
+use crate::cancel::CancellationToken;
+use crate::limits::{Budget, ResourceLimits};
+use crate::progress::{self, Progress};
+use crate::stream::{self, StreamEvent};
+use crate::{extract_plot, hooks, telemetry, warmup, Error, NgSpice, Simulation};
+use ngspice_sys::*;
+use std::os::raw::{c_int, c_void};
+use std::sync::mpsc::Sender;
+use std::sync::{Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Counts background runs that have finished, so a waiter cannot miss a run that ends before
+/// it starts waiting.
+static FINISHED: Mutex<u64> = Mutex::new(0);
+static FINISHED_CHANGED: Condvar = Condvar::new();
+
+/// ngSPICE calls this when its background thread starts and stops. Despite the header's
+/// documentation, the flag is true when the thread is *not* running.
+pub(crate) extern "C" fn bg_thread_running(
+    not_running: NG_BOOL,
+    _: c_int,
+    _: *mut c_void,
+) -> c_int {
+    if not_running {
+        let mut finished = FINISHED.lock().unwrap_or_else(|e| e.into_inner());
+        *finished += 1;
+        FINISHED_CHANGED.notify_all();
+    }
+    0
+}
+
+/// Why a background run ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Stop {
+    Finished,
+    Cancelled,
+    TimedOut,
+}
+
+/// Wakes every thread waiting on a background run, so it notices a cancellation.
+pub(crate) fn wake_waiters() {
+    // the lock prevents a lost wakeup
+    let _finished = FINISHED.lock().unwrap_or_else(|e| e.into_inner());
+    FINISHED_CHANGED.notify_all();
+}
+
+/// Runs `command` on ngSPICE's background thread and blocks until it finishes, halting it if
+/// `token` is cancelled or `timeout` passes in the meantime.
+///
+/// # Errors
+///
+/// Returns an error if ngSPICE rejects the command.
+pub(crate) fn run_in_background(
+    mut spice: std::pin::Pin<&mut NgSpice>,
+    command: &str,
+    token: &CancellationToken,
+    timeout: Option<Duration>,
+) -> Result<Stop, Error> {
+    let mut finished = FINISHED.lock().unwrap_or_else(|e| e.into_inner());
+    let target = *finished + 1;
+    let deadline = timeout.map(|t| Instant::now() + t);
+    // hold the counter while issuing the command, so the thread cannot report back first
+    spice.as_mut().command(&format!("bg_{}", command))?;
+    let mut stop = Stop::Finished;
+    while *finished < target {
+        if stop == Stop::Finished {
+            if token.is_cancelled() {
+                stop = Stop::Cancelled;
+            } else if deadline.is_some_and(|d| Instant::now() >= d) {
+                stop = Stop::TimedOut;
+            }
+            if stop != Stop::Finished {
+                // ngSPICE joins its thread while halting, and the thread reports back through
+                // the counter on its way out, so release it first
+                drop(finished);
+                spice.as_mut().command("bg_halt")?;
+                finished = FINISHED.lock().unwrap_or_else(|e| e.into_inner());
+                continue;
+            }
+        }
+        finished = match deadline {
+            Some(d) if stop == Stop::Finished => {
+                let wait = d.saturating_duration_since(Instant::now());
+                FINISHED_CHANGED
+                    .wait_timeout(finished, wait)
+                    .unwrap_or_else(|e| e.into_inner())
+                    .0
+            }
+            _ => FINISHED_CHANGED
+                .wait(finished)
+                .unwrap_or_else(|e| e.into_inner()),
+        };
+    }
+    Ok(stop)
+}
+
+/// A simulation running in the background, returned by [`NgSpice::simulate_async`].
+#[derive(Debug)]
+pub struct SimulationHandle {
+    thread: JoinHandle<Result<Simulation, Error>>,
+    token: CancellationToken,
+}
+
+impl SimulationHandle {
+    /// Asks the simulation to stop, with ngSPICE's `bg_halt` if it is already running.
+    /// [`SimulationHandle::join`] then returns [`Error::Cancelled`].
+    ///
+    /// Simulations started with [`NgSpice::simulate`] block their thread and cannot be
+    /// cancelled; start long runs with [`NgSpice::simulate_async`] instead.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    /// The token that cancels this simulation. It is a child of the token the simulation was
+    /// started with, if any.
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Whether the simulation has finished and [`SimulationHandle::join`] will not block.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Blocks until the simulation finishes and returns its results.
+    ///
+    /// # Panics
+    ///
+    /// Panics if ngSPICE encountered an unrecoverable error.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if ngSPICE cannot parse the circuit or the command, or
+    /// [`Error::Cancelled`] if [`SimulationHandle::cancel`] stopped the run.
+    pub fn join(self) -> Result<Simulation, Error> {
+        self.thread
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    }
+}
+
+impl NgSpice {
+    /// Like [`NgSpice::simulate`], but returns immediately while the command runs on ngSPICE's
+    /// background thread (as with ngSPICE's `bg_run`).
+    ///
+    /// The shared instance remains locked until the simulation finishes, so other simulations
+    /// queue behind it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error immediately if the circuit or command fails validation. Errors from
+    /// ngSPICE itself are returned by [`SimulationHandle::join`].
+    pub fn simulate_async(circuit: &str, command: &str) -> Result<SimulationHandle, Error> {
+        spawn(circuit, command, Options::default())
+    }
+
+    /// Like [`NgSpice::simulate`], but halts the simulation if it runs longer than `timeout`.
+    ///
+    /// The timeout starts when ngSPICE starts the command, not while waiting for other
+    /// simulations to finish.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the errors returned by [`NgSpice::simulate`], returns
+    /// [`Error::Timeout`] with the results computed so far if the timeout passes.
+    pub fn simulate_with_timeout(
+        circuit: &str,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<Simulation, Error> {
+        let options = Options {
+            timeout: Some(timeout),
+            ..Options::default()
+        };
+        spawn(circuit, command, options)?.join()
+    }
+
+    /// Like [`NgSpice::simulate`], but halts the simulation if `token`, or one of its
+    /// ancestors, is cancelled. A simulation whose token is cancelled before it starts never
+    /// loads its circuit.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the errors returned by [`NgSpice::simulate`], returns
+    /// [`Error::Cancelled`] with any results computed so far if the token is cancelled.
+    pub fn simulate_cancellable(
+        circuit: &str,
+        command: &str,
+        token: &CancellationToken,
+    ) -> Result<Simulation, Error> {
+        let options = Options {
+            token: Some(token.clone()),
+            ..Options::default()
+        };
+        spawn(circuit, command, options)?.join()
+    }
+}
+
+/// What to attach to a background run besides the simulation itself.
+#[derive(Default)]
+pub(crate) struct Options {
+    /// Receives each plan and data point as ngSPICE produces it.
+    pub stream: Option<Sender<StreamEvent>>,
+    /// Called with the position of each point.
+    pub progress: Option<Box<dyn FnMut(Progress) + Send>>,
+    /// How long the command may run before it is halted.
+    pub timeout: Option<Duration>,
+    /// Cancels the run along with any other run started with the same token.
+    pub token: Option<CancellationToken>,
+}
+
+/// Validates `circuit` and `command`, then runs them in the background.
+pub(crate) fn spawn(
+    circuit: &str,
+    command: &str,
+    options: Options,
+) -> Result<SimulationHandle, Error> {
+    let mut circuit = circuit.to_owned();
+    warmup::add_libraries(&mut circuit);
+    hooks::run_pre_load(&mut circuit, command);
+    NgSpice::check_circuit(&circuit)?;
+    NgSpice::check_command(command)?;
+    let command = command.to_owned();
+    let token = options
+        .token
+        .as_ref()
+        .map_or_else(CancellationToken::new, CancellationToken::child);
+    let flag = token.clone();
+    let thread = thread::spawn(move || {
+        let start = Instant::now();
+        let result = run(&circuit, &command, &flag, options);
+        telemetry::record(&result, start.elapsed());
+        result
+    });
+    Ok(SimulationHandle { thread, token })
+}
+
+/// Loads and runs a background simulation on the current thread.
+fn run(
+    circuit: &str,
+    command: &str,
+    token: &CancellationToken,
+    options: Options,
+) -> Result<Simulation, Error> {
+    let mut handle = NgSpice::shared().lock().expect(
+        "ngSPICE mutex was poisoned, meaning ngSPICE encountered a fatal error on another thread",
+    );
+    if token.is_cancelled() {
+        return Err(Error::Cancelled { partial: None });
+    }
+    handle.as_mut().stdout().truncate(0);
+    handle.as_mut().stderr().truncate(0);
+    handle.as_mut().load_circuit(circuit)?;
+    if let Some(sender) = options.stream {
+        stream::attach(sender);
+    }
+    if let Some(callback) = options.progress {
+        progress::attach(callback, command);
+    }
+    let result = run_in_background(handle.as_mut(), command, token, options.timeout);
+    // dropping the sender closes the stream
+    stream::detach();
+    progress::detach();
+    let stop = result?;
+    let limits = ResourceLimits::default();
+    let (mut sim, _) = unsafe { extract_plot(ngSpice_CurPlot(), &mut Budget::new(&limits)) };
+    std::mem::swap(handle.as_mut().stdout(), &mut sim.stdout);
+    std::mem::swap(handle.as_mut().stderr(), &mut sim.stderr);
+    drop(handle);
+    match stop {
+        Stop::Finished => {}
+        Stop::Cancelled => {
+            return Err(Error::Cancelled {
+                partial: Some(Box::new(sim)),
+            })
+        }
+        Stop::TimedOut => {
+            return Err(Error::Timeout {
+                partial: Some(Box::new(sim)),
+            })
+        }
+    }
+    hooks::run_post_extract(&mut sim);
+    Ok(sim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_finished_runs() {
+        // a fake report would end another test's wait while its run is still going
+        let _serial = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+        let before = *FINISHED.lock().unwrap();
+        bg_thread_running(false, 0, std::ptr::null_mut());
+        bg_thread_running(true, 0, std::ptr::null_mut());
+        assert!(*FINISHED.lock().unwrap() > before);
+    }
+
+    #[test]
+    fn cancels_queued_run() {
+        // hold the instance so the run cannot start before it is cancelled
+        let session = NgSpice::session();
+        let handle =
+            NgSpice::simulate_async("* queued\nR1 a 0 1k\nI1 0 a DC 1m\n.end", "op").unwrap();
+        handle.cancel();
+        drop(session);
+        assert!(matches!(
+            handle.join(),
+            Err(Error::Cancelled { partial: None })
+        ));
+    }
+
+    const LONG: &str = "* long\nR1 a 0 1k\nC1 a 0 1u\nI1 0 a DC 1m\n.end";
+
+    /// Keeps the tests that wait for a run to start from seeing each other's runs.
+    static RUNNING: Mutex<()> = Mutex::new(());
+
+    fn wait_until_running() {
+        while !unsafe { ngSpice_running() } {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn cancels_running_run() {
+        let _serial = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+        let handle = NgSpice::simulate_async(LONG, "tran 1n 10").unwrap();
+        wait_until_running();
+        handle.cancel();
+        assert!(matches!(
+            handle.join(),
+            Err(Error::Cancelled { partial: Some(_) })
+        ));
+    }
+
+    #[test]
+    fn times_out_running_run() {
+        let _serial = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+        let result = NgSpice::simulate_with_timeout(LONG, "tran 1n 10", Duration::from_millis(5));
+        assert!(matches!(result, Err(Error::Timeout { partial: Some(_) })));
+    }
+
+    #[test]
+    fn parent_cancels_running_child() {
+        let _serial = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+        let parent = CancellationToken::new();
+        let token = parent.clone();
+        let run = thread::spawn(move || NgSpice::simulate_cancellable(LONG, "tran 1n 10", &token));
+        wait_until_running();
+        parent.cancel();
+        assert!(matches!(
+            run.join().unwrap(),
+            Err(Error::Cancelled { partial: Some(_) })
+        ));
+    }
+
+    #[test]
+    fn halts_at_runtime_limit() {
+        let _serial = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+        let limits = ResourceLimits {
+            max_runtime: Some(Duration::from_millis(5)),
+            ..ResourceLimits::default()
+        };
+        let result = NgSpice::simulate_with_limits(LONG, "tran 1n 10", &limits);
+        assert!(matches!(
+            result,
+            Err(Error::ResourceLimit {
+                resource: crate::limits::Resource::Runtime,
+                partial: Some(_),
+            })
+        ));
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright 2022 Andrew Morrow.
+// battery.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Equivalent-circuit battery models with state-of-charge tracking.
+
+use std::fmt::Write;
+
+/// The impedance between a battery's open-circuit voltage and its terminals.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BatteryModel {
+    /// A series resistance only.
+    Rint { r0: f64 },
+    /// A series resistance and one RC pair for the polarization response.
+    Thevenin { r0: f64, r1: f64, c1: f64 },
+    /// A series resistance and two RC pairs for fast and slow polarization.
+    DualRc {
+        r0: f64,
+        r1: f64,
+        c1: f64,
+        r2: f64,
+        c2: f64,
+    },
+}
+
+/// A battery cell or pack.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Battery {
+    /// In amp-hours.
+    pub capacity: f64,
+    /// Open-circuit voltage by state of charge, as `(soc, volts)` pairs with `soc` from 0 to 1
+    /// in ascending order.
+    pub ocv: Vec<(f64, f64)>,
+    pub model: BatteryModel,
+    /// The state of charge at the start of a transient, from 0 to 1.
+    pub initial_soc: f64,
+}
+
+impl Battery {
+    /// Renders the battery as a subcircuit with ports `pos`, `neg`, and `soc`.
+    ///
+    /// The voltage of `soc` relative to ground is the state of charge, 1 V meaning full. It
+    /// starts at [`Battery::initial_soc`] only if the transient is run with `uic`; otherwise
+    /// the operating point discharges it.
+    pub fn subcircuit(&self, name: &str) -> String {
+        let mut out = format!(".subckt {} pos neg soc\n", name);
+        out.push_str("Bocv ocv neg V=pwl(v(soc)");
+        for (soc, v) in &self.ocv {
+            write!(out, ", {:e}, {:e}", soc, v).unwrap();
+        }
+        out.push_str(")\n");
+        let (r0, pairs) = match self.model {
+            BatteryModel::Rint { r0 } => (r0, vec![]),
+            BatteryModel::Thevenin { r0, r1, c1 } => (r0, vec![(r1, c1)]),
+            BatteryModel::DualRc { r0, r1, c1, r2, c2 } => (r0, vec![(r1, c1), (r2, c2)]),
+        };
+        writeln!(out, "R0 ocv n0 {:e}", r0).unwrap();
+        for (k, (r, c)) in pairs.iter().enumerate() {
+            writeln!(out, "R{} n{} n{} {:e}", k + 1, k, k + 1, r).unwrap();
+            writeln!(out, "C{} n{} n{} {:e}", k + 1, k, k + 1, c).unwrap();
+        }
+        // discharge current flows out of pos through the sense source
+        writeln!(out, "Vsense n{} pos DC 0", pairs.len()).unwrap();
+        // 1 V across the charge capacitor holds the full capacity
+        writeln!(
+            out,
+            "Csoc soc 0 {:e} IC={:e}",
+            self.capacity * 3600.0,
+            self.initial_soc
+        )
+        .unwrap();
+        out.push_str("Rsoc soc 0 1e12\n");
+        out.push_str("Bsoc soc 0 I=i(Vsense)\n");
+        writeln!(out, ".ends {}", name).unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_thevenin_cell() {
+        let cell = Battery {
+            capacity: 2.5,
+            ocv: vec![(0.0, 3.0), (0.5, 3.7), (1.0, 4.2)],
+            model: BatteryModel::Thevenin {
+                r0: 0.05,
+                r1: 0.02,
+                c1: 1e3,
+            },
+            initial_soc: 0.8,
+        };
+        assert_eq!(
+            cell.subcircuit("cell"),
+            ".subckt cell pos neg soc
+Bocv ocv neg V=pwl(v(soc), 0e0, 3e0, 5e-1, 3.7e0, 1e0, 4.2e0)
+R0 ocv n0 5e-2
+R1 n0 n1 2e-2
+C1 n0 n1 1e3
+Vsense n1 pos DC 0
+Csoc soc 0 9e3 IC=8e-1
+Rsoc soc 0 1e12
+Bsoc soc 0 I=i(Vsense)
+.ends cell
+"
+        );
+    }
+}
@@ -0,0 +1,248 @@
+// Copyright 2022 Andrew Morrow.
+// builder.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Typed constructors for building circuits in code instead of concatenating netlist text.
+
+use crate::circuit::{Card, Circuit, Element, Model};
+use crate::value::SpiceValue;
+
+/// The parameters of a `PULSE` source.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pulse {
+    pub initial: f64,
+    pub pulsed: f64,
+    pub delay: f64,
+    pub rise: f64,
+    pub fall: f64,
+    /// How long the pulse stays at `pulsed`.
+    pub width: f64,
+    pub period: f64,
+}
+
+fn number(value: impl Into<SpiceValue>) -> String {
+    value.into().to_string()
+}
+
+/// Prepends the element type letter unless `name` already starts with it.
+fn prefixed(kind: char, name: &str) -> String {
+    match name.chars().next() {
+        Some(c) if c.eq_ignore_ascii_case(&kind) => name.to_owned(),
+        _ => format!("{}{}", kind, name),
+    }
+}
+
+impl Circuit {
+    /// Starts an empty circuit. It renders with a `.title` line and ends with `.end`.
+    pub fn new(title: &str) -> Self {
+        let mut circuit = Circuit::default();
+        circuit.title = format!(".title {}", title);
+        circuit
+    }
+
+    /// Adds an element. The type letter is prepended to `name` unless it is already there,
+    /// so `resistor("load", ..)` adds `Rload`.
+    fn push_element(mut self, kind: char, name: &str, nodes: &[&str], params: Vec<String>) -> Self {
+        self.cards.push(Card::Element(Element {
+            name: prefixed(kind, name),
+            nodes: nodes.iter().map(|&n| n.to_owned()).collect(),
+            params,
+        }));
+        self
+    }
+
+    /// Adds a resistor, in Ω.
+    pub fn resistor(self, name: &str, a: &str, b: &str, ohms: impl Into<SpiceValue>) -> Self {
+        self.push_element('R', name, &[a, b], vec![number(ohms)])
+    }
+
+    /// Adds a capacitor, in F.
+    pub fn capacitor(self, name: &str, a: &str, b: &str, farads: impl Into<SpiceValue>) -> Self {
+        self.push_element('C', name, &[a, b], vec![number(farads)])
+    }
+
+    /// Adds an inductor, in H.
+    pub fn inductor(self, name: &str, a: &str, b: &str, henries: impl Into<SpiceValue>) -> Self {
+        self.push_element('L', name, &[a, b], vec![number(henries)])
+    }
+
+    /// Adds a DC voltage source from `pos` to `neg`.
+    pub fn vsource_dc(
+        self,
+        name: &str,
+        pos: &str,
+        neg: &str,
+        volts: impl Into<SpiceValue>,
+    ) -> Self {
+        self.push_element('V', name, &[pos, neg], vec!["DC".to_owned(), number(volts)])
+    }
+
+    /// Adds a voltage source with a DC value and an AC magnitude for small-signal analyses.
+    pub fn vsource_ac(
+        self,
+        name: &str,
+        pos: &str,
+        neg: &str,
+        dc: impl Into<SpiceValue>,
+        ac: impl Into<SpiceValue>,
+    ) -> Self {
+        let params = vec!["DC".to_owned(), number(dc), "AC".to_owned(), number(ac)];
+        self.push_element('V', name, &[pos, neg], params)
+    }
+
+    /// Adds a sinusoidal voltage source, with the frequency in Hz.
+    pub fn vsource_sin(
+        self,
+        name: &str,
+        pos: &str,
+        neg: &str,
+        offset: impl Into<SpiceValue>,
+        amplitude: impl Into<SpiceValue>,
+        frequency: impl Into<SpiceValue>,
+    ) -> Self {
+        let sin = format!(
+            "SIN({} {} {})",
+            number(offset),
+            number(amplitude),
+            number(frequency)
+        );
+        self.push_element('V', name, &[pos, neg], vec![sin])
+    }
+
+    /// Adds a pulsed voltage source.
+    pub fn vsource_pulse(self, name: &str, pos: &str, neg: &str, pulse: &Pulse) -> Self {
+        let values = [
+            pulse.initial,
+            pulse.pulsed,
+            pulse.delay,
+            pulse.rise,
+            pulse.fall,
+            pulse.width,
+            pulse.period,
+        ];
+        let values: Vec<String> = values.iter().map(|&v| number(v)).collect();
+        let pulse = format!("PULSE({})", values.join(" "));
+        self.push_element('V', name, &[pos, neg], vec![pulse])
+    }
+
+    /// Adds a DC current source driving current from `pos` through the source to `neg`.
+    pub fn isource_dc(self, name: &str, pos: &str, neg: &str, amps: impl Into<SpiceValue>) -> Self {
+        self.push_element('I', name, &[pos, neg], vec!["DC".to_owned(), number(amps)])
+    }
+
+    /// Adds a voltage-controlled voltage source: `v(pos, neg) = gain · v(cpos, cneg)`.
+    pub fn vcvs(
+        self,
+        name: &str,
+        out: (&str, &str),
+        control: (&str, &str),
+        gain: impl Into<SpiceValue>,
+    ) -> Self {
+        let nodes = [out.0, out.1, control.0, control.1];
+        self.push_element('E', name, &nodes, vec![number(gain)])
+    }
+
+    /// Adds a voltage-controlled current source with a transconductance in S.
+    pub fn vccs(
+        self,
+        name: &str,
+        out: (&str, &str),
+        control: (&str, &str),
+        gm: impl Into<SpiceValue>,
+    ) -> Self {
+        let nodes = [out.0, out.1, control.0, control.1];
+        self.push_element('G', name, &nodes, vec![number(gm)])
+    }
+
+    /// Adds a diode.
+    pub fn diode(self, name: &str, anode: &str, cathode: &str, model: &str) -> Self {
+        self.push_element('D', name, &[anode, cathode], vec![model.to_owned()])
+    }
+
+    /// Adds a bipolar transistor.
+    pub fn bjt(self, name: &str, collector: &str, base: &str, emitter: &str, model: &str) -> Self {
+        self.push_element(
+            'Q',
+            name,
+            &[collector, base, emitter],
+            vec![model.to_owned()],
+        )
+    }
+
+    /// Adds a MOSFET with its width and length in m. `nodes` are the drain, gate, source and
+    /// bulk.
+    pub fn mosfet(
+        self,
+        name: &str,
+        nodes: [&str; 4],
+        model: &str,
+        w: impl Into<SpiceValue>,
+        l: impl Into<SpiceValue>,
+    ) -> Self {
+        let params = vec![
+            model.to_owned(),
+            format!("w={}", number(w)),
+            format!("l={}", number(l)),
+        ];
+        self.push_element('M', name, &nodes, params)
+    }
+
+    /// Adds an instance of a subcircuit.
+    pub fn instance(self, name: &str, nodes: &[&str], subcircuit: &str) -> Self {
+        self.push_element('X', name, nodes, vec![subcircuit.to_owned()])
+    }
+
+    /// Adds a `.model` card, e.g. `model("d1", "d", &[("is", 1e-14)])`.
+    pub fn model(mut self, name: &str, kind: &str, params: &[(&str, f64)]) -> Self {
+        self.cards.push(Card::Model(Model {
+            name: name.to_owned(),
+            kind: kind.to_owned(),
+            params: params
+                .iter()
+                .map(|(k, v)| (k.to_string(), number(*v)))
+                .collect(),
+        }));
+        self
+    }
+
+    /// Adds a dot command verbatim, e.g. `.tran 1u 1m` or `.include models.lib`.
+    pub fn directive(mut self, line: &str) -> Self {
+        self.cards.push(Card::Directive(line.to_owned()));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_netlist() {
+        let circuit = Circuit::new("rc filter")
+            .vsource_ac("in", "in", "0", 0.0, 1.0)
+            .resistor("R1", "in", "out", "10k".parse::<SpiceValue>().unwrap())
+            .capacitor("load", "out", "0", 1e-9)
+            .vcvs("buf", ("y", "0"), ("out", "0"), 2.0)
+            .diode("D1", "y", "0", "dmod")
+            .model("dmod", "d", &[("is", 1e-14)]);
+        assert_eq!(
+            circuit.to_string(),
+            ".title rc filter\nVin in 0 DC 0 AC 1\nR1 in out 10k\nCload out 0 1n\n\
+             Ebuf y 0 out 0 2\nD1 y 0 dmod\n.model dmod d (is=10f)\n.end\n"
+        );
+        assert_eq!(Circuit::parse(&circuit.to_string()), circuit);
+    }
+}
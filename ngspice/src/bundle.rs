@@ -0,0 +1,603 @@
+// Copyright 2022 Andrew Morrow.
+// bundle.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Packs a simulation and everything needed to reproduce it into a single tar archive, for
+//! bug reports against ngSPICE or design reviews, and replays such archives as regression
+//! tests.
+
+use crate::compare::Deviation;
+use crate::options::SimOptions;
+use crate::warmup::insert_after_title;
+use crate::{DataType, Error, NgSpice, Simulation, VectorInfo, VectorValues};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The contents of a simulation artifact, returned by [`Simulation::bundle`].
+///
+/// The archive holds `netlist.cir`, `command.txt`, `options.txt`, `stdout.log`, `stderr.log`,
+/// `vectors.raw` (an ngSPICE ASCII rawfile) and `metadata.txt`, omitting entries that were
+/// not supplied.
+#[derive(Clone, Debug)]
+pub struct Bundle<'a> {
+    pub simulation: &'a Simulation,
+    pub netlist: Option<String>,
+    pub command: Option<String>,
+    pub options: Option<SimOptions>,
+    /// Free-form `key = value` lines written to `metadata.txt`.
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl Simulation {
+    /// Starts a bundle of this simulation's logs and vectors. Add the netlist and command with
+    /// [`Bundle::netlist`] and [`Bundle::command`] to make it reproducible.
+    pub fn bundle(&self) -> Bundle<'_> {
+        Bundle {
+            simulation: self,
+            netlist: None,
+            command: None,
+            options: None,
+            metadata: BTreeMap::new(),
+        }
+    }
+}
+
+impl Bundle<'_> {
+    pub fn netlist(mut self, netlist: &str) -> Self {
+        self.netlist = Some(netlist.to_owned());
+        self
+    }
+
+    pub fn command(mut self, command: &str) -> Self {
+        self.command = Some(command.to_owned());
+        self
+    }
+
+    pub fn options(mut self, options: &SimOptions) -> Self {
+        self.options = Some(options.clone());
+        self
+    }
+
+    pub fn metadata(mut self, key: &str, value: &str) -> Self {
+        self.metadata.insert(key.to_owned(), value.to_owned());
+        self
+    }
+
+    /// The simulation's vectors as an ngSPICE ASCII rawfile, readable by ngSPICE's `load`
+    /// command. The scale comes first; vectors whose length differs from the scale's are
+    /// left out and listed in `metadata.txt`.
+    pub fn rawfile(&self) -> String {
+        rawfile(self.simulation, "simulation")
+    }
+
+    fn metadata_text(&self, created: u64) -> String {
+        let mut text = format!(
+            "ngspice-rs = {}\ncreated = {}\n",
+            env!("CARGO_PKG_VERSION"),
+            created
+        );
+        let (_, skipped) = raw_vectors(self.simulation);
+        if !skipped.is_empty() {
+            writeln!(text, "omitted vectors = {}", skipped.join(" ")).unwrap();
+        }
+        for (key, value) in &self.metadata {
+            writeln!(text, "{} = {}", key, value).unwrap();
+        }
+        text
+    }
+
+    /// The archive as an uncompressed POSIX tar file.
+    pub fn to_tar(&self) -> Vec<u8> {
+        let created = now();
+        let sim = self.simulation;
+        let mut entries: Vec<(&str, String)> = Vec::new();
+        if let Some(netlist) = &self.netlist {
+            entries.push(("netlist.cir", netlist.clone()));
+        }
+        if let Some(command) = &self.command {
+            entries.push(("command.txt", format!("{}\n", command)));
+        }
+        if let Some(options) = &self.options {
+            entries.push(("options.txt", format!("{}\n", options)));
+        }
+        entries.push(("stdout.log", sim.stdout.clone()));
+        entries.push(("stderr.log", sim.stderr.clone()));
+        entries.push(("vectors.raw", self.rawfile()));
+        entries.push(("metadata.txt", self.metadata_text(created)));
+        tar(&entries, created)
+    }
+
+    /// Writes the archive to `path`, conventionally with a `.tar` extension.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the file cannot be written.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        std::fs::write(path, self.to_tar()).map_err(Error::Io)
+    }
+}
+
+/// A bundle read back from its archive.
+#[derive(Clone, Debug, Default)]
+pub struct Artifact {
+    pub netlist: Option<String>,
+    pub command: Option<String>,
+    /// The `.options` card, if options were bundled.
+    pub options: Option<String>,
+    /// The stored logs and vectors.
+    pub simulation: Simulation,
+    pub metadata: BTreeMap<String, String>,
+}
+
+pub(crate) fn invalid(message: &str) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+impl Artifact {
+    /// Reads a bundle written by [`Bundle::write`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the file cannot be read or is not a valid bundle.
+    pub fn read(path: impl AsRef<Path>) -> Result<Artifact, Error> {
+        Artifact::from_tar(&std::fs::read(path)?)
+    }
+
+    /// Parses a bundle from the bytes of its tar archive.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the archive or its rawfile is malformed.
+    pub fn from_tar(tar: &[u8]) -> Result<Artifact, Error> {
+        let mut artifact = Artifact::default();
+        for (name, contents) in untar(tar)? {
+            match name.as_str() {
+                "netlist.cir" => artifact.netlist = Some(contents),
+                "command.txt" => artifact.command = Some(contents.trim_end().to_owned()),
+                "options.txt" => artifact.options = Some(contents.trim_end().to_owned()),
+                "stdout.log" => artifact.simulation.stdout = contents,
+                "stderr.log" => artifact.simulation.stderr = contents,
+                "vectors.raw" => artifact.simulation.vectors = parse_rawfile(&contents)?,
+                "metadata.txt" => {
+                    artifact.metadata = contents
+                        .lines()
+                        .filter_map(|l| l.split_once(" = "))
+                        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                        .collect()
+                }
+                _ => {}
+            }
+        }
+        Ok(artifact)
+    }
+}
+
+/// Reads the vectors of a rawfile written by [`Bundle::rawfile`].
+pub(crate) fn parse_rawfile(raw: &str) -> Result<HashMap<String, VectorInfo>, Error> {
+    let mut lines = raw.lines();
+    let mut complex = false;
+    let mut variables = Vec::new();
+    for line in lines.by_ref() {
+        if let Some(flags) = line.strip_prefix("Flags:") {
+            complex = flags.contains("complex");
+        } else if line.starts_with("Variables:") {
+            break;
+        }
+    }
+    for line in lines.by_ref() {
+        if line.starts_with("Values:") {
+            break;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            [_, name, kind, ..] => variables.push((name.to_owned(), from_raw_type(kind))),
+            _ => return Err(invalid("bad rawfile variable")),
+        }
+    }
+    let mut columns: Vec<Vec<num_complex::Complex64>> = vec![Vec::new(); variables.len()];
+    let mut column = 0;
+    for line in lines {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        // the first variable of each point is preceded by the point index
+        let value = match fields[..] {
+            [_, value] => value,
+            [value] => value,
+            _ => continue,
+        };
+        let number = |s: &str| s.parse::<f64>().map_err(|_| invalid("bad rawfile value"));
+        let value = match value.split_once(',') {
+            Some((re, im)) => num_complex::Complex64::new(number(re)?, number(im)?),
+            None => num_complex::Complex64::new(number(value)?, 0.0),
+        };
+        columns
+            .get_mut(column)
+            .ok_or_else(|| invalid("too many rawfile values"))?
+            .push(value);
+        column = (column + 1) % variables.len().max(1);
+    }
+    Ok(variables
+        .into_iter()
+        .zip(columns)
+        .map(|((name, datatype), column)| {
+            let values = if complex && datatype != DataType::Frequency {
+                VectorValues::from(column)
+            } else {
+                VectorValues::from(column.iter().map(|c| c.re).collect::<Vec<f64>>())
+            };
+            let info = VectorInfo {
+                datatype,
+                values,
+                scale: None,
+            };
+            (name, info)
+        })
+        .collect())
+}
+
+/// How far a replayed vector may stray from the stored one: `absolute` plus `relative` times
+/// the largest stored magnitude.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReplayTolerance {
+    pub absolute: f64,
+    pub relative: f64,
+}
+
+impl Default for ReplayTolerance {
+    fn default() -> Self {
+        ReplayTolerance {
+            absolute: 1e-9,
+            relative: 1e-6,
+        }
+    }
+}
+
+/// The result of [`NgSpice::replay`].
+#[derive(Clone, Debug)]
+pub struct Replay {
+    /// The new simulation.
+    pub simulation: Simulation,
+    /// How far each stored vector strays in the new simulation.
+    pub deviations: HashMap<String, Deviation>,
+    /// Stored vectors that are missing from the new simulation or exceed the tolerance,
+    /// sorted.
+    pub failures: Vec<String>,
+}
+
+impl Replay {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Compares vectors point by point, for simulations without a scale such as operating points.
+fn compare_points(stored: &Simulation, new: &Simulation) -> HashMap<String, Deviation> {
+    let mut result = HashMap::new();
+    for (name, reference) in &stored.vectors {
+        let Some(candidate) = new.vectors.get(name) else {
+            continue;
+        };
+        let diffs: Vec<f64> = (0..reference.values.len().min(candidate.values.len()))
+            .map(|i| (value_at(&reference.values, i) - value_at(&candidate.values, i)).norm())
+            .collect();
+        if diffs.is_empty() {
+            continue;
+        }
+        let max = diffs.iter().copied().fold(0.0f64, f64::max);
+        let rms = crate::kernels::rms(&diffs);
+        result.insert(name.clone(), Deviation { max, rms });
+    }
+    result
+}
+
+fn value_at(values: &VectorValues, i: usize) -> num_complex::Complex64 {
+    match values {
+        VectorValues::Real(x) => num_complex::Complex64::new(x[i], 0.0),
+        VectorValues::Complex(x) => x[i],
+    }
+}
+
+impl NgSpice {
+    /// Re-runs a bundled simulation and compares the result against the stored vectors.
+    ///
+    /// The bundle must include its netlist and command. Vectors are compared on the stored
+    /// scale as by [`Simulation::compare`], or point by point if there is no scale.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the bundle is malformed or lacks a netlist or command, or the
+    /// errors of [`NgSpice::simulate`].
+    pub fn replay(artifact: &Artifact, tolerance: &ReplayTolerance) -> Result<Replay, Error> {
+        let mut netlist = artifact
+            .netlist
+            .clone()
+            .ok_or_else(|| invalid("bundle has no netlist"))?;
+        let command = artifact
+            .command
+            .as_deref()
+            .ok_or_else(|| invalid("bundle has no command"))?;
+        if let Some(options) = artifact.options.as_deref().filter(|o| !o.is_empty()) {
+            insert_after_title(&mut netlist, &format!("{}\n", options));
+        }
+        let simulation = NgSpice::simulate(&netlist, command)?;
+        let stored = &artifact.simulation;
+        let deviations = match stored.compare(&simulation) {
+            Ok(deviations) => deviations,
+            Err(Error::MissingScale) => compare_points(stored, &simulation),
+            Err(e) => return Err(e),
+        };
+        let scale = stored.scale_vector().map(|(name, _)| name);
+        let mut failures: Vec<String> = stored
+            .vectors
+            .iter()
+            .filter(|(name, _)| Some(name.as_str()) != scale)
+            .filter(|(name, info)| match deviations.get(*name) {
+                Some(d) => {
+                    let peak = (0..info.values.len())
+                        .map(|i| value_at(&info.values, i).norm())
+                        .fold(0.0f64, f64::max);
+                    d.max > tolerance.absolute + tolerance.relative * peak
+                }
+                None => true,
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        failures.sort();
+        Ok(Replay {
+            simulation,
+            deviations,
+            failures,
+        })
+    }
+}
+
+/// Writes the vectors of `sim` as an ngSPICE ASCII rawfile plot named `plot`. The scale comes
+/// first; vectors whose length differs from the scale's are left out.
+pub(crate) fn rawfile(sim: &Simulation, plot: &str) -> String {
+    let (vectors, _) = raw_vectors(sim);
+    let complex = vectors
+        .iter()
+        .any(|(_, v)| matches!(v.values, VectorValues::Complex(_)));
+    let points = vectors.first().map_or(0, |(_, v)| v.values.len());
+    let mut raw = String::new();
+    raw.push_str("Title: ngspice-rs bundle\n");
+    writeln!(raw, "Plotname: {}", plot).unwrap();
+    writeln!(raw, "Flags: {}", if complex { "complex" } else { "real" }).unwrap();
+    writeln!(raw, "No. Variables: {}", vectors.len()).unwrap();
+    writeln!(raw, "No. Points: {}", points).unwrap();
+    raw.push_str("Variables:\n");
+    for (i, (name, info)) in vectors.iter().enumerate() {
+        writeln!(raw, "\t{}\t{}\t{}", i, name, raw_type(&info.datatype)).unwrap();
+    }
+    raw.push_str("Values:\n");
+    for point in 0..points {
+        for (i, (_, info)) in vectors.iter().enumerate() {
+            let prefix = if i == 0 {
+                format!(" {}", point)
+            } else {
+                String::new()
+            };
+            let (re, im) = match &info.values {
+                VectorValues::Real(x) => (x[point], 0.0),
+                VectorValues::Complex(x) => (x[point].re, x[point].im),
+            };
+            if complex {
+                writeln!(raw, "{}\t{:e},{:e}", prefix, re, im).unwrap();
+            } else {
+                writeln!(raw, "{}\t{:e}", prefix, re).unwrap();
+            }
+        }
+    }
+    raw
+}
+
+/// The vectors that fit in one rawfile plot, scale first, and the names of the rest.
+pub(crate) fn raw_vectors(sim: &Simulation) -> (Vec<(&str, &VectorInfo)>, Vec<&str>) {
+    let scale = sim.scale_vector().map(|(name, _)| name);
+    let mut names: Vec<&str> = sim.vectors.keys().map(String::as_str).collect();
+    names.sort_by_key(|&n| (Some(n) != scale, n));
+    let length = names.first().map_or(0, |n| sim.vectors[*n].values.len());
+    let (fit, rest): (Vec<&str>, Vec<&str>) = names
+        .into_iter()
+        .partition(|n| sim.vectors[*n].values.len() == length);
+    (
+        fit.into_iter().map(|n| (n, &sim.vectors[n])).collect(),
+        rest,
+    )
+}
+
+/// Packs `(name, contents)` entries into an uncompressed POSIX tar file.
+pub(crate) fn tar(entries: &[(&str, String)], mtime: u64) -> Vec<u8> {
+    let mut tar = Vec::new();
+    for (name, contents) in entries {
+        tar.extend_from_slice(&tar_header(name, contents.len(), mtime));
+        tar.extend_from_slice(contents.as_bytes());
+        tar.resize(tar.len().next_multiple_of(512), 0);
+    }
+    // two zero blocks end the archive
+    tar.resize(tar.len() + 1024, 0);
+    tar
+}
+
+/// Unpacks the `(name, contents)` entries of a tar file.
+pub(crate) fn untar(tar: &[u8]) -> Result<Vec<(String, String)>, Error> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset + 512 <= tar.len() && tar[offset] != 0 {
+        let header = &tar[offset..offset + 512];
+        let name_end = header[..100].iter().position(|&b| b == 0).unwrap_or(100);
+        let name = String::from_utf8_lossy(&header[..name_end]).into_owned();
+        let size = std::str::from_utf8(&header[124..135])
+            .ok()
+            .and_then(|s| usize::from_str_radix(s.trim_matches(['\0', ' ']), 8).ok())
+            .ok_or_else(|| invalid("bad tar entry size"))?;
+        let start = offset + 512;
+        let contents = tar
+            .get(start..start + size)
+            .ok_or_else(|| invalid("truncated tar entry"))?;
+        entries.push((name, String::from_utf8_lossy(contents).into_owned()));
+        offset = (start + size).next_multiple_of(512);
+    }
+    Ok(entries)
+}
+
+/// The seconds since the Unix epoch, for archive timestamps.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// The type name ngSPICE writes for a vector in a rawfile.
+fn raw_type(datatype: &DataType) -> &'static str {
+    match datatype {
+        DataType::Time => "time",
+        DataType::Frequency => "frequency",
+        DataType::Voltage => "voltage",
+        DataType::Current => "current",
+        DataType::VoltageDensity => "voltage-density",
+        DataType::CurrentDensity => "current-density",
+        DataType::Temperature => "temp-sweep",
+        DataType::Resistance => "res-sweep",
+        DataType::Impedance => "impedance",
+        DataType::Admittance => "admittance",
+        DataType::Power => "power",
+        DataType::Phase => "phase",
+        DataType::Decibel => "decibel",
+        DataType::Capacitance => "capacitance",
+        DataType::Charge => "charge",
+        _ => "notype",
+    }
+}
+
+fn from_raw_type(name: &str) -> DataType {
+    match name {
+        "time" => DataType::Time,
+        "frequency" => DataType::Frequency,
+        "voltage" => DataType::Voltage,
+        "current" => DataType::Current,
+        "voltage-density" => DataType::VoltageDensity,
+        "current-density" => DataType::CurrentDensity,
+        "temp-sweep" => DataType::Temperature,
+        "res-sweep" => DataType::Resistance,
+        "impedance" => DataType::Impedance,
+        "admittance" => DataType::Admittance,
+        "power" => DataType::Power,
+        "phase" => DataType::Phase,
+        "decibel" => DataType::Decibel,
+        "capacitance" => DataType::Capacitance,
+        "charge" => DataType::Charge,
+        _ => DataType::Unknown,
+    }
+}
+
+/// A ustar header for a regular file.
+fn tar_header(name: &str, size: usize, mtime: u64) -> [u8; 512] {
+    let mut header = [0u8; 512];
+    let mut put = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+    put(0, &name.as_bytes()[..name.len().min(100)]);
+    put(100, b"0000644\0");
+    put(108, b"0000000\0");
+    put(116, b"0000000\0");
+    put(124, format!("{:011o}\0", size).as_bytes());
+    put(136, format!("{:011o}\0", mtime).as_bytes());
+    // the checksum is computed with its own field filled with spaces
+    put(148, b"        ");
+    put(156, b"0");
+    put(257, b"ustar\0");
+    put(263, b"00");
+    let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VectorInfo;
+
+    #[test]
+    fn archives_simulation() {
+        let mut sim = Simulation {
+            stdout: "Circuit: * rc".to_owned(),
+            ..Simulation::default()
+        };
+        for (name, datatype, values) in [
+            ("time", DataType::Time, vec![0.0, 1e-3]),
+            ("out", DataType::Voltage, vec![0.0, 0.5]),
+            ("vdd#branch", DataType::Current, vec![1.0]),
+        ] {
+            sim.vectors.insert(
+                name.to_owned(),
+                VectorInfo {
+                    datatype,
+                    values: VectorValues::Real(values.into()),
+                    scale: None,
+                },
+            );
+        }
+        let bundle = sim
+            .bundle()
+            .netlist("* rc\nR1 out 0 1k\n.end")
+            .command("tran 1m 1m")
+            .metadata("issue", "42");
+        assert_eq!(
+            bundle.rawfile(),
+            "Title: ngspice-rs bundle\nPlotname: simulation\nFlags: real\nNo. Variables: 2\n\
+             No. Points: 2\nVariables:\n\t0\ttime\ttime\n\t1\tout\tvoltage\nValues:\n \
+             0\t0e0\n\t0e0\n 1\t1e-3\n\t5e-1\n"
+        );
+        let tar = bundle.to_tar();
+        assert_eq!(tar.len() % 512, 0);
+        assert_eq!(&tar[..11], b"netlist.cir");
+        assert_eq!(&tar[257..263], b"ustar\0");
+        let checksum: u32 = tar[..512]
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| {
+                if (148..156).contains(&i) {
+                    32
+                } else {
+                    u32::from(b)
+                }
+            })
+            .sum();
+        let stored = std::str::from_utf8(&tar[148..154]).unwrap();
+        assert_eq!(u32::from_str_radix(stored, 8).unwrap(), checksum);
+        let text = String::from_utf8_lossy(&tar);
+        assert!(text.contains("omitted vectors = vdd#branch\nissue = 42\n"));
+        assert!(text.contains("tran 1m 1m\n"));
+
+        let artifact = Artifact::from_tar(&tar).unwrap();
+        assert_eq!(artifact.command.as_deref(), Some("tran 1m 1m"));
+        assert_eq!(artifact.metadata["issue"], "42");
+        assert_eq!(artifact.simulation.stdout, sim.stdout);
+        assert_eq!(
+            artifact.simulation.real_vector("time").unwrap(),
+            [0.0, 1e-3]
+        );
+        assert_eq!(artifact.simulation.real_vector("out").unwrap(), [0.0, 0.5]);
+        assert_eq!(
+            artifact.simulation.vectors["out"].datatype,
+            DataType::Voltage
+        );
+        assert!(!artifact.simulation.vectors.contains_key("vdd#branch"));
+    }
+}
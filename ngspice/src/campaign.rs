@@ -0,0 +1,328 @@
+// Copyright 2022 Andrew Morrow.
+// campaign.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Groups many simulation runs under user-defined tags.
+
+use crate::cancel::CancellationToken;
+use crate::{Error, NgSpice, Simulation};
+use std::collections::BTreeMap;
+use std::fmt::{self, Formatter};
+
+/// The value of a single tag: a number such as a temperature, or text such as a corner name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TagValue {
+    Number(f64),
+    Text(String),
+}
+
+impl TagValue {
+    pub fn number(&self) -> Option<f64> {
+        match self {
+            TagValue::Number(x) => Some(*x),
+            TagValue::Text(_) => None,
+        }
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            TagValue::Number(_) => None,
+            TagValue::Text(x) => Some(x),
+        }
+    }
+}
+
+impl fmt::Display for TagValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TagValue::Number(x) => write!(f, "{}", x),
+            TagValue::Text(x) => f.write_str(x),
+        }
+    }
+}
+
+impl From<f64> for TagValue {
+    fn from(x: f64) -> Self {
+        TagValue::Number(x)
+    }
+}
+
+impl From<&str> for TagValue {
+    fn from(x: &str) -> Self {
+        TagValue::Text(x.to_owned())
+    }
+}
+
+impl From<String> for TagValue {
+    fn from(x: String) -> Self {
+        TagValue::Text(x)
+    }
+}
+
+/// Named tags describing one run, e.g. `corner=ss`, `temp=125`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tags(BTreeMap<String, TagValue>);
+
+impl Tags {
+    pub fn new() -> Self {
+        Tags::default()
+    }
+
+    /// Adds or replaces a tag.
+    pub fn with(mut self, key: &str, value: impl Into<TagValue>) -> Self {
+        self.0.insert(key.to_owned(), value.into());
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<&TagValue> {
+        self.0.get(key)
+    }
+
+    pub fn number(&self, key: &str) -> Option<f64> {
+        self.get(key).and_then(TagValue::number)
+    }
+
+    pub fn text(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(TagValue::text)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &TagValue)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// A stable 64-bit hash of every tag, identifying the point in a sweep a run was made at.
+    /// Runs with equal tags have equal fingerprints across program runs and platforms.
+    pub fn fingerprint(&self) -> u64 {
+        // 64-bit FNV-1a
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut feed = |bytes: &[u8]| {
+            for &b in bytes {
+                hash ^= u64::from(b);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        for (key, value) in self.iter() {
+            feed(key.as_bytes());
+            match value {
+                TagValue::Number(x) => {
+                    feed(b"=n");
+                    feed(&x.to_bits().to_le_bytes());
+                }
+                TagValue::Text(x) => {
+                    feed(b"=t");
+                    feed(x.as_bytes());
+                }
+            }
+            feed(&[0]);
+        }
+        hash
+    }
+}
+
+/// A single run within a campaign.
+#[derive(Clone, Debug)]
+pub struct Run {
+    pub tags: Tags,
+    pub simulation: Simulation,
+    /// Scalar measurements derived from the simulation, by name.
+    pub measurements: BTreeMap<String, f64>,
+}
+
+/// A campaign flattened into rows and columns, produced by [`Campaign::table`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Table {
+    pub columns: Vec<String>,
+    /// The number of leading columns that hold tags. The remaining columns hold measurements.
+    pub parameter_columns: usize,
+    /// One row per run, with one cell per column.
+    pub rows: Vec<Vec<Option<TagValue>>>,
+}
+
+/// A named collection of tagged runs, e.g. every corner of a sweep.
+#[derive(Clone, Debug, Default)]
+pub struct Campaign {
+    pub name: String,
+    runs: Vec<Run>,
+}
+
+impl Campaign {
+    pub fn new(name: &str) -> Self {
+        Campaign {
+            name: name.to_owned(),
+            runs: Vec::new(),
+        }
+    }
+
+    /// Adds a completed simulation and returns the run's index.
+    pub fn add(&mut self, tags: Tags, simulation: Simulation) -> usize {
+        self.runs.push(Run {
+            tags,
+            simulation,
+            measurements: BTreeMap::new(),
+        });
+        self.runs.len() - 1
+    }
+
+    /// Runs a simulation and adds it to the campaign, returning the run's index.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`NgSpice::simulate`]. Failed runs are not added.
+    pub fn simulate(&mut self, tags: Tags, circuit: &str, command: &str) -> Result<usize, Error> {
+        let sim = NgSpice::simulate(circuit, command)?;
+        Ok(self.add(tags, sim))
+    }
+
+    /// Like [`Campaign::simulate`], but halts the run if `token` is cancelled.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`NgSpice::simulate_cancellable`]. Failed and cancelled runs are
+    /// not added.
+    pub fn simulate_cancellable(
+        &mut self,
+        tags: Tags,
+        circuit: &str,
+        command: &str,
+        token: &CancellationToken,
+    ) -> Result<usize, Error> {
+        let sim = NgSpice::simulate_cancellable(circuit, command, token)?;
+        Ok(self.add(tags, sim))
+    }
+
+    pub fn runs(&self) -> &[Run] {
+        &self.runs
+    }
+
+    pub fn runs_mut(&mut self) -> &mut [Run] {
+        &mut self.runs
+    }
+
+    /// Returns every run whose tags satisfy `predicate`.
+    pub fn query<'a, F>(&'a self, predicate: F) -> impl Iterator<Item = &'a Run>
+    where
+        F: Fn(&Tags) -> bool + 'a,
+    {
+        self.runs.iter().filter(move |r| predicate(&r.tags))
+    }
+
+    /// Returns every run where the tag `key` equals `value`.
+    pub fn with_tag<'a>(
+        &'a self,
+        key: &'a str,
+        value: impl Into<TagValue>,
+    ) -> impl Iterator<Item = &'a Run> {
+        let value = value.into();
+        self.query(move |t| t.get(key) == Some(&value))
+    }
+
+    /// Flattens the campaign into a table with one row per run.
+    ///
+    /// The columns are every tag key followed by every measurement name, each sorted
+    /// alphabetically. Cells are empty where a run lacks that tag or measurement.
+    pub fn table(&self) -> Table {
+        let mut tags: Vec<&str> = self
+            .runs
+            .iter()
+            .flat_map(|r| r.tags.iter().map(|(k, _)| k))
+            .collect();
+        tags.sort_unstable();
+        tags.dedup();
+        let mut measurements: Vec<&str> = self
+            .runs
+            .iter()
+            .flat_map(|r| r.measurements.keys().map(String::as_str))
+            .collect();
+        measurements.sort_unstable();
+        measurements.dedup();
+        let rows = self
+            .runs
+            .iter()
+            .map(|r| {
+                let tag_cells = tags.iter().map(|&k| r.tags.get(k).cloned());
+                let meas_cells = measurements
+                    .iter()
+                    .map(|&k| r.measurements.get(k).map(|&x| TagValue::Number(x)));
+                tag_cells.chain(meas_cells).collect()
+            })
+            .collect();
+        Table {
+            parameter_columns: tags.len(),
+            columns: tags
+                .into_iter()
+                .chain(measurements)
+                .map(str::to_owned)
+                .collect(),
+            rows,
+        }
+    }
+
+    /// Returns the distinct values of a tag, in the order they first appear.
+    pub fn tag_values(&self, key: &str) -> Vec<&TagValue> {
+        let mut result: Vec<&TagValue> = Vec::new();
+        for v in self.runs.iter().filter_map(|r| r.tags.get(key)) {
+            if !result.contains(&v) {
+                result.push(v);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_by_tag() {
+        let mut campaign = Campaign::new("corners");
+        for corner in ["tt", "ss", "ff"] {
+            for temp in [-40.0, 25.0, 125.0] {
+                let tags = Tags::new().with("corner", corner).with("temp", temp);
+                campaign.add(tags, Simulation::default());
+            }
+        }
+        assert_eq!(campaign.runs().len(), 9);
+        assert_eq!(campaign.with_tag("corner", "ss").count(), 3);
+        let hot_slow = campaign
+            .query(|t| t.text("corner") == Some("ss") && t.number("temp") > Some(100.0))
+            .count();
+        assert_eq!(hot_slow, 1);
+        assert_eq!(campaign.tag_values("temp").len(), 3);
+        assert_eq!(campaign.tag_values("corner")[1], &TagValue::from("ss"));
+        let ss = Tags::new().with("corner", "ss").with("temp", 125.0);
+        assert_eq!(
+            ss.fingerprint(),
+            Tags::new()
+                .with("temp", 125.0)
+                .with("corner", "ss")
+                .fingerprint()
+        );
+        assert_ne!(
+            ss.fingerprint(),
+            ss.clone().with("temp", 25.0).fingerprint()
+        );
+
+        campaign.runs_mut()[0]
+            .measurements
+            .insert("gain".to_owned(), 20.0);
+        let table = campaign.table();
+        assert_eq!(table.columns, vec!["corner", "temp", "gain"]);
+        assert_eq!(table.parameter_columns, 2);
+        assert_eq!(table.rows[0][2], Some(TagValue::Number(20.0)));
+        assert_eq!(table.rows[1][2], None);
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright 2022 Andrew Morrow.
+// cancel.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Cooperative cancellation shared between runs, sweeps and whole jobs.
+
+use crate::background;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, Default)]
+struct Node {
+    cancelled: AtomicBool,
+    parent: Option<Arc<Node>>,
+}
+
+/// A flag that stops every background simulation watching it, and every simulation watching
+/// one of its children.
+///
+/// Give a job one token, each sweep in the job a [`CancellationToken::child`] of it, and each
+/// run a child of its sweep's token. Cancelling the run's token stops only that run, the
+/// sweep's stops the rest of the sweep, and the job's stops everything.
+///
+/// Clones share the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    node: Arc<Node>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// A token that is also cancelled when this one is. Cancelling the child does not cancel
+    /// this token.
+    pub fn child(&self) -> Self {
+        CancellationToken {
+            node: Arc::new(Node {
+                cancelled: AtomicBool::new(false),
+                parent: Some(self.node.clone()),
+            }),
+        }
+    }
+
+    /// Cancels this token and all of its children. Running simulations are halted with
+    /// ngSPICE's `bg_halt`, and queued ones never start.
+    pub fn cancel(&self) {
+        self.node.cancelled.store(true, Ordering::SeqCst);
+        background::wake_waiters();
+    }
+
+    /// Whether this token or any of its ancestors has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        let mut node = Some(&self.node);
+        while let Some(n) = node {
+            if n.cancelled.load(Ordering::SeqCst) {
+                return true;
+            }
+            node = n.parent.as_ref();
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancels_children() {
+        let job = CancellationToken::new();
+        let sweep = job.child();
+        let run = sweep.child();
+        let other = job.child();
+        run.cancel();
+        assert!(run.is_cancelled());
+        assert!(!sweep.is_cancelled());
+        sweep.clone().cancel();
+        assert!(sweep.is_cancelled());
+        assert!(!other.is_cancelled());
+        job.cancel();
+        assert!(other.is_cancelled());
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright 2022 Andrew Morrow.
+// characterize.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Runs a standard battery of analyses on a single circuit.
+
+use crate::analysis::{NoiseCommand, NoiseResult};
+use crate::{Error, NgSpice, Simulation};
+
+/// The analyses to run when characterizing a circuit.
+///
+/// Each of `op`, `ac` and `tran` holds a complete ngSPICE command, e.g. `ac dec 10 1 1meg`.
+/// Analyses set to `None` are skipped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CharacterizationPlan {
+    pub op: Option<String>,
+    pub ac: Option<String>,
+    pub tran: Option<String>,
+    pub noise: Option<NoiseCommand>,
+}
+
+impl Default for CharacterizationPlan {
+    /// Only the operating point is computed by default, because every other analysis needs
+    /// circuit-specific arguments.
+    fn default() -> Self {
+        CharacterizationPlan {
+            op: Some("op".to_owned()),
+            ac: None,
+            tran: None,
+            noise: None,
+        }
+    }
+}
+
+/// The combined results of every analysis in a [`CharacterizationPlan`].
+#[derive(Clone, Debug, Default)]
+pub struct Characterization {
+    pub op: Option<Simulation>,
+    pub ac: Option<Simulation>,
+    pub tran: Option<Simulation>,
+    /// Both plots of the noise analysis: its spectral densities and its integrated totals.
+    pub noise: Option<NoiseResult>,
+}
+
+impl NgSpice {
+    /// Runs every analysis in `plan` against the same circuit and collects the results.
+    ///
+    /// ngSPICE supports only one instance per process, so there is no pool to spread the
+    /// analyses over: they run one after another on the shared instance, exactly as if
+    /// [`NgSpice::simulate`] had been called for each one. The noise analysis runs with
+    /// [`NoiseCommand::run`] instead, so hooks do not run for it.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error encountered. Analyses after the failing one are not run.
+    pub fn characterize(
+        circuit: &str,
+        plan: &CharacterizationPlan,
+    ) -> Result<Characterization, Error> {
+        let run = |cmd: &Option<String>| -> Result<Option<Simulation>, Error> {
+            cmd.as_deref()
+                .map(|cmd| NgSpice::simulate(circuit, cmd))
+                .transpose()
+        };
+        Ok(Characterization {
+            op: run(&plan.op)?,
+            ac: run(&plan.ac)?,
+            tran: run(&plan.tran)?,
+            noise: plan.noise.as_ref().map(|n| n.run(circuit)).transpose()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_only_planned_analyses() -> Result<(), Error> {
+        let plan = CharacterizationPlan::default();
+        assert_eq!(plan.op.as_deref(), Some("op"));
+        assert_eq!((&plan.ac, &plan.tran, &plan.noise), (&None, &None, &None));
+        let divider = "* divider\nV1 in 0 DC 1\nR1 in out 1k\nR2 out 0 1k\n.end";
+        let result = NgSpice::characterize(divider, &plan)?;
+        assert!(result.op.is_some());
+        assert!(result.ac.is_none() && result.tran.is_none() && result.noise.is_none());
+        let archive = result.archive();
+        let names: Vec<&str> = archive.plots.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["op"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,1170 @@
+// Copyright 2022 Andrew Morrow.
+// circuit.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A parsed, editable netlist.
+
+use crate::montecarlo::{Distribution, Tolerance};
+use crate::state::Snapshot;
+use crate::{Error, NgSpice, Simulation};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Formatter};
+
+/// One element line, e.g. `R1 in out 1k`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Element {
+    pub name: String,
+    pub nodes: Vec<String>,
+    /// Everything after the nodes: the value or model name, then any parameters.
+    pub params: Vec<String>,
+}
+
+impl Element {
+    /// Splits an element line into its name, nodes, and parameters.
+    ///
+    /// The number of nodes is inferred from the element type. Bipolar transistors are assumed
+    /// to have three nodes, so a substrate node is read as the model name.
+    pub fn parse(line: &str) -> Option<Element> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let name = *tokens.first()?;
+        let count = match name.chars().next()?.to_ascii_lowercase() {
+            'r' | 'c' | 'l' | 'v' | 'i' | 'd' | 'b' | 'f' | 'h' | 'w' => 2,
+            'q' | 'j' | 'z' => 3,
+            'm' | 's' | 't' | 'o' => 4,
+            'e' | 'g' => match tokens.get(3) {
+                Some(t) if is_source_keyword(t) => 2,
+                _ => 4,
+            },
+            'x' => {
+                // the subcircuit name is the last token before any parameters
+                let end = tokens
+                    .iter()
+                    .position(|t| t.contains('=') || t.eq_ignore_ascii_case("params:"))
+                    .unwrap_or(tokens.len());
+                end.saturating_sub(2)
+            }
+            'k' => 0,
+            _ => return None,
+        };
+        let count = count.min(tokens.len() - 1);
+        Some(Element {
+            name: name.to_owned(),
+            nodes: tokens[1..=count].iter().map(|&t| t.to_owned()).collect(),
+            params: tokens[count + 1..].iter().map(|&t| t.to_owned()).collect(),
+        })
+    }
+
+    /// The element type letter, in uppercase.
+    pub fn kind(&self) -> char {
+        self.name
+            .chars()
+            .next()
+            .map_or('?', |c| c.to_ascii_uppercase())
+    }
+
+    /// The first parameter, which is the value of passive elements and the model of devices.
+    pub fn value(&self) -> Option<&str> {
+        self.params.first().map(String::as_str)
+    }
+}
+
+fn is_source_keyword(token: &str) -> bool {
+    let lower = token.to_ascii_lowercase();
+    token.contains('=')
+        || ["value", "table", "poly", "vol", "cur"]
+            .iter()
+            .any(|k| lower.starts_with(k))
+}
+
+impl fmt::Display for Element {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        for token in self.nodes.iter().chain(&self.params) {
+            write!(f, " {}", token)?;
+        }
+        Ok(())
+    }
+}
+
+/// A `.model` card, e.g. `.model d1n4148 D (IS=2.52n N=1.752)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Model {
+    pub name: String,
+    /// The device type, e.g. `D` or `nmos`.
+    pub kind: String,
+    /// Parameter names and values, in order.
+    pub params: Vec<(String, String)>,
+}
+
+impl Model {
+    /// Parses a `.model` card. Returns `None` for other cards, and for models whose parameters
+    /// are not all plain `name=value` pairs, such as vector-valued code model parameters.
+    pub fn parse(line: &str) -> Option<Model> {
+        let rest = line.trim().split_once(char::is_whitespace)?;
+        if !rest.0.eq_ignore_ascii_case(".model") {
+            return None;
+        }
+        // `a = 1` and `a=1` are the same parameter
+        let mut flat = rest.1.replace(['(', ')'], " ");
+        while flat.contains(" =") || flat.contains("= ") {
+            flat = flat.replace(" =", "=").replace("= ", "=");
+        }
+        let mut tokens = flat.split_whitespace();
+        let name = tokens.next()?.to_owned();
+        let kind = tokens.next()?.to_owned();
+        let params = tokens
+            .map(|t| {
+                let (key, value) = t.split_once('=')?;
+                let plain = !key.is_empty() && !value.is_empty() && !t.contains(['[', ']']);
+                plain.then(|| (key.to_owned(), value.to_owned()))
+            })
+            .collect::<Option<_>>()?;
+        Some(Model { name, kind, params })
+    }
+
+    /// The value of a parameter, ignoring case.
+    pub fn get(&self, param: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(param))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Changes a parameter, or adds it if the model does not set it.
+    pub fn set(&mut self, param: &str, value: &str) {
+        match self
+            .params
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case(param))
+        {
+            Some((_, v)) => *v = value.to_owned(),
+            None => self.params.push((param.to_owned(), value.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, ".model {} {}", self.name, self.kind)?;
+        if !self.params.is_empty() {
+            let params: Vec<String> = self
+                .params
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect();
+            write!(f, " ({})", params.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A `.subckt` definition.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subcircuit {
+    pub name: String,
+    pub ports: Vec<String>,
+    /// Default parameters and anything else after the ports, e.g. `params: r=1k`.
+    pub params: Vec<String>,
+    pub cards: Vec<Card>,
+}
+
+/// One logical line of a netlist, or a block of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Card {
+    Element(Element),
+    Model(Model),
+    /// Any other dot command, such as `.tran`, kept verbatim.
+    Directive(String),
+    /// A `*` comment line.
+    Comment(String),
+    Subcircuit(Subcircuit),
+    /// The lines between `.control` and `.endc`.
+    Control(Vec<String>),
+}
+
+/// A parsed netlist. Rendering it with `to_string()` gives a deck ending with `.end`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Circuit {
+    pub title: String,
+    pub cards: Vec<Card>,
+    /// Friendly result names of inserted ammeters, mapped to the vectors ngSPICE gives them.
+    probes: BTreeMap<String, String>,
+    /// Tolerances by lowercase element name.
+    tolerances: BTreeMap<String, Tolerance>,
+}
+
+/// Joins `+` continuation lines onto the line before them.
+pub(crate) fn logical_lines(deck: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in deck.lines() {
+        match (line.trim_start().strip_prefix('+'), lines.last_mut()) {
+            (Some(rest), Some(last)) => {
+                last.push(' ');
+                last.push_str(rest.trim());
+            }
+            _ => lines.push(line.trim().to_owned()),
+        }
+    }
+    lines
+}
+
+fn first_word(line: &str) -> String {
+    line.split_whitespace()
+        .next()
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+fn parse_cards(lines: &mut std::vec::IntoIter<String>, cards: &mut Vec<Card>) {
+    while let Some(line) = lines.next() {
+        let word = first_word(&line);
+        match word.as_str() {
+            "" => {}
+            ".end" => return,
+            ".ends" => return,
+            ".subckt" => {
+                let tokens: Vec<&str> = line.split_whitespace().collect();
+                let end = tokens
+                    .iter()
+                    .position(|t| t.contains('=') || t.eq_ignore_ascii_case("params:"))
+                    .unwrap_or(tokens.len());
+                let mut sub = Subcircuit {
+                    name: tokens.get(1).copied().unwrap_or("").to_owned(),
+                    ports: tokens[2.min(end)..end]
+                        .iter()
+                        .map(|&t| t.to_owned())
+                        .collect(),
+                    params: tokens[end..].iter().map(|&t| t.to_owned()).collect(),
+                    cards: Vec::new(),
+                };
+                parse_cards(lines, &mut sub.cards);
+                cards.push(Card::Subcircuit(sub));
+            }
+            ".model" => match Model::parse(&line) {
+                Some(m) => cards.push(Card::Model(m)),
+                None => cards.push(Card::Directive(line)),
+            },
+            ".control" => {
+                let block = lines
+                    .by_ref()
+                    .take_while(|l| first_word(l) != ".endc")
+                    .collect();
+                cards.push(Card::Control(block));
+            }
+            _ if word.starts_with('*') => cards.push(Card::Comment(line)),
+            _ if word.starts_with('.') => cards.push(Card::Directive(line)),
+            _ => match Element::parse(&line) {
+                Some(e) => cards.push(Card::Element(e)),
+                None => cards.push(Card::Directive(line)),
+            },
+        }
+    }
+}
+
+impl Circuit {
+    /// Parses a deck. The first line is always the title, as in ngSPICE. Everything after
+    /// `.end` is ignored.
+    pub fn parse(deck: &str) -> Circuit {
+        let mut lines = logical_lines(deck).into_iter();
+        let mut circuit = Circuit {
+            title: lines.next().unwrap_or_default(),
+            ..Circuit::default()
+        };
+        parse_cards(&mut lines, &mut circuit.cards);
+        circuit
+    }
+
+    /// The top-level elements, not including those inside subcircuit definitions.
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.cards.iter().filter_map(|c| match c {
+            Card::Element(e) => Some(e),
+            _ => None,
+        })
+    }
+
+    /// Finds a top-level element by name, ignoring case.
+    pub fn element(&self, name: &str) -> Option<&Element> {
+        self.elements().find(|e| e.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Finds a top-level element by name, ignoring case.
+    pub fn element_mut(&mut self, name: &str) -> Option<&mut Element> {
+        self.cards.iter_mut().find_map(|c| match c {
+            Card::Element(e) if e.name.eq_ignore_ascii_case(name) => Some(e),
+            _ => None,
+        })
+    }
+
+    /// The top-level `.model` cards.
+    pub fn models(&self) -> impl Iterator<Item = &Model> {
+        self.cards.iter().filter_map(|c| match c {
+            Card::Model(m) => Some(m),
+            _ => None,
+        })
+    }
+
+    /// Finds a top-level model by name, ignoring case.
+    pub fn model_mut(&mut self, name: &str) -> Option<&mut Model> {
+        self.cards.iter_mut().find_map(|c| match c {
+            Card::Model(m) if m.name.eq_ignore_ascii_case(name) => Some(m),
+            _ => None,
+        })
+    }
+
+    /// Finds a top-level subcircuit definition by name, ignoring case.
+    pub fn subcircuit_mut(&mut self, name: &str) -> Option<&mut Subcircuit> {
+        self.cards.iter_mut().find_map(|c| match c {
+            Card::Subcircuit(s) if s.name.eq_ignore_ascii_case(name) => Some(s),
+            _ => None,
+        })
+    }
+
+    /// Every node that a top-level element connects to, in lowercase, including ground.
+    pub fn nodes(&self) -> BTreeSet<String> {
+        self.elements()
+            .flat_map(|e| e.nodes.iter().map(|n| n.to_ascii_lowercase()))
+            .collect()
+    }
+
+    /// Compares the top-level elements and nodes of two circuits. Element names are compared
+    /// ignoring case, and nodes and parameters exactly.
+    pub fn diff(&self, other: &Circuit) -> CircuitDiff {
+        let index = |c: &Circuit| -> BTreeMap<String, Element> {
+            c.elements()
+                .map(|e| (e.name.to_ascii_lowercase(), e.clone()))
+                .collect()
+        };
+        let (mine, theirs) = (index(self), index(other));
+        let mut diff = CircuitDiff::default();
+        for (name, e) in &mine {
+            match theirs.get(name) {
+                None => diff.removed.push(e.clone()),
+                Some(o) if o.nodes != e.nodes || o.params != e.params => {
+                    diff.changed.push((e.clone(), o.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        diff.added = theirs
+            .iter()
+            .filter(|(name, _)| !mine.contains_key(*name))
+            .map(|(_, e)| e.clone())
+            .collect();
+        let (my_nodes, their_nodes) = (self.nodes(), other.nodes());
+        diff.added_nodes = their_nodes.difference(&my_nodes).cloned().collect();
+        diff.removed_nodes = my_nodes.difference(&their_nodes).cloned().collect();
+        let rest = |c: &Circuit| -> Vec<Card> {
+            c.cards
+                .iter()
+                .filter(|c| !matches!(c, Card::Element(_) | Card::Comment(_)))
+                .cloned()
+                .collect()
+        };
+        diff.other_changes = rest(self) != rest(other);
+        diff
+    }
+
+    /// Measures the current into the first terminal of a top-level element, available as
+    /// `i(<name>)` in the results of [`Circuit::simulate`]. Returns that name.
+    ///
+    /// A 0 V source is inserted in series with the element's first terminal, so the circuit
+    /// gains a node named `<name>_probe`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingElement`] if there is no such element, or it has no nodes.
+    pub fn probe_current(&mut self, name: &str) -> Result<String, Error> {
+        let lower = name.to_ascii_lowercase();
+        let friendly = format!("i({})", lower);
+        if self.probes.contains_key(&friendly) {
+            return Ok(friendly);
+        }
+        let element = self
+            .element_mut(name)
+            .filter(|e| !e.nodes.is_empty())
+            .ok_or_else(|| Error::MissingElement(name.to_owned()))?;
+        let probe_node = format!("{}_probe", lower);
+        let node = std::mem::replace(&mut element.nodes[0], probe_node.clone());
+        let ammeter = Element {
+            name: format!("Vprobe_{}", lower),
+            nodes: vec![node, probe_node],
+            params: vec!["DC".to_owned(), "0".to_owned()],
+        };
+        self.probes
+            .insert(friendly.clone(), format!("vprobe_{}#branch", lower));
+        self.cards.push(Card::Element(ammeter));
+        Ok(friendly)
+    }
+
+    /// Simulates the circuit with [`NgSpice::simulate`], renaming the vectors of probed
+    /// currents to their friendly names.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`NgSpice::simulate`].
+    pub fn simulate(&self, command: &str) -> Result<Simulation, Error> {
+        let mut sim = NgSpice::simulate(&self.to_string(), command)?;
+        for (friendly, vector) in &self.probes {
+            if let Some(v) = sim.vectors.remove(vector) {
+                sim.vectors.insert(friendly.clone(), v);
+            }
+        }
+        Ok(sim)
+    }
+
+    /// Renames a top-level node, updating every element and every `v(...)` reference in
+    /// directives and control blocks. Subcircuit definitions are not changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidCircuit`] if `old` is ground, `new` is already a node, or `old`
+    /// is not a node.
+    pub fn rename_node(&mut self, old: &str, new: &str) -> Result<(), Error> {
+        let nodes = self.nodes();
+        if is_ground(old) || !nodes.contains(&old.to_ascii_lowercase()) {
+            return Err(Error::InvalidCircuit(format!("cannot rename node {}", old)));
+        }
+        if nodes.contains(&new.to_ascii_lowercase()) {
+            return Err(Error::InvalidCircuit(format!(
+                "node {} already exists",
+                new
+            )));
+        }
+        for card in &mut self.cards {
+            match card {
+                Card::Element(e) => e
+                    .nodes
+                    .iter_mut()
+                    .filter(|n| n.eq_ignore_ascii_case(old))
+                    .for_each(|n| *n = new.to_owned()),
+                Card::Directive(line) => *line = rename_node_refs(line, old, new),
+                Card::Control(lines) => lines
+                    .iter_mut()
+                    .for_each(|l| *l = rename_node_refs(l, old, new)),
+                Card::Comment(_) | Card::Model(_) | Card::Subcircuit(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Renames a top-level element, updating elements that refer to it by name (such as `K`,
+    /// `F`, and `H`) and every `i(...)` and `@name[...]` reference in directives and control
+    /// blocks.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingElement`] if there is no such element, or
+    /// [`Error::InvalidCircuit`] if `new` changes the element type or is already taken.
+    pub fn rename_element(&mut self, old: &str, new: &str) -> Result<(), Error> {
+        let kind = self
+            .element(old)
+            .ok_or_else(|| Error::MissingElement(old.to_owned()))?
+            .kind();
+        if !new.starts_with(|c: char| c.to_ascii_uppercase() == kind) {
+            return Err(Error::InvalidCircuit(format!(
+                "{} must keep the element type {}",
+                new, kind
+            )));
+        }
+        if self.element(new).is_some() {
+            return Err(Error::InvalidCircuit(format!(
+                "element {} already exists",
+                new
+            )));
+        }
+        if let Some(probe) = self
+            .probes
+            .remove(&format!("i({})", old.to_ascii_lowercase()))
+        {
+            self.probes
+                .insert(format!("i({})", new.to_ascii_lowercase()), probe);
+        }
+        for card in &mut self.cards {
+            match card {
+                Card::Element(e) => {
+                    if e.name.eq_ignore_ascii_case(old) {
+                        e.name = new.to_owned();
+                    }
+                    e.params
+                        .iter_mut()
+                        .filter(|p| p.eq_ignore_ascii_case(old))
+                        .for_each(|p| *p = new.to_owned());
+                }
+                Card::Directive(line) => *line = rename_element_refs(line, old, new),
+                Card::Control(lines) => lines
+                    .iter_mut()
+                    .for_each(|l| *l = rename_element_refs(l, old, new)),
+                Card::Comment(_) | Card::Model(_) | Card::Subcircuit(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces every top-level instance of a subcircuit with a copy of its contents, then
+    /// removes the definition.
+    ///
+    /// Element `R1` of instance `X2` becomes `R1_x2`, and internal node `mid` becomes
+    /// `x2_mid`. Directives inside the definition, such as `.model` cards, are moved to the
+    /// top level once.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingElement`] if there is no such subcircuit, or
+    /// [`Error::InvalidCircuit`] if it takes parameters or an instance has the wrong number of
+    /// nodes.
+    pub fn flatten(&mut self, subcircuit: &str) -> Result<(), Error> {
+        let idx = self
+            .cards
+            .iter()
+            .position(
+                |c| matches!(c, Card::Subcircuit(s) if s.name.eq_ignore_ascii_case(subcircuit)),
+            )
+            .ok_or_else(|| Error::MissingElement(subcircuit.to_owned()))?;
+        let def = match &self.cards[idx] {
+            Card::Subcircuit(s) => s.clone(),
+            _ => unreachable!(),
+        };
+        if !def.params.is_empty() {
+            return Err(Error::InvalidCircuit(format!(
+                "parameterized subcircuit {} cannot be flattened",
+                def.name
+            )));
+        }
+        let local: Vec<String> = def
+            .cards
+            .iter()
+            .filter_map(|c| match c {
+                Card::Element(e) => Some(e.name.to_ascii_lowercase()),
+                _ => None,
+            })
+            .collect();
+        let mut cards = Vec::with_capacity(self.cards.len());
+        for card in std::mem::take(&mut self.cards) {
+            let instance = match card {
+                Card::Element(e)
+                    if e.kind() == 'X'
+                        && e.value().is_some_and(|v| v.eq_ignore_ascii_case(&def.name)) =>
+                {
+                    e
+                }
+                Card::Subcircuit(s) if s.name.eq_ignore_ascii_case(&def.name) => continue,
+                other => {
+                    cards.push(other);
+                    continue;
+                }
+            };
+            if instance.nodes.len() != def.ports.len() {
+                return Err(Error::InvalidCircuit(format!(
+                    "{} connects {} nodes but {} has {} ports",
+                    instance.name,
+                    instance.nodes.len(),
+                    def.name,
+                    def.ports.len()
+                )));
+            }
+            let inst = instance.name.to_ascii_lowercase();
+            let map_node = |n: &String| -> String {
+                match def.ports.iter().position(|p| p.eq_ignore_ascii_case(n)) {
+                    Some(i) => instance.nodes[i].clone(),
+                    None if is_ground(n) => n.clone(),
+                    None => format!("{}_{}", inst, n),
+                }
+            };
+            let map_name = |n: &String| format!("{}_{}", n, inst);
+            for inner in &def.cards {
+                match inner {
+                    Card::Element(e) => cards.push(Card::Element(Element {
+                        name: map_name(&e.name),
+                        nodes: e.nodes.iter().map(map_node).collect(),
+                        params: e
+                            .params
+                            .iter()
+                            .map(|p| {
+                                if local.contains(&p.to_ascii_lowercase()) {
+                                    map_name(p)
+                                } else {
+                                    p.clone()
+                                }
+                            })
+                            .collect(),
+                    })),
+                    Card::Directive(_) | Card::Model(_) | Card::Subcircuit(_)
+                        if !cards.contains(inner) =>
+                    {
+                        cards.push(inner.clone())
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.cards = cards;
+        Ok(())
+    }
+
+    /// Annotates an element's value with a tolerance, e.g. `0.01` for a 1% resistor. The
+    /// nominal value is the element's current value, which must be a plain number.
+    ///
+    /// [`MonteCarlo::run_circuit`](crate::montecarlo::MonteCarlo::run_circuit) varies
+    /// annotated elements without any further setup.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingElement`] if there is no such top-level element, or
+    /// [`Error::InvalidCircuit`] if its value is not a number.
+    pub fn set_tolerance(
+        &mut self,
+        element: &str,
+        relative: f64,
+        distribution: Distribution,
+    ) -> Result<(), Error> {
+        let found = self
+            .element(element)
+            .ok_or_else(|| Error::MissingElement(element.to_owned()))?;
+        let nominal = found.value().and_then(parse_number).ok_or_else(|| {
+            Error::InvalidCircuit(format!("{} does not have a numeric value", found.name))
+        })?;
+        self.tolerances.insert(
+            element.to_ascii_lowercase(),
+            Tolerance {
+                nominal,
+                relative,
+                distribution,
+            },
+        );
+        Ok(())
+    }
+
+    /// Tolerances by lowercase element name.
+    pub fn tolerances(&self) -> &BTreeMap<String, Tolerance> {
+        &self.tolerances
+    }
+
+    /// Replaces the value of every toleranced element with a parameter named `tol_<element>`,
+    /// returning the new circuit and the parameters' tolerances.
+    pub fn toleranced(&self) -> (Circuit, BTreeMap<String, Tolerance>) {
+        let mut circuit = self.clone();
+        let mut params = BTreeMap::new();
+        for (name, &tolerance) in &self.tolerances {
+            if let Some(element) = circuit.element_mut(name) {
+                let param = format!("tol_{}", name);
+                element.params[0] = format!("{{{}}}", param);
+                params.insert(param, tolerance);
+            }
+        }
+        (circuit, params)
+    }
+
+    /// Starts editing a copy of the circuit.
+    pub fn patch(&self) -> Patch {
+        Patch {
+            circuit: self.clone(),
+            changes: Vec::new(),
+        }
+    }
+
+    /// Returns a copy of the circuit that starts from the node voltages of `snapshot`.
+    ///
+    /// The voltages become a single `.ic` card, replacing any existing `.ic` cards. Nodes
+    /// that are not top-level nodes of this circuit, such as nodes inside subcircuit
+    /// instances or devices, are left out, as is ground.
+    pub fn with_initial_state(&self, snapshot: &Snapshot) -> Circuit {
+        let nodes = self.nodes();
+        let mut state = Snapshot {
+            time: snapshot.time,
+            ..Snapshot::default()
+        };
+        state.voltages = snapshot
+            .voltages
+            .iter()
+            .filter(|(n, _)| {
+                let n = n.to_ascii_lowercase();
+                n != "0" && n != "gnd" && nodes.contains(&n)
+            })
+            .map(|(n, &v)| (n.clone(), v))
+            .collect();
+        let mut circuit = self.clone();
+        circuit
+            .cards
+            .retain(|c| !matches!(c, Card::Directive(d) if first_word(d) == ".ic"));
+        if !state.voltages.is_empty() {
+            circuit.cards.push(Card::Directive(state.to_ic()));
+        }
+        circuit
+    }
+}
+
+/// The differences between two circuits, produced by [`Circuit::diff`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CircuitDiff {
+    /// Top-level elements only in the other circuit.
+    pub added: Vec<Element>,
+    /// Top-level elements only in this circuit.
+    pub removed: Vec<Element>,
+    /// Top-level elements in both circuits with different nodes or parameters, as
+    /// `(this, other)`.
+    pub changed: Vec<(Element, Element)>,
+    pub added_nodes: BTreeSet<String>,
+    pub removed_nodes: BTreeSet<String>,
+    /// Whether anything besides top-level elements differs, e.g. a directive, subcircuit, or
+    /// control block. Comments and the title are ignored.
+    pub other_changes: bool,
+}
+
+impl CircuitDiff {
+    /// Whether the circuits would simulate identically.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && !self.other_changes
+    }
+}
+
+/// One edit made by a [`Patch`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    SetValue {
+        element: String,
+        old: String,
+        new: String,
+    },
+    SwapModel {
+        element: String,
+        old: String,
+        new: String,
+    },
+    /// Holds the added element line.
+    Add(String),
+    /// Holds the removed element line.
+    Remove(String),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::SetValue { element, old, new } => {
+                write!(f, "set value of {}: {} -> {}", element, old, new)
+            }
+            Change::SwapModel { element, old, new } => {
+                write!(f, "swap model of {}: {} -> {}", element, old, new)
+            }
+            Change::Add(line) => write!(f, "add {}", line),
+            Change::Remove(line) => write!(f, "remove {}", line),
+        }
+    }
+}
+
+/// A set of edits to a copy of a circuit, recorded as they are made.
+///
+/// ```no_run
+/// # use ngspice::circuit::Circuit;
+/// # fn main() -> Result<(), ngspice::Error> {
+/// let circuit = Circuit::parse("* divider\nR1 in out 1k\nR2 out 0 1k\n.end");
+/// let mut patch = circuit.patch();
+/// patch.set_value("R2", "3k")?.add("C1 out 0 1n")?;
+/// let (patched, changes) = patch.finish();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Patch {
+    circuit: Circuit,
+    changes: Vec<Change>,
+}
+
+impl Patch {
+    fn find(&mut self, name: &str) -> Result<&mut Element, Error> {
+        self.circuit
+            .element_mut(name)
+            .ok_or_else(|| Error::MissingElement(name.to_owned()))
+    }
+
+    /// Replaces an element's value. For resistors, capacitors, and inductors only the first
+    /// parameter is replaced, keeping e.g. temperature coefficients; for every other element
+    /// all parameters are replaced, e.g. `DC 5` with `PULSE(0 5 0 1n 1n 1u 2u)`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingElement`] if there is no such top-level element.
+    pub fn set_value(&mut self, name: &str, value: &str) -> Result<&mut Self, Error> {
+        let element = self.find(name)?;
+        let new: Vec<String> = value.split_whitespace().map(str::to_owned).collect();
+        let old = if matches!(element.kind(), 'R' | 'C' | 'L') && !element.params.is_empty() {
+            let tail = element.params.split_off(1);
+            let old = std::mem::replace(&mut element.params, new);
+            element.params.extend(tail);
+            old.join(" ")
+        } else {
+            std::mem::replace(&mut element.params, new).join(" ")
+        };
+        let change = Change::SetValue {
+            element: element.name.clone(),
+            old,
+            new: value.to_owned(),
+        };
+        self.changes.push(change);
+        Ok(self)
+    }
+
+    /// Replaces the model of a device, or the subcircuit of an `X` instance.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingElement`] if there is no such top-level element or it has no
+    /// model.
+    pub fn swap_model(&mut self, name: &str, model: &str) -> Result<&mut Self, Error> {
+        let element = self.find(name)?;
+        let old = match element.params.first_mut() {
+            Some(m) => std::mem::replace(m, model.to_owned()),
+            None => return Err(Error::MissingElement(name.to_owned())),
+        };
+        let change = Change::SwapModel {
+            element: element.name.clone(),
+            old,
+            new: model.to_owned(),
+        };
+        self.changes.push(change);
+        Ok(self)
+    }
+
+    /// Adds an element line to the end of the circuit.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidCircuit`] if the line is not an element or an element with the
+    /// same name already exists.
+    pub fn add(&mut self, line: &str) -> Result<&mut Self, Error> {
+        let element = Element::parse(line)
+            .ok_or_else(|| Error::InvalidCircuit(format!("not an element: {}", line)))?;
+        if self.circuit.element(&element.name).is_some() {
+            return Err(Error::InvalidCircuit(format!(
+                "element {} already exists",
+                element.name
+            )));
+        }
+        self.changes.push(Change::Add(element.to_string()));
+        self.circuit.cards.push(Card::Element(element));
+        Ok(self)
+    }
+
+    /// Removes a top-level element.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingElement`] if there is no such element.
+    pub fn remove(&mut self, name: &str) -> Result<&mut Self, Error> {
+        let idx = self
+            .circuit
+            .cards
+            .iter()
+            .position(|c| matches!(c, Card::Element(e) if e.name.eq_ignore_ascii_case(name)))
+            .ok_or_else(|| Error::MissingElement(name.to_owned()))?;
+        if let Card::Element(e) = self.circuit.cards.remove(idx) {
+            self.changes.push(Change::Remove(e.to_string()));
+        }
+        Ok(self)
+    }
+
+    /// The changes made so far, in order.
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// Returns the patched circuit and the changes that produced it.
+    pub fn finish(self) -> (Circuit, Vec<Change>) {
+        (self.circuit, self.changes)
+    }
+}
+
+/// Replaces node `old` with `new` in every `v(...)` reference in a directive or command.
+fn rename_node_refs(line: &str, old: &str, new: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(pos) = rest.to_ascii_lowercase().find("v(") {
+        // only a standalone v, not e.g. the end of `dev(`
+        let standalone = !rest[..pos].ends_with(|c: char| c.is_ascii_alphanumeric());
+        out.push_str(&rest[..pos + 2]);
+        rest = &rest[pos + 2..];
+        let close = match rest.find(')') {
+            Some(c) if standalone => c,
+            _ => continue,
+        };
+        let args: Vec<&str> = rest[..close]
+            .split(',')
+            .map(|a| {
+                if a.trim().eq_ignore_ascii_case(old) {
+                    new
+                } else {
+                    a
+                }
+            })
+            .collect();
+        out.push_str(&args.join(","));
+        rest = &rest[close..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Replaces element `old` with `new` in every `i(...)` and `@old[...]` reference.
+fn rename_element_refs(line: &str, old: &str, new: &str) -> String {
+    let lower = line.to_ascii_lowercase();
+    let old_lower = old.to_ascii_lowercase();
+    let mut out = String::with_capacity(line.len());
+    let mut i = 0;
+    while i < line.len() {
+        let candidates = [format!("i({})", old_lower), format!("@{}[", old_lower)];
+        if let Some(pattern) = candidates.iter().find(|p| {
+            lower[i..].starts_with(p.as_str())
+                && !lower[..i].ends_with(|c: char| c.is_ascii_alphanumeric())
+        }) {
+            let prefix = &pattern[..pattern.find(&old_lower).unwrap()];
+            out.push_str(&line[i..i + prefix.len()]);
+            out.push_str(new);
+            out.push_str(&pattern[prefix.len() + old_lower.len()..]);
+            i += pattern.len();
+        } else {
+            let c = line[i..].chars().next().unwrap();
+            out.push(c);
+            i += c.len_utf8();
+        }
+    }
+    out
+}
+
+/// Parses a SPICE number such as `4.7k`, `10meg`, or `100nF`. Letters after the scale factor
+/// are ignored, as in ngSPICE.
+pub(crate) fn parse_number(token: &str) -> Option<f64> {
+    let lower = token.to_ascii_lowercase();
+    let end = lower
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e')))
+        .unwrap_or(lower.len());
+    // an `e` with no exponent after it is not part of the number
+    let end = match lower[..end].rfind('e') {
+        Some(e) if !lower[e + 1..end].contains(|c: char| c.is_ascii_digit()) => e,
+        _ => end,
+    };
+    let suffix = &lower[end..];
+    if suffix.starts_with("mil") {
+        return lower[..end].parse::<f64>().ok().map(|x| x * 25.4e-6);
+    }
+    let scale = [
+        ("meg", 6),
+        ("t", 12),
+        ("g", 9),
+        ("k", 3),
+        ("m", -3),
+        ("u", -6),
+        ("n", -9),
+        ("p", -12),
+        ("f", -15),
+    ]
+    .iter()
+    .find(|(s, _)| suffix.starts_with(s))
+    .map_or(0, |&(_, x)| x);
+    // fold the scale into the exponent so that e.g. 10u parses to exactly 10e-6
+    let (mantissa, exponent) = match lower[..end].split_once('e') {
+        Some((m, e)) => (m, e.parse::<i32>().ok()?),
+        None => (&lower[..end], 0),
+    };
+    format!("{}e{}", mantissa, exponent + scale).parse().ok()
+}
+
+fn is_ground(node: &str) -> bool {
+    node == "0" || node.eq_ignore_ascii_case("gnd")
+}
+
+fn write_cards(f: &mut Formatter<'_>, cards: &[Card]) -> fmt::Result {
+    for card in cards {
+        match card {
+            Card::Element(e) => writeln!(f, "{}", e)?,
+            Card::Model(m) => writeln!(f, "{}", m)?,
+            Card::Directive(line) | Card::Comment(line) => writeln!(f, "{}", line)?,
+            Card::Subcircuit(sub) => {
+                write!(f, ".subckt {}", sub.name)?;
+                for token in sub.ports.iter().chain(&sub.params) {
+                    write!(f, " {}", token)?;
+                }
+                writeln!(f)?;
+                write_cards(f, &sub.cards)?;
+                writeln!(f, ".ends {}", sub.name)?;
+            }
+            Card::Control(lines) => {
+                writeln!(f, ".control")?;
+                for line in lines {
+                    writeln!(f, "{}", line)?;
+                }
+                writeln!(f, ".endc")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Circuit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.title)?;
+        write_cards(f, &self.cards)?;
+        f.write_str(".end\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DECK: &str = "* rc
+V1 in 0 PULSE(0 1 0 1n 1n
++ 5u 10u)
+R1 in out 1k
+.subckt buf a y
+E1 y 0 a 0 1
+.ends buf
+X1 out buffered buf
+C1 out 0 1n
+.tran 10n 20u
+.end";
+
+    #[test]
+    fn parses_and_warm_starts() {
+        let circuit = Circuit::parse(DECK);
+        assert_eq!(circuit.title, "* rc");
+        let v1 = circuit.element("v1").unwrap();
+        assert_eq!(v1.nodes, vec!["in", "0"]);
+        assert_eq!(v1.to_string(), "V1 in 0 PULSE(0 1 0 1n 1n 5u 10u)");
+        assert_eq!(
+            circuit.element("X1").unwrap().nodes,
+            vec!["out", "buffered"]
+        );
+        assert_eq!(circuit.element("X1").unwrap().value(), Some("buf"));
+        assert!(circuit.element("E1").is_none());
+        assert_eq!(Circuit::parse(&circuit.to_string()), circuit);
+
+        let mut snapshot = Snapshot::default();
+        for (node, v) in [("in", 1.0), ("out", 0.5), ("x1.y", 0.5), ("0", 0.0)] {
+            snapshot.voltages.insert(node.to_owned(), v);
+        }
+        let warm = circuit.with_initial_state(&snapshot);
+        assert!(warm
+            .to_string()
+            .ends_with(".tran 10n 20u\n.ic v(in)=1e0 v(out)=5e-1\n.end\n"));
+    }
+
+    #[test]
+    fn parses_models() {
+        let mut circuit = Circuit::parse(
+            "* m\n.model d1 D (IS = 2.52n\n+ N=1.752)\n.model core core (H_array=[1 2])\n\
+             .subckt s a\n.model q1 npn\n.ends\nD1 a 0 d1\n.end",
+        );
+        let d1 = circuit.models().next().unwrap();
+        assert_eq!((d1.kind.as_str(), d1.get("is")), ("D", Some("2.52n")));
+        circuit.model_mut("D1").unwrap().set("rs", "0.5");
+        assert!(matches!(&circuit.cards[1], Card::Directive(d) if d.contains("H_array")));
+        let sub = circuit.subcircuit_mut("s").unwrap();
+        assert!(matches!(&sub.cards[0], Card::Model(m) if m.params.is_empty()));
+        sub.name = "t".to_owned();
+        assert_eq!(
+            circuit.to_string(),
+            "* m\n.model d1 D (IS=2.52n N=1.752 rs=0.5)\n.model core core (H_array=[1 2])\n\
+             .subckt t a\n.model q1 npn\n.ends t\nD1 a 0 d1\n.end\n"
+        );
+    }
+
+    #[test]
+    fn patches_elements() -> Result<(), Error> {
+        let circuit = Circuit::parse("* t\nR1 a 0 1k tc1=0.001\nV1 a 0 DC 5\nD1 a 0 d1n4148\n.end");
+        let mut patch = circuit.patch();
+        patch
+            .set_value("r1", "2k")?
+            .set_value("V1", "PULSE(0 5 0 1n 1n 1u 2u)")?
+            .swap_model("D1", "d1n914")?
+            .remove("V1")?
+            .add("C1 a 0 1n")?;
+        assert!(patch.set_value("R9", "1").is_err());
+        assert!(patch.add("C1 a 0 2n").is_err());
+        let (patched, changes) = patch.finish();
+        assert_eq!(
+            patched.element("R1").unwrap().to_string(),
+            "R1 a 0 2k tc1=0.001"
+        );
+        assert_eq!(patched.element("D1").unwrap().value(), Some("d1n914"));
+        assert!(patched.element("V1").is_none());
+        assert_eq!(changes.len(), 5);
+        assert_eq!(changes[0].to_string(), "set value of R1: 1k -> 2k");
+        assert_eq!(
+            changes[3],
+            Change::Remove("V1 a 0 PULSE(0 5 0 1n 1n 1u 2u)".to_owned())
+        );
+        assert_eq!(circuit.element("R1").unwrap().value(), Some("1k"));
+
+        let diff = circuit.diff(&patched);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.removed[0].name, "V1");
+        assert_eq!(diff.changed.len(), 2);
+        assert!(diff.added_nodes.is_empty() && diff.removed_nodes.is_empty());
+        assert!(!diff.other_changes);
+        assert!(circuit.diff(&circuit.patch().finish().0).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn renames_and_flattens() -> Result<(), Error> {
+        let mut circuit = Circuit::parse(
+            "* t
+V1 in 0 DC 1
+L1 in mid 1u
+L2 mid 0 1u
+K1 L1 L2 0.9
+.subckt div a y
+R1 a m 1k
+R2 m 0 1k
+.ends div
+X1 mid out div
+X2 out out2 div
+.ic v(mid)=0.5
+.print tran i(L1) v(in,mid)
+.end",
+        );
+        circuit.rename_node("mid", "center")?;
+        assert!(circuit.rename_node("in", "out").is_err());
+        assert!(circuit.rename_node("0", "gnd2").is_err());
+        circuit.rename_element("L1", "Lprimary")?;
+        assert!(circuit.rename_element("L2", "R2").is_err());
+        circuit.flatten("div")?;
+        let deck = circuit.to_string();
+        assert!(deck.contains("Lprimary in center 1u\n"));
+        assert!(deck.contains("K1 Lprimary L2 0.9\n"));
+        assert!(deck.contains("R1_x1 center x1_m 1k\nR2_x1 x1_m 0 1k\n"));
+        assert!(deck.contains("R1_x2 out x2_m 1k\n"));
+        assert!(!deck.contains(".subckt"));
+        assert!(deck.contains(".ic v(center)=0.5\n"));
+        assert!(deck.contains(".print tran i(Lprimary) v(in,center)\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn inserts_ammeter() -> Result<(), Error> {
+        let mut circuit = Circuit::parse("* t\nV1 in 0 DC 1\nR5 in 0 1k\n.end");
+        assert_eq!(circuit.probe_current("R5")?, "i(r5)");
+        assert_eq!(circuit.probe_current("r5")?, "i(r5)");
+        assert!(circuit.probe_current("R6").is_err());
+        assert_eq!(
+            circuit.to_string(),
+            "* t\nV1 in 0 DC 1\nR5 r5_probe 0 1k\nVprobe_r5 in r5_probe DC 0\n.end\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+// Copyright 2022 Andrew Morrow.
+// compare.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Compares simulations whose scales (e.g. time points) differ.
+
+use crate::kernels::{interpolate_all, rms};
+use crate::{Error, Simulation, VectorValues};
+use std::collections::HashMap;
+
+/// How far one vector strays from a reference vector.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Deviation {
+    /// The largest absolute difference at any reference point.
+    pub max: f64,
+    /// The root-mean-square difference over all compared reference points.
+    pub rms: f64,
+}
+
+impl Simulation {
+    /// Returns the name and values of this simulation's scale vector, as recorded in
+    /// [`crate::VectorInfo::scale`]. Results built by hand without scales fall back to the
+    /// guess ngSPICE results are given when they are extracted.
+    pub(crate) fn scale_vector(&self) -> Option<(&str, &VectorValues)> {
+        let recorded = self.vectors.values().find_map(|v| v.scale.as_deref());
+        let name = recorded.or_else(|| self.guess_scale())?;
+        self.vectors
+            .get_key_value(name)
+            .map(|(k, v)| (k.as_str(), &v.values))
+    }
+
+    /// Returns the real values of the scale vector. ngSPICE stores the frequency of an AC
+    /// analysis as complex numbers with no imaginary part.
+    pub(crate) fn scale_values(&self) -> Option<Vec<f64>> {
+        self.scale_vector().map(|(_, v)| match v {
+            VectorValues::Real(x) => x.to_vec(),
+            VectorValues::Complex(x) => x.iter().map(|c| c.re).collect(),
+        })
+    }
+
+    /// Compares every vector that is present in both `self` and `other`.
+    ///
+    /// `other` is linearly interpolated onto the scale of `self`, so the two simulations may
+    /// use different time steps. Reference points that fall outside the scale of `other` are
+    /// not compared. Complex vectors are compared by the magnitude of their difference.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingScale`] if either simulation has no time or frequency vector.
+    /// Returns [`Error::MissingVector`] if a compared vector's length differs from its scale.
+    pub fn compare(&self, other: &Simulation) -> Result<HashMap<String, Deviation>, Error> {
+        let (scale_name, _) = self.scale_vector().ok_or(Error::MissingScale)?;
+        let ref_scale = self.scale_values().ok_or(Error::MissingScale)?;
+        let other_scale = other.scale_values().ok_or(Error::MissingScale)?;
+        let (lo, hi) = match (other_scale.first(), other_scale.last()) {
+            (Some(&lo), Some(&hi)) => (lo, hi),
+            _ => return Err(Error::MissingScale),
+        };
+        let mut result = HashMap::new();
+        for (name, reference) in &self.vectors {
+            if name == scale_name {
+                continue;
+            }
+            let candidate = match other.vectors.get(name) {
+                Some(x) => x,
+                None => continue,
+            };
+            if reference.values.len() != ref_scale.len()
+                || candidate.values.len() != other_scale.len()
+            {
+                return Err(Error::MissingVector(name.clone()));
+            }
+            let (indices, times): (Vec<usize>, Vec<f64>) = ref_scale
+                .iter()
+                .enumerate()
+                .filter(|(_, &t)| t >= lo && t <= hi)
+                .unzip();
+            if indices.is_empty() {
+                continue;
+            }
+            let diffs: Vec<f64> = match (&reference.values, &candidate.values) {
+                (VectorValues::Real(a), VectorValues::Real(b)) => {
+                    let b = interpolate_all(&other_scale, b, &times);
+                    indices
+                        .iter()
+                        .zip(b)
+                        .map(|(&i, b)| (a[i] - b).abs())
+                        .collect()
+                }
+                (a, b) => {
+                    let (re, im) = split(b);
+                    let re = interpolate_all(&other_scale, &re, &times);
+                    let im = interpolate_all(&other_scale, &im, &times);
+                    indices
+                        .iter()
+                        .zip(re.into_iter().zip(im))
+                        .map(|(&i, (re, im))| {
+                            (complex_at(a, i) - num_complex::Complex64::new(re, im)).norm()
+                        })
+                        .collect()
+                }
+            };
+            let max = diffs.iter().copied().fold(0.0f64, f64::max);
+            let rms = rms(&diffs);
+            result.insert(name.clone(), Deviation { max, rms });
+        }
+        Ok(result)
+    }
+}
+
+fn complex_at(values: &VectorValues, i: usize) -> num_complex::Complex64 {
+    match values {
+        VectorValues::Real(x) => num_complex::Complex64::new(x[i], 0.0),
+        VectorValues::Complex(x) => x[i],
+    }
+}
+
+fn split(values: &VectorValues) -> (Vec<f64>, Vec<f64>) {
+    match values {
+        VectorValues::Real(x) => (x.to_vec(), vec![0.0; x.len()]),
+        VectorValues::Complex(x) => x.iter().map(|c| (c.re, c.im)).unzip(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DataType, Error, Simulation, VectorInfo, VectorValues};
+    use num_complex::Complex64;
+
+    fn sim(time: Vec<f64>, out: Vec<f64>) -> Simulation {
+        let mut sim = Simulation::default();
+        sim.vectors.insert(
+            "time".to_owned(),
+            VectorInfo {
+                datatype: DataType::Time,
+                values: VectorValues::Real(time.into()),
+                scale: None,
+            },
+        );
+        sim.vectors.insert(
+            "out".to_owned(),
+            VectorInfo {
+                datatype: DataType::Voltage,
+                values: VectorValues::Real(out.into()),
+                scale: None,
+            },
+        );
+        sim
+    }
+
+    #[test]
+    fn compares_different_grids() {
+        let a = sim(vec![0.0, 1.0, 2.0], vec![0.0, 1.0, 2.0]);
+        let b = sim(vec![0.0, 2.0], vec![0.0, 4.0]);
+        let devs = a.compare(&b).unwrap();
+        assert_eq!(devs.len(), 1);
+        let out = devs["out"];
+        assert_eq!(out.max, 2.0);
+        assert!((out.rms - (5.0f64 / 3.0).sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn compares_ac_runs() {
+        // AC frequencies are complex, like the vectors measured against them
+        let ac = |gain: f64| {
+            let mut sim = Simulation::default();
+            let freq: Vec<Complex64> = [1.0, 10.0]
+                .iter()
+                .map(|&f| Complex64::new(f, 0.0))
+                .collect();
+            for (name, datatype, values) in [
+                ("frequency", DataType::Frequency, freq),
+                ("out", DataType::Voltage, vec![Complex64::new(gain, 0.0); 2]),
+            ] {
+                sim.vectors.insert(
+                    name.to_owned(),
+                    VectorInfo {
+                        datatype,
+                        values: VectorValues::Complex(values.into()),
+                        scale: None,
+                    },
+                );
+            }
+            sim
+        };
+        let devs = ac(1.0).compare(&ac(0.5)).unwrap();
+        assert!((devs["out"].max - 0.5).abs() < 1e-12);
+        let mut short = ac(0.5);
+        short.vectors.get_mut("out").unwrap().values = VectorValues::Complex(Vec::new().into());
+        assert!(matches!(
+            ac(1.0).compare(&short),
+            Err(Error::MissingVector(_))
+        ));
+    }
+
+    #[test]
+    fn prefers_recorded_scale() {
+        let mut sim = sim(vec![0.0, 1.0], vec![2.0, 3.0]);
+        let mut delay = sim.vectors["time"].clone();
+        delay.values = VectorValues::Real(vec![5.0, 6.0].into());
+        sim.vectors.insert("delay".to_owned(), delay);
+        assert_eq!(sim.scale_vector().unwrap().0, "time");
+        for (name, info) in &mut sim.vectors {
+            info.scale = Some("delay".to_owned()).filter(|s| s != name);
+        }
+        assert_eq!(sim.scale_values(), Some(vec![5.0, 6.0]));
+    }
+}
@@ -0,0 +1,303 @@
+// Copyright 2022 Andrew Morrow.
+// control.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Translates simple `.control` blocks from legacy decks into calls to this crate.
+
+use crate::format::NumericFormat;
+use crate::{Error, NgSpice, Simulation};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Analysis cards that `run` executes.
+const ANALYSES: &[&str] = &[
+    "op", "ac", "dc", "tran", "noise", "sens", "disto", "pz", "tf",
+];
+
+/// A `wrdata` command from a `.control` block.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DataExport {
+    pub path: PathBuf,
+    /// Vector names as they appear in the simulation results, e.g. `out` for `v(out)`.
+    pub vectors: Vec<String>,
+}
+
+/// A legacy deck split into a circuit and the work its `.control` blocks asked for.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ControlScript {
+    /// The deck with `.control` blocks removed and `meas` commands turned into `.meas` cards.
+    pub circuit: String,
+    /// Simulation commands, in the order they are run.
+    pub analyses: Vec<String>,
+    /// Names of the measurements requested with `meas`.
+    pub measurements: Vec<String>,
+    pub exports: Vec<DataExport>,
+    /// Control lines that could not be translated.
+    pub unsupported: Vec<String>,
+}
+
+/// The results of running a [`ControlScript`].
+#[derive(Clone, Debug, Default)]
+pub struct ControlResults {
+    /// One simulation per entry in [`ControlScript::analyses`].
+    pub simulations: Vec<Simulation>,
+    /// Measurement results by name.
+    pub measurements: HashMap<String, f64>,
+}
+
+/// Converts a vector expression such as `v(out)` or `i(vdd)` into the name ngSPICE gives the
+/// vector in its results.
+pub fn vector_name(expr: &str) -> String {
+    let lower = expr.trim().to_ascii_lowercase();
+    if let Some(node) = lower.strip_prefix("v(").and_then(|x| x.strip_suffix(')')) {
+        if !node.contains(',') {
+            return node.to_owned();
+        }
+    }
+    if let Some(src) = lower.strip_prefix("i(").and_then(|x| x.strip_suffix(')')) {
+        return format!("{}#branch", src);
+    }
+    lower
+}
+
+/// Splits a deck into its circuit and a translation of its `.control` blocks.
+///
+/// `run` is replaced by the analysis cards found in the deck, and analysis commands are kept
+/// as they are. `meas` commands become `.meas` cards so ngSPICE evaluates them during the
+/// analyses. `wrdata` commands become [`DataExport`]s. Anything else is reported in
+/// [`ControlScript::unsupported`].
+pub fn translate(deck: &str) -> ControlScript {
+    let mut script = ControlScript::default();
+    let mut circuit: Vec<String> = Vec::new();
+    let mut cards: Vec<String> = Vec::new();
+    let mut control: Vec<&str> = Vec::new();
+    let mut in_control = false;
+    for (idx, line) in deck.lines().enumerate() {
+        let word = line
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        if idx == 0 {
+            circuit.push(line.to_owned());
+        } else if in_control {
+            if word == ".endc" {
+                in_control = false;
+            } else if !word.is_empty() && !word.starts_with('*') {
+                control.push(line.trim());
+            }
+        } else if word == ".control" {
+            in_control = true;
+        } else {
+            if let Some(analysis) = word.strip_prefix('.') {
+                if ANALYSES.contains(&analysis) {
+                    cards.push(line.trim()[1..].to_owned());
+                }
+            }
+            circuit.push(line.to_owned());
+        }
+    }
+    let mut meas_cards = Vec::new();
+    for line in control {
+        let mut words = line.split_whitespace();
+        let word = words.next().unwrap_or("").to_ascii_lowercase();
+        match word.as_str() {
+            "run" => script.analyses.extend(cards.iter().cloned()),
+            _ if ANALYSES.contains(&word.as_str()) => script.analyses.push(line.to_owned()),
+            "meas" | "measure" => {
+                if let Some(name) = line.split_whitespace().nth(2) {
+                    script.measurements.push(name.to_ascii_lowercase());
+                    meas_cards.push(format!(".{}", line));
+                } else {
+                    script.unsupported.push(line.to_owned());
+                }
+            }
+            "wrdata" => match words.next() {
+                Some(path) => script.exports.push(DataExport {
+                    path: PathBuf::from(path),
+                    vectors: words.map(vector_name).collect(),
+                }),
+                None => script.unsupported.push(line.to_owned()),
+            },
+            // these only affect interactive output
+            "set" | "echo" | "print" | "plot" | "quit" | "exit" => {}
+            _ => script.unsupported.push(line.to_owned()),
+        }
+    }
+    // .meas cards must come before .end
+    let end = circuit
+        .iter()
+        .rposition(|l| l.trim().eq_ignore_ascii_case(".end"))
+        .unwrap_or(circuit.len());
+    circuit.splice(end..end, meas_cards);
+    script.circuit = circuit.join("\n");
+    script
+}
+
+/// Finds the value of each named measurement in ngSPICE's output.
+///
+/// ngSPICE prints measurement results as lines like `tdelay = 1.2e-09 targ= ... trig= ...`.
+pub(crate) fn parse_measurements(stdout: &str, names: &[String]) -> HashMap<String, f64> {
+    let mut result = HashMap::new();
+    for line in stdout.lines() {
+        let (lhs, rhs) = match line.split_once('=') {
+            Some(x) => x,
+            None => continue,
+        };
+        let lhs = lhs.trim().to_ascii_lowercase();
+        if !names.contains(&lhs) {
+            continue;
+        }
+        if let Some(value) = rhs
+            .split_whitespace()
+            .next()
+            .and_then(|v| v.parse::<f64>().ok())
+        {
+            result.insert(lhs, value);
+        }
+    }
+    result
+}
+
+impl ControlScript {
+    /// Runs every analysis and collects the measurement results.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first simulation error.
+    pub fn run(&self) -> Result<ControlResults, Error> {
+        let mut results = ControlResults::default();
+        for cmd in &self.analyses {
+            let sim = NgSpice::simulate(&self.circuit, cmd)?;
+            results
+                .measurements
+                .extend(parse_measurements(&sim.stdout, &self.measurements));
+            results.simulations.push(sim);
+        }
+        Ok(results)
+    }
+}
+
+impl ControlResults {
+    /// Writes each export from the last simulation that contains all of its vectors.
+    ///
+    /// Files are written as whitespace-separated columns: the scale, then each vector. Complex
+    /// vectors are written as their magnitude.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file cannot be written, or if no simulation has every vector.
+    pub fn write_exports(&self, exports: &[DataExport]) -> io::Result<()> {
+        self.write_exports_with(exports, &NumericFormat::default())
+    }
+
+    /// Like [`ControlResults::write_exports`], with numbers written in `format`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file cannot be written, or if no simulation has every vector.
+    pub fn write_exports_with(
+        &self,
+        exports: &[DataExport],
+        format: &NumericFormat,
+    ) -> io::Result<()> {
+        for export in exports {
+            let sim = self
+                .simulations
+                .iter()
+                .rev()
+                .find(|s| export.vectors.iter().all(|v| s.vectors.contains_key(v)))
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no simulation has all vectors for {:?}", export.path),
+                    )
+                })?;
+            write_columns(sim, &export.vectors, &export.path, format)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_columns(
+    sim: &Simulation,
+    vectors: &[String],
+    path: &Path,
+    format: &NumericFormat,
+) -> io::Result<()> {
+    let mut columns: Vec<Vec<f64>> = Vec::new();
+    if let Some((_, scale)) = sim.scale_vector() {
+        columns.push(magnitudes(scale));
+    }
+    for name in vectors {
+        columns.push(magnitudes(&sim.vectors[name].values));
+    }
+    let rows = columns.iter().map(Vec::len).min().unwrap_or(0);
+    let mut out = BufWriter::new(File::create(path)?);
+    for row in 0..rows {
+        let line: Vec<String> = columns.iter().map(|c| format.format(c[row])).collect();
+        writeln!(out, "{}", line.join(" "))?;
+    }
+    out.flush()
+}
+
+fn magnitudes(values: &crate::VectorValues) -> Vec<f64> {
+    match values {
+        crate::VectorValues::Real(x) => x.to_vec(),
+        crate::VectorValues::Complex(x) => crate::kernels::magnitude(x),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_control_block() {
+        let deck = "* legacy deck
+V1 in 0 pulse(0 1 0 1n 1n 5u 10u)
+R1 in out 1k
+C1 out 0 1n
+.tran 10n 20u
+.control
+run
+meas tran tdelay TRIG v(in) VAL=0.5 RISE=1 TARG v(out) VAL=0.5 RISE=1
+wrdata out.txt v(out) i(V1)
+linearize
+.endc
+.end";
+        let script = translate(deck);
+        assert_eq!(script.analyses, vec!["tran 10n 20u"]);
+        assert_eq!(script.measurements, vec!["tdelay"]);
+        assert_eq!(
+            script.exports,
+            vec![DataExport {
+                path: PathBuf::from("out.txt"),
+                vectors: vec!["out".to_owned(), "v1#branch".to_owned()],
+            }]
+        );
+        assert_eq!(script.unsupported, vec!["linearize"]);
+        assert!(script
+            .circuit
+            .ends_with(".tran 10n 20u\n.meas tran tdelay TRIG v(in) VAL=0.5 RISE=1 TARG v(out) VAL=0.5 RISE=1\n.end"));
+
+        let stdout = "tdelay              =  6.931472e-07 targ=  6.941472e-07 trig=  1e-09\n";
+        let meas = parse_measurements(stdout, &script.measurements);
+        assert_eq!(meas["tdelay"], 6.931472e-07);
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright 2022 Andrew Morrow.
+// converter.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Averaged and switching models of basic DC-DC converters.
+//!
+//! The averaged model replaces the switch and diode with Vorpérian's PWM switch, so AC
+//! analyses give the small-signal control-to-output response directly. The switching model
+//! of the same converter can be compared against it with [`crate::Simulation::compare`].
+
+use std::fmt::Write;
+
+/// The averaged PWM switch in continuous conduction mode, with terminals `a` (active switch),
+/// `c` (common, to the inductor), `p` (passive switch), and `d` (duty cycle, as a voltage from
+/// 0 to 1).
+pub const PWM_SWITCH: &str = ".subckt pwm_switch a c p d
+Vsense cx c DC 0
+Bcp cx p V=v(d)*v(a,p)
+Ba a p I=v(d)*i(Vsense)
+.ends pwm_switch
+";
+
+/// A converter topology.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Topology {
+    Buck,
+    Boost,
+    /// Inverting buck-boost. The output is negative.
+    BuckBoost,
+}
+
+/// A converter's power stage and load. Circuits use nodes `vin` and `vout`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Converter {
+    pub topology: Topology,
+    /// In henries.
+    pub inductance: f64,
+    /// Output capacitance, in farads.
+    pub capacitance: f64,
+    /// Output capacitor ESR, in ohms.
+    pub esr: f64,
+    /// Load resistance, in ohms.
+    pub load: f64,
+}
+
+impl Converter {
+    /// The ideal DC conversion ratio `vout / vin` at duty cycle `duty` in continuous
+    /// conduction.
+    pub fn conversion_ratio(&self, duty: f64) -> f64 {
+        match self.topology {
+            Topology::Buck => duty,
+            Topology::Boost => 1.0 / (1.0 - duty),
+            Topology::BuckBoost => -duty / (1.0 - duty),
+        }
+    }
+
+    /// The PWM switch terminals `(a, c, p)` and the inductor's other node.
+    fn terminals(&self) -> (&'static str, &'static str, &'static str, &'static str) {
+        match self.topology {
+            Topology::Buck => ("vin", "sw", "0", "vout"),
+            Topology::Boost => ("0", "sw", "vout", "vin"),
+            Topology::BuckBoost => ("vin", "sw", "vout", "0"),
+        }
+    }
+
+    fn passives(&self) -> String {
+        let (_, c, _, l) = self.terminals();
+        let mut out = String::new();
+        writeln!(out, "Lconv {} {} {:e}", c, l, self.inductance).unwrap();
+        writeln!(out, "Cout vout cesr {:e}", self.capacitance).unwrap();
+        writeln!(out, "Resr cesr 0 {:e}", self.esr.max(1e-9)).unwrap();
+        writeln!(out, "Rload vout 0 {:e}", self.load).unwrap();
+        out
+    }
+
+    /// Renders the averaged model. The duty cycle is the voltage of node `d`, which the caller
+    /// drives, e.g. with `Vd d 0 DC 0.5 AC 1` for a control-to-output analysis.
+    pub fn averaged(&self) -> String {
+        let (a, c, p, _) = self.terminals();
+        let mut out = String::from(PWM_SWITCH);
+        writeln!(out, "Xpwm {} {} {} d pwm_switch", a, c, p).unwrap();
+        out.push_str(&self.passives());
+        out
+    }
+
+    /// Renders the switching model at `frequency` Hz and a fixed `duty` cycle, with a
+    /// near-ideal switch and diode.
+    pub fn switching(&self, frequency: f64, duty: f64) -> String {
+        let (a, c, p, _) = self.terminals();
+        let period = 1.0 / frequency;
+        let edge = period / 1000.0;
+        let mut out = String::new();
+        writeln!(
+            out,
+            "Vgate gate 0 PULSE(0 1 0 {:e} {:e} {:e} {:e})",
+            edge,
+            edge,
+            duty * period - edge,
+            period
+        )
+        .unwrap();
+        writeln!(out, "Sconv {} {} gate 0 conv_sw", a, c).unwrap();
+        // the diode conducts toward the inductor in buck and buck-boost, away in boost
+        match self.topology {
+            Topology::Boost => writeln!(out, "Dconv {} {} conv_d", c, p),
+            _ => writeln!(out, "Dconv {} {} conv_d", p, c),
+        }
+        .unwrap();
+        out.push_str(".model conv_sw sw vt=0.5 vh=0 ron=1m roff=1e9\n");
+        out.push_str(".model conv_d d is=1e-12 n=0.05 rs=1m\n");
+        out.push_str(&self.passives());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_models() {
+        let buck = Converter {
+            topology: Topology::Buck,
+            inductance: 10e-6,
+            capacitance: 100e-6,
+            esr: 0.01,
+            load: 5.0,
+        };
+        assert_eq!(buck.conversion_ratio(0.25), 0.25);
+        let boost = Converter {
+            topology: Topology::Boost,
+            ..buck
+        };
+        assert_eq!(boost.conversion_ratio(0.5), 2.0);
+        assert_eq!(
+            Converter {
+                topology: Topology::BuckBoost,
+                ..buck
+            }
+            .conversion_ratio(0.5),
+            -1.0
+        );
+        let avg = boost.averaged();
+        assert!(avg.starts_with(PWM_SWITCH));
+        assert!(avg.contains("Xpwm 0 sw vout d pwm_switch\nLconv sw vin 1e-5\n"));
+        let sw = buck.switching(100e3, 0.5);
+        assert!(sw.contains("Sconv vin sw gate 0 conv_sw\nDconv 0 sw conv_d\n"));
+        assert!(sw.ends_with("Rload vout 0 5e0\n"));
+    }
+}
@@ -0,0 +1,393 @@
+// Copyright 2022 Andrew Morrow.
+// corners.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Runs a circuit at each process corner and compares the measurements across corners.
+
+use crate::campaign::{Campaign, Tags};
+use crate::circuit::{Card, Circuit};
+use crate::format::NumericFormat;
+use crate::measure::Measurement;
+use crate::temperature::strip_temperature;
+use crate::{Error, NgSpice};
+use std::fmt::{self, Formatter, Write as _};
+use std::path::PathBuf;
+
+/// One process, voltage, and temperature corner, e.g. slow-slow at 125 °C and low supply.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Corner {
+    pub name: String,
+    /// Library files and the section to use from each, as with `.lib models.lib ss`.
+    pub libraries: Vec<(PathBuf, String)>,
+    /// Model decks to include, as with `.include ss.mod`.
+    pub includes: Vec<PathBuf>,
+    /// The temperature in °C, replacing any `.temp` card or `temp=` option in the circuit.
+    /// The circuit's own temperature is used if this is `None`.
+    pub temperature: Option<f64>,
+    /// DC voltages for supply sources, by source name.
+    pub supplies: Vec<(String, f64)>,
+}
+
+impl Corner {
+    pub fn new(name: &str) -> Self {
+        Corner {
+            name: name.to_owned(),
+            ..Corner::default()
+        }
+    }
+
+    /// Uses `section` of the library at `path`. A `.lib` card in the circuit that names the
+    /// same file has its section replaced; otherwise a card is added.
+    pub fn library(mut self, path: impl Into<PathBuf>, section: &str) -> Self {
+        self.libraries.push((path.into(), section.to_owned()));
+        self
+    }
+
+    /// Includes a model deck.
+    pub fn include(mut self, path: impl Into<PathBuf>) -> Self {
+        self.includes.push(path.into());
+        self
+    }
+
+    pub fn temperature(mut self, celsius: f64) -> Self {
+        self.temperature = Some(celsius);
+        self
+    }
+
+    /// Sets the DC voltage of a supply source.
+    pub fn supply(mut self, source: &str, volts: f64) -> Self {
+        self.supplies.push((source.to_owned(), volts));
+        self
+    }
+
+    /// Returns `circuit` with this corner's models, temperature, and supplies.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingElement`] if a supply source is not in the circuit.
+    pub fn apply(&self, circuit: &Circuit) -> Result<Circuit, Error> {
+        let mut patch = circuit.patch();
+        for (source, volts) in &self.supplies {
+            patch.set_value(source, &format!("DC {:e}", volts))?;
+        }
+        let (mut circuit, _) = patch.finish();
+        for (path, section) in &self.libraries {
+            let file = path_token(path);
+            let existing = circuit.cards.iter_mut().find_map(|c| match c {
+                Card::Directive(line) if library_file(line).as_deref() == Some(&unquote(&file)) => {
+                    Some(line)
+                }
+                _ => None,
+            });
+            let card = format!(".lib {} {}", file, section);
+            match existing {
+                Some(line) => *line = card,
+                None => circuit.cards.push(Card::Directive(card)),
+            }
+        }
+        for path in &self.includes {
+            let card = format!(".include {}", path_token(path));
+            circuit.cards.push(Card::Directive(card));
+        }
+        if let Some(t) = self.temperature {
+            circuit.cards = std::mem::take(&mut circuit.cards)
+                .into_iter()
+                .filter_map(|c| match c {
+                    Card::Directive(line) => strip_temperature(&line).map(Card::Directive),
+                    c => Some(c),
+                })
+                .collect();
+            circuit.cards.push(Card::Directive(format!(".temp {}", t)));
+        }
+        Ok(circuit)
+    }
+
+    /// The tags a corner's run is recorded with: its name, its temperature, and each supply
+    /// voltage by lowercase source name.
+    pub fn tags(&self) -> Tags {
+        let mut tags = Tags::new().with("corner", self.name.as_str());
+        if let Some(t) = self.temperature {
+            tags = tags.with("temp", t);
+        }
+        for (source, volts) in &self.supplies {
+            tags = tags.with(&source.to_ascii_lowercase(), *volts);
+        }
+        tags
+    }
+}
+
+fn first_word(line: &str) -> String {
+    line.split_whitespace()
+        .next()
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+fn unquote(token: &str) -> String {
+    token.trim_matches(|c| c == '"' || c == '\'').to_owned()
+}
+
+/// A path as a netlist token, quoted if it contains spaces.
+fn path_token(path: &std::path::Path) -> String {
+    let path = path.to_string_lossy();
+    if path.contains(char::is_whitespace) {
+        format!("\"{}\"", path)
+    } else {
+        path.into_owned()
+    }
+}
+
+/// The file named by a `.lib file section` card.
+fn library_file(line: &str) -> Option<String> {
+    if first_word(line) != ".lib" {
+        return None;
+    }
+    let rest = line.trim_start()[4..].trim();
+    let file = match rest.chars().next()? {
+        q @ ('"' | '\'') => rest[1..].split(q).next()?,
+        _ => rest.split_whitespace().next()?,
+    };
+    Some(file.to_owned())
+}
+
+/// Runs a circuit at every corner, measuring each run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CornerAnalysis {
+    pub corners: Vec<Corner>,
+    pub measurements: Vec<Measurement>,
+}
+
+impl CornerAnalysis {
+    pub fn new() -> Self {
+        CornerAnalysis::default()
+    }
+
+    pub fn corner(mut self, corner: Corner) -> Self {
+        self.corners.push(corner);
+        self
+    }
+
+    /// Adds a measurement to evaluate at every corner. `.meas` cards already in the circuit
+    /// are evaluated too.
+    pub fn measure(mut self, measurement: Measurement) -> Self {
+        self.measurements.push(measurement);
+        self
+    }
+
+    /// Runs `command` at every corner. Each run is tagged as described in [`Corner::tags`]
+    /// and holds its measurement results; see [`Campaign::corner_table`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error from [`Corner::apply`] or [`NgSpice::simulate_measured`].
+    pub fn run(&self, circuit: &Circuit, command: &str) -> Result<Campaign, Error> {
+        let mut campaign = Campaign::new("corners");
+        for corner in &self.corners {
+            let deck = corner.apply(circuit)?.to_string();
+            let (sim, results) = NgSpice::simulate_measured(&deck, command, &self.measurements)?;
+            let run = campaign.add(corner.tags(), sim);
+            campaign.runs_mut()[run].measurements.extend(results);
+        }
+        Ok(campaign)
+    }
+}
+
+/// Measurement results side by side, one column per corner.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CornerTable {
+    pub corners: Vec<String>,
+    /// Measurement names, sorted.
+    pub measurements: Vec<String>,
+    /// One row per measurement, with one cell per corner. Cells are empty where ngSPICE could
+    /// not evaluate the measurement.
+    pub values: Vec<Vec<Option<f64>>>,
+}
+
+/// The range of one measurement across corners, from [`CornerTable::spread`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spread {
+    pub min: f64,
+    pub min_corner: String,
+    pub max: f64,
+    pub max_corner: String,
+}
+
+impl Campaign {
+    /// Compares the measurements of every run, naming each run by its `corner` tag, or
+    /// `run N` if it has none.
+    pub fn corner_table(&self) -> CornerTable {
+        let corners: Vec<String> = self
+            .runs()
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                r.tags
+                    .text("corner")
+                    .map_or_else(|| format!("run {}", i), str::to_owned)
+            })
+            .collect();
+        let mut measurements: Vec<String> = self
+            .runs()
+            .iter()
+            .flat_map(|r| r.measurements.keys().cloned())
+            .collect();
+        measurements.sort_unstable();
+        measurements.dedup();
+        let values = measurements
+            .iter()
+            .map(|m| {
+                self.runs()
+                    .iter()
+                    .map(|r| r.measurements.get(m).copied())
+                    .collect()
+            })
+            .collect();
+        CornerTable {
+            corners,
+            measurements,
+            values,
+        }
+    }
+}
+
+impl CornerTable {
+    pub fn get(&self, measurement: &str, corner: &str) -> Option<f64> {
+        let row = self.measurements.iter().position(|m| m == measurement)?;
+        let col = self.corners.iter().position(|c| c == corner)?;
+        self.values[row][col]
+    }
+
+    /// The smallest and largest value of a measurement across corners.
+    pub fn spread(&self, measurement: &str) -> Option<Spread> {
+        let row = self.measurements.iter().position(|m| m == measurement)?;
+        let cells = || {
+            self.corners
+                .iter()
+                .zip(&self.values[row])
+                .filter_map(|(c, v)| Some((c, (*v)?)))
+        };
+        let (min_corner, min) = cells().min_by(|a, b| a.1.total_cmp(&b.1))?;
+        let (max_corner, max) = cells().max_by(|a, b| a.1.total_cmp(&b.1))?;
+        Some(Spread {
+            min,
+            min_corner: min_corner.clone(),
+            max,
+            max_corner: max_corner.clone(),
+        })
+    }
+
+    /// Renders the table as CSV with a header row: the measurement name, then one column
+    /// per corner.
+    pub fn csv(&self) -> String {
+        self.csv_with(&NumericFormat::default())
+    }
+
+    /// Like [`CornerTable::csv`], with numbers written in `format`.
+    pub fn csv_with(&self, format: &NumericFormat) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('"', "\"\""));
+        let mut out = quote("measurement");
+        for c in &self.corners {
+            write!(out, ",{}", quote(c)).unwrap();
+        }
+        out.push('\n');
+        for (name, row) in self.measurements.iter().zip(&self.values) {
+            out.push_str(&quote(name));
+            for v in row {
+                out.push(',');
+                if let Some(v) = v {
+                    out.push_str(&format.format(*v));
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl fmt::Display for CornerTable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let width = self
+            .measurements
+            .iter()
+            .map(String::len)
+            .max()
+            .unwrap_or(0)
+            .max(11);
+        write!(f, "{:width$}", "measurement", width = width)?;
+        for c in &self.corners {
+            write!(f, " {:>12}", c)?;
+        }
+        writeln!(f)?;
+        for (name, row) in self.measurements.iter().zip(&self.values) {
+            write!(f, "{:width$}", name, width = width)?;
+            for v in row {
+                match v {
+                    Some(v) => write!(f, " {:>12.4e}", v)?,
+                    None => write!(f, " {:>12}", "-")?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Simulation;
+
+    #[test]
+    fn applies_and_tabulates_corners() -> Result<(), Error> {
+        let circuit = Circuit::parse(
+            "* amp
+.lib \"models.lib\" tt
+.temp 27
+VDD vdd 0 DC 1.8
+M1 out in 0 0 nch
+.end",
+        );
+        let ss = Corner::new("ss")
+            .library("models.lib", "ss")
+            .temperature(125.0)
+            .supply("VDD", 1.62);
+        let deck = ss.apply(&circuit)?.to_string();
+        assert!(deck.contains(".lib models.lib ss\n"));
+        assert!(!deck.contains("tt"));
+        assert!(deck.contains("VDD vdd 0 DC 1.62e0\n"));
+        assert!(deck.contains(".temp 125\n") && !deck.contains(".temp 27"));
+        assert!(Corner::new("x").supply("V9", 1.0).apply(&circuit).is_err());
+
+        let mut campaign = Campaign::new("corners");
+        for (corner, gain) in [(Corner::new("tt"), 40.0), (ss, 31.5)] {
+            let run = campaign.add(corner.tags(), Simulation::default());
+            campaign.runs_mut()[run]
+                .measurements
+                .insert("gain".to_owned(), gain);
+        }
+        let table = campaign.corner_table();
+        assert_eq!(table.corners, vec!["tt", "ss"]);
+        assert_eq!(table.get("gain", "ss"), Some(31.5));
+        let spread = table.spread("gain").unwrap();
+        assert_eq!((spread.min, spread.min_corner.as_str()), (31.5, "ss"));
+        assert_eq!(spread.max_corner, "tt");
+        assert_eq!(
+            table.csv(),
+            "\"measurement\",\"tt\",\"ss\"\n\"gain\",4e1,3.15e1\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+// Copyright 2022 Andrew Morrow.
+// cosim.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Co-simulation: computing the values of ngSPICE `external` sources in Rust while a
+//! transient analysis runs.
+
+use crate::{Error, NgSpice, Simulation};
+use ngspice_sys::*;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_double, c_int, c_void};
+use std::sync::{Arc, Mutex};
+
+type SourceFn = Box<dyn FnMut(&str, f64) -> f64 + Send>;
+
+/// The function computing external source values for the running simulation, if any.
+static SOURCES: Mutex<Option<SourceFn>> = Mutex::new(None);
+/// Serializes co-simulations, so one cannot replace another's sources mid-run.
+static COSIM: Mutex<()> = Mutex::new(());
+
+/// Called by ngSPICE for the value of each `external` voltage or current source.
+pub(crate) extern "C" fn get_source_data(
+    value: *mut c_double,
+    time: c_double,
+    name: *mut c_char,
+    _: c_int,
+    _: *mut c_void,
+) -> c_int {
+    let name = unsafe { CStr::from_ptr(name) }
+        .to_string_lossy()
+        .to_ascii_lowercase();
+    let mut sources = SOURCES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(f) = sources.as_mut() {
+        unsafe { *value = f(&name, time) };
+    }
+    0
+}
+
+/// Returns the most recent value of a real vector of the running simulation, e.g. `out` or
+/// `vdd#branch`.
+///
+/// This is only meaningful inside the source function of
+/// [`NgSpice::simulate_with_sources`]. It returns `None` if the vector does not exist or has
+/// no points yet.
+pub fn probe(vector: &str) -> Option<f64> {
+    let name = CString::new(vector).ok()?;
+    unsafe {
+        let v = ngGet_Vec_Info(name.as_ptr() as *mut c_char);
+        if v.is_null() || (*v).v_realdata.is_null() || (*v).v_length <= 0 {
+            return None;
+        }
+        Some(*(*v).v_realdata.add((*v).v_length as usize - 1))
+    }
+}
+
+/// A digital controller sampled once per switching period.
+pub trait PwmController: Send {
+    /// Computes the duty cycle, from 0 to 1, for the period starting at `sample.time`.
+    fn update(&mut self, sample: &PwmSample) -> f64;
+}
+
+/// The sensed values at the start of a switching period.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PwmSample {
+    /// In seconds.
+    pub time: f64,
+    /// The index of the period that is starting.
+    pub period: usize,
+    /// The value of each [`PwmDrive::sense`] vector, or NaN if it had no value yet.
+    pub values: Vec<f64>,
+}
+
+/// How a [`PwmController`] is connected to a circuit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PwmDrive {
+    /// The voltage source the PWM signal drives, declared as e.g. `Vgate gate 0 dc 0 external`.
+    pub source: String,
+    /// The switching frequency, in Hz.
+    pub frequency: f64,
+    pub low: f64,
+    pub high: f64,
+    /// Vectors sampled for the controller at the start of every period, e.g. `out`.
+    pub sense: Vec<String>,
+    /// The duty cycle of the first period, before the controller has run.
+    pub initial_duty: f64,
+}
+
+/// The results of [`NgSpice::simulate_pwm`].
+#[derive(Debug)]
+pub struct PwmRun<C> {
+    pub simulation: Simulation,
+    /// The duty cycle applied in each period.
+    pub duties: Vec<f64>,
+    /// The controller, in its final state.
+    pub controller: C,
+}
+
+struct PwmState<C> {
+    controller: C,
+    duties: Vec<f64>,
+}
+
+impl<C: PwmController> PwmState<C> {
+    /// The drive level at `time`, running the controller first if a new period has started.
+    ///
+    /// ngSPICE may revisit earlier times after rejecting a step, so the duty of every period
+    /// is kept.
+    fn level(&mut self, drive: &PwmDrive, time: f64, sample: impl Fn() -> Vec<f64>) -> f64 {
+        let cycles = (time * drive.frequency).max(0.0);
+        let k = cycles.floor() as usize;
+        while self.duties.len() <= k {
+            let period = self.duties.len();
+            let duty = self.controller.update(&PwmSample {
+                time,
+                period,
+                values: sample(),
+            });
+            self.duties.push(duty.clamp(0.0, 1.0));
+        }
+        if cycles - (k as f64) < self.duties[k] {
+            drive.high
+        } else {
+            drive.low
+        }
+    }
+}
+
+impl NgSpice {
+    /// Like [`NgSpice::simulate`], but computes the value of every `external` voltage and
+    /// current source with `sources`.
+    ///
+    /// `sources` receives the lowercase source name and the simulation time, and must not
+    /// panic. It may call [`probe`] to read the circuit's present state. ngSPICE cannot see
+    /// edges in external sources, so limit the maximum time step to resolve them.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`NgSpice::simulate`].
+    pub fn simulate_with_sources<F>(
+        circuit: &str,
+        command: &str,
+        sources: F,
+    ) -> Result<Simulation, Error>
+    where
+        F: FnMut(&str, f64) -> f64 + Send + 'static,
+    {
+        let _cosim = COSIM.lock().unwrap_or_else(|e| e.into_inner());
+        *SOURCES.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(sources));
+        let result = NgSpice::simulate(circuit, command);
+        *SOURCES.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        result
+    }
+
+    /// Runs a transient with `controller` closing the loop once per switching period.
+    ///
+    /// At the start of every period the crate samples the sensed vectors, asks the controller
+    /// for a duty cycle, and drives the source high for that fraction of the period. Set the
+    /// maximum time step of `command` well below the switching period.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`NgSpice::simulate`].
+    pub fn simulate_pwm<C: PwmController + 'static>(
+        circuit: &str,
+        command: &str,
+        drive: &PwmDrive,
+        controller: C,
+    ) -> Result<PwmRun<C>, Error> {
+        let state = Arc::new(Mutex::new(PwmState {
+            controller,
+            duties: vec![drive.initial_duty.clamp(0.0, 1.0)],
+        }));
+        let source = drive.source.to_ascii_lowercase();
+        let shared = Arc::clone(&state);
+        let callback_drive = drive.clone();
+        let simulation = NgSpice::simulate_with_sources(circuit, command, move |name, time| {
+            if name != source {
+                return 0.0;
+            }
+            let sense = &callback_drive.sense;
+            let sample = || sense.iter().map(|v| probe(v).unwrap_or(f64::NAN)).collect();
+            let mut state = shared.lock().unwrap_or_else(|e| e.into_inner());
+            state.level(&callback_drive, time, sample)
+        })?;
+        // the source function was dropped when the simulation ended
+        let state = Arc::try_unwrap(state)
+            .unwrap_or_else(|_| unreachable!("source function outlived its simulation"))
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner());
+        Ok(PwmRun {
+            simulation,
+            duties: state.duties,
+            controller: state.controller,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Integrator {
+        duty: f64,
+    }
+
+    impl PwmController for Integrator {
+        fn update(&mut self, sample: &PwmSample) -> f64 {
+            self.duty += 0.1 * (1.0 - sample.values[0]);
+            self.duty
+        }
+    }
+
+    #[test]
+    fn updates_once_per_period() {
+        let drive = PwmDrive {
+            source: "Vgate".to_owned(),
+            frequency: 1.0,
+            low: 0.0,
+            high: 5.0,
+            sense: vec!["out".to_owned()],
+            initial_duty: 0.5,
+        };
+        let mut state = PwmState {
+            controller: Integrator { duty: 0.5 },
+            duties: vec![0.5],
+        };
+        assert_eq!(state.level(&drive, 0.25, || vec![0.0]), 5.0);
+        assert_eq!(state.level(&drive, 0.75, || vec![0.0]), 0.0);
+        // entering period 1 runs the controller, which raises the duty to 0.6
+        assert_eq!(state.level(&drive, 1.55, || vec![0.0]), 5.0);
+        assert_eq!(state.level(&drive, 1.65, || vec![0.0]), 0.0);
+        // a rejected step back into period 0 reuses its duty
+        assert_eq!(state.level(&drive, 0.75, || panic!("no new period")), 0.0);
+        assert_eq!(state.duties, vec![0.5, 0.6]);
+    }
+}
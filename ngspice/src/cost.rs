@@ -0,0 +1,270 @@
+// Copyright 2022 Andrew Morrow.
+// cost.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Objectives that reduce a simulation to a scalar cost for optimization. Lower is better, and
+//! zero means every requirement is met.
+
+use crate::control::vector_name;
+use crate::kernels::magnitude_db;
+use crate::waveform::{interpolate, ripple};
+use crate::{Error, Simulation};
+
+/// Scores a simulation.
+pub trait Objective {
+    /// # Errors
+    ///
+    /// Returns an error if the simulation lacks a vector the objective needs.
+    fn cost(&self, sim: &Simulation) -> Result<f64, Error>;
+}
+
+impl<F> Objective for F
+where
+    F: Fn(&Simulation) -> Result<f64, Error>,
+{
+    fn cost(&self, sim: &Simulation) -> Result<f64, Error> {
+        self(sim)
+    }
+}
+
+/// The scale and real values of a vector given as an expression like `v(out)`.
+fn waveform(sim: &Simulation, expr: &str) -> Result<(Vec<f64>, Vec<f64>), Error> {
+    let scale = sim.scale_values().ok_or(Error::MissingScale)?;
+    let values = sim.real_vector(&vector_name(expr))?.to_vec();
+    Ok((scale, values))
+}
+
+/// Limits on the magnitude of an AC response, as `(frequency, min_db, max_db)` points. Use
+/// infinities for one-sided limits.
+///
+/// The cost is the sum of squared violations in dB.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BodeMask {
+    pub vector: String,
+    pub points: Vec<(f64, f64, f64)>,
+}
+
+impl Objective for BodeMask {
+    fn cost(&self, sim: &Simulation) -> Result<f64, Error> {
+        let name = vector_name(&self.vector);
+        let values = sim
+            .vectors
+            .get(&name)
+            .and_then(|v| v.values.complex())
+            .ok_or(Error::MissingVector(name))?;
+        let freq = sim.scale_values().ok_or(Error::MissingScale)?;
+        let db = magnitude_db(values);
+        Ok(self
+            .points
+            .iter()
+            .map(|&(f, lo, hi)| {
+                let g = interpolate(&freq, &db, f);
+                let excess = (lo - g).max(g - hi).max(0.0);
+                excess * excess
+            })
+            .sum())
+    }
+}
+
+/// Requires a transient to settle within `tolerance` (a fraction) of `target` by `limit`
+/// seconds.
+///
+/// The cost is the overshoot of the settling time as a fraction of `limit`. A waveform that
+/// never settles costs as if it settled at the end of the simulation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SettlingTime {
+    pub vector: String,
+    pub target: f64,
+    pub tolerance: f64,
+    pub limit: f64,
+}
+
+impl Objective for SettlingTime {
+    fn cost(&self, sim: &Simulation) -> Result<f64, Error> {
+        let (time, values) = waveform(sim, &self.vector)?;
+        let band = (self.target * self.tolerance).abs();
+        let settled = match values.iter().rposition(|&v| (v - self.target).abs() > band) {
+            Some(i) if i + 1 < time.len() => time[i + 1],
+            Some(_) => time.last().copied().unwrap_or(0.0),
+            None => time.first().copied().unwrap_or(0.0),
+        };
+        Ok(((settled - self.limit) / self.limit).max(0.0))
+    }
+}
+
+/// Limits the average power drawn from a DC voltage source over the whole transient.
+///
+/// The cost is the excess power as a fraction of `limit`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PowerBudget {
+    /// The name of the voltage source, e.g. `Vdd`.
+    pub source: String,
+    /// The source's voltage.
+    pub voltage: f64,
+    pub limit: f64,
+}
+
+impl PowerBudget {
+    /// The average power delivered by the source, in watts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the source's current or the scale is missing, or
+    /// [`Error::MissingScale`] if the scale is empty.
+    pub fn average_power(&self, sim: &Simulation) -> Result<f64, Error> {
+        let (time, current) = waveform(sim, &format!("i({})", self.source))?;
+        let (start, stop) = match (time.first(), time.last()) {
+            (Some(&start), Some(&stop)) => (start, stop),
+            _ => return Err(Error::MissingScale),
+        };
+        let mean = ripple(&time, &current, start, stop).map_or(0.0, |r| r.mean);
+        // ngSPICE reports the current flowing into the positive terminal, so a source
+        // delivering power has a negative current
+        Ok(-mean * self.voltage)
+    }
+}
+
+impl Objective for PowerBudget {
+    fn cost(&self, sim: &Simulation) -> Result<f64, Error> {
+        Ok(((self.average_power(sim)? - self.limit) / self.limit).max(0.0))
+    }
+}
+
+/// A weighted sum of other objectives.
+#[derive(Default)]
+pub struct WeightedSum {
+    terms: Vec<(f64, Box<dyn Objective + Send + Sync>)>,
+}
+
+impl WeightedSum {
+    pub fn new() -> Self {
+        WeightedSum::default()
+    }
+
+    /// Adds a term.
+    pub fn with<O: Objective + Send + Sync + 'static>(mut self, weight: f64, objective: O) -> Self {
+        self.terms.push((weight, Box::new(objective)));
+        self
+    }
+}
+
+impl Objective for WeightedSum {
+    fn cost(&self, sim: &Simulation) -> Result<f64, Error> {
+        self.terms
+            .iter()
+            .map(|(w, o)| o.cost(sim).map(|c| w * c))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, VectorInfo, VectorValues};
+    use num_complex::Complex64;
+
+    fn insert(sim: &mut Simulation, name: &str, datatype: DataType, values: VectorValues) {
+        sim.vectors.insert(
+            name.to_owned(),
+            VectorInfo {
+                datatype,
+                values,
+                scale: None,
+            },
+        );
+    }
+
+    #[test]
+    fn scores_simulations() -> Result<(), Error> {
+        let mut tran = Simulation::default();
+        let time: Vec<f64> = (0..=10).map(|i| i as f64 * 1e-3).collect();
+        let out: Vec<f64> = time
+            .iter()
+            .map(|&t| if t < 4e-3 { 0.0 } else { 1.0 })
+            .collect();
+        insert(
+            &mut tran,
+            "time",
+            DataType::Time,
+            VectorValues::Real(time.into()),
+        );
+        insert(
+            &mut tran,
+            "out",
+            DataType::Voltage,
+            VectorValues::Real(out.into()),
+        );
+        insert(
+            &mut tran,
+            "vdd#branch",
+            DataType::Current,
+            VectorValues::Real(vec![-0.1; 11].into()),
+        );
+        let settling = SettlingTime {
+            vector: "v(out)".to_owned(),
+            target: 1.0,
+            tolerance: 0.02,
+            limit: 2e-3,
+        };
+        assert!((settling.cost(&tran)? - 1.0).abs() < 1e-9);
+        let power = PowerBudget {
+            source: "Vdd".to_owned(),
+            voltage: 5.0,
+            limit: 0.4,
+        };
+        assert!((power.average_power(&tran)? - 0.5).abs() < 1e-12);
+        let mut empty = Simulation::default();
+        for (name, datatype) in [("time", DataType::Time), ("vdd#branch", DataType::Current)] {
+            insert(
+                &mut empty,
+                name,
+                datatype,
+                VectorValues::Real(Vec::new().into()),
+            );
+        }
+        assert!(matches!(
+            power.average_power(&empty),
+            Err(Error::MissingScale)
+        ));
+        let total = WeightedSum::new()
+            .with(1.0, settling)
+            .with(2.0, power)
+            .with(1.0, |_: &Simulation| Ok(0.5));
+        assert!((total.cost(&tran)? - 2.0).abs() < 1e-9);
+
+        let mut ac = Simulation::default();
+        let freq = vec![Complex64::new(10.0, 0.0), Complex64::new(1000.0, 0.0)];
+        let gain = vec![Complex64::new(1.0, 0.0), Complex64::new(0.1, 0.0)];
+        insert(
+            &mut ac,
+            "frequency",
+            DataType::Frequency,
+            VectorValues::Complex(freq.into()),
+        );
+        insert(
+            &mut ac,
+            "out",
+            DataType::Voltage,
+            VectorValues::Complex(gain.into()),
+        );
+        let mask = BodeMask {
+            vector: "v(out)".to_owned(),
+            points: vec![(10.0, -1.0, 1.0), (1000.0, f64::NEG_INFINITY, -23.0)],
+        };
+        assert!((mask.cost(&ac)? - 9.0).abs() < 1e-9);
+        Ok(())
+    }
+}
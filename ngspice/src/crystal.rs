@@ -0,0 +1,186 @@
+// Copyright 2022 Andrew Morrow.
+// crystal.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Quartz crystal and resonator models, and oscillator startup analysis.
+
+use crate::circuit::Circuit;
+use crate::waveform::interpolate;
+use crate::{Error, NgSpice};
+use num_complex::Complex64;
+use std::f64::consts::PI;
+use std::fmt::Write;
+
+/// The Butterworth-Van Dyke model: a motional RLC branch in parallel with the electrode
+/// capacitance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Crystal {
+    /// The motional resistance Rm, or ESR, in ohms.
+    pub resistance: f64,
+    /// The motional inductance Lm, in henries.
+    pub inductance: f64,
+    /// The motional capacitance Cm, in farads.
+    pub capacitance: f64,
+    /// The shunt capacitance C0, in farads.
+    pub shunt_capacitance: f64,
+}
+
+impl Crystal {
+    /// Derives the motional inductance and capacitance from datasheet values: the series
+    /// resonant frequency, the unloaded Q, the ESR, and C0.
+    pub fn from_datasheet(frequency: f64, q: f64, esr: f64, shunt_capacitance: f64) -> Self {
+        let w = 2.0 * PI * frequency;
+        let inductance = q * esr / w;
+        Crystal {
+            resistance: esr,
+            inductance,
+            capacitance: 1.0 / (w * w * inductance),
+            shunt_capacitance,
+        }
+    }
+
+    /// The frequency where the motional branch resonates, in hertz.
+    pub fn series_resonance(&self) -> f64 {
+        1.0 / (2.0 * PI * (self.inductance * self.capacitance).sqrt())
+    }
+
+    /// The frequency where the motional branch resonates with C0, in hertz.
+    pub fn parallel_resonance(&self) -> f64 {
+        self.series_resonance() * (1.0 + self.capacitance / self.shunt_capacitance).sqrt()
+    }
+
+    /// The unloaded quality factor.
+    pub fn quality_factor(&self) -> f64 {
+        2.0 * PI * self.series_resonance() * self.inductance / self.resistance
+    }
+
+    /// The impedance between the terminals at `frequency`.
+    pub fn impedance(&self, frequency: f64) -> Complex64 {
+        let s = Complex64::new(0.0, 2.0 * PI * frequency);
+        let motional = self.resistance + s * self.inductance + 1.0 / (s * self.capacitance);
+        let shunt = 1.0 / (s * self.shunt_capacitance);
+        motional * shunt / (motional + shunt)
+    }
+
+    /// Renders the crystal as a subcircuit with ports `a` and `b`.
+    pub fn subcircuit(&self, name: &str) -> String {
+        let mut out = format!(".subckt {} a b\n", name);
+        writeln!(out, "Rm a m1 {:e}", self.resistance).unwrap();
+        writeln!(out, "Lm m1 m2 {:e}", self.inductance).unwrap();
+        writeln!(out, "Cm m2 b {:e}", self.capacitance).unwrap();
+        writeln!(out, "C0 a b {:e}", self.shunt_capacitance).unwrap();
+        writeln!(out, ".ends {}", name).unwrap();
+        out
+    }
+}
+
+/// The small-signal impedance an oscillator's active circuit presents to its crystal,
+/// returned by [`NgSpice::negative_resistance`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct NegativeResistance {
+    pub frequencies: Vec<f64>,
+    pub impedance: Vec<Complex64>,
+}
+
+impl NegativeResistance {
+    /// The resistance at `frequency`, interpolated linearly. Negative values mean the circuit
+    /// supplies energy.
+    pub fn resistance(&self, frequency: f64) -> f64 {
+        let re: Vec<f64> = self.impedance.iter().map(|z| z.re).collect();
+        interpolate(&self.frequencies, &re, frequency)
+    }
+
+    /// How many times over the circuit can overcome the crystal's ESR at its series
+    /// resonance. Designs commonly aim for 5 or more; `None` means the oscillator will not
+    /// start.
+    pub fn margin(&self, crystal: &Crystal) -> Option<f64> {
+        let r = self.resistance(crystal.series_resonance());
+        (r < 0.0).then(|| -r / crystal.resistance)
+    }
+}
+
+impl NgSpice {
+    /// Measures the impedance that `amplifier` presents between the nodes where the crystal
+    /// connects, by injecting an AC current there. The crystal itself must be left out of the
+    /// circuit.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if `start` is not positive, or an error if the
+    /// simulation fails.
+    pub fn negative_resistance(
+        amplifier: &Circuit,
+        nodes: (&str, &str),
+        start: f64,
+        stop: f64,
+    ) -> Result<NegativeResistance, Error> {
+        if start <= 0.0 {
+            return Err(Error::InvalidArgument(
+                "start frequency must be positive".to_owned(),
+            ));
+        }
+        let (a, b) = nodes;
+        // the source pushes its current out of its negative node, into `a`
+        let mut patch = amplifier.patch();
+        patch.add(&format!("Ixtal_probe {} {} DC 0 AC 1", b, a))?;
+        let (deck, _) = patch.finish();
+        let sim = NgSpice::simulate(
+            &deck.to_string(),
+            &format!("ac dec 200 {:e} {:e}", start, stop),
+        )?;
+        let frequencies = sim.scale_values().ok_or(Error::MissingScale)?;
+        let node = |name: &str| -> Result<Vec<Complex64>, Error> {
+            if name == "0" || name.eq_ignore_ascii_case("gnd") {
+                return Ok(vec![Complex64::new(0.0, 0.0); frequencies.len()]);
+            }
+            sim.vectors
+                .get(&name.to_ascii_lowercase())
+                .and_then(|v| v.values.complex())
+                .map(<[Complex64]>::to_vec)
+                .ok_or_else(|| Error::MissingVector(name.to_owned()))
+        };
+        let (va, vb) = (node(a)?, node(b)?);
+        Ok(NegativeResistance {
+            impedance: va.iter().zip(&vb).map(|(x, y)| x - y).collect(),
+            frequencies,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn models_crystal() {
+        let xtal = Crystal::from_datasheet(10e6, 50e3, 20.0, 5e-12);
+        assert!((xtal.series_resonance() - 10e6).abs() < 1e-3);
+        assert!((xtal.quality_factor() - 50e3).abs() < 1e-6);
+        assert!(xtal.parallel_resonance() > xtal.series_resonance());
+        // at series resonance the motional branch is just its ESR, shunted by C0
+        let z = xtal.impedance(xtal.series_resonance());
+        assert!((z.re - 20.0).abs() < 0.1);
+        let subckt = xtal.subcircuit("x10m");
+        assert!(subckt.starts_with(".subckt x10m a b\nRm a m1 2e1\n"));
+        assert!(subckt.ends_with("C0 a b 5e-12\n.ends x10m\n"));
+
+        let probe = NegativeResistance {
+            frequencies: vec![9e6, 11e6],
+            impedance: vec![Complex64::new(-300.0, 0.0), Complex64::new(-100.0, 0.0)],
+        };
+        assert_eq!(probe.margin(&xtal), Some(10.0));
+    }
+}
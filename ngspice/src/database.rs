@@ -0,0 +1,758 @@
+// Copyright 2022 Andrew Morrow.
+// database.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Durable, queryable storage of campaigns in an SQLite database. Requires the `sqlite`
+//! feature.
+//!
+//! Each run is stored with its tags, its measurements, its ngSPICE output, and its vectors,
+//! which are kept as little-endian `f64` blobs (interleaved real and imaginary parts for
+//! complex vectors). Queries over tags and measurements never load the vectors.
+
+use crate::campaign::{Campaign, Run, TagValue, Tags};
+use crate::circuit::Circuit;
+use crate::corners::CornerAnalysis;
+use crate::montecarlo::{apply_params, MonteCarlo};
+use crate::temperature::{with_temperature, TemperatureSweep};
+use crate::{DataType, Error, NgSpice, Simulation, VectorInfo, VectorValues};
+use ngspice_sys::simulation_types;
+use num_complex::Complex64;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS campaigns (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    campaign INTEGER NOT NULL REFERENCES campaigns(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    -- the fingerprint of the run's tags
+    point INTEGER NOT NULL,
+    stdout TEXT NOT NULL,
+    stderr TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS tags (
+    run INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    number REAL,
+    text TEXT
+);
+CREATE TABLE IF NOT EXISTS measurements (
+    run INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    value REAL NOT NULL
+);
+CREATE TABLE IF NOT EXISTS vectors (
+    run INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    datatype INTEGER NOT NULL,
+    complex INTEGER NOT NULL,
+    scale TEXT,
+    data BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS runs_by_point ON runs (campaign, point);
+CREATE INDEX IF NOT EXISTS tags_by_key ON tags (key, number, text);
+CREATE INDEX IF NOT EXISTS measurements_by_name ON measurements (name, value);
+CREATE INDEX IF NOT EXISTS vectors_by_run ON vectors (run);
+";
+
+/// A campaign database, in a file or in memory.
+#[derive(Debug)]
+pub struct Database {
+    connection: Connection,
+}
+
+/// A stored run without its vectors, returned by [`Database::query`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredRun {
+    /// The run's id in the database, for [`Database::simulation`].
+    pub id: i64,
+    pub campaign: String,
+    /// The run's index within its campaign.
+    pub position: usize,
+    pub tags: Tags,
+    pub measurements: BTreeMap<String, f64>,
+}
+
+/// Selects runs by campaign, tags, and measurement values. Every condition must hold.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    campaign: Option<String>,
+    tags: Vec<(String, TagValue)>,
+    measurements: Vec<(String, f64, f64)>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Query::default()
+    }
+
+    /// Only runs from campaigns with this name.
+    pub fn campaign(mut self, name: &str) -> Self {
+        self.campaign = Some(name.to_owned());
+        self
+    }
+
+    /// Only runs where the tag `key` equals `value`.
+    pub fn tag(mut self, key: &str, value: impl Into<TagValue>) -> Self {
+        self.tags.push((key.to_owned(), value.into()));
+        self
+    }
+
+    /// Only runs with a measurement between `min` and `max`, inclusive. Either bound may be
+    /// infinite.
+    pub fn measurement(mut self, name: &str, min: f64, max: f64) -> Self {
+        self.measurements.push((name.to_owned(), min, max));
+        self
+    }
+
+    fn sql(&self) -> (String, Vec<Value>) {
+        let mut sql = String::from(
+            "SELECT runs.id, campaigns.name, runs.position FROM runs \
+             JOIN campaigns ON campaigns.id = runs.campaign WHERE 1",
+        );
+        let mut values = Vec::new();
+        if let Some(name) = &self.campaign {
+            sql.push_str(" AND campaigns.name = ?");
+            values.push(Value::Text(name.clone()));
+        }
+        for (key, value) in &self.tags {
+            let column = match value {
+                TagValue::Number(x) => {
+                    values.push(Value::Text(key.clone()));
+                    values.push(Value::Real(*x));
+                    "number"
+                }
+                TagValue::Text(x) => {
+                    values.push(Value::Text(key.clone()));
+                    values.push(Value::Text(x.clone()));
+                    "text"
+                }
+            };
+            sql.push_str(&format!(
+                " AND EXISTS (SELECT 1 FROM tags WHERE tags.run = runs.id \
+                 AND tags.key = ? AND tags.{} = ?)",
+                column
+            ));
+        }
+        for (name, min, max) in &self.measurements {
+            sql.push_str(
+                " AND EXISTS (SELECT 1 FROM measurements WHERE measurements.run = runs.id \
+                 AND measurements.name = ? AND measurements.value >= ? \
+                 AND measurements.value <= ?)",
+            );
+            values.push(Value::Text(name.clone()));
+            values.push(Value::Real(*min));
+            values.push(Value::Real(*max));
+        }
+        sql.push_str(" ORDER BY runs.id");
+        (sql, values)
+    }
+}
+
+impl Database {
+    /// Opens a database file, creating it and its tables if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened or is not a campaign database.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Database::init(Connection::open(path)?)
+    }
+
+    /// Creates a database that lives only as long as the returned value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if SQLite cannot allocate the database.
+    pub fn in_memory() -> Result<Self> {
+        Database::init(Connection::open_in_memory()?)
+    }
+
+    fn init(connection: Connection) -> Result<Self> {
+        connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Database { connection })
+    }
+
+    /// Stores a whole campaign in one transaction and returns its id. Saving a campaign twice
+    /// stores two copies.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the campaign cannot be written; nothing is stored in that case.
+    pub fn save(&mut self, campaign: &Campaign) -> Result<i64> {
+        let tx = self.connection.transaction()?;
+        tx.execute(
+            "INSERT INTO campaigns (name) VALUES (?1)",
+            params![campaign.name],
+        )?;
+        let campaign_id = tx.last_insert_rowid();
+        for (position, run) in campaign.runs().iter().enumerate() {
+            insert_run(&tx, campaign_id, position, run)?;
+        }
+        tx.commit()?;
+        Ok(campaign_id)
+    }
+
+    /// The id and name of every stored campaign, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be read.
+    pub fn campaigns(&self) -> Result<Vec<(i64, String)>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT id, name FROM campaigns ORDER BY id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    /// Loads a whole campaign, including every run's vectors.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`rusqlite::Error::QueryReturnedNoRows`] if there is no such campaign, or an
+    /// error if the database cannot be read.
+    pub fn load(&self, id: i64) -> Result<Campaign> {
+        let name: String = self.connection.query_row(
+            "SELECT name FROM campaigns WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+        let mut campaign = Campaign::new(&name);
+        let mut stmt = self
+            .connection
+            .prepare("SELECT id FROM runs WHERE campaign = ?1 ORDER BY position")?;
+        let runs: Vec<i64> = stmt
+            .query_map(params![id], |row| row.get(0))?
+            .collect::<Result<_>>()?;
+        for run in runs {
+            let tags = self.tags(run)?;
+            let measurements = self.measurements(run)?;
+            let index = campaign.add(tags, self.simulation(run)?);
+            campaign.runs_mut()[index].measurements = measurements;
+        }
+        Ok(campaign)
+    }
+
+    /// Finds the runs that match `query`, in the order they were stored, without loading their
+    /// vectors.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be read.
+    pub fn query(&self, query: &Query) -> Result<Vec<StoredRun>> {
+        let (sql, values) = query.sql();
+        let mut stmt = self.connection.prepare(&sql)?;
+        let rows: Vec<(i64, String, i64)> = stmt
+            .query_map(params_from_iter(values.iter()), |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<Result<_>>()?;
+        rows.into_iter()
+            .map(|(id, campaign, position)| {
+                Ok(StoredRun {
+                    id,
+                    campaign,
+                    position: position as usize,
+                    tags: self.tags(id)?,
+                    measurements: self.measurements(id)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Loads the simulation of one run, with its vectors and ngSPICE output.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`rusqlite::Error::QueryReturnedNoRows`] if there is no such run, or an error
+    /// if the database cannot be read.
+    pub fn simulation(&self, run: i64) -> Result<Simulation> {
+        let (stdout, stderr) = self.connection.query_row(
+            "SELECT stdout, stderr FROM runs WHERE id = ?1",
+            params![run],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let mut sim = Simulation {
+            stdout,
+            stderr,
+            ..Simulation::default()
+        };
+        let mut stmt = self
+            .connection
+            .prepare("SELECT name, datatype, complex, scale, data FROM vectors WHERE run = ?1")?;
+        let rows = stmt.query_map(params![run], |row| {
+            let name: String = row.get(0)?;
+            let datatype: u32 = row.get(1)?;
+            let complex: bool = row.get(2)?;
+            let info = VectorInfo {
+                datatype: DataType::from(datatype),
+                values: decode(complex, &row.get::<_, Vec<u8>>(4)?),
+                scale: row.get(3)?,
+            };
+            Ok((name, info))
+        })?;
+        for row in rows {
+            let (name, info) = row?;
+            sim.vectors.insert(name, info);
+        }
+        Ok(sim)
+    }
+
+    /// Loads a single vector of one run, if it exists.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be read.
+    pub fn vector(&self, run: i64, name: &str) -> Result<Option<VectorInfo>> {
+        self.connection
+            .query_row(
+                "SELECT datatype, complex, scale, data FROM vectors WHERE run = ?1 AND name = ?2",
+                params![run, name],
+                |row| {
+                    let datatype: u32 = row.get(0)?;
+                    let complex: bool = row.get(1)?;
+                    Ok(VectorInfo {
+                        datatype: DataType::from(datatype),
+                        values: decode(complex, &row.get::<_, Vec<u8>>(3)?),
+                        scale: row.get(2)?,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    /// Deletes a campaign and all of its runs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be written.
+    pub fn delete(&mut self, id: i64) -> Result<()> {
+        self.connection
+            .execute("DELETE FROM campaigns WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// Continues the most recent campaign named `name`, or starts one if there is none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be read or written.
+    pub fn resume(&mut self, name: &str) -> Result<Resumed<'_>> {
+        let existing: Option<i64> = self
+            .connection
+            .query_row(
+                "SELECT id FROM campaigns WHERE name = ?1 ORDER BY id DESC LIMIT 1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                self.connection
+                    .execute("INSERT INTO campaigns (name) VALUES (?1)", params![name])?;
+                self.connection.last_insert_rowid()
+            }
+        };
+        let mut stmt = self
+            .connection
+            .prepare("SELECT point FROM runs WHERE campaign = ?1")?;
+        let done: HashSet<u64> = stmt
+            .query_map(params![id], |row| Ok(row.get::<_, i64>(0)? as u64))?
+            .collect::<Result<_>>()?;
+        drop(stmt);
+        // runs with the same tags share a fingerprint, so `done` may be smaller than the
+        // number of runs
+        let next: i64 = self.connection.query_row(
+            "SELECT COALESCE(MAX(position) + 1, 0) FROM runs WHERE campaign = ?1",
+            params![id],
+            |row| row.get(0),
+        )?;
+        Ok(Resumed {
+            next: next as usize,
+            database: self,
+            id,
+            done,
+        })
+    }
+
+    fn tags(&self, run: i64) -> Result<Tags> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT key, number, text FROM tags WHERE run = ?1")?;
+        let rows = stmt.query_map(params![run], |row| {
+            let key: String = row.get(0)?;
+            let number: Option<f64> = row.get(1)?;
+            let text: Option<String> = row.get(2)?;
+            Ok((key, number, text))
+        })?;
+        let mut tags = Tags::new();
+        for row in rows {
+            let (key, number, text) = row?;
+            let value = match (number, text) {
+                (Some(x), _) => TagValue::Number(x),
+                (None, text) => TagValue::Text(text.unwrap_or_default()),
+            };
+            tags = tags.with(&key, value);
+        }
+        Ok(tags)
+    }
+
+    fn measurements(&self, run: i64) -> Result<BTreeMap<String, f64>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT name, value FROM measurements WHERE run = ?1")?;
+        let rows = stmt.query_map(params![run], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+}
+
+/// A campaign being extended one run at a time, from [`Database::resume`].
+///
+/// Each run is committed as soon as it is recorded, so an interrupted sweep loses at most the
+/// run in progress, and running the sweep again skips every recorded point.
+#[derive(Debug)]
+pub struct Resumed<'a> {
+    database: &'a mut Database,
+    id: i64,
+    /// Fingerprints of the recorded runs' tags.
+    done: HashSet<u64>,
+    next: usize,
+}
+
+impl Resumed<'_> {
+    /// The campaign's id in the database.
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// How many runs the campaign holds.
+    pub fn len(&self) -> usize {
+        self.next
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.next == 0
+    }
+
+    /// Whether a run with exactly these tags has been recorded.
+    pub fn is_done(&self, tags: &Tags) -> bool {
+        self.done.contains(&tags.fingerprint())
+    }
+
+    /// Adds a completed run to the campaign.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the run cannot be written; nothing is stored in that case.
+    pub fn record(&mut self, run: &Run) -> Result<()> {
+        let tx = self.database.connection.transaction()?;
+        insert_run(&tx, self.id, self.next, run)?;
+        tx.commit()?;
+        self.done.insert(run.tags.fingerprint());
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Loads the whole campaign, including runs recorded before it was resumed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be read.
+    pub fn finish(self) -> Result<Campaign> {
+        self.database.load(self.id)
+    }
+}
+
+impl MonteCarlo {
+    /// Like [`MonteCarlo::run`], but records each run in the campaign `name` of `database` as
+    /// soon as it finishes, and skips runs already recorded there. Raising
+    /// [`MonteCarlo::runs`] and running again adds only the new samples.
+    ///
+    /// Runs are matched by their tags, i.e. their index and parameter values, so changing the
+    /// seed or a tolerance starts new points rather than reusing old ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first simulation or database error. Runs recorded before the error are
+    /// kept.
+    pub fn run_resumable(
+        &self,
+        circuit: &str,
+        command: &str,
+        database: &mut Database,
+        name: &str,
+    ) -> std::result::Result<Campaign, Error> {
+        let mut resumed = database.resume(name)?;
+        for run in 0..self.runs {
+            let (tags, values) = self.point(run);
+            if resumed.is_done(&tags) {
+                continue;
+            }
+            let simulation = NgSpice::simulate(&apply_params(circuit, &values), command)?;
+            resumed.record(&Run {
+                tags,
+                simulation,
+                measurements: BTreeMap::new(),
+            })?;
+        }
+        Ok(resumed.finish()?)
+    }
+}
+
+impl CornerAnalysis {
+    /// Like [`CornerAnalysis::run`], but records each corner in the campaign `name` of
+    /// `database` as soon as it finishes, and skips corners already recorded there, so new
+    /// corners can be added to a finished analysis.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first simulation or database error. Corners recorded before the error are
+    /// kept.
+    pub fn run_resumable(
+        &self,
+        circuit: &Circuit,
+        command: &str,
+        database: &mut Database,
+        name: &str,
+    ) -> std::result::Result<Campaign, Error> {
+        let mut resumed = database.resume(name)?;
+        for corner in &self.corners {
+            let tags = corner.tags();
+            if resumed.is_done(&tags) {
+                continue;
+            }
+            let deck = corner.apply(circuit)?.to_string();
+            let (simulation, results) =
+                NgSpice::simulate_measured(&deck, command, &self.measurements)?;
+            resumed.record(&Run {
+                tags,
+                simulation,
+                measurements: results.into_iter().collect(),
+            })?;
+        }
+        Ok(resumed.finish()?)
+    }
+}
+
+impl TemperatureSweep {
+    /// Like [`TemperatureSweep::run`], but records each temperature in the campaign `name` of
+    /// `database`, tagged `temp`, as soon as it finishes, and skips temperatures already
+    /// recorded there.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first simulation or database error. Temperatures recorded before the error
+    /// are kept.
+    pub fn run_resumable(
+        &self,
+        circuit: &str,
+        command: &str,
+        database: &mut Database,
+        name: &str,
+    ) -> std::result::Result<Campaign, Error> {
+        let mut resumed = database.resume(name)?;
+        for &t in &self.temperatures {
+            let tags = Tags::new().with("temp", t);
+            if resumed.is_done(&tags) {
+                continue;
+            }
+            let simulation = NgSpice::simulate(&with_temperature(circuit, t), command)?;
+            resumed.record(&Run {
+                tags,
+                simulation,
+                measurements: BTreeMap::new(),
+            })?;
+        }
+        Ok(resumed.finish()?)
+    }
+}
+
+/// Stores a run and everything in it.
+fn insert_run(connection: &Connection, campaign: i64, position: usize, run: &Run) -> Result<()> {
+    connection.execute(
+        "INSERT INTO runs (campaign, position, point, stdout, stderr) \
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            campaign,
+            position as i64,
+            // SQLite integers are signed; the bits are what matter
+            run.tags.fingerprint() as i64,
+            run.simulation.stdout,
+            run.simulation.stderr
+        ],
+    )?;
+    let run_id = connection.last_insert_rowid();
+    for (key, value) in run.tags.iter() {
+        connection.execute(
+            "INSERT INTO tags (run, key, number, text) VALUES (?1, ?2, ?3, ?4)",
+            params![run_id, key, value.number(), value.text()],
+        )?;
+    }
+    for (name, value) in &run.measurements {
+        connection.execute(
+            "INSERT INTO measurements (run, name, value) VALUES (?1, ?2, ?3)",
+            params![run_id, name, value],
+        )?;
+    }
+    for (name, info) in &run.simulation.vectors {
+        let (complex, data) = encode(&info.values);
+        connection.execute(
+            "INSERT INTO vectors (run, name, datatype, complex, scale, data) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                run_id,
+                name,
+                type_code(&info.datatype),
+                complex,
+                info.scale,
+                data
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Packs vector values into a blob, returning whether they are complex.
+fn encode(values: &VectorValues) -> (bool, Vec<u8>) {
+    match values {
+        VectorValues::Real(x) => (false, x.iter().flat_map(|v| v.to_le_bytes()).collect()),
+        VectorValues::Complex(x) => (
+            true,
+            x.iter()
+                .flat_map(|c| c.re.to_le_bytes().into_iter().chain(c.im.to_le_bytes()))
+                .collect(),
+        ),
+    }
+}
+
+/// Unpacks a blob written by [`encode`].
+fn decode(complex: bool, data: &[u8]) -> VectorValues {
+    let numbers: Vec<f64> = data
+        .chunks_exact(8)
+        .map(|b| f64::from_le_bytes(b.try_into().expect("chunks are eight bytes")))
+        .collect();
+    if complex {
+        let values: Vec<Complex64> = numbers
+            .chunks_exact(2)
+            .map(|c| Complex64::new(c[0], c[1]))
+            .collect();
+        values.into()
+    } else {
+        numbers.into()
+    }
+}
+
+/// The ngSPICE vector type of a data type, the inverse of `DataType::from`.
+fn type_code(datatype: &DataType) -> u32 {
+    match datatype {
+        DataType::Unknown => simulation_types::SV_NOTYPE,
+        DataType::Time => simulation_types::SV_TIME,
+        DataType::Frequency => simulation_types::SV_FREQUENCY,
+        DataType::Voltage => simulation_types::SV_VOLTAGE,
+        DataType::Current => simulation_types::SV_CURRENT,
+        DataType::VoltageDensity => simulation_types::SV_VOLTAGE_DENSITY,
+        DataType::CurrentDensity => simulation_types::SV_CURRENT_DENSITY,
+        DataType::SquaredVoltageDensity => simulation_types::SV_SQR_VOLTAGE_DENSITY,
+        DataType::SquaredCurrentDensity => simulation_types::SV_SQR_CURRENT_DENSITY,
+        DataType::SquaredVoltage => simulation_types::SV_SQR_VOLTAGE,
+        DataType::SquaredCurrent => simulation_types::SV_SQR_CURRENT,
+        DataType::Pole => simulation_types::SV_POLE,
+        DataType::Zero => simulation_types::SV_ZERO,
+        DataType::SParameter => simulation_types::SV_SPARAM,
+        DataType::Temperature => simulation_types::SV_TEMP,
+        DataType::Resistance => simulation_types::SV_RES,
+        DataType::Impedance => simulation_types::SV_IMPEDANCE,
+        DataType::Admittance => simulation_types::SV_ADMITTANCE,
+        DataType::Power => simulation_types::SV_POWER,
+        DataType::Phase => simulation_types::SV_PHASE,
+        DataType::Decibel => simulation_types::SV_DB,
+        DataType::Capacitance => simulation_types::SV_CAPACITANCE,
+        DataType::Charge => simulation_types::SV_CHARGE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_and_queries_campaigns() -> Result<()> {
+        let mut campaign = Campaign::new("corners");
+        for (corner, gain) in [("tt", 40.0), ("ss", 31.5)] {
+            let mut sim = Simulation::default();
+            sim.vectors.insert(
+                "out".to_owned(),
+                VectorInfo {
+                    datatype: DataType::Voltage,
+                    values: vec![Complex64::new(1.0, -2.0)].into(),
+                    scale: Some("frequency".to_owned()),
+                },
+            );
+            let run = campaign.add(Tags::new().with("corner", corner), sim);
+            campaign.runs_mut()[run]
+                .measurements
+                .insert("gain".to_owned(), gain);
+        }
+        let mut db = Database::in_memory()?;
+        let id = db.save(&campaign)?;
+        assert_eq!(db.campaigns()?, vec![(id, "corners".to_owned())]);
+
+        let slow = db.query(&Query::new().campaign("corners").tag("corner", "ss"))?;
+        assert_eq!(slow.len(), 1);
+        assert_eq!(slow[0].measurements["gain"], 31.5);
+        let high = db.query(&Query::new().measurement("gain", 35.0, f64::INFINITY))?;
+        assert_eq!(high[0].tags.text("corner"), Some("tt"));
+
+        let out = db.vector(high[0].id, "out")?.unwrap();
+        assert_eq!(out.datatype, DataType::Voltage);
+        assert_eq!(out.values.complex(), Some(&[Complex64::new(1.0, -2.0)][..]));
+        let loaded = db.load(id)?;
+        assert_eq!(loaded.runs().len(), 2);
+        assert_eq!(loaded.runs()[1].tags.text("corner"), Some("ss"));
+
+        let mut resumed = db.resume("corners")?;
+        assert_eq!((resumed.id(), resumed.len()), (id, 2));
+        let ff = Tags::new().with("corner", "ff");
+        assert!(resumed.is_done(&Tags::new().with("corner", "tt")) && !resumed.is_done(&ff));
+        resumed.record(&Run {
+            tags: ff.clone(),
+            simulation: Simulation::default(),
+            measurements: BTreeMap::new(),
+        })?;
+        assert_eq!(resumed.finish()?.runs()[2].tags, ff);
+        assert!(db.resume("corners")?.is_done(&ff));
+        // a repeated point is a new run, so it counts even though its fingerprint does not
+        db.resume("corners")?.record(&Run {
+            tags: ff,
+            simulation: Simulation::default(),
+            measurements: BTreeMap::new(),
+        })?;
+        assert_eq!(db.resume("corners")?.len(), 4);
+
+        db.delete(id)?;
+        assert!(db.campaigns()?.is_empty());
+        Ok(())
+    }
+}
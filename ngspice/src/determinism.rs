@@ -0,0 +1,242 @@
+// Copyright 2022 Andrew Morrow.
+// determinism.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Seeded runs and checks that repeated runs produce bit-identical results.
+
+use crate::circuit::logical_lines;
+use crate::{Error, NgSpice, Simulation, VectorValues};
+use std::collections::BTreeSet;
+
+/// Netlist functions and sources that draw random numbers. They are only reproducible when
+/// ngSPICE's seed is pinned, which it otherwise takes from the clock.
+const RANDOM_FUNCTIONS: [&str; 10] = [
+    "agauss", "aunif", "gauss", "unif", "limit", "random", "trnoise", "trrandom", "sgauss", "sunif",
+];
+
+/// How a vector differed between two runs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Discrepancy {
+    /// The vector exists in only one run.
+    Missing,
+    /// The runs produced different numbers of points.
+    Length { first: usize, second: usize },
+    /// One run is real and the other complex.
+    Type,
+    /// The values differ. `index` is the first point that differs and `max` the largest
+    /// absolute difference.
+    Values { index: usize, max: f64 },
+}
+
+/// A vector that was not bit-identical between two runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Difference {
+    pub vector: String,
+    pub discrepancy: Discrepancy,
+}
+
+/// The outcome of [`NgSpice::check_determinism`].
+#[derive(Clone, Debug)]
+pub struct DeterminismReport {
+    pub seed: u32,
+    /// The results of the first run.
+    pub simulation: Simulation,
+    /// Every vector that differed between the runs.
+    pub differences: Vec<Difference>,
+    /// Netlist lines that draw random numbers, which are the usual cause of differences.
+    pub random_sources: Vec<String>,
+}
+
+impl DeterminismReport {
+    pub fn is_deterministic(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+/// Compares every vector of two runs bit for bit, so that even a difference in the last
+/// place, or a NaN with a different payload, is reported.
+pub fn compare_bits(first: &Simulation, second: &Simulation) -> Vec<Difference> {
+    let names: BTreeSet<&String> = first.vectors.keys().chain(second.vectors.keys()).collect();
+    let mut out = Vec::new();
+    for name in names {
+        let discrepancy = match (first.vectors.get(name), second.vectors.get(name)) {
+            (Some(a), Some(b)) => match (&a.values, &b.values) {
+                (VectorValues::Real(a), VectorValues::Real(b)) => compare_values(a, b),
+                (VectorValues::Complex(a), VectorValues::Complex(b)) => {
+                    let flatten = |x: &[num_complex::Complex64]| -> Vec<f64> {
+                        x.iter().flat_map(|c| [c.re, c.im]).collect()
+                    };
+                    compare_values(&flatten(a), &flatten(b)).map(|d| match d {
+                        Discrepancy::Values { index, max } => Discrepancy::Values {
+                            index: index / 2,
+                            max,
+                        },
+                        Discrepancy::Length { first, second } => Discrepancy::Length {
+                            first: first / 2,
+                            second: second / 2,
+                        },
+                        d => d,
+                    })
+                }
+                _ => Some(Discrepancy::Type),
+            },
+            _ => Some(Discrepancy::Missing),
+        };
+        if let Some(discrepancy) = discrepancy {
+            out.push(Difference {
+                vector: name.clone(),
+                discrepancy,
+            });
+        }
+    }
+    out
+}
+
+fn compare_values(a: &[f64], b: &[f64]) -> Option<Discrepancy> {
+    if a.len() != b.len() {
+        return Some(Discrepancy::Length {
+            first: a.len(),
+            second: b.len(),
+        });
+    }
+    let index = a
+        .iter()
+        .zip(b)
+        .position(|(x, y)| x.to_bits() != y.to_bits())?;
+    let max = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| (x - y).abs())
+        .filter(|d| !d.is_nan())
+        .fold(0.0, f64::max);
+    Some(Discrepancy::Values { index, max })
+}
+
+/// The logical lines of `circuit` that call a random function, such as `agauss` or
+/// `trnoise`.
+pub fn random_sources(circuit: &str) -> Vec<String> {
+    logical_lines(circuit)
+        .into_iter()
+        .filter(|line| {
+            let lower = line.to_ascii_lowercase();
+            if lower.trim_start().starts_with('*') {
+                return false;
+            }
+            RANDOM_FUNCTIONS.iter().any(|f| {
+                lower.match_indices(f).any(|(k, _)| {
+                    let before = lower[..k].chars().next_back();
+                    let after = lower[k + f.len()..].trim_start().chars().next();
+                    !before.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+                        && after == Some('(')
+                })
+            })
+        })
+        .collect()
+}
+
+impl NgSpice {
+    /// Like [`NgSpice::simulate`], but pins ngSPICE's random seed first, so random functions
+    /// and noise sources produce the same values on every run.
+    ///
+    /// Hooks do not run, because the simulation uses a [`crate::session::Session`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`NgSpice::simulate`].
+    pub fn simulate_seeded(circuit: &str, command: &str, seed: u32) -> Result<Simulation, Error> {
+        let mut session = NgSpice::session();
+        seeded_run(&mut session, circuit, command, seed)
+    }
+
+    /// Runs `command` twice with the same seed and reports every vector whose results were
+    /// not bit-identical. Run this in CI to catch models or options that make results depend
+    /// on more than the netlist.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`NgSpice::simulate`].
+    pub fn check_determinism(
+        circuit: &str,
+        command: &str,
+        seed: u32,
+    ) -> Result<DeterminismReport, Error> {
+        let mut session = NgSpice::session();
+        let first = seeded_run(&mut session, circuit, command, seed)?;
+        let second = seeded_run(&mut session, circuit, command, seed)?;
+        Ok(DeterminismReport {
+            seed,
+            differences: compare_bits(&first, &second),
+            random_sources: random_sources(circuit),
+            simulation: first,
+        })
+    }
+}
+
+/// Seeds ngSPICE, then loads and runs the circuit, keeping no plots afterwards.
+fn seeded_run(
+    session: &mut crate::session::Session,
+    circuit: &str,
+    command: &str,
+    seed: u32,
+) -> Result<Simulation, Error> {
+    // the seed is applied when the circuit is parsed
+    session.command(&format!("set rndseed={}", seed))?;
+    session.load_circuit(circuit)?;
+    let plot = session.run(command)?;
+    let sim = session.plot(&plot)?;
+    session.destroy(&plot)?;
+    Ok(sim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, VectorInfo};
+
+    #[test]
+    fn compares_bitwise() {
+        let sim = |values: Vec<f64>| {
+            let mut sim = Simulation::default();
+            let values = VectorValues::Real(values.into());
+            let datatype = DataType::Voltage;
+            sim.vectors.insert(
+                "out".to_owned(),
+                VectorInfo {
+                    datatype,
+                    values,
+                    scale: None,
+                },
+            );
+            sim
+        };
+        let a = sim(vec![1.0, 2.0, f64::NAN]);
+        assert!(compare_bits(&a, &a.clone()).is_empty());
+        let b = sim(vec![1.0, 2.0 + 1e-15, f64::NAN]);
+        let diff = compare_bits(&a, &b);
+        assert!(matches!(
+            diff[0].discrepancy,
+            Discrepancy::Values { index: 1, max } if max > 0.0
+        ));
+        assert_eq!(
+            compare_bits(&a, &Simulation::default())[0].discrepancy,
+            Discrepancy::Missing
+        );
+        let deck = "* t\nV1 a 0 DC 1\nR1 a 0 {agauss(1k, 0.01, 3)}\n* gauss(1)\nVn b 0 trnoise (1m 1n)\n.end";
+        let sources = random_sources(deck);
+        assert_eq!(sources.len(), 2);
+        assert!(sources[0].starts_with("R1"));
+    }
+}
@@ -0,0 +1,244 @@
+// Copyright 2022 Andrew Morrow.
+// dialect.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Rewrites decks written for other SPICE simulators into forms ngSPICE accepts.
+
+use crate::circuit::logical_lines;
+use crate::session::Session;
+use crate::Error;
+
+/// The simulator a deck was written for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vendor {
+    PSpice,
+    Hspice,
+    LTspice,
+}
+
+impl Vendor {
+    /// The value of ngSPICE's `ngbehavior` option that emulates this simulator.
+    pub fn ngbehavior(&self) -> &'static str {
+        match self {
+            Vendor::PSpice => "ps",
+            Vendor::Hspice => "hs",
+            Vendor::LTspice => "lt",
+        }
+    }
+}
+
+/// Which rewrites to apply to a vendor deck. [`Dialect::new`] enables all of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dialect {
+    pub vendor: Vendor,
+    /// Turn `.probe` cards into `.save` cards.
+    pub probe: bool,
+    /// Keep only the first temperature of a `.temp` list, reporting the rest in
+    /// [`Normalized::temperatures`].
+    pub temperature: bool,
+    /// Replace the `**` power operator with `^`.
+    pub power: bool,
+    /// Turn PSpice `VALUE={...}` E and G sources into B sources, and HSPICE `'...'`
+    /// expressions into `{...}`.
+    pub expressions: bool,
+    /// Set `ngbehavior` before loading the deck in [`Session::load_dialect`].
+    pub set_behavior: bool,
+}
+
+/// A deck rewritten by [`Dialect::normalize`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Normalized {
+    pub deck: String,
+    /// Every temperature of the deck's `.temp` card, in order. Only the first remains in the
+    /// deck; sweep the others with separate runs.
+    pub temperatures: Vec<f64>,
+    /// One line per rewrite, for review.
+    pub notes: Vec<String>,
+}
+
+impl Dialect {
+    pub fn new(vendor: Vendor) -> Self {
+        Dialect {
+            vendor,
+            probe: true,
+            temperature: true,
+            power: true,
+            expressions: true,
+            set_behavior: true,
+        }
+    }
+
+    /// Rewrites `deck`. Continuation lines are joined, and the title line is never changed.
+    pub fn normalize(&self, deck: &str) -> Normalized {
+        let mut result = Normalized::default();
+        let mut lines = logical_lines(deck).into_iter();
+        if let Some(title) = lines.next() {
+            result.deck.push_str(&title);
+            result.deck.push('\n');
+        }
+        for line in lines {
+            let line = if line.starts_with('*') {
+                line
+            } else {
+                self.rewrite(line, &mut result)
+            };
+            result.deck.push_str(&line);
+            result.deck.push('\n');
+        }
+        result
+    }
+
+    fn rewrite(&self, mut line: String, result: &mut Normalized) -> String {
+        let original = line.clone();
+        if self.power && line.contains("**") {
+            line = line.replace("**", "^");
+        }
+        let word = line
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        // quoted file names are the usual HSPICE form, and ngSPICE accepts them as they are
+        let file_card = matches!(word.as_str(), ".lib" | ".include" | ".inc");
+        if self.expressions && self.vendor == Vendor::Hspice && !file_card {
+            line = quotes_to_braces(&line);
+        }
+        if self.probe && word == ".probe" {
+            let args = line[word.len()..].trim();
+            line = if args.is_empty() {
+                // ngSPICE saves every vector unless told otherwise
+                format!("* {}", line)
+            } else {
+                format!(".save {}", args)
+            };
+        } else if self.temperature && (word == ".temp" || word == ".temperature") {
+            let temps: Vec<&str> = line.split_whitespace().skip(1).collect();
+            result.temperatures = temps.iter().filter_map(|t| t.parse().ok()).collect();
+            if temps.len() > 1 {
+                line = format!(".temp {}", temps[0]);
+            }
+        } else if self.expressions && (word.starts_with('e') || word.starts_with('g')) {
+            if let Some(b) = value_source(&line) {
+                line = b;
+            }
+        }
+        if line != original {
+            result.notes.push(format!("{} -> {}", original, line));
+        }
+        line
+    }
+}
+
+/// Replaces each `'expr'` that is a value, i.e. a whole token or the right-hand side of
+/// `name='expr'`, with `{expr}`. Other quotes are left alone.
+fn quotes_to_braces(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut open = false;
+    let mut previous = None;
+    for c in line.chars() {
+        match c {
+            '\'' if open => {
+                out.push('}');
+                open = false;
+            }
+            '\'' if previous.is_none_or(|p: char| p.is_whitespace() || p == '=') => {
+                out.push('{');
+                open = true;
+            }
+            _ => out.push(c),
+        }
+        previous = Some(c);
+    }
+    out
+}
+
+/// Turns `E1 out 0 VALUE={expr}` into `BE1 out 0 V=expr`, and likewise G into `I=`.
+fn value_source(line: &str) -> Option<String> {
+    let mut tokens = line.splitn(4, char::is_whitespace);
+    let (name, pos, neg) = (tokens.next()?, tokens.next()?, tokens.next()?);
+    let rest = tokens.next()?.trim();
+    if !rest.get(..5)?.eq_ignore_ascii_case("value") {
+        return None;
+    }
+    let expr = rest[5..].trim_start().strip_prefix('=')?.trim();
+    let expr = expr
+        .strip_prefix('{')
+        .and_then(|e| e.strip_suffix('}'))
+        .unwrap_or(expr);
+    let quantity = if name.starts_with(['e', 'E']) {
+        'V'
+    } else {
+        'I'
+    };
+    Some(format!("B{} {} {} {}={}", name, pos, neg, quantity, expr))
+}
+
+impl Session {
+    /// Normalizes `deck` and loads it, first setting `ngbehavior` if the dialect asks for it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command policy forbids `set`, or if loading the deck fails.
+    pub fn load_dialect(&mut self, deck: &str, dialect: &Dialect) -> Result<Normalized, Error> {
+        let normalized = dialect.normalize(deck);
+        if dialect.set_behavior {
+            self.command(&format!("set ngbehavior={}", dialect.vendor.ngbehavior()))?;
+        }
+        self.load_circuit(&normalized.deck)?;
+        Ok(normalized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_vendor_decks() {
+        let pspice = Dialect::new(Vendor::PSpice).normalize(
+            "* amp
+E1 out 0 VALUE={V(in)**2}
+G1 a 0 value = {2*V(b)}
+.temp 0 25
++ 125
+.probe
+.probe V(out)
+* keep ** here
+.end",
+        );
+        assert_eq!(
+            pspice.deck,
+            "* amp
+BE1 out 0 V=V(in)^2
+BG1 a 0 I=2*V(b)
+.temp 0
+* .probe
+.save V(out)
+* keep ** here
+.end
+"
+        );
+        assert_eq!(pspice.temperatures, vec![0.0, 25.0, 125.0]);
+        assert_eq!(pspice.notes.len(), 5);
+
+        let hspice = Dialect::new(Vendor::Hspice)
+            .normalize("* t\n.lib 'models.lib' tt\n.inc 'x.sp'\n.param w='2*l'\nR1 a b 'w/2'\n");
+        assert_eq!(
+            hspice.deck,
+            "* t\n.lib 'models.lib' tt\n.inc 'x.sp'\n.param w={2*l}\nR1 a b {w/2}\n"
+        );
+    }
+}
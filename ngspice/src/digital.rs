@@ -0,0 +1,212 @@
+// Copyright 2022 Andrew Morrow.
+// digital.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Converts analog waveforms into logic-level transitions.
+
+use crate::{Error, Simulation};
+
+/// A logic level.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Level {
+    Low,
+    High,
+}
+
+impl Level {
+    fn invert(self) -> Level {
+        match self {
+            Level::Low => Level::High,
+            Level::High => Level::Low,
+        }
+    }
+}
+
+/// Thresholds used to decide the logic level of an analog signal.
+///
+/// The signal goes high when it rises above `high` and goes low when it falls below `low`, so
+/// setting `low` below `high` adds hysteresis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Thresholds {
+    pub low: f64,
+    pub high: f64,
+    /// Pulses shorter than this are treated as glitches and removed. Set to 0 to keep all pulses.
+    pub min_pulse: f64,
+}
+
+impl Thresholds {
+    /// Thresholds at fixed fractions of a supply voltage: 30% and 70%, with no glitch filter.
+    pub fn cmos(vdd: f64) -> Self {
+        Thresholds {
+            low: 0.3 * vdd,
+            high: 0.7 * vdd,
+            min_pulse: 0.0,
+        }
+    }
+}
+
+/// A change of logic level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transition {
+    /// The interpolated time when the signal crossed the threshold.
+    pub time: f64,
+    /// The level after the transition.
+    pub level: Level,
+}
+
+/// A digitized signal: a starting level followed by a list of transitions in time order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DigitalSignal {
+    pub initial: Level,
+    pub transitions: Vec<Transition>,
+}
+
+impl DigitalSignal {
+    /// Returns the logic level at time `t`.
+    pub fn level_at(&self, t: f64) -> Level {
+        self.transitions
+            .iter()
+            .take_while(|tr| tr.time <= t)
+            .last()
+            .map_or(self.initial, |tr| tr.level)
+    }
+
+    /// Returns only the transitions to `level`, e.g. all rising edges.
+    pub fn edges(&self, level: Level) -> impl Iterator<Item = &Transition> {
+        self.transitions.iter().filter(move |tr| tr.level == level)
+    }
+}
+
+/// Converts an analog waveform into a digital signal.
+///
+/// The initial level is high if the first sample is at or above the midpoint of the thresholds.
+///
+/// # Panics
+///
+/// Panics if `time` and `values` have different lengths or are empty.
+pub fn digitize(time: &[f64], values: &[f64], thresholds: &Thresholds) -> DigitalSignal {
+    assert_eq!(
+        time.len(),
+        values.len(),
+        "time and values must have the same length"
+    );
+    assert!(!time.is_empty(), "cannot digitize an empty waveform");
+    let mid = 0.5 * (thresholds.low + thresholds.high);
+    let initial = if values[0] >= mid {
+        Level::High
+    } else {
+        Level::Low
+    };
+    let mut level = initial;
+    let mut transitions: Vec<Transition> = Vec::new();
+    for i in 1..time.len() {
+        let threshold = match level {
+            Level::Low if values[i] > thresholds.high => thresholds.high,
+            Level::High if values[i] < thresholds.low => thresholds.low,
+            _ => continue,
+        };
+        let (t0, t1, v0, v1) = (time[i - 1], time[i], values[i - 1], values[i]);
+        let t = if v1 == v0 {
+            t1
+        } else {
+            (t0 + (threshold - v0) * (t1 - t0) / (v1 - v0)).clamp(t0, t1)
+        };
+        level = level.invert();
+        match transitions.last() {
+            // the previous transition started a pulse that was too short, so drop both edges
+            Some(prev) if t - prev.time < thresholds.min_pulse => {
+                transitions.pop();
+            }
+            _ => transitions.push(Transition { time: t, level }),
+        }
+    }
+    DigitalSignal {
+        initial,
+        transitions,
+    }
+}
+
+impl Simulation {
+    /// Digitizes the named vector against this simulation's time scale.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingVector`] if the vector does not exist, is complex, or has a
+    /// different length from the scale, or [`Error::MissingScale`] if the simulation has no
+    /// real, non-empty time vector.
+    pub fn digitize(&self, vector: &str, thresholds: &Thresholds) -> Result<DigitalSignal, Error> {
+        let (_, scale) = self.scale_vector().ok_or(Error::MissingScale)?;
+        let scale = scale
+            .real()
+            .filter(|s| !s.is_empty())
+            .ok_or(Error::MissingScale)?;
+        let values = self.real_vector(vector)?;
+        if values.len() != scale.len() {
+            return Err(Error::MissingVector(vector.to_owned()));
+        }
+        Ok(digitize(scale, values, thresholds))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, VectorInfo, VectorValues};
+
+    #[test]
+    fn digitizes_with_hysteresis_and_glitch_filter() {
+        let time = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0];
+        let values = [0.0, 1.0, 0.5, 0.9, 0.0, 1.0, 0.0, 0.0];
+        let mut th = Thresholds {
+            low: 0.2,
+            high: 0.8,
+            min_pulse: 0.0,
+        };
+        let sig = digitize(&time, &values, &th);
+        assert_eq!(sig.initial, Level::Low);
+        // the dip to 0.5 is inside the hysteresis band and must not toggle the level
+        let times: Vec<f64> = sig.transitions.iter().map(|t| t.time).collect();
+        assert_eq!(times.len(), 4);
+        assert!((times[0] - 0.8).abs() < 1e-12);
+        assert_eq!(sig.level_at(2.0), Level::High);
+        assert_eq!(sig.edges(Level::High).count(), 2);
+
+        th.min_pulse = 1.5;
+        let sig = digitize(&time, &values, &th);
+        // the low gap between the pulses lasts about one time unit, so the pulses merge
+        assert_eq!(sig.transitions.len(), 2);
+        assert_eq!(sig.level_at(7.0), Level::Low);
+
+        let mut sim = Simulation::default();
+        for (name, datatype, values) in [
+            ("time", DataType::Time, time.to_vec()),
+            ("out", DataType::Voltage, values[..3].to_vec()),
+        ] {
+            let info = VectorInfo {
+                datatype,
+                values: VectorValues::Real(values.into()),
+                scale: None,
+            };
+            sim.vectors.insert(name.to_owned(), info);
+        }
+        assert!(matches!(
+            sim.digitize("out", &th),
+            Err(Error::MissingVector(_))
+        ));
+        sim.vectors.clear();
+        assert!(matches!(sim.digitize("out", &th), Err(Error::MissingScale)));
+    }
+}
@@ -0,0 +1,171 @@
+// Copyright 2022 Andrew Morrow.
+// eseries.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Rounds component values to the IEC 60063 preferred number series.
+
+use crate::circuit::{parse_number, Change, Circuit};
+use crate::{Error, NgSpice, Simulation};
+
+const E6: [f64; 6] = [1.0, 1.5, 2.2, 3.3, 4.7, 6.8];
+const E12: [f64; 12] = [1.0, 1.2, 1.5, 1.8, 2.2, 2.7, 3.3, 3.9, 4.7, 5.6, 6.8, 8.2];
+const E24: [f64; 24] = [
+    1.0, 1.1, 1.2, 1.3, 1.5, 1.6, 1.8, 2.0, 2.2, 2.4, 2.7, 3.0, 3.3, 3.6, 3.9, 4.3, 4.7, 5.1, 5.6,
+    6.2, 6.8, 7.5, 8.2, 9.1,
+];
+
+/// A preferred number series. Each has that many values per decade.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Series {
+    E6,
+    E12,
+    E24,
+    E48,
+    E96,
+}
+
+impl Series {
+    /// The values of one decade, from 1 up to but excluding 10.
+    pub fn mantissas(&self) -> Vec<f64> {
+        let n = match self {
+            Series::E6 => return E6.to_vec(),
+            Series::E12 => return E12.to_vec(),
+            Series::E24 => return E24.to_vec(),
+            Series::E48 => 48,
+            Series::E96 => 96,
+        };
+        // unlike the coarser series, these follow the formula exactly at three digits
+        (0..n)
+            .map(|i| (100.0 * 10f64.powf(i as f64 / n as f64)).round() / 100.0)
+            .collect()
+    }
+
+    /// The mantissa and decade exponent of the preferred value nearest to `value` on a
+    /// logarithmic scale.
+    fn nearest_parts(&self, value: f64) -> (f64, i32) {
+        let exponent = value.abs().log10().floor() as i32;
+        let scaled = value.abs() / 10f64.powi(exponent);
+        let mut candidates: Vec<(f64, i32)> = self
+            .mantissas()
+            .into_iter()
+            .map(|m| (m, exponent))
+            .collect();
+        candidates.push((1.0, exponent + 1));
+        let (m, e) = candidates
+            .into_iter()
+            .min_by(|a, b| {
+                let da = (a.0 * 10f64.powi(a.1 - exponent) / scaled).ln().abs();
+                let db = (b.0 * 10f64.powi(b.1 - exponent) / scaled).ln().abs();
+                da.total_cmp(&db)
+            })
+            .expect("every series has values");
+        (m.copysign(value), e)
+    }
+
+    /// The preferred value nearest to `value` on a logarithmic scale. Zero stays zero.
+    pub fn nearest(&self, value: f64) -> f64 {
+        if value == 0.0 || !value.is_finite() {
+            return value;
+        }
+        let (m, e) = self.nearest_parts(value);
+        m * 10f64.powi(e)
+    }
+
+    /// The preferred value nearest to `value`, written as a SPICE number like `4.7e3`.
+    pub fn nearest_spice(&self, value: f64) -> String {
+        if value == 0.0 || !value.is_finite() {
+            return format!("{:e}", value);
+        }
+        let (m, e) = self.nearest_parts(value);
+        format!("{}e{}", m, e)
+    }
+}
+
+/// Rounds the value of every element of type `kind` (such as `'R'`) to the nearest value in
+/// `series`, returning the new circuit and what changed. Elements without a plain numeric
+/// value are left alone.
+pub fn snap(circuit: &Circuit, kind: char, series: Series) -> (Circuit, Vec<Change>) {
+    let mut patch = circuit.patch();
+    let targets: Vec<(String, f64)> = circuit
+        .elements()
+        .filter(|e| e.kind() == kind.to_ascii_uppercase())
+        .filter_map(|e| Some((e.name.clone(), e.value().and_then(parse_number)?)))
+        .collect();
+    for (name, value) in targets {
+        let snapped = series.nearest(value);
+        // parsed values such as 2.2k are not always exact
+        if (snapped - value).abs() > value.abs() * 1e-9 {
+            patch
+                .set_value(&name, &series.nearest_spice(value))
+                .expect("the element was just found");
+        }
+    }
+    patch.finish()
+}
+
+/// A performance metric before and after snapping, from [`verify`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Verification {
+    pub original: f64,
+    pub snapped: f64,
+}
+
+impl Verification {
+    /// The change in the metric as a fraction of the original.
+    pub fn relative_change(&self) -> f64 {
+        (self.snapped - self.original) / self.original
+    }
+}
+
+/// Simulates `original` and `snapped` with `command` and measures both with `metric`.
+///
+/// # Errors
+///
+/// Returns any simulation error, or any error from `metric`.
+pub fn verify<F>(
+    original: &Circuit,
+    snapped: &Circuit,
+    command: &str,
+    metric: F,
+) -> Result<Verification, Error>
+where
+    F: Fn(&Simulation) -> Result<f64, Error>,
+{
+    Ok(Verification {
+        original: metric(&NgSpice::simulate(&original.to_string(), command)?)?,
+        snapped: metric(&NgSpice::simulate(&snapped.to_string(), command)?)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snaps_to_preferred_values() {
+        assert_eq!(Series::E96.mantissas().len(), 96);
+        assert_eq!(Series::E96.mantissas()[1], 1.02);
+        assert_eq!(Series::E12.nearest(4.9e3), 4.7e3);
+        assert_eq!(Series::E12.nearest(9.6e-9), 1e-8);
+        assert_eq!(Series::E24.nearest_spice(-5.03), "-5.1e0");
+        assert_eq!(Series::E96.nearest_spice(1234.0), "1.24e3");
+
+        let circuit = Circuit::parse("* t\nR1 a b 1.234k\nR2 b 0 2.21k\nC1 b 0 {cval}\n.end");
+        let (snapped, changes) = snap(&circuit, 'r', Series::E96);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(snapped.element("R1").unwrap().value(), Some("1.24e3"));
+    }
+}
@@ -0,0 +1,406 @@
+// Copyright 2022 Andrew Morrow.
+// filter.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Synthesizes doubly-terminated LC ladder filters.
+
+use crate::waveform::interpolate;
+use crate::{Error, NgSpice};
+use num_complex::Complex64;
+use std::f64::consts::PI;
+use std::fmt::Write;
+
+/// The shape of a filter's passband and transition.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Approximation {
+    /// Maximally flat passband.
+    Butterworth,
+    /// Equiripple passband with the given ripple in dB, and a steeper transition.
+    Chebyshev { ripple: f64 },
+    /// Maximally flat group delay. Orders 1 to 5 are supported.
+    Bessel,
+}
+
+/// Which frequencies a filter passes. Frequencies are in Hz.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Response {
+    LowPass { cutoff: f64 },
+    HighPass { cutoff: f64 },
+    BandPass { center: f64, bandwidth: f64 },
+}
+
+/// What to synthesize.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilterSpec {
+    pub approximation: Approximation,
+    pub response: Response,
+    pub order: usize,
+    /// The source and load resistance, in ohms.
+    pub impedance: f64,
+}
+
+/// Where a ladder section connects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placement {
+    /// From the ladder's signal path to ground. An inductor and capacitor are in parallel.
+    Shunt,
+    /// In the ladder's signal path. An inductor and capacitor are in series.
+    Series,
+}
+
+/// One element, or resonator, of a ladder.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Section {
+    pub placement: Placement,
+    /// In henries.
+    pub inductance: Option<f64>,
+    /// In farads.
+    pub capacitance: Option<f64>,
+}
+
+/// A synthesized ladder filter, listed from the source end.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    pub sections: Vec<Section>,
+    pub source_resistance: f64,
+    pub load_resistance: f64,
+}
+
+/// The results of [`Filter::verify`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilterVerification {
+    pub frequencies: Vec<f64>,
+    /// The simulated transducer gain at each frequency, in dB.
+    pub simulated: Vec<f64>,
+    /// The designed transducer gain at each frequency, in dB.
+    pub expected: Vec<f64>,
+}
+
+impl FilterVerification {
+    /// The largest difference between the simulated and designed gains, in dB.
+    pub fn max_error(&self) -> f64 {
+        self.simulated
+            .iter()
+            .zip(&self.expected)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max)
+    }
+}
+
+/// Returns the normalized low-pass prototype values `g_1..g_n`, for a cutoff of 1 rad/s,
+/// followed by the load resistance ratio `g_(n+1)`. Returns `None` if the order is
+/// unsupported.
+pub fn prototype(approximation: Approximation, order: usize) -> Option<Vec<f64>> {
+    if order == 0 {
+        return None;
+    }
+    let n = order as f64;
+    let mut g = Vec::with_capacity(order + 1);
+    match approximation {
+        Approximation::Butterworth => {
+            g.extend((1..=order).map(|k| 2.0 * ((2 * k - 1) as f64 * PI / (2.0 * n)).sin()));
+            g.push(1.0);
+        }
+        Approximation::Chebyshev { ripple } => {
+            let beta = (1.0 / (ripple / 17.37).tanh()).ln();
+            let gamma = (beta / (2.0 * n)).sinh();
+            let a = |k: usize| ((2 * k - 1) as f64 * PI / (2.0 * n)).sin();
+            let b = |k: usize| gamma * gamma + (k as f64 * PI / n).sin().powi(2);
+            g.push(2.0 * a(1) / gamma);
+            for k in 2..=order {
+                let prev = g[k - 2];
+                g.push(4.0 * a(k - 1) * a(k) / (b(k - 1) * prev));
+            }
+            g.push(if order % 2 == 1 {
+                1.0
+            } else {
+                (1.0 / (beta / 4.0).tanh()).powi(2)
+            });
+        }
+        Approximation::Bessel => {
+            // tables for unit group delay, scaled by each order's 3 dB frequency
+            let (scale, table): (f64, &[f64]) = match order {
+                1 => (1.0, &[2.0]),
+                2 => (1.3617, &[1.5774, 0.4226]),
+                3 => (1.7557, &[1.2550, 0.5528, 0.1922]),
+                4 => (2.1139, &[1.0598, 0.5116, 0.3181, 0.1104]),
+                5 => (2.4274, &[0.9303, 0.4577, 0.3312, 0.2090, 0.0718]),
+                _ => return None,
+            };
+            g.extend(table.iter().map(|x| x * scale));
+            g.push(1.0);
+        }
+    }
+    Some(g)
+}
+
+/// Synthesizes a ladder that starts with a shunt element at the source. Returns `None` if the
+/// order is unsupported by the approximation.
+pub fn synthesize(spec: &FilterSpec) -> Option<Filter> {
+    let g = prototype(spec.approximation, spec.order)?;
+    let r = spec.impedance;
+    let sections = g[..spec.order]
+        .iter()
+        .enumerate()
+        .map(|(k, &g)| {
+            let placement = if k % 2 == 0 {
+                Placement::Shunt
+            } else {
+                Placement::Series
+            };
+            // a shunt prototype element is a capacitor and a series one an inductor
+            let shunt = placement == Placement::Shunt;
+            let (inductance, capacitance) = match spec.response {
+                Response::LowPass { cutoff } => {
+                    let w = 2.0 * PI * cutoff;
+                    if shunt {
+                        (None, Some(g / (r * w)))
+                    } else {
+                        (Some(g * r / w), None)
+                    }
+                }
+                Response::HighPass { cutoff } => {
+                    let w = 2.0 * PI * cutoff;
+                    if shunt {
+                        (Some(r / (g * w)), None)
+                    } else {
+                        (None, Some(1.0 / (g * r * w)))
+                    }
+                }
+                Response::BandPass { center, bandwidth } => {
+                    let (w0, dw) = (2.0 * PI * center, 2.0 * PI * bandwidth);
+                    if shunt {
+                        (Some(r * dw / (w0 * w0 * g)), Some(g / (r * dw)))
+                    } else {
+                        (Some(g * r / dw), Some(dw / (w0 * w0 * g * r)))
+                    }
+                }
+            };
+            Section {
+                placement,
+                inductance,
+                capacitance,
+            }
+        })
+        .collect();
+    Some(Filter {
+        sections,
+        source_resistance: r,
+        load_resistance: r * g[spec.order],
+    })
+}
+
+impl Section {
+    /// The section's impedance (series) or admittance (shunt) at angular frequency `w`.
+    fn immittance(&self, w: f64) -> Complex64 {
+        let s = Complex64::new(0.0, w);
+        let (direct, inverse) = match self.placement {
+            Placement::Series => (self.inductance, self.capacitance),
+            Placement::Shunt => (self.capacitance, self.inductance),
+        };
+        direct.map_or(Complex64::new(0.0, 0.0), |x| s * x)
+            + inverse.map_or(Complex64::new(0.0, 0.0), |x| 1.0 / (s * x))
+    }
+}
+
+impl Filter {
+    /// The designed transducer gain at `frequency` Hz, relative to the maximum power the
+    /// source could deliver. A lossless passband has a gain of 1.
+    pub fn response(&self, frequency: f64) -> Complex64 {
+        let w = 2.0 * PI * frequency;
+        let one = Complex64::new(1.0, 0.0);
+        let zero = Complex64::new(0.0, 0.0);
+        // chain the sections' ABCD matrices, starting with the source resistance
+        let mut m = [[one, one * self.source_resistance], [zero, one]];
+        for section in &self.sections {
+            let x = section.immittance(w);
+            let s = match section.placement {
+                Placement::Series => [[one, x], [zero, one]],
+                Placement::Shunt => [[one, zero], [x, one]],
+            };
+            m = [
+                [
+                    m[0][0] * s[0][0] + m[0][1] * s[1][0],
+                    m[0][0] * s[0][1] + m[0][1] * s[1][1],
+                ],
+                [
+                    m[1][0] * s[0][0] + m[1][1] * s[1][0],
+                    m[1][0] * s[0][1] + m[1][1] * s[1][1],
+                ],
+            ];
+        }
+        let vout = one / (m[0][0] + m[0][1] / self.load_resistance);
+        vout * 2.0 * (self.source_resistance / self.load_resistance).sqrt()
+    }
+
+    /// Renders the ladder's elements between nodes `in` and `out`.
+    pub fn ladder(&self) -> String {
+        let mut out = String::new();
+        let series = self
+            .sections
+            .iter()
+            .filter(|s| s.placement == Placement::Series)
+            .count();
+        if series == 0 {
+            out.push_str("Vlink in out DC 0\n");
+        }
+        let mut node = "in".to_owned();
+        let mut seen = 0;
+        for (k, section) in self.sections.iter().enumerate() {
+            match section.placement {
+                Placement::Shunt => {
+                    if let Some(l) = section.inductance {
+                        writeln!(out, "L{} {} 0 {:e}", k, node, l).unwrap();
+                    }
+                    if let Some(c) = section.capacitance {
+                        writeln!(out, "C{} {} 0 {:e}", k, node, c).unwrap();
+                    }
+                }
+                Placement::Series => {
+                    seen += 1;
+                    let next = if seen == series {
+                        "out".to_owned()
+                    } else {
+                        format!("n{}", k)
+                    };
+                    match (section.inductance, section.capacitance) {
+                        (Some(l), Some(c)) => {
+                            writeln!(out, "L{} {} m{} {:e}", k, node, k, l).unwrap();
+                            writeln!(out, "C{} m{} {} {:e}", k, k, next, c).unwrap();
+                        }
+                        (Some(l), None) => {
+                            writeln!(out, "L{} {} {} {:e}", k, node, next, l).unwrap()
+                        }
+                        (None, Some(c)) => {
+                            writeln!(out, "C{} {} {} {:e}", k, node, next, c).unwrap()
+                        }
+                        (None, None) => writeln!(out, "Vlink{} {} {} DC 0", k, node, next).unwrap(),
+                    }
+                    node = next;
+                }
+            }
+        }
+        out
+    }
+
+    /// Renders a complete deck driving the ladder from its source resistance into its load.
+    pub fn testbench(&self) -> String {
+        format!(
+            "* filter testbench\nVsrc src 0 AC 1\nRsrc src in {:e}\n{}Rload out 0 {:e}\n.end\n",
+            self.source_resistance,
+            self.ladder(),
+            self.load_resistance
+        )
+    }
+
+    /// Simulates the ladder with an AC analysis and compares its gain to the design at each
+    /// frequency.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if `frequencies` is empty or not all positive, or an
+    /// error if the simulation fails.
+    pub fn verify(&self, frequencies: &[f64]) -> Result<FilterVerification, Error> {
+        let lo = frequencies.iter().copied().fold(f64::INFINITY, f64::min);
+        let hi = frequencies.iter().copied().fold(0.0, f64::max);
+        if frequencies.is_empty() || lo <= 0.0 {
+            return Err(Error::InvalidArgument(
+                "verification frequencies must be positive".to_owned(),
+            ));
+        }
+        let cmd = format!("ac dec 100 {:e} {:e}", lo / 2.0, hi * 2.0);
+        let sim = NgSpice::simulate(&self.testbench(), &cmd)?;
+        let freq = sim.scale_values().ok_or(Error::MissingScale)?;
+        let out = sim
+            .vectors
+            .get("out")
+            .and_then(|v| v.values.complex())
+            .ok_or_else(|| Error::MissingVector("out".to_owned()))?;
+        let norm = 2.0 * (self.source_resistance / self.load_resistance).sqrt();
+        let db: Vec<f64> = out
+            .iter()
+            .map(|c| 20.0 * (c.norm() * norm).log10())
+            .collect();
+        Ok(FilterVerification {
+            frequencies: frequencies.to_vec(),
+            simulated: frequencies
+                .iter()
+                .map(|&f| interpolate(&freq, &db, f))
+                .collect(),
+            expected: frequencies
+                .iter()
+                .map(|&f| 20.0 * self.response(f).norm().log10())
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gain_db(spec: FilterSpec, f: f64) -> f64 {
+        20.0 * synthesize(&spec).unwrap().response(f).norm().log10()
+    }
+
+    #[test]
+    fn meets_cutoff() {
+        let lp = |approximation, order| FilterSpec {
+            approximation,
+            response: Response::LowPass { cutoff: 1e3 },
+            order,
+            impedance: 50.0,
+        };
+        for order in 1..=5 {
+            let butter = lp(Approximation::Butterworth, order);
+            assert!((gain_db(butter, 1e3) + 3.0103).abs() < 1e-3);
+            assert!(gain_db(butter, 1.0).abs() < 1e-4);
+            let bessel = lp(Approximation::Bessel, order);
+            assert!((gain_db(bessel, 1e3) + 3.01).abs() < 0.05);
+        }
+        let cheby3 = lp(Approximation::Chebyshev { ripple: 0.5 }, 3);
+        assert!((gain_db(cheby3, 1e3) + 0.5).abs() < 1e-2);
+        let cheby4 = lp(Approximation::Chebyshev { ripple: 0.5 }, 4);
+        assert!((gain_db(cheby4, 1.0) + 0.5).abs() < 1e-2);
+        assert!(prototype(Approximation::Bessel, 6).is_none());
+
+        let hp = FilterSpec {
+            response: Response::HighPass { cutoff: 1e3 },
+            ..lp(Approximation::Butterworth, 3)
+        };
+        assert!((gain_db(hp, 1e3) + 3.0103).abs() < 1e-3);
+        assert!(gain_db(hp, 1e6).abs() < 1e-6);
+        let bp = FilterSpec {
+            response: Response::BandPass {
+                center: 1e6,
+                bandwidth: 1e5,
+            },
+            ..lp(Approximation::Butterworth, 2)
+        };
+        assert!(gain_db(bp, 1e6).abs() < 1e-6);
+        assert!(gain_db(bp, 2e6) < -20.0);
+
+        let ladder = synthesize(&lp(Approximation::Butterworth, 2)).unwrap();
+        assert!(matches!(
+            ladder.verify(&[0.0, 1e3]),
+            Err(Error::InvalidArgument(_))
+        ));
+        let ladder = ladder.ladder();
+        let lines: Vec<&str> = ladder.lines().collect();
+        assert!(lines[0].starts_with("C0 in 0 "));
+        assert!(lines[1].starts_with("L1 in out "));
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright 2022 Andrew Morrow.
+// format.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Number formatting shared by the text exporters, so their output parses the same way
+//! everywhere.
+
+/// How the exponent of a formatted number is chosen.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Notation {
+    /// One digit before the decimal point, e.g. `4.7e3`.
+    #[default]
+    Scientific,
+    /// An exponent that is a multiple of 3, matching SI prefixes, e.g. `47e3`.
+    Engineering,
+}
+
+/// How exporters write numbers.
+///
+/// Output never depends on the locale: the decimal separator is always `.`, there are no
+/// digit group separators, and non-finite values are written as `NaN`, `inf` and `-inf`.
+/// The default writes the shortest digits that read back as the same `f64`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct NumericFormat {
+    /// The number of significant digits, or `None` for the shortest exact representation.
+    pub significant: Option<usize>,
+    pub notation: Notation,
+}
+
+impl NumericFormat {
+    /// Scientific notation with `significant` digits, e.g. `4.70e3` for 3 digits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `significant` is zero.
+    pub fn scientific(significant: usize) -> Self {
+        assert!(significant > 0, "at least one significant digit is needed");
+        NumericFormat {
+            significant: Some(significant),
+            notation: Notation::Scientific,
+        }
+    }
+
+    /// Engineering notation with `significant` digits, e.g. `47.0e3` for 3 digits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `significant` is zero.
+    pub fn engineering(significant: usize) -> Self {
+        NumericFormat {
+            notation: Notation::Engineering,
+            ..NumericFormat::scientific(significant)
+        }
+    }
+
+    pub fn format(&self, value: f64) -> String {
+        if !value.is_finite() {
+            return format!("{}", value);
+        }
+        let scientific = match self.significant {
+            Some(digits) => format!("{:.*e}", digits.max(1) - 1, value),
+            None => format!("{:e}", value),
+        };
+        if self.notation == Notation::Scientific {
+            return scientific;
+        }
+        let (mantissa, exponent) = scientific
+            .split_once('e')
+            .expect("scientific notation has an exponent");
+        let exponent: i32 = exponent.parse().expect("exponents are integers");
+        let (sign, mantissa) = match mantissa.strip_prefix('-') {
+            Some(m) => ("-", m),
+            None => ("", mantissa),
+        };
+        let mut digits = mantissa.replace('.', "");
+        let shifted = exponent.rem_euclid(3) as usize;
+        while digits.len() < shifted + 1 {
+            digits.push('0');
+        }
+        let (int, frac) = digits.split_at(shifted + 1);
+        let point = if frac.is_empty() { "" } else { "." };
+        format!(
+            "{}{}{}{}e{}",
+            sign,
+            int,
+            point,
+            frac,
+            exponent - shifted as i32
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_numbers() {
+        let shortest = NumericFormat::default();
+        assert_eq!(shortest.format(4700.0), "4.7e3");
+        assert_eq!(shortest.format(f64::NAN), "NaN");
+        assert_eq!(NumericFormat::scientific(3).format(4700.0), "4.70e3");
+        let eng = NumericFormat {
+            notation: Notation::Engineering,
+            ..NumericFormat::default()
+        };
+        assert_eq!(eng.format(4.7e4), "47e3");
+        assert_eq!(eng.format(1e4), "10e3");
+        assert_eq!(eng.format(-2.2e-7), "-220e-9");
+        assert_eq!(eng.format(0.0), "0e0");
+        assert_eq!(NumericFormat::engineering(3).format(4.7e4), "47.0e3");
+        assert_eq!(NumericFormat::engineering(2).format(999.6), "1.0e3");
+        assert_eq!(NumericFormat::engineering(4).format(123456.0), "123.5e3");
+    }
+}
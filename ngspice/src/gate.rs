@@ -0,0 +1,190 @@
+// Copyright 2022 Andrew Morrow.
+// gate.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Extracts the logic function and propagation delays of a logic gate.
+
+use crate::digital::{digitize, Level, Thresholds};
+use crate::{Error, NgSpice, Simulation};
+use std::fmt::Write;
+
+/// Describes how to exercise a logic gate.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GateTest {
+    /// Input node names, most significant bit first.
+    pub inputs: Vec<String>,
+    /// Output node name.
+    pub output: String,
+    /// Logic high voltage. Logic low is 0 V.
+    pub vdd: f64,
+    /// How long each input combination is held, in seconds.
+    pub period: f64,
+    /// Input rise and fall time, in seconds.
+    pub edge: f64,
+}
+
+/// The extracted behavior of a logic gate.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GateTiming {
+    /// The output level for each input combination. Index `k` holds the output when the
+    /// inputs are the binary representation of `k`, most significant bit first.
+    pub truth_table: Vec<bool>,
+    /// Every measured low-to-high propagation delay, in seconds.
+    pub tplh: Vec<f64>,
+    /// Every measured high-to-low propagation delay, in seconds.
+    pub tphl: Vec<f64>,
+}
+
+impl GateTiming {
+    /// The worst-case low-to-high delay, if the output ever rose.
+    pub fn max_tplh(&self) -> Option<f64> {
+        self.tplh.iter().copied().reduce(f64::max)
+    }
+
+    /// The worst-case high-to-low delay, if the output ever fell.
+    pub fn max_tphl(&self) -> Option<f64> {
+        self.tphl.iter().copied().reduce(f64::max)
+    }
+}
+
+impl GateTest {
+    fn combinations(&self) -> usize {
+        1 << self.inputs.len()
+    }
+
+    fn bit(&self, combination: usize, input: usize) -> bool {
+        (combination >> (self.inputs.len() - 1 - input)) & 1 == 1
+    }
+
+    /// Renders PWL voltage sources that step the inputs through every combination in order.
+    pub fn stimulus(&self) -> String {
+        let mut out = String::new();
+        for (j, node) in self.inputs.iter().enumerate() {
+            let level = |k: usize| if self.bit(k, j) { self.vdd } else { 0.0 };
+            write!(out, "Vgate_{} {} 0 PWL(0 {:e}", node, node, level(0)).unwrap();
+            for k in 1..self.combinations() {
+                let t = k as f64 * self.period;
+                write!(
+                    out,
+                    " {:e} {:e} {:e} {:e}",
+                    t,
+                    level(k - 1),
+                    t + self.edge,
+                    level(k)
+                )
+                .unwrap();
+            }
+            out.push_str(")\n");
+        }
+        out
+    }
+
+    /// Extracts the truth table and delays from an output waveform produced by
+    /// [`GateTest::stimulus`].
+    pub fn extract(&self, time: &[f64], output: &[f64]) -> GateTiming {
+        let half = 0.5 * self.vdd;
+        let signal = digitize(
+            time,
+            output,
+            &Thresholds {
+                low: half,
+                high: half,
+                min_pulse: 0.0,
+            },
+        );
+        let truth_table = (0..self.combinations())
+            .map(|k| signal.level_at((k as f64 + 0.95) * self.period) == Level::High)
+            .collect();
+        let mut timing = GateTiming {
+            truth_table,
+            ..GateTiming::default()
+        };
+        for tr in &signal.transitions {
+            let k = (tr.time / self.period).floor();
+            if k < 1.0 {
+                continue;
+            }
+            // inputs cross 50% halfway through their edge
+            let delay = tr.time - (k * self.period + 0.5 * self.edge);
+            match tr.level {
+                Level::High => timing.tplh.push(delay),
+                Level::Low => timing.tphl.push(delay),
+            }
+        }
+        timing
+    }
+}
+
+impl NgSpice {
+    /// Drives a logic gate through every input combination and extracts its behavior.
+    ///
+    /// `dut` holds the netlist lines for the gate and its supplies, without a title or `.end`.
+    /// Input sources are added automatically and must not already be present.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the simulation fails or produces no real output vector.
+    pub fn characterize_gate(dut: &str, test: &GateTest) -> Result<GateTiming, Error> {
+        let circuit = format!(
+            "* gate characterization\n{}\n{}.end\n",
+            dut.trim_end(),
+            test.stimulus()
+        );
+        let stop = test.combinations() as f64 * test.period;
+        let cmd = format!("tran {:e} {:e}", test.edge / 10.0, stop);
+        let sim: Simulation = NgSpice::simulate(&circuit, &cmd)?;
+        let (_, time) = sim.scale_vector().ok_or(Error::MissingScale)?;
+        let time = time.real().ok_or(Error::MissingScale)?;
+        let output = sim.real_vector(&test.output)?;
+        Ok(test.extract(time, output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_nand_behavior() {
+        let test = GateTest {
+            inputs: vec!["a".to_owned(), "b".to_owned()],
+            output: "y".to_owned(),
+            vdd: 1.0,
+            period: 10.0,
+            edge: 1.0,
+        };
+        let stim = test.stimulus();
+        assert!(stim.starts_with("Vgate_a a 0 PWL(0 0e0 1e1 0e0 1.1e1 0e0 2e1 0e0 2.1e1 1e0"));
+        // an ideal NAND with a 2 s delay after each input edge reaches 50%
+        let time: Vec<f64> = (0..=400).map(|i| i as f64 * 0.1).collect();
+        let output: Vec<f64> = time
+            .iter()
+            .map(|&t| {
+                let k = ((t - 2.5) / 10.0).floor().max(0.0) as usize;
+                if k == 3 {
+                    0.0
+                } else {
+                    1.0
+                }
+            })
+            .collect();
+        let timing = test.extract(&time, &output);
+        assert_eq!(timing.truth_table, vec![true, true, true, false]);
+        assert_eq!(timing.tphl.len(), 1);
+        assert!((timing.max_tphl().unwrap() - 2.0).abs() < 0.11);
+        assert!(timing.tplh.is_empty());
+    }
+}
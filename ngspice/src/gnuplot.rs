@@ -0,0 +1,215 @@
+// Copyright 2022 Andrew Morrow.
+// gnuplot.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Generates gnuplot data files and scripts from simulation results.
+
+use crate::control::vector_name;
+use crate::format::NumericFormat;
+use crate::kernels::magnitude_db;
+use crate::{DataType, Error, Simulation, VectorValues};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A gnuplot data table and the information needed to write a script that plots it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gnuplot {
+    /// Whitespace-separated columns: the scale, then one column per vector.
+    pub data: String,
+    pub x_label: String,
+    pub y_label: String,
+    /// Whether the x axis should be logarithmic, as for AC analyses.
+    pub log_x: bool,
+    /// The title of each data column after the scale.
+    pub titles: Vec<String>,
+}
+
+impl Simulation {
+    /// Collects the named vectors into a gnuplot data table.
+    ///
+    /// Names may be given as expressions like `v(out)` or `i(vdd)`. Complex vectors are
+    /// plotted as their magnitude in dB.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the simulation has no scale or any vector is missing.
+    pub fn to_gnuplot(&self, vectors: &[&str]) -> Result<Gnuplot, Error> {
+        self.to_gnuplot_with(vectors, &NumericFormat::default())
+    }
+
+    /// Like [`Simulation::to_gnuplot`], with numbers written in `format`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the simulation has no scale or any vector is missing.
+    pub fn to_gnuplot_with(
+        &self,
+        vectors: &[&str],
+        format: &NumericFormat,
+    ) -> Result<Gnuplot, Error> {
+        let (scale_name, _) = self.scale_vector().ok_or(Error::MissingScale)?;
+        let scale = self.scale_values().ok_or(Error::MissingScale)?;
+        let scale_type = &self.vectors[scale_name].datatype;
+        let mut columns: Vec<Vec<f64>> = Vec::new();
+        let mut types: Vec<&DataType> = Vec::new();
+        let mut complex = false;
+        for &expr in vectors {
+            let name = vector_name(expr);
+            let info = self
+                .vectors
+                .get(&name)
+                .ok_or_else(|| Error::MissingVector(expr.to_owned()))?;
+            types.push(&info.datatype);
+            columns.push(match &info.values {
+                VectorValues::Real(x) => x.to_vec(),
+                VectorValues::Complex(x) => {
+                    complex = true;
+                    magnitude_db(x)
+                }
+            });
+        }
+        let mut data = format!("# {} {}\n", scale_name, vectors.join(" "));
+        for (row, x) in scale.iter().enumerate() {
+            data.push_str(&format.format(*x));
+            for col in &columns {
+                data.push(' ');
+                data.push_str(&format.format(col.get(row).copied().unwrap_or(f64::NAN)));
+            }
+            data.push('\n');
+        }
+        let y_label = match types.first() {
+            _ if complex => "Magnitude (dB)".to_owned(),
+            Some(&t) if types.iter().all(|&x| x == t) => t.axis_label(),
+            _ => DataType::Unknown.axis_label(),
+        };
+        Ok(Gnuplot {
+            data,
+            x_label: scale_type.axis_label(),
+            y_label,
+            log_x: *scale_type == DataType::Frequency,
+            titles: vectors.iter().map(|&v| v.to_owned()).collect(),
+        })
+    }
+}
+
+impl Gnuplot {
+    /// Renders a gnuplot script that plots `data_file`, which must hold [`Gnuplot::data`].
+    pub fn script(&self, data_file: &str) -> String {
+        let mut script = String::new();
+        writeln!(script, "set xlabel \"{}\"", self.x_label).unwrap();
+        writeln!(script, "set ylabel \"{}\"", self.y_label).unwrap();
+        if self.log_x {
+            script.push_str("set logscale x\n");
+        }
+        script.push_str("set grid\n");
+        let plots: Vec<String> = self
+            .titles
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                format!(
+                    "\"{}\" using 1:{} with lines title \"{}\"",
+                    data_file,
+                    i + 2,
+                    t.replace('"', "'")
+                )
+            })
+            .collect();
+        writeln!(script, "plot {}", plots.join(", \\\n     ")).unwrap();
+        script
+    }
+
+    /// Writes `<stem>.dat` and `<stem>.gp`. Run `gnuplot -p <stem>.gp` to view the plot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either file cannot be written.
+    pub fn save<P: AsRef<Path>>(&self, stem: P) -> io::Result<()> {
+        let stem = stem.as_ref();
+        let data_path = stem.with_extension("dat");
+        fs::write(&data_path, &self.data)?;
+        let data_file = data_path
+            .file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        fs::write(stem.with_extension("gp"), self.script(&data_file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DataType, Simulation, VectorInfo, VectorValues};
+    use num_complex::Complex64;
+
+    #[test]
+    fn renders_data_and_script() {
+        let mut sim = Simulation::default();
+        sim.vectors.insert(
+            "time".to_owned(),
+            VectorInfo {
+                datatype: DataType::Time,
+                values: VectorValues::Real(vec![0.0, 1e-3].into()),
+                scale: None,
+            },
+        );
+        sim.vectors.insert(
+            "out".to_owned(),
+            VectorInfo {
+                datatype: DataType::Voltage,
+                values: VectorValues::Real(vec![0.0, 3.3].into()),
+                scale: None,
+            },
+        );
+        let plot = sim.to_gnuplot(&["v(out)"]).unwrap();
+        assert_eq!(plot.data, "# time v(out)\n0e0 0e0\n1e-3 3.3e0\n");
+        let script = plot.script("out.dat");
+        assert!(script.contains("set xlabel \"Time (s)\""));
+        assert!(script.contains("set ylabel \"Voltage (V)\""));
+        assert!(script.contains("plot \"out.dat\" using 1:2 with lines title \"v(out)\""));
+        assert!(!script.contains("logscale"));
+        assert!(sim.to_gnuplot(&["v(missing)"]).is_err());
+    }
+
+    #[test]
+    fn plots_ac_on_log_axis() {
+        // ngSPICE stores the frequency of an AC analysis as complex numbers
+        let mut sim = Simulation::default();
+        let freq = vec![Complex64::new(10.0, 0.0), Complex64::new(100.0, 0.0)];
+        let gain = vec![Complex64::new(1.0, 0.0), Complex64::new(0.1, 0.0)];
+        sim.vectors.insert(
+            "frequency".to_owned(),
+            VectorInfo {
+                datatype: DataType::Frequency,
+                values: VectorValues::Complex(freq.into()),
+                scale: None,
+            },
+        );
+        sim.vectors.insert(
+            "out".to_owned(),
+            VectorInfo {
+                datatype: DataType::Voltage,
+                values: VectorValues::Complex(gain.into()),
+                scale: None,
+            },
+        );
+        let plot = sim.to_gnuplot(&["v(out)"]).unwrap();
+        assert_eq!(plot.data, "# frequency v(out)\n1e1 0e0\n1e2 -2e1\n");
+        assert!(plot.log_x);
+        assert_eq!(plot.y_label, "Magnitude (dB)");
+    }
+}
@@ -0,0 +1,222 @@
+// Copyright 2022 Andrew Morrow.
+// graph.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The connectivity of a circuit as a graph of nets and elements.
+
+use crate::circuit::Circuit;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// A vertex of a [`Connectivity`] graph.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Vertex {
+    /// A node, in lowercase.
+    Net(String),
+    Element(String),
+}
+
+/// An edge of a [`Connectivity`] graph: one terminal of an element connected to a net.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Terminal {
+    /// The index of the element vertex.
+    pub element: usize,
+    /// The index of the net vertex.
+    pub net: usize,
+    /// The position of the terminal in the element line, starting at 0.
+    pub pin: usize,
+}
+
+/// A bipartite graph of a circuit's top-level nets and elements, with one edge per element
+/// terminal.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Connectivity {
+    pub vertices: Vec<Vertex>,
+    pub terminals: Vec<Terminal>,
+}
+
+impl Circuit {
+    /// Builds the connectivity graph of the top-level elements.
+    pub fn connectivity(&self) -> Connectivity {
+        let mut graph = Connectivity::default();
+        let mut nets: BTreeMap<String, usize> = BTreeMap::new();
+        for element in self.elements() {
+            let e = graph.vertices.len();
+            graph.vertices.push(Vertex::Element(element.name.clone()));
+            for (pin, node) in element.nodes.iter().enumerate() {
+                let node = node.to_ascii_lowercase();
+                let net = *nets.entry(node.clone()).or_insert_with(|| {
+                    graph.vertices.push(Vertex::Net(node));
+                    graph.vertices.len() - 1
+                });
+                graph.terminals.push(Terminal {
+                    element: e,
+                    net,
+                    pin,
+                });
+            }
+        }
+        graph
+    }
+}
+
+/// Escapes a string for use inside a double-quoted Graphviz ID.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl Circuit {
+    /// Renders the connectivity graph in Graphviz DOT format. Elements are boxes labelled with
+    /// their name and value, nets are ellipses, and ground is drawn as a filled point.
+    pub fn to_dot(&self) -> String {
+        let graph = self.connectivity();
+        let mut out = String::from("graph circuit {\n");
+        let elements: Vec<_> = self.elements().collect();
+        let mut next_element = 0;
+        for (i, vertex) in graph.vertices.iter().enumerate() {
+            match vertex {
+                Vertex::Element(name) => {
+                    // a literal \n is a line break in Graphviz labels
+                    let label = match elements[next_element].value() {
+                        Some(v) => format!("{}\\n{}", escape(name), escape(v)),
+                        None => escape(name),
+                    };
+                    next_element += 1;
+                    writeln!(out, "  v{} [shape=box, label=\"{}\"];", i, label).unwrap();
+                }
+                Vertex::Net(name) if name == "0" || name == "gnd" => {
+                    writeln!(
+                        out,
+                        "  v{} [shape=point, width=0.15, xlabel=\"{}\"];",
+                        i,
+                        escape(name)
+                    )
+                    .unwrap();
+                }
+                Vertex::Net(name) => {
+                    writeln!(out, "  v{} [shape=ellipse, label=\"{}\"];", i, escape(name)).unwrap();
+                }
+            }
+        }
+        for t in &graph.terminals {
+            writeln!(
+                out,
+                "  v{} -- v{} [taillabel=\"{}\"];",
+                t.element, t.net, t.pin
+            )
+            .unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+impl Connectivity {
+    fn find(&self, vertex: &Vertex) -> Option<usize> {
+        self.vertices.iter().position(|v| match (v, vertex) {
+            (Vertex::Net(a), Vertex::Net(b)) | (Vertex::Element(a), Vertex::Element(b)) => {
+                a.eq_ignore_ascii_case(b)
+            }
+            _ => false,
+        })
+    }
+
+    fn name(&self, idx: usize) -> &str {
+        match &self.vertices[idx] {
+            Vertex::Net(n) | Vertex::Element(n) => n,
+        }
+    }
+
+    /// The names of every element with a terminal on `net`, each listed once.
+    pub fn elements_on(&self, net: &str) -> Vec<&str> {
+        let mut result: Vec<&str> = match self.find(&Vertex::Net(net.to_owned())) {
+            Some(n) => self
+                .terminals
+                .iter()
+                .filter(|t| t.net == n)
+                .map(|t| self.name(t.element))
+                .collect(),
+            None => Vec::new(),
+        };
+        result.dedup();
+        result
+    }
+
+    /// The nets an element connects to, in terminal order.
+    pub fn nets_of(&self, element: &str) -> Vec<&str> {
+        match self.find(&Vertex::Element(element.to_owned())) {
+            Some(e) => self
+                .terminals
+                .iter()
+                .filter(|t| t.element == e)
+                .map(|t| self.name(t.net))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Nets with only one terminal, which are usually mistakes. ngSPICE rejects most of
+    /// them with a singular matrix error.
+    pub fn dangling_nets(&self) -> Vec<&str> {
+        (0..self.vertices.len())
+            .filter(|&i| matches!(self.vertices[i], Vertex::Net(_)))
+            .filter(|&i| self.terminals.iter().filter(|t| t.net == i).count() == 1)
+            .map(|i| self.name(i))
+            .collect()
+    }
+
+    /// Converts the graph to a petgraph graph with the same vertex indices, weighting each
+    /// edge with its terminal's pin number.
+    #[cfg(feature = "petgraph")]
+    pub fn to_petgraph(&self) -> petgraph::graph::UnGraph<Vertex, usize> {
+        let mut graph =
+            petgraph::graph::UnGraph::with_capacity(self.vertices.len(), self.terminals.len());
+        for v in &self.vertices {
+            graph.add_node(v.clone());
+        }
+        for t in &self.terminals {
+            graph.add_edge(
+                petgraph::graph::NodeIndex::new(t.element),
+                petgraph::graph::NodeIndex::new(t.net),
+                t.pin,
+            );
+        }
+        graph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit::Circuit;
+
+    #[test]
+    fn queries_connectivity() {
+        let circuit =
+            Circuit::parse("* t\nV1 in 0 DC 1\nR1 in out 1k\nR2 out 0 1k\nR3 out nc 1k\n.end");
+        let graph = circuit.connectivity();
+        assert_eq!(graph.elements_on("OUT"), vec!["R1", "R2", "R3"]);
+        assert_eq!(graph.nets_of("r1"), vec!["in", "out"]);
+        assert_eq!(graph.dangling_nets(), vec!["nc"]);
+        assert_eq!(graph.terminals.len(), 8);
+
+        let dot = circuit.to_dot();
+        assert!(dot.starts_with("graph circuit {\n  v0 [shape=box, label=\"V1\\nDC\"];\n"));
+        assert!(dot.contains("  v2 [shape=point, width=0.15, xlabel=\"0\"];\n"));
+        assert!(dot.contains("  v3 [shape=box, label=\"R1\\n1k\"];\n"));
+        assert!(dot.contains("  v3 -- v4 [taillabel=\"1\"];\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
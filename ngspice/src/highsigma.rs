@@ -0,0 +1,289 @@
+// Copyright 2022 Andrew Morrow.
+// highsigma.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Estimates rare failure probabilities, such as 6σ offsets, with far fewer runs than plain
+//! Monte Carlo would need.
+
+use crate::montecarlo::{apply_params, MonteCarlo};
+use crate::random::Rng;
+use crate::stats::normal_quantile;
+use crate::{Error, NgSpice, Simulation};
+use std::collections::BTreeMap;
+
+/// How a [`HighSigma`] estimate samples the parameter space.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Method {
+    /// Scaled-sigma sampling: run ordinary Monte Carlo with every standard deviation
+    /// multiplied by each scale, fit `ln P(s) = α + β ln s − γ / s²` to the failure rates,
+    /// and extrapolate to `s = 1`. Scales should be above 1, and each should fail often
+    /// enough to count.
+    ScaledSigma { scales: Vec<f64> },
+    /// Mean-shift importance sampling: find the failing point nearest nominal among
+    /// `presample` runs drawn with standard deviations multiplied by `scale`, then sample
+    /// around it and weight each run by its likelihood ratio.
+    Importance { presample: usize, scale: f64 },
+}
+
+/// A high-sigma failure probability estimator.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HighSigma {
+    pub method: Method,
+    /// Runs per scale for scaled-sigma sampling, or runs after the presample for importance
+    /// sampling.
+    pub runs: usize,
+    pub seed: u64,
+}
+
+/// The result of a [`HighSigma`] estimate.
+#[derive(Clone, Debug, PartialEq)]
+pub struct YieldEstimate {
+    /// The estimated probability that a run fails.
+    pub probability: f64,
+    /// The one-sided normal quantile with the same tail probability, e.g. 6 for about 1e-9.
+    /// Infinite if no failures were seen.
+    pub sigma: f64,
+    /// The standard error of `probability`, when the method provides one.
+    pub standard_error: Option<f64>,
+    /// Every simulation run, including any presample.
+    pub runs: usize,
+    /// How many runs failed.
+    pub failures: usize,
+}
+
+impl YieldEstimate {
+    fn new(probability: f64, standard_error: Option<f64>, runs: usize, failures: usize) -> Self {
+        let sigma = if probability <= 0.0 {
+            f64::INFINITY
+        } else if probability >= 1.0 {
+            f64::NEG_INFINITY
+        } else {
+            -normal_quantile(probability)
+        };
+        YieldEstimate {
+            probability,
+            sigma,
+            standard_error,
+            runs,
+            failures,
+        }
+    }
+}
+
+impl HighSigma {
+    /// Scaled-sigma sampling at scales 2, 2.5, 3, 3.5, and 4.
+    pub fn scaled_sigma(runs: usize, seed: u64) -> Self {
+        HighSigma {
+            method: Method::ScaledSigma {
+                scales: vec![2.0, 2.5, 3.0, 3.5, 4.0],
+            },
+            runs,
+            seed,
+        }
+    }
+
+    /// Importance sampling after a presample of `runs` runs at 3σ.
+    pub fn importance(runs: usize, seed: u64) -> Self {
+        HighSigma {
+            method: Method::Importance {
+                presample: runs,
+                scale: 3.0,
+            },
+            runs,
+            seed,
+        }
+    }
+
+    /// Estimates the probability that a run of `mc`'s circuit fails. `fails` decides from
+    /// each simulation whether the run failed, e.g. whether an offset exceeds its limit.
+    ///
+    /// Every parameter `mc` varies is sampled, in its scope, but the number of runs in `mc`
+    /// and its seed are not used.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first simulation error.
+    pub fn run<F>(
+        &self,
+        mc: &MonteCarlo,
+        circuit: &str,
+        command: &str,
+        mut fails: F,
+    ) -> Result<YieldEstimate, Error>
+    where
+        F: FnMut(&Simulation) -> bool,
+    {
+        self.estimate(mc, |values| {
+            let sim = NgSpice::simulate(&apply_params(circuit, values), command)?;
+            Ok(fails(&sim))
+        })
+    }
+
+    /// Like [`HighSigma::run`], but with the pass/fail decision made directly from each run's
+    /// parameter values, which lets expensive evaluations be customized or cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error from `fails`.
+    pub fn estimate<F>(&self, mc: &MonteCarlo, mut fails: F) -> Result<YieldEstimate, Error>
+    where
+        F: FnMut(&BTreeMap<String, f64>) -> Result<bool, Error>,
+    {
+        let mut rng = Rng::new(self.seed);
+        let dims = mc.dimensions();
+        let draw = |rng: &mut Rng, center: &[f64], scale: f64| -> Vec<f64> {
+            center.iter().map(|c| c + scale * rng.normal()).collect()
+        };
+        let nominal = vec![0.0; dims];
+        match &self.method {
+            Method::ScaledSigma { scales } => {
+                let mut points = Vec::new();
+                let mut failures = 0;
+                for &s in scales {
+                    let mut failed = 0;
+                    for _ in 0..self.runs {
+                        let z = draw(&mut rng, &nominal, s);
+                        if fails(&mc.values_at(&z))? {
+                            failed += 1;
+                        }
+                    }
+                    failures += failed;
+                    if failed > 0 {
+                        points.push((s, (failed as f64 / self.runs as f64).ln()));
+                    }
+                }
+                let probability = extrapolate(&points).unwrap_or(0.0);
+                Ok(YieldEstimate::new(
+                    probability,
+                    None,
+                    scales.len() * self.runs,
+                    failures,
+                ))
+            }
+            Method::Importance { presample, scale } => {
+                let mut shift: Option<Vec<f64>> = None;
+                let norm = |z: &[f64]| z.iter().map(|x| x * x).sum::<f64>();
+                let mut failures = 0;
+                for _ in 0..*presample {
+                    let z = draw(&mut rng, &nominal, *scale);
+                    if fails(&mc.values_at(&z))? {
+                        failures += 1;
+                        if shift.as_ref().is_none_or(|s| norm(&z) < norm(s)) {
+                            shift = Some(z);
+                        }
+                    }
+                }
+                let shift = match shift {
+                    Some(s) => s,
+                    None => return Ok(YieldEstimate::new(0.0, None, *presample, 0)),
+                };
+                let offset = norm(&shift) / 2.0;
+                let mut weights = Vec::with_capacity(self.runs);
+                for _ in 0..self.runs {
+                    let z = draw(&mut rng, &shift, 1.0);
+                    if fails(&mc.values_at(&z))? {
+                        failures += 1;
+                        // the ratio of the nominal density to the shifted one
+                        let dot: f64 = z.iter().zip(&shift).map(|(a, b)| a * b).sum();
+                        weights.push((offset - dot).exp());
+                    } else {
+                        weights.push(0.0);
+                    }
+                }
+                let n = self.runs.max(1) as f64;
+                let mean = weights.iter().sum::<f64>() / n;
+                let variance = weights.iter().map(|w| (w - mean).powi(2)).sum::<f64>() / n;
+                Ok(YieldEstimate::new(
+                    mean,
+                    Some((variance / n).sqrt()),
+                    presample + self.runs,
+                    failures,
+                ))
+            }
+        }
+    }
+}
+
+/// Fits `ln P = α + β ln s − γ / s²` to `(s, ln P)` points by least squares and evaluates it
+/// at `s = 1`. With fewer than three points, `β` and `γ` cannot both be fitted, so the fit
+/// falls back to `ln P = α − γ / s²`.
+fn extrapolate(points: &[(f64, f64)]) -> Option<f64> {
+    let rows: Vec<Vec<f64>> = points
+        .iter()
+        .map(|&(s, _)| {
+            if points.len() >= 3 {
+                vec![1.0, s.ln(), -1.0 / (s * s)]
+            } else {
+                vec![1.0, -1.0 / (s * s)]
+            }
+        })
+        .collect();
+    if points.len() < 2 {
+        return None;
+    }
+    let k = rows[0].len();
+    // solve the normal equations by Gaussian elimination
+    let mut a = vec![vec![0.0; k + 1]; k];
+    for (row, &(_, y)) in rows.iter().zip(points) {
+        for i in 0..k {
+            for j in 0..k {
+                a[i][j] += row[i] * row[j];
+            }
+            a[i][k] += row[i] * y;
+        }
+    }
+    for col in 0..k {
+        let pivot = (col..k).max_by(|&x, &y| a[x][col].abs().total_cmp(&a[y][col].abs()))?;
+        a.swap(col, pivot);
+        if a[col][col].abs() < 1e-300 {
+            return None;
+        }
+        let pivot_row = a[col].clone();
+        for (r, row) in a.iter_mut().enumerate() {
+            if r != col {
+                let f = row[col] / pivot_row[col];
+                for (x, p) in row.iter_mut().zip(&pivot_row).skip(col) {
+                    *x -= f * p;
+                }
+            }
+        }
+    }
+    let coefficients: Vec<f64> = (0..k).map(|i| a[i][k] / a[i][i]).collect();
+    // at s = 1, ln s vanishes and 1/s² is 1
+    let ln_p = coefficients[0] - coefficients[k - 1];
+    Some(ln_p.exp().min(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::montecarlo::Mismatch;
+
+    #[test]
+    fn estimates_five_sigma_tail() {
+        let mc = MonteCarlo::new(0, 0).mismatch("offset", Mismatch::new(0.0, 1e-3));
+        let fails = |v: &BTreeMap<String, f64>| Ok(v["offset"] > 5e-3);
+        // P(Z > 5) = 2.8665e-7
+        let is = HighSigma::importance(2000, 3).estimate(&mc, fails).unwrap();
+        assert!((is.probability / 2.8665e-7 - 1.0).abs() < 0.15, "{:?}", is);
+        assert!((is.sigma - 5.0).abs() < 0.05);
+        let sss = HighSigma::scaled_sigma(4000, 3)
+            .estimate(&mc, fails)
+            .unwrap();
+        assert!((sss.sigma - 5.0).abs() < 0.5, "{:?}", sss);
+        assert_eq!(sss.runs, 20000);
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright 2022 Andrew Morrow.
+// hooks.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! User-registered hooks that run at fixed points of every simulation.
+
+use crate::{NgSpice, Simulation};
+use once_cell::sync::OnceCell;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+type PreLoadHook = Arc<dyn Fn(&mut String, &str) + Send + Sync>;
+type PostExtractHook = Arc<dyn Fn(&mut Simulation) + Send + Sync>;
+
+#[derive(Default)]
+struct Hooks {
+    pre_load: Vec<PreLoadHook>,
+    post_extract: Vec<PostExtractHook>,
+}
+
+static HOOKS: OnceCell<Mutex<Hooks>> = OnceCell::new();
+
+fn hooks() -> &'static Mutex<Hooks> {
+    HOOKS.get_or_init(|| Mutex::new(Hooks::default()))
+}
+
+impl NgSpice {
+    /// Registers a hook that runs before every circuit is validated and loaded.
+    ///
+    /// The hook receives the circuit listing, which it may modify (e.g. to add standard
+    /// `.options` lines), and the simulation command. Hooks run in registration order.
+    pub fn on_pre_load<F>(hook: F)
+    where
+        F: Fn(&mut String, &str) + Send + Sync + 'static,
+    {
+        lock(hooks()).pre_load.push(Arc::new(hook));
+    }
+
+    /// Registers a hook that runs after the output vectors of every simulation are extracted.
+    ///
+    /// The hook may inspect or modify the results before they are returned to the caller.
+    /// Hooks run in registration order, after ngSPICE has been released, so they may start
+    /// other simulations.
+    pub fn on_post_extract<F>(hook: F)
+    where
+        F: Fn(&mut Simulation) + Send + Sync + 'static,
+    {
+        lock(hooks()).post_extract.push(Arc::new(hook));
+    }
+
+    /// Removes every registered hook. Hooks already running for a simulation still finish.
+    pub fn clear_hooks() {
+        clear(hooks());
+    }
+}
+
+fn lock(hooks: &Mutex<Hooks>) -> MutexGuard<'_, Hooks> {
+    // a panicking hook leaves the hook lists intact, so poisoning is harmless here
+    hooks.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn clear(hooks: &Mutex<Hooks>) {
+    let mut hooks = lock(hooks);
+    hooks.pre_load.clear();
+    hooks.post_extract.clear();
+}
+
+pub(crate) fn run_pre_load(circuit: &mut String, command: &str) {
+    pre_load(hooks(), circuit, command);
+}
+
+pub(crate) fn run_post_extract(sim: &mut Simulation) {
+    post_extract(hooks(), sim);
+}
+
+fn pre_load(hooks: &Mutex<Hooks>, circuit: &mut String, command: &str) {
+    // run a copy of the list unlocked, so hooks may start simulations or register hooks
+    let pre_load = lock(hooks).pre_load.clone();
+    for hook in pre_load {
+        hook(circuit, command);
+    }
+}
+
+fn post_extract(hooks: &Mutex<Hooks>, sim: &mut Simulation) {
+    let post_extract = lock(hooks).post_extract.clone();
+    for hook in post_extract {
+        hook(sim);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hooks_run_in_order() {
+        // a private registry, so the hooks do not touch other tests' simulations
+        let registry: &'static Mutex<Hooks> = Box::leak(Box::default());
+        let mut hooks = lock(registry);
+        hooks.pre_load.push(Arc::new(|circuit, cmd| {
+            circuit.push_str(&format!("* before {}\n", cmd));
+        }));
+        hooks
+            .pre_load
+            .push(Arc::new(|circuit, _| circuit.push_str("* second\n")));
+        // a nested simulation runs the pre-load hooks again from inside this one
+        hooks.post_extract.push(Arc::new(move |sim| {
+            pre_load(registry, &mut sim.stdout, "nested");
+            sim.stdout.push_str("extracted");
+        }));
+        drop(hooks);
+        let mut circuit = String::new();
+        pre_load(registry, &mut circuit, "op");
+        assert_eq!(circuit, "* before op\n* second\n");
+        let mut sim = Simulation::default();
+        post_extract(registry, &mut sim);
+        assert_eq!(sim.stdout, "* before nested\n* second\nextracted");
+        clear(registry);
+        pre_load(registry, &mut circuit, "op");
+        assert_eq!(circuit, "* before op\n* second\n");
+    }
+}
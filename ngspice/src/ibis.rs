@@ -0,0 +1,293 @@
+// Copyright 2022 Andrew Morrow.
+// ibis.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Converts IBIS I/O buffer data into behavioral SPICE subcircuits.
+
+use std::fmt::Write;
+
+/// Which column of an IBIS table to use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Corner {
+    Typ,
+    Min,
+    Max,
+}
+
+impl Corner {
+    fn column(self) -> usize {
+        match self {
+            Corner::Typ => 0,
+            Corner::Min => 1,
+            Corner::Max => 2,
+        }
+    }
+}
+
+/// An `(volts, amps)` table. Positive current flows into the pad, as in IBIS.
+pub type IvTable = Vec<(f64, f64)>;
+
+/// The 20% to 80% transition of a driver into its test load.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ramp {
+    /// In volts.
+    pub dv: f64,
+    /// In seconds.
+    pub dt: f64,
+}
+
+/// One `[Model]` of an IBIS file at a single corner.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IbisModel {
+    pub name: String,
+    /// `Output`, `Input`, `I/O`, and so on, as written in the file.
+    pub model_type: String,
+    /// The die capacitance, in farads.
+    pub c_comp: f64,
+    pub voltage_range: Option<f64>,
+    /// Referenced to the supply: the voltage is `Vcc - Vpad`.
+    pub pullup: IvTable,
+    pub pulldown: IvTable,
+    pub gnd_clamp: IvTable,
+    /// Referenced to the supply: the voltage is `Vcc - Vpad`.
+    pub power_clamp: IvTable,
+    pub rising: Option<Ramp>,
+    pub falling: Option<Ramp>,
+}
+
+/// Parses an IBIS number such as `0.36n`, `1.57V`, or `2.2M` (mega). Returns `None` for `NA`.
+fn number(token: &str) -> Option<f64> {
+    let end = token
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E')))
+        .unwrap_or(token.len());
+    // an exponent marker with no digits after it is really the start of a unit
+    let end = match token[..end].rfind(['e', 'E']) {
+        Some(e) if e + 1 == end => e,
+        _ => end,
+    };
+    let value: f64 = token[..end].parse().ok()?;
+    let scale = match token[end..].chars().next() {
+        Some('T') => 1e12,
+        Some('G') => 1e9,
+        Some('M') => 1e6,
+        Some('k') => 1e3,
+        Some('m') => 1e-3,
+        Some('u') => 1e-6,
+        Some('n') => 1e-9,
+        Some('p') => 1e-12,
+        Some('f') => 1e-15,
+        _ => 1.0,
+    };
+    Some(value * scale)
+}
+
+/// Picks a column from `typ min max` values, falling back to `typ` where the corner is `NA`.
+fn pick(values: &[&str], corner: Corner) -> Option<f64> {
+    values
+        .get(corner.column())
+        .and_then(|v| number(v))
+        .or_else(|| values.first().and_then(|v| number(v)))
+}
+
+fn ramp(values: &[&str], corner: Corner) -> Option<Ramp> {
+    let parse = |v: &&str| {
+        let (dv, dt) = v.split_once('/')?;
+        Some(Ramp {
+            dv: number(dv)?,
+            dt: number(dt)?,
+        })
+    };
+    values
+        .get(corner.column())
+        .and_then(parse)
+        .or_else(|| values.first().and_then(parse))
+}
+
+/// Parses every `[Model]` in an IBIS file, taking values from the given corner.
+///
+/// Only the data needed by [`IbisModel::subcircuit`] is read; other keywords are ignored, as
+/// are rows that cannot be parsed.
+pub fn parse(text: &str, corner: Corner) -> Vec<IbisModel> {
+    let mut models: Vec<IbisModel> = Vec::new();
+    let mut section = String::new();
+    for line in text.lines() {
+        let line = line.split('|').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix('[') {
+            let (keyword, arg) = rest.split_once(']').unwrap_or((rest, ""));
+            section = keyword.trim().to_ascii_lowercase().replace('_', " ");
+            if section == "model" {
+                models.push(IbisModel {
+                    name: arg.trim().to_owned(),
+                    ..IbisModel::default()
+                });
+            } else if section == "voltage range" {
+                if let Some(model) = models.last_mut() {
+                    let values: Vec<&str> = arg.split_whitespace().collect();
+                    model.voltage_range = pick(&values, corner);
+                }
+            }
+            continue;
+        }
+        let Some(model) = models.last_mut() else {
+            continue;
+        };
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match section.as_str() {
+            "model" => {
+                let (key, values) = match line.split_once('=') {
+                    Some((k, v)) => (k.trim(), v.split_whitespace().collect()),
+                    None => (tokens[0], tokens[1..].to_vec()),
+                };
+                match key.to_ascii_lowercase().as_str() {
+                    "model_type" => model.model_type = values.join(" "),
+                    "c_comp" => model.c_comp = pick(&values, corner).unwrap_or(0.0),
+                    _ => {}
+                }
+            }
+            "pullup" | "pulldown" | "gnd clamp" | "power clamp" if tokens.len() >= 2 => {
+                let (Some(v), Some(i)) = (number(tokens[0]), pick(&tokens[1..], corner)) else {
+                    continue;
+                };
+                let table = match section.as_str() {
+                    "pullup" => &mut model.pullup,
+                    "pulldown" => &mut model.pulldown,
+                    "gnd clamp" => &mut model.gnd_clamp,
+                    _ => &mut model.power_clamp,
+                };
+                table.push((v, i));
+            }
+            "ramp" if tokens.len() >= 2 => match tokens[0].to_ascii_lowercase().as_str() {
+                "dv/dt_r" => model.rising = ramp(&tokens[1..], corner),
+                "dv/dt_f" => model.falling = ramp(&tokens[1..], corner),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    for model in &mut models {
+        for table in [
+            &mut model.pullup,
+            &mut model.pulldown,
+            &mut model.gnd_clamp,
+            &mut model.power_clamp,
+        ] {
+            table.sort_by(|a, b| a.0.total_cmp(&b.0));
+        }
+    }
+    models
+}
+
+fn pwl(out: &mut String, control: &str, table: &IvTable) {
+    write!(out, "pwl({}", control).unwrap();
+    for (v, i) in table {
+        write!(out, ", {:e}, {:e}", v, i).unwrap();
+    }
+    out.push(')');
+}
+
+impl IbisModel {
+    /// Whether the model drives its pad.
+    pub fn is_driver(&self) -> bool {
+        !self.pullup.is_empty() || !self.pulldown.is_empty()
+    }
+
+    /// Renders the model as a subcircuit with ports `pad`, `in`, `vcc`, and `gnd`.
+    ///
+    /// A logic level of 1 V on `in` turns the pullup on and the pulldown off. The switch
+    /// between them is an RC whose 20% to 80% time matches the rising ramp, so edge rates
+    /// track the IBIS data only approximately. `in` is left unconnected inside receivers.
+    pub fn subcircuit(&self, name: &str) -> String {
+        let mut out = format!(".subckt {} pad in vcc gnd\n", name);
+        writeln!(out, "Ccomp pad gnd {:e}", self.c_comp).unwrap();
+        if self.is_driver() {
+            // an RC crosses 20% to 80% in ln(4) time constants
+            let dt = self.rising.or(self.falling).map_or(1e-9, |r| r.dt);
+            writeln!(out, "Bsw sw0 gnd V=v(in,gnd)").unwrap();
+            writeln!(out, "Rsw sw0 sw 1e3").unwrap();
+            writeln!(out, "Csw sw gnd {:e}", dt / 4f64.ln() / 1e3).unwrap();
+        } else {
+            writeln!(out, "Rin in gnd 1e12").unwrap();
+        }
+        let mut source = |line: &str, gate: &str, control: &str, table: &IvTable| {
+            if table.is_empty() {
+                return;
+            }
+            write!(out, "{}I={}", line, gate).unwrap();
+            pwl(&mut out, control, table);
+            out.push('\n');
+        };
+        source("Bpu pad vcc ", "v(sw,gnd)*", "v(vcc,pad)", &self.pullup);
+        source(
+            "Bpd pad gnd ",
+            "(1-v(sw,gnd))*",
+            "v(pad,gnd)",
+            &self.pulldown,
+        );
+        source("Bgc pad gnd ", "", "v(pad,gnd)", &self.gnd_clamp);
+        source("Bpc pad vcc ", "", "v(vcc,pad)", &self.power_clamp);
+        writeln!(out, ".ends {}", name).unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_driver() {
+        let models = parse(
+            "[IBIS Ver] 4.2
+[Model] drv | a 3.3 V driver
+Model_type Output
+C_comp 2.0pF 1.5pF NA
+[Voltage Range] 3.3V 3.0V 3.6V
+[Pulldown]
+| voltage I(typ) I(min) I(max)
+ 3.3  40m  30m  50m
+-3.3 -40m -30m -50m
+ 0     0    0    0
+[Pullup]
+-3.3  40m  30m  50m
+ 3.3 -40m -30m -50m
+[GND Clamp]
+-1.0 -100m NA NA
+[Ramp]
+dV/dt_r 1.57/0.36n 1.2/0.5n NA
+R_load = 50
+",
+            Corner::Min,
+        );
+        assert_eq!(models.len(), 1);
+        let drv = &models[0];
+        assert_eq!(drv.model_type, "Output");
+        assert_eq!(drv.c_comp, 1.5e-12);
+        assert_eq!(drv.voltage_range, Some(3.0));
+        assert_eq!(drv.pulldown[0], (-3.3, -30e-3));
+        assert_eq!(drv.gnd_clamp, vec![(-1.0, -0.1)]);
+        assert_eq!(drv.rising.unwrap().dv, 1.2);
+        let subckt = drv.subcircuit("drv");
+        assert!(subckt.starts_with(".subckt drv pad in vcc gnd\nCcomp pad gnd 1.5e-12\n"));
+        assert!(subckt.contains(
+            "Bpd pad gnd I=(1-v(sw,gnd))*pwl(v(pad,gnd), -3.3e0, -3e-2, 0e0, 0e0, 3.3e0, 3e-2)\n"
+        ));
+        assert!(subckt.contains("Bgc pad gnd I=pwl(v(pad,gnd), -1e0, -1e-1)\n"));
+        assert!(!subckt.contains("Bpc"));
+    }
+}
@@ -0,0 +1,171 @@
+// Copyright 2022 Andrew Morrow.
+// identify.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Estimates small-signal frequency response from a transient step response.
+//!
+//! This is useful for switching circuits, where ngSPICE's AC analysis sees only one switch
+//! state and an averaged model may not be available.
+
+use crate::{Error, Simulation};
+use num_complex::Complex64;
+use std::f64::consts::PI;
+
+/// Normalizes `response` so it goes from 0 before the step to 1 at the end of the waveform.
+fn normalize(time: &[f64], response: &[f64], step_time: f64) -> Option<(Vec<f64>, Vec<f64>)> {
+    let start = time.partition_point(|&t| t < step_time).saturating_sub(1);
+    if time.len() < start + 2 {
+        return None;
+    }
+    let (y0, y1) = (response[start], response[response.len() - 1]);
+    if y1 == y0 {
+        return None;
+    }
+    Some((
+        time[start..].iter().map(|&t| t - step_time).collect(),
+        response[start..]
+            .iter()
+            .map(|&y| (y - y0) / (y1 - y0))
+            .collect(),
+    ))
+}
+
+/// Estimates the frequency response of a system from its response to a step at `step_time`.
+///
+/// The step response must have settled by the end of the waveform; the result is normalized
+/// to the final value, so it is 1 at DC. Each frequency is in Hz.
+pub fn step_frequency_response(
+    time: &[f64],
+    response: &[f64],
+    step_time: f64,
+    frequencies: &[f64],
+) -> Vec<Complex64> {
+    let (t, y) = match normalize(time, response, step_time) {
+        Some(x) => x,
+        None => return vec![Complex64::new(f64::NAN, f64::NAN); frequencies.len()],
+    };
+    // the transform of the impulse response, i.e. the step's derivative, summed interval by
+    // interval so non-uniform time steps are handled exactly
+    frequencies
+        .iter()
+        .map(|&f| {
+            let w = 2.0 * PI * f;
+            (1..t.len())
+                .map(|i| {
+                    let mid = 0.5 * (t[i] + t[i - 1]);
+                    (y[i] - y[i - 1]) * Complex64::from_polar(1.0, -w * mid)
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// A second-order model fitted to a step response.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SecondOrderFit {
+    /// Natural frequency, in Hz.
+    pub natural_frequency: f64,
+    pub damping: f64,
+    /// The closed-loop -3 dB bandwidth, in Hz.
+    pub bandwidth: f64,
+    /// The phase margin of the equivalent unity-feedback loop, in degrees.
+    pub phase_margin: f64,
+}
+
+/// Fits a second-order model to the response to a step at `step_time`.
+///
+/// The damping comes from the overshoot and the natural frequency from the time of the peak.
+/// A response without overshoot is treated as first-order: the bandwidth comes from the 10% to
+/// 90% rise time and the phase margin is 90°. Returns `None` if the response never changes.
+pub fn fit_second_order(time: &[f64], response: &[f64], step_time: f64) -> Option<SecondOrderFit> {
+    let (t, y) = normalize(time, response, step_time)?;
+    let (peak, &max) = y.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1))?;
+    let overshoot = max - 1.0;
+    if overshoot <= 1e-6 {
+        let cross = |level: f64| t[y.partition_point(|&v| v < level).min(t.len() - 1)];
+        let rise = cross(0.9) - cross(0.1);
+        let bandwidth = 0.35 / rise;
+        return Some(SecondOrderFit {
+            natural_frequency: bandwidth,
+            damping: 1.0,
+            bandwidth,
+            phase_margin: 90.0,
+        });
+    }
+    let ln = overshoot.ln();
+    let zeta = -ln / (PI * PI + ln * ln).sqrt();
+    let wn = PI / (t[peak] * (1.0 - zeta * zeta).sqrt());
+    let z2 = zeta * zeta;
+    let wb = wn * (1.0 - 2.0 * z2 + (4.0 * z2 * z2 - 4.0 * z2 + 2.0).sqrt()).sqrt();
+    let pm = (2.0 * zeta / ((1.0 + 4.0 * z2 * z2).sqrt() - 2.0 * z2).sqrt()).atan();
+    Some(SecondOrderFit {
+        natural_frequency: wn / (2.0 * PI),
+        damping: zeta,
+        bandwidth: wb / (2.0 * PI),
+        phase_margin: pm.to_degrees(),
+    })
+}
+
+impl Simulation {
+    /// Fits a second-order model to the response of the named vector to a step at
+    /// `step_time`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vector or the scale is missing, or [`Error::MissingVector`] if
+    /// the vector does not change after the step.
+    pub fn fit_step_response(&self, vector: &str, step_time: f64) -> Result<SecondOrderFit, Error> {
+        let (_, scale) = self.scale_vector().ok_or(Error::MissingScale)?;
+        let scale = scale.real().ok_or(Error::MissingScale)?;
+        fit_second_order(scale, self.real_vector(vector)?, step_time)
+            .ok_or_else(|| Error::MissingVector(vector.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifies_second_order_system() {
+        let (zeta, wn): (f64, f64) = (0.5, 2.0 * PI);
+        let wd = wn * (1.0 - zeta * zeta).sqrt();
+        let time: Vec<f64> = (0..=10000).map(|i| i as f64 * 1e-3).collect();
+        // step at t = 1
+        let y: Vec<f64> = time
+            .iter()
+            .map(|&t| {
+                let t = t - 1.0;
+                if t <= 0.0 {
+                    return 0.0;
+                }
+                1.0 - (-zeta * wn * t).exp() / (1.0 - zeta * zeta).sqrt()
+                    * (wd * t + zeta.acos()).sin()
+            })
+            .collect();
+        let fit = fit_second_order(&time, &y, 1.0).unwrap();
+        assert!((fit.damping - 0.5).abs() < 1e-3);
+        assert!((fit.natural_frequency - 1.0).abs() < 1e-2);
+        assert!((fit.phase_margin - 51.8).abs() < 0.1);
+        assert!((fit.bandwidth - 1.27).abs() < 0.01);
+
+        let h = step_frequency_response(&time, &y, 1.0, &[0.0, 1.0]);
+        assert!((h[0].norm() - 1.0).abs() < 1e-9);
+        // at the natural frequency, H = 1 / (2j zeta)
+        assert!((h[1].norm() - 1.0).abs() < 1e-2);
+        assert!((h[1].arg().to_degrees() + 90.0).abs() < 1.0);
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright 2022 Andrew Morrow.
+// impulse.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Converts frequency responses into impulse and step responses.
+
+use crate::analysis::AcResult;
+use crate::kernels::interpolate_all;
+use crate::spectrum::{fft, ifft};
+use crate::Error;
+use num_complex::Complex64;
+
+/// How to treat the part of an impulse response that the inverse transform places before
+/// time zero.
+///
+/// A response sampled only up to a finite frequency is rarely exactly causal, and any energy
+/// before time zero wraps around to the end of the record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Causality {
+    /// Keep the record as the inverse transform produces it.
+    None,
+    /// Zero the second half of the record, where the wrapped negative times are.
+    Truncate,
+    /// Replace the phase with the minimum phase implied by the magnitude, which is causal by
+    /// construction. This is the right choice for magnitude-only data.
+    MinimumPhase,
+}
+
+/// The time-domain response of a linear system, sampled uniformly from time zero.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeResponse {
+    pub time: Vec<f64>,
+    /// The impulse response, in units of the frequency response per second.
+    pub impulse: Vec<f64>,
+    /// The response to a unit step at time zero.
+    pub step: Vec<f64>,
+    /// The fraction of the impulse response's energy that fell in the second half of the
+    /// record before causality was enforced. Large values mean the frequency response is
+    /// under-sampled, non-causal, or has a delay longer than the record.
+    pub noncausal: f64,
+}
+
+/// Converts a frequency response into impulse and step responses.
+///
+/// `frequency` must be ascending, as in an AC analysis, but need not be uniform or start at
+/// DC: the response is interpolated linearly onto `points` uniform bins from DC to the
+/// highest frequency, and held at its first value below the lowest frequency. `points` is
+/// rounded up to a power of two. The record is `points` samples long, with a time step of
+/// `1 / (2 f_max)`.
+///
+/// # Panics
+///
+/// Panics if `frequency` and `response` have different lengths or fewer than two points, or
+/// if `points` is less than 4.
+pub fn time_response(
+    frequency: &[f64],
+    response: &[Complex64],
+    points: usize,
+    causality: Causality,
+) -> TimeResponse {
+    assert_eq!(
+        frequency.len(),
+        response.len(),
+        "one response per frequency"
+    );
+    assert!(frequency.len() >= 2, "need at least two frequencies");
+    assert!(points >= 4, "need at least four points");
+    let n = points.next_power_of_two();
+    let f_max = frequency[frequency.len() - 1];
+    let bins: Vec<f64> = (0..=n / 2)
+        .map(|k| k as f64 * f_max / (n / 2) as f64)
+        .collect();
+    let (re, im): (Vec<f64>, Vec<f64>) = response.iter().map(|c| (c.re, c.im)).unzip();
+    let re = interpolate_all(frequency, &re, &bins);
+    let im = interpolate_all(frequency, &im, &bins);
+    let mut spectrum = vec![Complex64::new(0.0, 0.0); n];
+    for k in 0..=n / 2 {
+        spectrum[k] = Complex64::new(re[k], im[k]);
+    }
+    // a real impulse response needs a real DC and Nyquist bin and a Hermitian spectrum
+    spectrum[0].im = 0.0;
+    spectrum[n / 2].im = 0.0;
+    for k in 1..n / 2 {
+        spectrum[n - k] = spectrum[k].conj();
+    }
+    let mut samples: Vec<f64> = ifft(&spectrum).iter().map(|c| c.re).collect();
+    let energy = |x: &[f64]| x.iter().map(|v| v * v).sum::<f64>();
+    let total = energy(&samples);
+    let noncausal = if total > 0.0 {
+        energy(&samples[n / 2..]) / total
+    } else {
+        0.0
+    };
+    match causality {
+        Causality::None => {}
+        Causality::Truncate => samples[n / 2..].iter_mut().for_each(|x| *x = 0.0),
+        Causality::MinimumPhase => samples = minimum_phase(&spectrum),
+    }
+    let dt = 1.0 / (2.0 * f_max);
+    let step = samples
+        .iter()
+        .scan(0.0, |sum, x| {
+            *sum += x;
+            Some(*sum)
+        })
+        .collect();
+    TimeResponse {
+        time: (0..n).map(|i| i as f64 * dt).collect(),
+        impulse: samples.iter().map(|x| x / dt).collect(),
+        step,
+        noncausal,
+    }
+}
+
+/// The impulse response of the minimum-phase system with the same magnitude as `spectrum`,
+/// found by folding the real cepstrum onto positive times.
+fn minimum_phase(spectrum: &[Complex64]) -> Vec<f64> {
+    let n = spectrum.len();
+    let log_magnitude: Vec<Complex64> = spectrum
+        .iter()
+        .map(|c| Complex64::new(c.norm().max(f64::MIN_POSITIVE).ln(), 0.0))
+        .collect();
+    let cepstrum = ifft(&log_magnitude);
+    let mut folded = vec![Complex64::new(0.0, 0.0); n];
+    folded[0] = cepstrum[0];
+    for k in 1..n / 2 {
+        folded[k] = cepstrum[k] * 2.0;
+    }
+    folded[n / 2] = cepstrum[n / 2];
+    let spectrum: Vec<Complex64> = fft(&folded).iter().map(|c| c.exp()).collect();
+    ifft(&spectrum).iter().map(|c| c.re).collect()
+}
+
+impl AcResult {
+    /// The impulse and step responses of a vector expression such as `v(out)`, for
+    /// comparing an AC analysis with a transient one. See [`time_response`].
+    ///
+    /// The AC source should have unit magnitude, so that the response is a transfer
+    /// function.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingVector`] if there is no such vector.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the analysis has fewer than two points, or if `points` is less than 4.
+    pub fn time_response(
+        &self,
+        expr: &str,
+        points: usize,
+        causality: Causality,
+    ) -> Result<TimeResponse, Error> {
+        Ok(time_response(
+            self.frequency(),
+            self.response(expr)?,
+            points,
+            causality,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovers_rc_step() {
+        // a single pole at 1 kHz, swept logarithmically like an AC analysis
+        let fc = 1e3;
+        let frequency: Vec<f64> = (0..=600).map(|i| 10f64.powf(i as f64 / 100.0)).collect();
+        let response: Vec<Complex64> = frequency
+            .iter()
+            .map(|f| 1.0 / Complex64::new(1.0, f / fc))
+            .collect();
+        let tau = 1.0 / (2.0 * std::f64::consts::PI * fc);
+        for causality in [Causality::Truncate, Causality::MinimumPhase] {
+            let r = time_response(&frequency, &response, 4096, causality);
+            assert_eq!(r.time.len(), 4096);
+            let i = r.time.iter().position(|&t| t >= tau).unwrap();
+            let expected = 1.0 - (-r.time[i] / tau).exp();
+            assert!((r.step[i] - expected).abs() < 0.02, "{:?}", causality);
+            let end = r.step[2047];
+            assert!((end - 1.0).abs() < 0.02, "{:?}", causality);
+        }
+        let r = time_response(&frequency, &response, 4096, Causality::None);
+        assert!(r.noncausal < 0.05);
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright 2022 Andrew Morrow.
+// incremental.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Monte Carlo runs that load the circuit once and change sampled values with `alter` and
+//! `altermod`, instead of re-parsing the whole netlist for every run.
+
+use crate::campaign::Campaign;
+use crate::circuit::{logical_lines, Element};
+use crate::montecarlo::{apply_params, MonteCarlo};
+use crate::{warmup, Error, NgSpice};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Where a sampled parameter's value ends up in the loaded circuit.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Target {
+    /// The value of a passive element or the DC value of a source.
+    Element(String),
+    /// An instance parameter, e.g. the `w` of a MOSFET.
+    Parameter(String, String),
+    /// A model parameter.
+    Model(String, String),
+}
+
+fn first_word(line: &str) -> String {
+    line.split_whitespace()
+        .next()
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+/// The lowercase contents of each `{...}` expression in `text`.
+fn expressions(text: &str) -> Vec<String> {
+    text.split('{')
+        .skip(1)
+        .filter_map(|s| s.split_once('}'))
+        .map(|(inner, _)| inner.trim().to_ascii_lowercase())
+        .collect()
+}
+
+/// Whether an expression mentions any of `names`.
+fn mentions(expression: &str, names: &BTreeSet<String>) -> bool {
+    expression
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .any(|word| names.contains(word))
+}
+
+/// `name` if `token` is exactly `{name}` with `name` sampled.
+fn sampled<'a>(token: &str, names: &'a BTreeSet<String>) -> Option<&'a String> {
+    let inner = token.strip_prefix('{')?.strip_suffix('}')?;
+    names.get(&inner.trim().to_ascii_lowercase())
+}
+
+/// Where `token` of `element` puts a sampled value, if `alter` can change it there.
+fn element_target(
+    element: &Element,
+    token: &str,
+    names: &BTreeSet<String>,
+) -> Option<(String, Target)> {
+    let name = element.name.clone();
+    if let Some((key, value)) = token.split_once('=') {
+        let param = sampled(value, names)?;
+        if key.is_empty() || key.contains(['(', ')']) || element.kind() == 'X' {
+            return None;
+        }
+        return Some((
+            param.clone(),
+            Target::Parameter(name, key.to_ascii_lowercase()),
+        ));
+    }
+    let param = sampled(token, names)?;
+    let position = element.params.iter().position(|p| p == token)?;
+    let is_value = match element.kind() {
+        'R' | 'C' | 'L' => position == 0,
+        'V' | 'I' => {
+            position == 0 || (position == 1 && element.params[0].eq_ignore_ascii_case("dc"))
+        }
+        _ => false,
+    };
+    is_value.then(|| (param.clone(), Target::Element(name)))
+}
+
+/// Finds every use of the sampled `names` in `circuit`. Returns `None` if any use cannot be
+/// changed with `alter` or `altermod`: a use inside a subcircuit, in an expression or another
+/// parameter, or anywhere else the value is not a plain instance or model parameter.
+fn plan(circuit: &str, names: &BTreeSet<String>) -> Option<BTreeMap<String, Vec<Target>>> {
+    let mut targets: BTreeMap<String, Vec<Target>> = BTreeMap::new();
+    let mut depth = 0;
+    for line in logical_lines(circuit).iter().skip(1) {
+        let word = first_word(line);
+        match word.as_str() {
+            ".subckt" => depth += 1,
+            ".ends" => depth -= 1,
+            _ => {}
+        }
+        if word.starts_with('*') {
+            continue;
+        }
+        let uses = expressions(line)
+            .iter()
+            .filter(|e| mentions(e, names))
+            .count();
+        if uses == 0 {
+            continue;
+        }
+        if depth > 0 {
+            return None;
+        }
+        let found: Vec<(String, Target)> = if word == ".model" {
+            let flat = line.replace(['(', ')'], " ");
+            let mut tokens = flat.split_whitespace().skip(1);
+            let model = tokens.next()?.to_owned();
+            tokens
+                .skip(1)
+                .filter_map(|t| {
+                    let (key, value) = t.split_once('=')?;
+                    let param = sampled(value, names)?;
+                    Some((
+                        param.clone(),
+                        Target::Model(model.clone(), key.to_ascii_lowercase()),
+                    ))
+                })
+                .collect()
+        } else if word.starts_with('.') {
+            return None;
+        } else {
+            let element = Element::parse(line)?;
+            element
+                .params
+                .iter()
+                .filter_map(|t| element_target(&element, t, names))
+                .collect()
+        };
+        // every use must be accounted for, or the altered run would differ from a reload
+        if found.len() != uses {
+            return None;
+        }
+        for (param, target) in found {
+            targets.entry(param).or_default().push(target);
+        }
+    }
+    Some(targets)
+}
+
+impl MonteCarlo {
+    /// Like [`MonteCarlo::run`], but loads the circuit once and applies each run's values with
+    /// `alter` and `altermod`, which is much faster for large netlists with few varying
+    /// parameters.
+    ///
+    /// Sampled parameters must be used only as the whole value of an element, e.g.
+    /// `R1 a b {r1}`, or of an instance or model parameter, e.g. `w={w1}`, outside any
+    /// subcircuit. If any use does not fit, every run reloads the circuit as with
+    /// [`MonteCarlo::run`] instead, so the results are the same either way. Like other
+    /// sessions, the incremental runs do not call hooks registered with
+    /// [`NgSpice::on_pre_load`] and [`NgSpice::on_post_extract`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the first simulation error, or [`Error::MissingElement`] if ngSPICE rejects an
+    /// `alter` command.
+    pub fn run_incremental(&self, circuit: &str, command: &str) -> Result<Campaign, Error> {
+        let names: BTreeSet<String> = self.sample(0).into_keys().collect();
+        let plan = match plan(circuit, &names) {
+            Some(plan) => plan,
+            None => return self.run(circuit, command),
+        };
+        let mut campaign = Campaign::new("monte carlo");
+        let mut deck = apply_params(circuit, &self.sample(0));
+        warmup::add_libraries(&mut deck);
+        let mut session = NgSpice::session();
+        session.load_circuit(&deck)?;
+        for run in 0..self.runs {
+            let (tags, values) = self.point(run);
+            for (param, targets) in &plan {
+                let value = values[param];
+                for target in targets {
+                    match target {
+                        Target::Element(e) => session.alter(e, value)?,
+                        Target::Parameter(e, p) => session.alter_parameter(e, p, value)?,
+                        Target::Model(m, p) => session.altermod(m, p, value)?,
+                    }
+                }
+            }
+            session.clear_output();
+            let plot = session.run(command)?;
+            let sim = session.plot(&plot)?;
+            session.destroy(&plot)?;
+            campaign.add(tags, sim);
+        }
+        Ok(campaign)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_alterations() {
+        let names: BTreeSet<String> = ["r1", "w", "vt", "vin"].map(str::to_owned).into();
+        let circuit = "* amp\nV1 in 0 DC {vin}\nR1 in d {R1}\nM1 d in 0 0 nch w={w} l=1u\n\
+                       .model nch nmos (vto={vt} kp=1e-4)\n.tran 1n 1u\n.end";
+        let found = plan(circuit, &names).unwrap();
+        assert_eq!(found["vin"], [Target::Element("V1".to_owned())]);
+        assert_eq!(found["r1"], [Target::Element("R1".to_owned())]);
+        assert_eq!(
+            found["w"],
+            [Target::Parameter("M1".to_owned(), "w".to_owned())]
+        );
+        assert_eq!(
+            found["vt"],
+            [Target::Model("nch".to_owned(), "vto".to_owned())]
+        );
+        for unsupported in [
+            "* t\nR1 a b {2*r1}\n.end",
+            "* t\n.param r2={r1}\n.end",
+            "* t\n.subckt s a b\nR1 a b {r1}\n.ends\n.end",
+            "* t\nV1 a 0 SIN(0 {vin} 1k)\n.end",
+        ] {
+            assert_eq!(plan(unsupported, &names), None, "{}", unsupported);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2022 Andrew Morrow.
+// interconnect.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Transmission line models of cables and interconnect from per-unit-length parameters.
+
+use num_complex::Complex64;
+use std::f64::consts::PI;
+use std::fmt::Write;
+
+/// Per-unit-length line parameters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rlgc {
+    /// Series resistance, in Ω/m.
+    pub r: f64,
+    /// Series inductance, in H/m.
+    pub l: f64,
+    /// Shunt conductance, in S/m.
+    pub g: f64,
+    /// Shunt capacitance, in F/m.
+    pub c: f64,
+}
+
+impl Rlgc {
+    /// A lossless line with impedance `z0` ohms and propagation `delay` seconds per metre.
+    pub fn lossless(z0: f64, delay: f64) -> Self {
+        Rlgc {
+            r: 0.0,
+            l: z0 * delay,
+            g: 0.0,
+            c: delay / z0,
+        }
+    }
+
+    /// The characteristic impedance at `frequency` Hz.
+    pub fn impedance(&self, frequency: f64) -> Complex64 {
+        let w = 2.0 * PI * frequency;
+        let z = Complex64::new(self.r, w * self.l);
+        let y = Complex64::new(self.g, w * self.c);
+        (z / y).sqrt()
+    }
+
+    /// The lossless propagation delay, in s/m.
+    pub fn delay(&self) -> f64 {
+        (self.l * self.c).sqrt()
+    }
+}
+
+/// A uniform line of a given length.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interconnect {
+    pub rlgc: Rlgc,
+    /// In metres.
+    pub length: f64,
+}
+
+impl Interconnect {
+    /// The number of ladder segments needed to model edges of `rise_time` seconds, keeping
+    /// each segment's delay under a tenth of the rise time.
+    pub fn segments_for(&self, rise_time: f64) -> usize {
+        ((10.0 * self.rlgc.delay() * self.length / rise_time).ceil() as usize).max(1)
+    }
+
+    /// Renders a lumped RLGC ladder of `segments` sections as a subcircuit with ports `in`,
+    /// `out`, and `ref`. Zero-valued elements are left out.
+    pub fn ladder(&self, name: &str, segments: usize) -> String {
+        let segments = segments.max(1);
+        let dx = self.length / segments as f64;
+        let p = &self.rlgc;
+        let mut out = format!(".subckt {} in out ref\n", name);
+        let node = |k: usize| match k {
+            0 => "in".to_owned(),
+            k if k == segments => "out".to_owned(),
+            k => format!("n{}", k),
+        };
+        for k in 0..segments {
+            let (a, b) = (node(k), node(k + 1));
+            match (p.r * dx, p.l * dx) {
+                (r, 0.0) => writeln!(out, "R{} {} {} {:e}", k, a, b, r).unwrap(),
+                (0.0, l) => writeln!(out, "L{} {} {} {:e}", k, a, b, l).unwrap(),
+                (r, l) => {
+                    writeln!(out, "R{} {} m{} {:e}", k, a, k, r).unwrap();
+                    writeln!(out, "L{} m{} {} {:e}", k, k, b, l).unwrap();
+                }
+            }
+            if p.c != 0.0 {
+                writeln!(out, "C{} {} ref {:e}", k, b, p.c * dx).unwrap();
+            }
+            if p.g != 0.0 {
+                writeln!(out, "Rg{} {} ref {:e}", k, b, 1.0 / (p.g * dx)).unwrap();
+            }
+        }
+        writeln!(out, ".ends {}", name).unwrap();
+        out
+    }
+
+    /// Renders an ngSPICE lossy transmission line (LTRA) instance named `O<name>` from
+    /// `in`/`ref_in` to `out`/`ref_out`, with its model.
+    pub fn ltra(&self, name: &str, ports: [&str; 4]) -> String {
+        let p = &self.rlgc;
+        format!(
+            "O{name} {} {} {} {} {name}_ltra\n.model {name}_ltra ltra r={:e} l={:e} g={:e} c={:e} len={:e}\n",
+            ports[0],
+            ports[1],
+            ports[2],
+            ports[3],
+            p.r,
+            p.l,
+            p.g,
+            p.c,
+            self.length,
+            name = name
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_line_models() {
+        let line = Interconnect {
+            rlgc: Rlgc {
+                r: 0.0,
+                l: 2.5e-7,
+                g: 0.0,
+                c: 1e-10,
+            },
+            length: 2.0,
+        };
+        assert!((line.rlgc.impedance(1e9).re - 50.0).abs() < 1e-9);
+        assert!((Rlgc::lossless(50.0, 5e-9).delay() - 5e-9).abs() < 1e-20);
+        assert_eq!(line.segments_for(1e-9), 100);
+        let ladder = line.ladder("coax", 2);
+        assert_eq!(
+            ladder,
+            ".subckt coax in out ref\nL0 in n1 2.5e-7\nC0 n1 ref 1e-10\nL1 n1 out 2.5e-7\nC1 out ref 1e-10\n.ends coax\n"
+        );
+        let lossy = Interconnect {
+            rlgc: Rlgc {
+                g: 1e-3,
+                r: 1.0,
+                ..line.rlgc
+            },
+            ..line
+        };
+        assert!(lossy.ladder("x", 1).contains("Rg0 out ref 5e2\n"));
+        assert!(lossy
+            .ltra("w1", ["a", "0", "b", "0"])
+            .starts_with("Ow1 a 0 b 0 w1_ltra\n.model w1_ltra ltra r=1e0"));
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright 2022 Andrew Morrow.
+// intermod.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Two-tone intermodulation tests: a transient run driven by two sines, with the third-order
+//! products read from the output spectrum to estimate IIP3 and OIP3 without harmonic balance.
+
+use crate::circuit::{Card, Circuit, Element};
+use crate::control::vector_name;
+use crate::spectrum::{coherent_frequency, power_spectrum, resample, Window};
+use crate::stimuli::{Stimulus, Tone};
+use crate::{Error, NgSpice};
+
+/// Levels read from one two-tone capture. Levels are peak amplitudes in dBV.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Intermodulation {
+    /// The input level of each tone.
+    pub input: f64,
+    /// The mean output level of the two fundamentals.
+    pub fundamental: f64,
+    /// The larger of the products at `2·f1 − f2` and `2·f2 − f1`.
+    pub im3: f64,
+}
+
+impl Intermodulation {
+    /// The input-referred third-order intercept, extrapolated with slopes of 1 and 3.
+    pub fn iip3(&self) -> f64 {
+        self.input + (self.fundamental - self.im3) / 2.0
+    }
+
+    /// The output-referred third-order intercept.
+    pub fn oip3(&self) -> f64 {
+        self.fundamental + (self.fundamental - self.im3) / 2.0
+    }
+}
+
+/// The peak amplitude, in dBV, of the tone in `bin` of a rectangular-window spectrum of `n`
+/// samples.
+fn level(spectrum: &[f64], bin: usize, n: usize) -> f64 {
+    let power = spectrum.get(bin).copied().unwrap_or(0.0);
+    20.0 * (2.0 * power.sqrt() / n as f64).log10()
+}
+
+/// Reads the fundamental and third-order products from a coherent capture of two tones at
+/// `f1` and `f2`, each driven at `input` volts peak.
+///
+/// Both tones and their products must fall exactly on FFT bins, as with frequencies from
+/// [`TwoTone::frequencies`]. A product outside the first Nyquist zone reads as `-inf`.
+pub fn intermodulation(
+    samples: &[f64],
+    sample_rate: f64,
+    f1: f64,
+    f2: f64,
+    input: f64,
+) -> Intermodulation {
+    let n = samples.len();
+    let spectrum = power_spectrum(samples, Window::Rectangular);
+    let bin = |f: f64| (f * n as f64 / sample_rate).round() as i64;
+    let (b1, b2) = (bin(f1), bin(f2));
+    let at = |b: i64| usize::try_from(b).map_or(f64::NEG_INFINITY, |b| level(&spectrum, b, n));
+    Intermodulation {
+        input: 20.0 * input.log10(),
+        fundamental: (at(b1) + at(b2)) / 2.0,
+        im3: at(2 * b1 - b2).max(at(2 * b2 - b1)),
+    }
+}
+
+/// A two-tone test swept over input levels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TwoTone {
+    /// The requested tone frequencies, in Hz. They are moved to the nearest coherent bins.
+    pub f1: f64,
+    pub f2: f64,
+    /// The peak amplitude of each tone at each sweep step, in V.
+    pub amplitudes: Vec<f64>,
+    pub sample_rate: f64,
+    /// The number of samples captured per run. Powers of two use the fast FFT.
+    pub points: usize,
+    /// How long to run before capturing, so start-up transients die out.
+    pub settle: f64,
+}
+
+impl TwoTone {
+    pub fn new(f1: f64, f2: f64, sample_rate: f64, points: usize) -> Self {
+        TwoTone {
+            f1,
+            f2,
+            amplitudes: vec![1e-3],
+            sample_rate,
+            points,
+            settle: 0.0,
+        }
+    }
+
+    pub fn amplitudes(mut self, amplitudes: &[f64]) -> Self {
+        self.amplitudes = amplitudes.to_vec();
+        self
+    }
+
+    pub fn settle(mut self, settle: f64) -> Self {
+        self.settle = settle;
+        self
+    }
+
+    /// The coherent tone frequencies nearest `f1` and `f2`, which always differ.
+    pub fn frequencies(&self) -> (f64, f64) {
+        let f1 = coherent_frequency(self.sample_rate, self.points, self.f1);
+        let step = self.sample_rate / self.points as f64;
+        let mut target = self.f2;
+        let mut f2 = coherent_frequency(self.sample_rate, self.points, target);
+        while f2 == f1 && target < self.sample_rate / 2.0 {
+            target += step;
+            f2 = coherent_frequency(self.sample_rate, self.points, target);
+        }
+        (f1, f2)
+    }
+
+    /// Drives `input` (relative to ground) with the two tones at each amplitude and measures
+    /// the `output` vector, e.g. `v(out)`.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`NgSpice::simulate`], or [`Error::MissingScale`] or
+    /// [`Error::MissingVector`] if the time scale or the output is not in the results.
+    pub fn run(
+        &self,
+        circuit: &Circuit,
+        input: &str,
+        output: &str,
+    ) -> Result<Vec<Intermodulation>, Error> {
+        let (f1, f2) = self.frequencies();
+        let step = 1.0 / self.sample_rate;
+        let stop = self.settle + self.points as f64 * step;
+        let command = format!("tran {:e} {:e} {:e} {:e}", step, stop, self.settle, step);
+        let name = vector_name(output);
+        self.amplitudes
+            .iter()
+            .map(|&amplitude| {
+                let tones = [f1, f2].map(|frequency| Tone {
+                    frequency,
+                    amplitude,
+                    phase: 0.0,
+                });
+                let source = Stimulus::multitone(&tones, 0.0).voltage_source("twotone", input, "0");
+                let mut deck = circuit.clone();
+                deck.cards
+                    .extend(Element::parse(source.trim()).map(Card::Element));
+                let sim = NgSpice::simulate(&deck.to_string(), &command)?;
+                let (_, time) = sim.scale_vector().ok_or(Error::MissingScale)?;
+                let time = time.real().ok_or(Error::MissingScale)?;
+                let values = sim.real_vector(&name)?;
+                let samples = resample(time, values, self.settle, self.sample_rate, self.points);
+                Ok(intermodulation(
+                    &samples,
+                    self.sample_rate,
+                    f1,
+                    f2,
+                    amplitude,
+                ))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn estimates_cubic_intercept() {
+        let test = TwoTone::new(100e3, 110e3, 1e6, 1024);
+        let (f1, f2) = test.frequencies();
+        assert!(f1 != f2);
+        // y = x - x³/30 has an input intercept of sqrt(4/3 · 30) = sqrt(40) V
+        let a = 0.05;
+        let samples: Vec<f64> = (0..test.points)
+            .map(|i| {
+                let t = i as f64 / test.sample_rate;
+                let x = a * ((2.0 * PI * f1 * t).sin() + (2.0 * PI * f2 * t).sin());
+                x - x.powi(3) / 30.0
+            })
+            .collect();
+        let im = intermodulation(&samples, test.sample_rate, f1, f2, a);
+        let expected = 20.0 * 40f64.sqrt().log10();
+        assert!((im.iip3() - expected).abs() < 0.01, "{}", im.iip3());
+        assert!((im.oip3() - im.iip3() - (im.fundamental - im.input)).abs() < 1e-9);
+    }
+}
@@ -24,7 +24,85 @@ use std::marker::PhantomPinned;
 use std::os::raw::{c_char, c_int, c_void};
 use std::pin::Pin;
 use std::ptr;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+pub mod analysis;
+pub mod archive;
+pub mod background;
+pub mod battery;
+pub mod builder;
+pub mod bundle;
+pub mod campaign;
+pub mod cancel;
+pub mod characterize;
+pub mod circuit;
+pub mod compare;
+pub mod control;
+pub mod converter;
+pub mod corners;
+pub mod cosim;
+pub mod cost;
+pub mod crystal;
+#[cfg(feature = "sqlite")]
+pub mod database;
+pub mod determinism;
+pub mod dialect;
+pub mod digital;
+pub mod eseries;
+pub mod filter;
+pub mod format;
+pub mod gate;
+pub mod gnuplot;
+pub mod graph;
+pub mod highsigma;
+pub mod hooks;
+pub mod ibis;
+pub mod identify;
+pub mod impulse;
+pub mod incremental;
+pub mod interconnect;
+pub mod intermod;
+pub mod kernels;
+pub mod library;
+pub mod limits;
+pub mod loader;
+pub mod magnetics;
+pub mod matching;
+pub mod measure;
+pub mod montecarlo;
+pub mod mosfet;
+pub mod opamp;
+pub mod options;
+pub mod opto;
+pub mod overlay;
+pub mod pdn;
+pub mod policy;
+pub mod pool;
+pub mod power;
+pub mod progress;
+pub mod rails;
+pub mod random;
+pub mod regions;
+pub mod session;
+pub mod soa;
+pub mod specs;
+pub mod spectrum;
+pub mod state;
+pub mod stats;
+pub mod stimuli;
+pub mod stream;
+pub mod stress;
+pub mod telemetry;
+pub mod temperature;
+pub mod thermal;
+pub mod units;
+pub mod validate;
+pub mod value;
+pub mod warmup;
+pub mod waveform;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
 
 #[derive(Debug)]
 pub enum Error {
@@ -32,6 +110,60 @@ pub enum Error {
     InvalidStringEncoding,
     /// ngSPICE was unable to parse the circuit. The contained String holds error logs.
     InvalidCircuit(String),
+    /// The netlist violates constraints that ngSPICE would not report. The contained Vec holds
+    /// every problem that was found.
+    InvalidNetlist(Vec<validate::Diagnostic>),
+    /// The named vector does not exist, or does not have the expected type.
+    MissingVector(String),
+    /// A command is not permitted by the current [`policy::CommandPolicy`]. The contained String
+    /// holds the offending command.
+    ForbiddenCommand(String),
+    /// A simulation exceeded one of its [`limits::ResourceLimits`]. `partial` holds whatever
+    /// results were extracted before the limit was hit, if any.
+    ResourceLimit {
+        resource: limits::Resource,
+        partial: Option<Box<Simulation>>,
+    },
+    /// A background simulation was cancelled. `partial` holds the results computed before it
+    /// stopped, if it had started.
+    Cancelled { partial: Option<Box<Simulation>> },
+    /// A simulation ran longer than its timeout and was halted. `partial` holds the results
+    /// computed before it stopped.
+    Timeout { partial: Option<Box<Simulation>> },
+    /// A simulation has no real time or frequency vector to use as its scale.
+    MissingScale,
+    /// A [`circuit::Circuit`] has no element with the contained name.
+    MissingElement(String),
+    /// A string is not a number, even with SPICE scale factors.
+    InvalidValue(String),
+    /// An argument is outside the range a function accepts. The contained String says which.
+    InvalidArgument(String),
+    /// A vector holds a quantity that cannot be read in the requested [`units::Unit`].
+    UnitMismatch {
+        vector: String,
+        unit: &'static str,
+        datatype: DataType,
+    },
+    /// Two different definitions of the same model or subcircuit were registered with a
+    /// [`library::ModelLibrary`].
+    DuplicateDefinition {
+        name: String,
+        /// Where the registered definition came from.
+        first: String,
+        /// Where the conflicting definition came from.
+        second: String,
+    },
+    /// A file referenced by `.include` or `.lib` could not be inlined by a
+    /// [`loader::CircuitLoader`].
+    Include {
+        path: std::path::PathBuf,
+        problem: loader::IncludeProblem,
+    },
+    /// A file could not be read or written.
+    Io(std::io::Error),
+    /// A [`database::Database`] could not be read or written.
+    #[cfg(feature = "sqlite")]
+    Database(rusqlite::Error),
     /// ngSPICE returned an unknown error. The contained String holds error logs.
     Unknown(String),
 }
@@ -46,6 +178,54 @@ impl fmt::Display for Error {
                 "error parsing circuit; ngSPICE logs follow:\n{}",
                 msg
             )),
+            Error::InvalidNetlist(diagnostics) => {
+                f.write_str("invalid netlist:")?;
+                for d in diagnostics {
+                    f.write_fmt(format_args!("\n{}", d))?;
+                }
+                Ok(())
+            }
+            Error::MissingVector(name) => {
+                f.write_fmt(format_args!("missing or mismatched vector: {}", name))
+            }
+            Error::MissingElement(name) => f.write_fmt(format_args!("no such element: {}", name)),
+            Error::InvalidValue(s) => f.write_fmt(format_args!("not a number: {}", s)),
+            Error::InvalidArgument(msg) => f.write_fmt(format_args!("invalid argument: {}", msg)),
+            Error::UnitMismatch {
+                vector,
+                unit,
+                datatype,
+            } => f.write_fmt(format_args!(
+                "{} holds {}, which cannot be read in {}",
+                vector,
+                datatype.quantity().to_ascii_lowercase(),
+                unit
+            )),
+            Error::DuplicateDefinition {
+                name,
+                first,
+                second,
+            } => f.write_fmt(format_args!(
+                "{} is defined differently in {} and {}",
+                name, first, second
+            )),
+            Error::ForbiddenCommand(cmd) => {
+                f.write_fmt(format_args!("command not permitted by policy: {}", cmd))
+            }
+            Error::ResourceLimit { resource, .. } => {
+                f.write_fmt(format_args!("simulation exceeded its {} limit", resource))
+            }
+            Error::Cancelled { .. } => f.write_str("simulation was cancelled"),
+            Error::Timeout { .. } => f.write_str("simulation timed out"),
+            Error::MissingScale => f.write_str("simulation has no time or frequency vector"),
+            Error::Include { path, problem } => f.write_fmt(format_args!(
+                "cannot include {}: {}",
+                path.display(),
+                problem
+            )),
+            Error::Io(e) => f.write_fmt(format_args!("I/O error: {}", e)),
+            #[cfg(feature = "sqlite")]
+            Error::Database(e) => f.write_fmt(format_args!("database error: {}", e)),
             Error::Unknown(msg) => {
                 f.write_fmt(format_args!("unknown error; ngSPICE logs follow:\n{}", msg))
             }
@@ -55,6 +235,19 @@ impl fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Database(e)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum DataType {
     Unknown,
@@ -62,7 +255,98 @@ pub enum DataType {
     Frequency,
     Voltage,
     Current,
-    // TODO: the rest
+    /// Noise voltage spectral density, as from `noise`.
+    VoltageDensity,
+    /// Noise current spectral density, as from `noise`.
+    CurrentDensity,
+    SquaredVoltageDensity,
+    SquaredCurrentDensity,
+    SquaredVoltage,
+    SquaredCurrent,
+    /// A pole location, as from `pz`.
+    Pole,
+    /// A zero location, as from `pz`.
+    Zero,
+    /// A scattering parameter, as from `sp`.
+    SParameter,
+    Temperature,
+    Resistance,
+    Impedance,
+    Admittance,
+    Power,
+    Phase,
+    Decibel,
+    Capacitance,
+    Charge,
+}
+
+impl DataType {
+    /// The unit symbol for this type, e.g. `V` or `Hz`. Empty if the type is unitless or
+    /// unknown.
+    pub fn unit(&self) -> &'static str {
+        match self {
+            DataType::Unknown => "",
+            DataType::Time => "s",
+            DataType::Frequency => "Hz",
+            DataType::Voltage => "V",
+            DataType::Current => "A",
+            DataType::VoltageDensity => "V/\u{221a}Hz",
+            DataType::CurrentDensity => "A/\u{221a}Hz",
+            DataType::SquaredVoltageDensity => "V\u{b2}/Hz",
+            DataType::SquaredCurrentDensity => "A\u{b2}/Hz",
+            DataType::SquaredVoltage => "V\u{b2}",
+            DataType::SquaredCurrent => "A\u{b2}",
+            // pole-zero analysis reports complex frequencies in radians per second
+            DataType::Pole | DataType::Zero => "rad/s",
+            DataType::SParameter => "",
+            DataType::Temperature => "\u{b0}C",
+            DataType::Resistance | DataType::Impedance => "\u{3a9}",
+            DataType::Admittance => "S",
+            DataType::Power => "W",
+            // ngSPICE uses degrees or radians depending on the `units` option
+            DataType::Phase => "",
+            DataType::Decibel => "dB",
+            DataType::Capacitance => "F",
+            DataType::Charge => "C",
+        }
+    }
+
+    /// The name of the physical quantity, e.g. `Voltage`.
+    pub fn quantity(&self) -> &'static str {
+        match self {
+            DataType::Unknown => "Value",
+            DataType::Time => "Time",
+            DataType::Frequency => "Frequency",
+            DataType::Voltage => "Voltage",
+            DataType::Current => "Current",
+            DataType::VoltageDensity => "Voltage noise density",
+            DataType::CurrentDensity => "Current noise density",
+            DataType::SquaredVoltageDensity => "Voltage noise power density",
+            DataType::SquaredCurrentDensity => "Current noise power density",
+            DataType::SquaredVoltage => "Squared voltage",
+            DataType::SquaredCurrent => "Squared current",
+            DataType::Pole => "Pole",
+            DataType::Zero => "Zero",
+            DataType::SParameter => "S-parameter",
+            DataType::Temperature => "Temperature",
+            DataType::Resistance => "Resistance",
+            DataType::Impedance => "Impedance",
+            DataType::Admittance => "Admittance",
+            DataType::Power => "Power",
+            DataType::Phase => "Phase",
+            DataType::Decibel => "Magnitude",
+            DataType::Capacitance => "Capacitance",
+            DataType::Charge => "Charge",
+        }
+    }
+
+    /// A chart axis label with the unit in parentheses, e.g. `Voltage (V)`.
+    pub fn axis_label(&self) -> String {
+        match self.unit() {
+            "" => self.quantity().to_owned(),
+            unit => format!("{} ({})", self.quantity(), unit),
+        }
+    }
 }
 
 impl From<simulation_types::Type> for DataType {
@@ -72,19 +356,64 @@ impl From<simulation_types::Type> for DataType {
             simulation_types::SV_FREQUENCY => DataType::Frequency,
             simulation_types::SV_VOLTAGE => DataType::Voltage,
             simulation_types::SV_CURRENT => DataType::Current,
-            // TODO: the rest
+            simulation_types::SV_VOLTAGE_DENSITY => DataType::VoltageDensity,
+            simulation_types::SV_CURRENT_DENSITY => DataType::CurrentDensity,
+            simulation_types::SV_SQR_VOLTAGE_DENSITY => DataType::SquaredVoltageDensity,
+            simulation_types::SV_SQR_CURRENT_DENSITY => DataType::SquaredCurrentDensity,
+            simulation_types::SV_SQR_VOLTAGE => DataType::SquaredVoltage,
+            simulation_types::SV_SQR_CURRENT => DataType::SquaredCurrent,
+            simulation_types::SV_POLE => DataType::Pole,
+            simulation_types::SV_ZERO => DataType::Zero,
+            simulation_types::SV_SPARAM => DataType::SParameter,
+            simulation_types::SV_TEMP => DataType::Temperature,
+            simulation_types::SV_RES => DataType::Resistance,
+            simulation_types::SV_IMPEDANCE => DataType::Impedance,
+            simulation_types::SV_ADMITTANCE => DataType::Admittance,
+            simulation_types::SV_POWER => DataType::Power,
+            simulation_types::SV_PHASE => DataType::Phase,
+            simulation_types::SV_DB => DataType::Decibel,
+            simulation_types::SV_CAPACITANCE => DataType::Capacitance,
+            simulation_types::SV_CHARGE => DataType::Charge,
             _ => DataType::Unknown,
         }
     }
 }
 
+/// The values of a vector. They are reference counted, so cloning a VectorValues, or a whole
+/// [`Simulation`], shares them instead of copying, and results can be fanned out across
+/// threads cheaply. [`VectorValues::real_mut`] and [`VectorValues::complex_mut`] copy them
+/// first if they are shared.
 #[derive(Clone, Debug)]
 pub enum VectorValues {
-    Real(Vec<f64>),
-    Complex(Vec<num_complex::Complex64>),
+    Real(Arc<Vec<f64>>),
+    Complex(Arc<Vec<num_complex::Complex64>>),
+}
+
+impl From<Vec<f64>> for VectorValues {
+    fn from(values: Vec<f64>) -> Self {
+        VectorValues::Real(Arc::new(values))
+    }
+}
+
+impl From<Vec<num_complex::Complex64>> for VectorValues {
+    fn from(values: Vec<num_complex::Complex64>) -> Self {
+        VectorValues::Complex(Arc::new(values))
+    }
 }
 
 impl VectorValues {
+    /// The number of values.
+    pub fn len(&self) -> usize {
+        match self {
+            VectorValues::Real(x) => x.len(),
+            VectorValues::Complex(x) => x.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// If this VectorValues contains real numbers, returns Some. Otherwise, returns None.
     pub fn real(&self) -> Option<&[f64]> {
         match self {
@@ -100,15 +429,41 @@ impl VectorValues {
             VectorValues::Complex(x) => Some(x),
         }
     }
+
+    /// Like [`VectorValues::real`], but mutable. The values are copied first if any other
+    /// VectorValues shares them.
+    pub fn real_mut(&mut self) -> Option<&mut Vec<f64>> {
+        match self {
+            VectorValues::Real(x) => Some(Arc::make_mut(x)),
+            VectorValues::Complex(_) => None,
+        }
+    }
+
+    /// Like [`VectorValues::complex`], but mutable. The values are copied first if any other
+    /// VectorValues shares them.
+    pub fn complex_mut(&mut self) -> Option<&mut Vec<num_complex::Complex64>> {
+        match self {
+            VectorValues::Real(_) => None,
+            VectorValues::Complex(x) => Some(Arc::make_mut(x)),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct VectorInfo {
     pub datatype: DataType,
     pub values: VectorValues,
+    /// The name of the vector this one is plotted against, e.g. `time` in a transient. `None`
+    /// for scales themselves and for analyses without one, such as `op`.
+    ///
+    /// ngSPICE's shared API does not report scales, so this is the plot's time or frequency
+    /// vector, or its sweep vector (e.g. `v-sweep`) in a DC analysis.
+    pub scale: Option<String>,
 }
 
 /// Represents the results of a single ngSPICE simulation (aka an ngSPICE plot).
+///
+/// Cloning is cheap: clones share their vector data until one of them is modified.
 #[derive(Clone, Debug, Default)]
 pub struct Simulation {
     /// ngSPICE log output to stdout.
@@ -120,6 +475,52 @@ pub struct Simulation {
 }
 
 impl Simulation {
+    /// Returns the values of the named vector if it exists and is real.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingVector`] if there is no such vector or if it is complex.
+    pub fn real_vector(&self, name: &str) -> Result<&[f64], Error> {
+        self.vectors
+            .get(name)
+            .and_then(|v| v.values.real())
+            .ok_or_else(|| Error::MissingVector(name.to_owned()))
+    }
+
+    /// The scale that the named vector is plotted against, if it has one.
+    pub fn scale_of(&self, name: &str) -> Option<(&str, &VectorInfo)> {
+        let scale = self.vectors.get(name)?.scale.as_deref()?;
+        self.vectors
+            .get_key_value(scale)
+            .map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Guesses the plot's scale: its time or frequency vector, or its sweep vector (e.g.
+    /// `v-sweep`) in a DC analysis. ngSPICE's own `time` and `frequency` win over other vectors
+    /// of those types, and ties go to the first name, so the guess never depends on map order.
+    pub(crate) fn guess_scale(&self) -> Option<&str> {
+        let first = |matches: &dyn Fn(&str, &VectorInfo) -> bool| {
+            self.vectors
+                .iter()
+                .filter(|(k, v)| matches(k, v))
+                .map(|(k, _)| k.as_str())
+                .min()
+        };
+        let is_scale =
+            |_: &str, v: &VectorInfo| matches!(v.datatype, DataType::Time | DataType::Frequency);
+        first(&|k, v| (k == "time" || k == "frequency") && is_scale(k, v))
+            .or_else(|| first(&is_scale))
+            .or_else(|| first(&|k, _| k.ends_with("-sweep")))
+    }
+
+    /// Points every vector at the plot's scale.
+    fn assign_scales(&mut self) {
+        let scale = self.guess_scale().map(str::to_owned);
+        for (name, info) in &mut self.vectors {
+            info.scale = scale.clone().filter(|s| s != name);
+        }
+    }
+
     unsafe fn insert_vecinfo(&mut self, v: *const vector_info) {
         let name = CStr::from_ptr((*v).v_name);
         let name = name
@@ -129,8 +530,8 @@ impl Simulation {
         let datatype = DataType::from((*v).v_type as u32);
         let len: usize = (*v).v_length as usize;
         let values: VectorValues = if (*v).v_realdata != ptr::null_mut() {
-            let ary = std::slice::from_raw_parts((*v).v_realdata, len).to_owned();
-            VectorValues::Real(ary)
+            let ary = pool::copy_real(std::slice::from_raw_parts((*v).v_realdata, len));
+            VectorValues::Real(ary.into())
         } else {
             assert_ne!(
                 (*v).v_compdata,
@@ -142,14 +543,50 @@ impl Simulation {
             // TODO: can I write a unit test to check this? or a build check?
             let ary: &[ngcomplex_t] = std::slice::from_raw_parts((*v).v_compdata, len);
             let ary: &[num_complex::Complex64] = std::mem::transmute(ary);
-            let ary = ary.to_owned();
-            VectorValues::Complex(ary)
+            VectorValues::Complex(pool::copy_complex(ary).into())
+        };
+        let vecinfo = VectorInfo {
+            datatype,
+            values,
+            scale: None,
         };
-        let vecinfo = VectorInfo { datatype, values };
         self.vectors.insert(name, vecinfo);
     }
 }
 
+/// Copies every vector of the named plot out of ngSPICE, stopping early if `budget` runs out.
+///
+/// Returns the vectors extracted so far and the resource that ran out, if any.
+unsafe fn extract_plot(
+    plot: *const c_char,
+    budget: &mut limits::Budget,
+) -> (Simulation, Option<limits::Resource>) {
+    let mut sim = Simulation::default();
+    let plot_name = CStr::from_ptr(plot).to_string_lossy().into_owned();
+    let mut vec_name = ngSpice_AllVecs(plot as *mut c_char) as *const *mut c_char;
+    if vec_name.is_null() {
+        return (sim, None);
+    }
+    while !(*vec_name).is_null() {
+        // qualify the name, because the plot may not be the current one
+        let name = CStr::from_ptr(*vec_name).to_string_lossy();
+        let qualified = CString::new(format!("{}.{}", plot_name, name))
+            .expect("ngSPICE vector names never contain null bytes");
+        let v = ngGet_Vec_Info(qualified.as_ptr() as *mut c_char);
+        if !v.is_null() {
+            let complex = (*v).v_realdata.is_null();
+            if let Err(resource) = budget.admit((*v).v_length as usize, complex) {
+                sim.assign_scales();
+                return (sim, Some(resource));
+            }
+            sim.insert_vecinfo(v);
+        }
+        vec_name = vec_name.add(1);
+    }
+    sim.assign_scales();
+    (sim, None)
+}
+
 extern "C" fn send_char(str: *mut c_char, _: c_int, ctx: *mut c_void) -> c_int {
     let ctx = ctx as *mut NgSpice;
     unsafe {
@@ -199,11 +636,21 @@ impl NgSpice {
                     Some(send_char),
                     None,
                     Some(controlled_exit),
-                    None,
-                    None,
-                    None,
+                    Some(stream::send_data),
+                    Some(stream::send_init_data),
+                    Some(background::bg_thread_running),
                     sim.as_mut().get_unchecked_mut() as *mut _ as *mut c_void,
                 );
+                // ngSPICE copies the ident; external sources are looked up by name, so the
+                // context is unused
+                let mut ident: c_int = 0;
+                ngSpice_Init_Sync(
+                    Some(cosim::get_source_data),
+                    Some(cosim::get_source_data),
+                    None,
+                    &mut ident,
+                    ptr::null_mut(),
+                );
             }
             Mutex::new(sim)
         })
@@ -222,10 +669,16 @@ impl NgSpice {
     /// This function will block until the simulation completes. It may safely be called from any
     /// thread, but only one simulation will be executed at a time.
     ///
+    /// Any hooks registered with [`NgSpice::on_pre_load`] and [`NgSpice::on_post_extract`] run
+    /// as part of this function.
+    /// Model libraries loaded with [`NgSpice::preload`] are added to the circuit before the
+    /// hooks run.
+    ///
     /// # Arguments
     ///
     /// * `circuit` - An ngSPICE circuit listing. Must be self-contained
-    ///   (i.e. may not use the `.include` command).
+    ///   (i.e. may not use the `.include` command); see [`loader::CircuitLoader`] to inline
+    ///   included files first.
     ///
     /// * `command` - An ngSPICE simulation command like `ac` or `tran`.
     ///
@@ -240,25 +693,81 @@ impl NgSpice {
     ///
     /// If ngSPICE cannot parse the circuit or the command, this function will return an error.
     pub fn simulate(circuit: &str, command: &str) -> Result<Simulation, Error> {
+        NgSpice::simulate_with_limits(circuit, command, &limits::ResourceLimits::default())
+    }
+
+    /// Like [`NgSpice::simulate`], but fails if the simulation exceeds any of `limits`.
+    ///
+    /// Point, vector, and memory limits are checked as each vector is extracted, so no more
+    /// than the permitted amount of data is ever copied out of ngSPICE. A runtime limit runs the
+    /// command on ngSPICE's background thread and halts it when the limit passes.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the errors returned by [`NgSpice::simulate`], returns
+    /// [`Error::ResourceLimit`] if a limit is exceeded. The error holds the vectors extracted
+    /// so far, or the results computed before the halt if the runtime limit was exceeded.
+    pub fn simulate_with_limits(
+        circuit: &str,
+        command: &str,
+        limits: &limits::ResourceLimits,
+    ) -> Result<Simulation, Error> {
+        let start = Instant::now();
+        let result = NgSpice::run_with_limits(circuit, command, limits);
+        telemetry::record(&result, start.elapsed());
+        result
+    }
+
+    fn run_with_limits(
+        circuit: &str,
+        command: &str,
+        limits: &limits::ResourceLimits,
+    ) -> Result<Simulation, Error> {
+        let mut circuit = circuit.to_owned();
+        warmup::add_libraries(&mut circuit);
+        hooks::run_pre_load(&mut circuit, command);
+        let circuit = circuit.as_str();
         NgSpice::check_circuit(circuit)?;
         NgSpice::check_command(command)?;
         // We intentionally panic if the Mutex is poisoned, because ngSPICE cannot recover
         let mut handle = NgSpice::shared().lock().expect("ngSPICE mutex was poisoned, meaning ngSPICE encountered a fatal error on another thread");
         handle.as_mut().stdout().truncate(0);
         handle.as_mut().stderr().truncate(0);
+        let start = Instant::now();
         handle.as_mut().load_circuit(circuit)?;
-        handle.as_mut().command(command)?;
-        let mut sim = Simulation::default();
-        unsafe {
-            let mut vec_name = ngSpice_AllVecs(ngSpice_CurPlot()) as *const *mut c_char;
-            debug_assert_ne!(vec_name, ptr::null());
-            while *vec_name != ptr::null_mut() {
-                sim.insert_vecinfo(ngGet_Vec_Info(*vec_name));
-                vec_name = vec_name.add(1);
+        let timed_out = match limits.max_runtime {
+            // only a command on the background thread can be halted once the limit passes
+            Some(max) => {
+                let remaining = max.saturating_sub(start.elapsed());
+                let token = cancel::CancellationToken::new();
+                let stop = background::run_in_background(
+                    handle.as_mut(),
+                    command,
+                    &token,
+                    Some(remaining),
+                )?;
+                stop == background::Stop::TimedOut
             }
-        }
+            None => {
+                handle.as_mut().command(command)?;
+                false
+            }
+        };
+        let mut budget = limits::Budget::new(limits);
+        let (mut sim, mut exceeded) = unsafe { extract_plot(ngSpice_CurPlot(), &mut budget) };
         std::mem::swap(handle.as_mut().stdout(), &mut sim.stdout);
         std::mem::swap(handle.as_mut().stderr(), &mut sim.stderr);
+        drop(handle);
+        if timed_out {
+            exceeded = Some(limits::Resource::Runtime);
+        }
+        if let Some(resource) = exceeded {
+            return Err(Error::ResourceLimit {
+                resource,
+                partial: Some(Box::new(sim)),
+            });
+        }
+        hooks::run_post_extract(&mut sim);
         Ok(sim)
     }
 
@@ -266,10 +775,14 @@ impl NgSpice {
         if circuit.as_bytes().contains(&0) {
             return Err(Error::InvalidStringEncoding);
         }
-        // TODO: make sure the circuit doesn't contain any commands that could screw up our state
-        // e.g. anything that would start a background process
-        // TODO: other checks?
-        // e.g. check for .end
+        let diagnostics: Vec<validate::Diagnostic> = validate::check_netlist(circuit)
+            .into_iter()
+            .filter(validate::Diagnostic::is_error)
+            .collect();
+        if !diagnostics.is_empty() {
+            return Err(Error::InvalidNetlist(diagnostics));
+        }
+        NgSpice::command_policy().check_circuit(circuit)?;
         Ok(())
     }
 
@@ -296,9 +809,7 @@ impl NgSpice {
         if cmd.as_bytes().contains(&0) {
             return Err(Error::InvalidStringEncoding);
         }
-
-        // TODO: prevent quit?
-        Ok(())
+        NgSpice::command_policy().check(cmd)
     }
 
     /// You must run check_command first or else this may panic
@@ -317,7 +828,8 @@ impl NgSpice {
 
 #[cfg(test)]
 mod tests {
-    use crate::{DataType, Error, NgSpice};
+    use crate::{DataType, Error, NgSpice, Simulation, VectorInfo, VectorValues};
+    use ngspice_sys::simulation_types;
 
     #[test]
     fn it_works() -> Result<(), Error> {
@@ -365,4 +877,64 @@ R4 refv meas 10k
         assert!(vin_peaks > 0);
         Ok(())
     }
+
+    #[test]
+    fn shares_vectors_until_modified() {
+        let mut sim = Simulation::default();
+        sim.vectors.insert(
+            "out".to_owned(),
+            VectorInfo {
+                datatype: DataType::Voltage,
+                values: vec![1.0, 2.0].into(),
+                scale: None,
+            },
+        );
+        let mut copy = sim.clone();
+        let shared = |a: &Simulation, b: &Simulation| {
+            let a = a.real_vector("out").unwrap();
+            std::ptr::eq(a, b.real_vector("out").unwrap())
+        };
+        assert!(shared(&sim, &copy));
+        let values = copy.vectors.get_mut("out").unwrap();
+        values.values.real_mut().unwrap()[0] = 5.0;
+        assert!(!shared(&sim, &copy));
+        assert_eq!(sim.real_vector("out").unwrap(), [1.0, 2.0]);
+        assert_eq!(copy.real_vector("out").unwrap(), [5.0, 2.0]);
+    }
+
+    #[test]
+    fn assigns_scales() {
+        let mut sim = Simulation::default();
+        for (name, datatype) in [("v-sweep", DataType::Voltage), ("out", DataType::Voltage)] {
+            let info = VectorInfo {
+                datatype,
+                values: VectorValues::Real(vec![0.0, 1.0].into()),
+                scale: None,
+            };
+            sim.vectors.insert(name.to_owned(), info);
+        }
+        sim.assign_scales();
+        assert_eq!(sim.scale_of("out").map(|(name, _)| name), Some("v-sweep"));
+        assert!(sim.scale_of("v-sweep").is_none());
+    }
+
+    #[test]
+    fn data_type_labels() {
+        assert_eq!(DataType::Frequency.unit(), "Hz");
+        assert_eq!(DataType::Power.axis_label(), "Power (W)");
+        assert_eq!(DataType::Unknown.axis_label(), "Value");
+        assert_eq!(DataType::Decibel.axis_label(), "Magnitude (dB)");
+        assert_eq!(
+            DataType::from(simulation_types::SV_IMPEDANCE).axis_label(),
+            "Impedance (\u{3a9})"
+        );
+        assert_eq!(
+            DataType::from(simulation_types::SV_CHARGE),
+            DataType::Charge
+        );
+        assert_eq!(
+            DataType::from(simulation_types::SV_NOTYPE),
+            DataType::Unknown
+        );
+    }
 }
@@ -0,0 +1,253 @@
+// Copyright 2022 Andrew Morrow.
+// library.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A registry of vendor `.model` and `.subckt` definitions, injected into circuits on demand.
+
+use crate::circuit::{logical_lines, Element};
+use crate::warmup::insert_after_title;
+use crate::{Error, NgSpice, Simulation};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+/// Whether a definition is a `.model` card or a `.subckt` block.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DefinitionKind {
+    Model,
+    Subcircuit,
+}
+
+/// One registered definition.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Definition {
+    pub kind: DefinitionKind,
+    pub name: String,
+    /// The definition's logical lines, with continuations joined.
+    pub text: String,
+    /// Where the definition was registered from, e.g. a file path.
+    pub source: String,
+}
+
+/// Model and subcircuit definitions registered once and added to each circuit that uses them.
+///
+/// Names are case-insensitive, as in ngSPICE.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModelLibrary {
+    /// Definitions by lowercase name.
+    definitions: BTreeMap<String, Definition>,
+}
+
+fn first_word(line: &str) -> String {
+    line.split_whitespace()
+        .next()
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+/// The definitions in a model file, in order.
+fn definitions(text: &str, source: &str) -> Vec<Definition> {
+    let mut found = Vec::new();
+    let mut lines = logical_lines(text).into_iter();
+    while let Some(line) = lines.next() {
+        let kind = match first_word(&line).as_str() {
+            ".model" => DefinitionKind::Model,
+            ".subckt" => DefinitionKind::Subcircuit,
+            _ => continue,
+        };
+        let name = match line.split_whitespace().nth(1) {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        let mut body = vec![line];
+        if kind == DefinitionKind::Subcircuit {
+            let mut depth = 1;
+            for line in lines.by_ref() {
+                match first_word(&line).as_str() {
+                    ".subckt" => depth += 1,
+                    ".ends" => depth -= 1,
+                    _ => {}
+                }
+                body.push(line);
+                if depth == 0 {
+                    break;
+                }
+            }
+        }
+        found.push(Definition {
+            kind,
+            name,
+            text: body.join("\n"),
+            source: source.to_owned(),
+        });
+    }
+    found
+}
+
+/// The lowercase names that the elements in `lines` could refer to as models or subcircuits:
+/// any parameter that is not an assignment.
+fn references<'a>(lines: impl Iterator<Item = &'a str>) -> BTreeSet<String> {
+    lines
+        .filter(|l| !first_word(l).starts_with(['.', '*']))
+        .filter_map(Element::parse)
+        .flat_map(|e| e.params)
+        .filter(|p| !p.contains('='))
+        .map(|p| p.to_ascii_lowercase())
+        .collect()
+}
+
+impl ModelLibrary {
+    pub fn new() -> Self {
+        ModelLibrary::default()
+    }
+
+    /// Registers every `.model` and `.subckt` definition in `text`. Other cards are ignored.
+    /// Registering an identical definition again does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::DuplicateDefinition`] if a name is already registered with a
+    /// different definition. Nothing in `text` is registered in that case.
+    pub fn register(&mut self, source: &str, text: &str) -> Result<&mut Self, Error> {
+        let found = definitions(text, source);
+        let mut added: BTreeMap<String, &Definition> = BTreeMap::new();
+        for definition in &found {
+            let key = definition.name.to_ascii_lowercase();
+            let existing = added.get(&key).copied().or(self.definitions.get(&key));
+            if let Some(existing) = existing {
+                if existing.kind != definition.kind || existing.text != definition.text {
+                    return Err(Error::DuplicateDefinition {
+                        name: definition.name.clone(),
+                        first: existing.source.clone(),
+                        second: source.to_owned(),
+                    });
+                }
+            }
+            added.insert(key, definition);
+        }
+        for definition in found {
+            // an identical definition keeps its original source
+            self.definitions
+                .entry(definition.name.to_ascii_lowercase())
+                .or_insert(definition);
+        }
+        Ok(self)
+    }
+
+    /// Reads and registers a model file.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if the file cannot be read, or the errors of
+    /// [`ModelLibrary::register`].
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<&mut Self, Error> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        self.register(&path.display().to_string(), &text)
+    }
+
+    /// The named definition, if it is registered.
+    pub fn get(&self, name: &str) -> Option<&Definition> {
+        self.definitions.get(&name.to_ascii_lowercase())
+    }
+
+    /// All registered definitions, by name.
+    pub fn definitions(&self) -> impl Iterator<Item = &Definition> {
+        self.definitions.values()
+    }
+
+    /// The definitions `circuit` needs, including those used inside needed subcircuits,
+    /// leaving out any the circuit defines itself.
+    pub fn needed(&self, circuit: &str) -> Vec<&Definition> {
+        let local: BTreeSet<String> = definitions(circuit, "")
+            .into_iter()
+            .map(|d| d.name.to_ascii_lowercase())
+            .collect();
+        let lines = logical_lines(circuit);
+        let mut pending: Vec<String> = references(lines.iter().skip(1).map(String::as_str))
+            .into_iter()
+            .collect();
+        let mut needed = BTreeSet::new();
+        while let Some(name) = pending.pop() {
+            if local.contains(&name) || needed.contains(&name) {
+                continue;
+            }
+            if let Some(definition) = self.definitions.get(&name) {
+                pending.extend(references(definition.text.lines().skip(1)));
+                needed.insert(name);
+            }
+        }
+        needed.iter().map(|n| &self.definitions[n]).collect()
+    }
+
+    /// Adds the definitions `circuit` needs after its title line.
+    pub fn inject(&self, circuit: &str) -> String {
+        let mut text = String::new();
+        for definition in self.needed(circuit) {
+            text.push_str(&definition.text);
+            text.push('\n');
+        }
+        let mut circuit = circuit.to_owned();
+        if !text.is_empty() {
+            insert_after_title(&mut circuit, &text);
+        }
+        circuit
+    }
+
+    /// Injects the needed definitions into `circuit` and simulates it.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`NgSpice::simulate`].
+    pub fn simulate(&self, circuit: &str, command: &str) -> Result<Simulation, Error> {
+        NgSpice::simulate(&self.inject(circuit), command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn injects_needed_definitions() {
+        let mut library = ModelLibrary::new();
+        library
+            .register(
+                "diodes.lib",
+                ".model D1N4148 D (IS=2.52n RS=0.568\n+ N=1.752)\n.model unused D",
+            )
+            .unwrap()
+            .register(
+                "opamps.lib",
+                "* vendor header\n.subckt buf in out\nD1 in out d1n4148\nE1 out 0 in 0 1\n.ends buf",
+            )
+            .unwrap();
+        assert!(library.register("again.lib", ".model unused D").is_ok());
+        assert!(matches!(
+            library.register("other.lib", ".model UNUSED D is=1f"),
+            Err(Error::DuplicateDefinition { first, .. }) if first == "diodes.lib"
+        ));
+
+        let circuit = "* test\nX1 a b BUF\nR1 b 0 1k\n.end";
+        assert_eq!(
+            library.inject(circuit),
+            "* test\n.subckt buf in out\nD1 in out d1n4148\nE1 out 0 in 0 1\n.ends buf\n\
+             .model D1N4148 D (IS=2.52n RS=0.568 N=1.752)\nX1 a b BUF\nR1 b 0 1k\n.end"
+        );
+        // a local definition takes precedence
+        let local = "* test\n.model d1n4148 d\nD1 a 0 d1n4148\n.end";
+        assert!(library.needed(local).is_empty());
+    }
+}
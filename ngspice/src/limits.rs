@@ -0,0 +1,117 @@
+// Copyright 2022 Andrew Morrow.
+// limits.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Limits on the resources a single simulation may consume.
+
+use std::fmt::{self, Formatter};
+use std::time::Duration;
+
+/// Limits enforced by [`crate::NgSpice::simulate_with_limits`]. `None` means unlimited.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ResourceLimits {
+    /// The maximum number of points in any single vector.
+    pub max_points: Option<usize>,
+    /// The maximum number of vectors.
+    pub max_vectors: Option<usize>,
+    /// The maximum total size of extracted vector data, in bytes.
+    pub max_bytes: Option<usize>,
+    /// The maximum time spent loading the circuit and running the command, after which the
+    /// command is halted.
+    pub max_runtime: Option<Duration>,
+}
+
+/// The resource whose limit was exceeded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Resource {
+    Points,
+    Vectors,
+    Memory,
+    Runtime,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Resource::Points => "points per vector",
+            Resource::Vectors => "vector count",
+            Resource::Memory => "extracted data size",
+            Resource::Runtime => "runtime",
+        })
+    }
+}
+
+/// Tracks the resources used while vectors are extracted.
+#[derive(Debug)]
+pub(crate) struct Budget<'a> {
+    limits: &'a ResourceLimits,
+    vectors: usize,
+    bytes: usize,
+}
+
+impl<'a> Budget<'a> {
+    pub(crate) fn new(limits: &'a ResourceLimits) -> Self {
+        Budget {
+            limits,
+            vectors: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Accounts for one more vector, or returns the resource it would exceed.
+    pub(crate) fn admit(&mut self, len: usize, complex: bool) -> Result<(), Resource> {
+        let exceeds = |limit: Option<usize>, value: usize| limit.is_some_and(|l| value > l);
+        if exceeds(self.limits.max_points, len) {
+            return Err(Resource::Points);
+        }
+        if exceeds(self.limits.max_vectors, self.vectors + 1) {
+            return Err(Resource::Vectors);
+        }
+        let size = if complex { 16 } else { 8 };
+        let bytes = self.bytes.saturating_add(len.saturating_mul(size));
+        if exceeds(self.limits.max_bytes, bytes) {
+            return Err(Resource::Memory);
+        }
+        self.vectors += 1;
+        self.bytes = bytes;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_enforces_limits() {
+        let limits = ResourceLimits {
+            max_points: Some(100),
+            max_vectors: Some(3),
+            max_bytes: Some(2000),
+            max_runtime: None,
+        };
+        let mut budget = Budget::new(&limits);
+        assert_eq!(budget.admit(101, false), Err(Resource::Points));
+        assert_eq!(budget.admit(100, false), Ok(()));
+        assert_eq!(budget.admit(76, true), Err(Resource::Memory));
+        assert_eq!(budget.admit(50, true), Ok(()));
+        assert_eq!(budget.admit(10, false), Ok(()));
+        assert_eq!(budget.admit(1, false), Err(Resource::Vectors));
+        assert!(Budget::new(&ResourceLimits::default())
+            .admit(usize::MAX / 16, true)
+            .is_ok());
+    }
+}
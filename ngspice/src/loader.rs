@@ -0,0 +1,360 @@
+// Copyright 2022 Andrew Morrow.
+// loader.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Inlines `.include` and `.lib` references, so that decks split across files can be passed to
+//! [`NgSpice::simulate`], which requires a self-contained netlist.
+
+use crate::{Error, NgSpice, Simulation};
+use std::fmt::{self, Formatter};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Why a referenced file could not be inlined.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum IncludeProblem {
+    /// No such file relative to the including file or in any search directory.
+    NotFound,
+    /// The library file has no `.lib` section with the contained name.
+    MissingSection(String),
+    /// The file includes itself, directly or through other files.
+    Cycle,
+    /// Includes are nested more deeply than [`CircuitLoader::max_depth`].
+    TooDeep,
+    /// The file is outside every allowed directory of a confined loader.
+    OutsideSearchPath,
+}
+
+impl fmt::Display for IncludeProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            IncludeProblem::NotFound => f.write_str("file not found"),
+            IncludeProblem::MissingSection(s) => write!(f, "no library section {}", s),
+            IncludeProblem::Cycle => f.write_str("file includes itself"),
+            IncludeProblem::TooDeep => f.write_str("includes nested too deeply"),
+            IncludeProblem::OutsideSearchPath => f.write_str("file is outside the search path"),
+        }
+    }
+}
+
+/// Resolves `.include file` and `.lib file section` cards against a search path and inlines
+/// the referenced text.
+///
+/// Relative paths are looked up next to the file that references them, then in each search
+/// directory in order. `.end` cards in included files are dropped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CircuitLoader {
+    pub search_path: Vec<PathBuf>,
+    /// Whether referenced files must lie within the search directories or the directory of
+    /// the top-level deck. Paths are compared after removing `.` and `..` components, but
+    /// symbolic links are not followed.
+    pub confine: bool,
+    pub max_depth: usize,
+}
+
+impl Default for CircuitLoader {
+    fn default() -> Self {
+        CircuitLoader {
+            search_path: Vec::new(),
+            confine: false,
+            max_depth: 16,
+        }
+    }
+}
+
+/// Splits a card into its lowercase name and its arguments, with quotes removed. A quoted
+/// argument is one word even if it contains spaces, e.g. `"my models/x.mod"`.
+fn card(line: &str) -> (String, Vec<String>) {
+    let mut words = Vec::new();
+    let mut rest = line.trim_start();
+    while let Some(first) = rest.chars().next() {
+        let (word, after) = match first {
+            '"' | '\'' => match rest[1..].find(first) {
+                Some(end) => (&rest[1..end + 1], &rest[end + 2..]),
+                None => (&rest[1..], ""),
+            },
+            _ => rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len())),
+        };
+        words.push(word.to_owned());
+        rest = after.trim_start();
+    }
+    let mut words = words.into_iter();
+    let name = words.next().unwrap_or_default().to_ascii_lowercase();
+    (name, words.collect())
+}
+
+/// Removes `.` and `..` components without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    out.push("..");
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The lines of one `.lib name` ... `.endl` section of a library file.
+fn section<'a>(text: &'a str, name: &str) -> Option<Vec<&'a str>> {
+    let mut lines = text.lines();
+    lines.by_ref().find(|l| {
+        let (card, args) = card(l);
+        card == ".lib" && args.len() == 1 && args[0].eq_ignore_ascii_case(name)
+    })?;
+    Some(lines.take_while(|l| card(l).0 != ".endl").collect())
+}
+
+struct Expansion<'a> {
+    loader: &'a CircuitLoader,
+    read: &'a mut dyn FnMut(&Path) -> io::Result<String>,
+    allowed: Vec<PathBuf>,
+    /// The files and sections being expanded, outermost first.
+    stack: Vec<(PathBuf, Option<String>)>,
+    out: Vec<String>,
+}
+
+impl Expansion<'_> {
+    fn expand<'t>(
+        &mut self,
+        lines: impl Iterator<Item = &'t str>,
+        base: Option<&Path>,
+    ) -> Result<(), Error> {
+        for line in lines {
+            let (name, args) = card(line);
+            match (name.as_str(), args.as_slice()) {
+                (".include" | ".inc", [file, ..]) => self.inline(file, None, base)?,
+                (".lib", [file, section]) => self.inline(file, Some(section), base)?,
+                (".end", _) if !self.stack.is_empty() => {}
+                _ => self.out.push(line.to_owned()),
+            }
+        }
+        Ok(())
+    }
+
+    fn inline(
+        &mut self,
+        file: &str,
+        section: Option<&str>,
+        base: Option<&Path>,
+    ) -> Result<(), Error> {
+        let (path, text) = self.find(file, base)?;
+        let error = |problem| Error::Include {
+            path: path.clone(),
+            problem,
+        };
+        let key = (path.clone(), section.map(str::to_ascii_lowercase));
+        if self.stack.contains(&key) {
+            return Err(error(IncludeProblem::Cycle));
+        }
+        if self.stack.len() >= self.loader.max_depth {
+            return Err(error(IncludeProblem::TooDeep));
+        }
+        let lines = match section {
+            Some(s) => section_lines(&text, s)
+                .ok_or_else(|| error(IncludeProblem::MissingSection(s.to_owned())))?,
+            None => text.lines().map(str::to_owned).collect(),
+        };
+        self.stack.push(key);
+        let parent = path.parent().map(Path::to_path_buf);
+        self.expand(lines.iter().map(String::as_str), parent.as_deref())?;
+        self.stack.pop();
+        Ok(())
+    }
+
+    /// Finds and reads a referenced file.
+    fn find(&mut self, file: &str, base: Option<&Path>) -> Result<(PathBuf, String), Error> {
+        let file = Path::new(file);
+        let candidates: Vec<PathBuf> = if file.is_absolute() {
+            vec![file.to_path_buf()]
+        } else {
+            base.into_iter()
+                .chain(self.loader.search_path.iter().map(PathBuf::as_path))
+                .map(|dir| dir.join(file))
+                .collect()
+        };
+        for candidate in candidates {
+            let candidate = normalize(&candidate);
+            if self.loader.confine && !self.allowed.iter().any(|d| candidate.starts_with(d)) {
+                return Err(Error::Include {
+                    path: candidate,
+                    problem: IncludeProblem::OutsideSearchPath,
+                });
+            }
+            match (self.read)(&candidate) {
+                Ok(text) => return Ok((candidate, text)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+        Err(Error::Include {
+            path: file.to_path_buf(),
+            problem: IncludeProblem::NotFound,
+        })
+    }
+}
+
+fn section_lines(text: &str, name: &str) -> Option<Vec<String>> {
+    section(text, name).map(|lines| lines.into_iter().map(str::to_owned).collect())
+}
+
+impl CircuitLoader {
+    pub fn new() -> Self {
+        CircuitLoader::default()
+    }
+
+    /// Adds a directory to search for referenced files.
+    pub fn search(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.search_path.push(dir.into());
+        self
+    }
+
+    /// Only allows files within the search directories or the top-level deck's directory.
+    pub fn confine(mut self, confine: bool) -> Self {
+        self.confine = confine;
+        self
+    }
+
+    /// Reads a deck and inlines everything it references.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Include`] if the deck or a file it references cannot be found or
+    /// inlined, or [`Error::Io`] if a file cannot be read.
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<String, Error> {
+        let path = path.as_ref();
+        let deck = std::fs::read_to_string(path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::Include {
+                path: path.to_path_buf(),
+                problem: IncludeProblem::NotFound,
+            },
+            _ => Error::Io(e),
+        })?;
+        self.resolve(&deck, path.parent())
+    }
+
+    /// Inlines everything a deck references. `base` is the directory relative paths in the
+    /// deck are resolved against first, usually the deck's own directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Include`] if a referenced file cannot be found or inlined, or
+    /// [`Error::Io`] if one cannot be read.
+    pub fn resolve(&self, deck: &str, base: Option<&Path>) -> Result<String, Error> {
+        self.resolve_with(deck, base, &mut |p| std::fs::read_to_string(p))
+    }
+
+    fn resolve_with(
+        &self,
+        deck: &str,
+        base: Option<&Path>,
+        read: &mut dyn FnMut(&Path) -> io::Result<String>,
+    ) -> Result<String, Error> {
+        let allowed = base
+            .into_iter()
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(normalize)
+            .collect();
+        let mut expansion = Expansion {
+            loader: self,
+            read,
+            allowed,
+            stack: Vec::new(),
+            out: Vec::new(),
+        };
+        let mut lines = deck.lines();
+        // the title is never a card
+        expansion.out.extend(lines.next().map(str::to_owned));
+        expansion.expand(lines, base)?;
+        Ok(expansion.out.join("\n"))
+    }
+
+    /// Loads a deck with [`CircuitLoader::load`] and simulates it.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`CircuitLoader::load`] and [`NgSpice::simulate`].
+    pub fn simulate(&self, path: impl AsRef<Path>, command: &str) -> Result<Simulation, Error> {
+        NgSpice::simulate(&self.load(path)?, command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn inlines_includes_and_sections() {
+        let files: HashMap<PathBuf, &str> = HashMap::from([
+            (PathBuf::from("/deck/sub.cir"), "R2 b 0 1k\n.end"),
+            (
+                PathBuf::from("/models/corners.lib"),
+                ".lib tt\n.include nmos.mod\n.endl tt\n.lib ss\n.model n nmos vto=0.8\n.endl",
+            ),
+            (PathBuf::from("/models/nmos.mod"), ".model n nmos vto=0.7"),
+            (PathBuf::from("/deck/loop.cir"), ".include loop.cir"),
+        ]);
+        let mut read = |p: &Path| {
+            files
+                .get(p)
+                .map(|s| s.to_string())
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+        };
+        let loader = CircuitLoader::new().search("/models");
+        let base = Some(Path::new("/deck"));
+        let deck = "* top\n.include './sub.cir'\n.lib \"corners.lib\" TT\nR1 a b 1k\n.end";
+        assert_eq!(
+            card(".include \"my models/x.mod\""),
+            (".include".to_owned(), vec!["my models/x.mod".to_owned()])
+        );
+        assert_eq!(
+            loader.resolve_with(deck, base, &mut read).unwrap(),
+            "* top\nR2 b 0 1k\n.model n nmos vto=0.7\nR1 a b 1k\n.end"
+        );
+
+        let problem = |deck: &str,
+                       loader: &CircuitLoader,
+                       read: &mut dyn FnMut(&Path) -> io::Result<String>| {
+            match loader.resolve_with(deck, base, read) {
+                Err(Error::Include { problem, .. }) => problem,
+                other => panic!("{:?}", other),
+            }
+        };
+        assert_eq!(
+            problem("*\n.inc gone.cir", &loader, &mut read),
+            IncludeProblem::NotFound
+        );
+        assert_eq!(
+            problem("*\n.lib corners.lib ff", &loader, &mut read),
+            IncludeProblem::MissingSection("ff".to_owned())
+        );
+        assert_eq!(
+            problem("*\n.inc loop.cir", &loader, &mut read),
+            IncludeProblem::Cycle
+        );
+        let confined = CircuitLoader::new().confine(true);
+        assert_eq!(
+            problem("*\n.inc ../models/nmos.mod", &confined, &mut read),
+            IncludeProblem::OutsideSearchPath
+        );
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright 2022 Andrew Morrow.
+// magnetics.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Nonlinear inductor and transformer models built from core datasheet values.
+
+use std::f64::consts::PI;
+use std::fmt::Write;
+
+/// The permeability of free space, in henries per meter.
+const MU0: f64 = 4e-7 * PI;
+
+/// The magnetic properties of a core material.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CoreMaterial {
+    /// The initial relative permeability.
+    pub permeability: f64,
+    /// The saturation flux density, in teslas.
+    pub saturation: f64,
+}
+
+/// A core's effective dimensions, as given on its datasheet.
+///
+/// The B-H curve is the anhysteretic `B = Bsat tanh(μ H / Bsat)`, with the air gap shearing
+/// it. Hysteresis and core loss are not modeled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Core {
+    pub material: CoreMaterial,
+    /// The effective cross-sectional area Ae, in square meters.
+    pub area: f64,
+    /// The effective magnetic path length le, in meters.
+    pub path_length: f64,
+    /// The total air gap, in meters.
+    pub gap: f64,
+}
+
+impl Core {
+    /// The reluctance of the unsaturated core and gap, in amp-turns per weber.
+    pub fn reluctance(&self) -> f64 {
+        (self.path_length / self.material.permeability + self.gap) / (MU0 * self.area)
+    }
+
+    /// The small-signal inductance of a winding, in henries.
+    pub fn inductance(&self, turns: f64) -> f64 {
+        turns * turns / self.reluctance()
+    }
+
+    /// The winding current at which the unsaturated flux would reach saturation, in amps.
+    /// The tanh curve is at 76% of saturation here.
+    pub fn saturation_current(&self, turns: f64) -> f64 {
+        self.material.saturation * self.area * self.reluctance() / turns
+    }
+
+    /// The flux density produced by `mmf` amp-turns, in teslas.
+    pub fn flux_density(&self, mmf: f64) -> f64 {
+        let bsat = self.material.saturation;
+        bsat * (mmf / (self.reluctance() * self.area * bsat)).tanh()
+    }
+
+    /// Renders a saturating inductor as a subcircuit with ports `a` and `b`.
+    ///
+    /// The winding voltage is the derivative of the flux linkage, so the model works in
+    /// transient analyses only.
+    pub fn inductor(&self, name: &str, turns: f64) -> String {
+        let bsat = self.material.saturation;
+        let mut out = format!(".subckt {} a b\n", name);
+        out.push_str("Vsense a n DC 0\n");
+        writeln!(
+            out,
+            "Bflux n b V=ddt({:e}*tanh({:e}*i(Vsense)))",
+            turns * self.area * bsat,
+            turns / (self.reluctance() * self.area * bsat)
+        )
+        .unwrap();
+        writeln!(out, ".ends {}", name).unwrap();
+        out
+    }
+
+    /// Renders a transformer on this core as a subcircuit using XSPICE's `lcouple` and `core`
+    /// models. Winding `k` (from 1) has ports `p{k}` and `n{k}`; dots are on the `p` ports.
+    ///
+    /// The gap is folded into the tabulated B-H curve, which is sampled up to 99% of
+    /// saturation.
+    pub fn transformer(&self, name: &str, turns: &[f64]) -> String {
+        let ports: Vec<String> = (1..=turns.len())
+            .map(|k| format!("p{} n{}", k, k))
+            .collect();
+        let mut out = format!(".subckt {} {}\n", name, ports.join(" "));
+        // the windings' mmf ports form a loop through the core
+        for (k, n) in turns.iter().enumerate() {
+            let from = if k == 0 {
+                "0".to_owned()
+            } else {
+                format!("m{}", k)
+            };
+            writeln!(
+                out,
+                "A{} (p{} n{}) ({} m{}) winding{}",
+                k + 1,
+                k + 1,
+                k + 1,
+                from,
+                k + 1,
+                k + 1
+            )
+            .unwrap();
+            writeln!(out, ".model winding{} lcouple (num_turns={:e})", k + 1, n).unwrap();
+        }
+        writeln!(out, "Acore (m{} 0) core", turns.len()).unwrap();
+        let (h, b) = self.bh_table(16);
+        let array = |v: &[f64]| {
+            v.iter()
+                .map(|x| format!("{:e}", x))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        writeln!(
+            out,
+            ".model core core (H_array=[{}] B_array=[{}] area={:e} length={:e})",
+            array(&h),
+            array(&b),
+            self.area,
+            self.path_length
+        )
+        .unwrap();
+        writeln!(out, ".ends {}", name).unwrap();
+        out
+    }
+
+    /// Samples the sheared B-H curve symmetrically at `points` positive flux densities.
+    fn bh_table(&self, points: usize) -> (Vec<f64>, Vec<f64>) {
+        let bsat = self.material.saturation;
+        let mu = MU0 * self.material.permeability;
+        let mut positive: Vec<(f64, f64)> = (1..=points)
+            .map(|k| {
+                let b = 0.99 * bsat * k as f64 / points as f64;
+                // effective H over the path length: the core's own H plus the gap's share
+                let h = bsat / mu * (b / bsat).atanh() + b * self.gap / (MU0 * self.path_length);
+                (h, b)
+            })
+            .collect();
+        // beyond the table the core behaves like air
+        let (h_last, b_last) = positive[points - 1];
+        positive.push((h_last * 10.0, b_last + MU0 * h_last * 9.0));
+        let mut h: Vec<f64> = positive.iter().rev().map(|p| -p.0).collect();
+        let mut b: Vec<f64> = positive.iter().rev().map(|p| -p.1).collect();
+        h.push(0.0);
+        b.push(0.0);
+        h.extend(positive.iter().map(|p| p.0));
+        b.extend(positive.iter().map(|p| p.1));
+        (h, b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn models_gapped_ferrite() {
+        // roughly an E25 core in a power ferrite
+        let core = Core {
+            material: CoreMaterial {
+                permeability: 2000.0,
+                saturation: 0.4,
+            },
+            area: 52e-6,
+            path_length: 57.5e-3,
+            gap: 0.5e-3,
+        };
+        let l = core.inductance(20.0);
+        assert!((l - 49.43e-6).abs() < 0.01e-6, "{}", l);
+        let isat = core.saturation_current(20.0);
+        assert!((core.flux_density(isat * 20.0) - 0.4 * 1f64.tanh()).abs() < 1e-12);
+        assert!(core.flux_density(1e6) <= 0.4);
+        assert!(core
+            .inductor("l1", 20.0)
+            .contains("Bflux n b V=ddt(4.16e-4*tanh("));
+        let xfmr = core.transformer("t1", &[20.0, 5.0]);
+        assert!(xfmr.starts_with(".subckt t1 p1 n1 p2 n2\nA1 (p1 n1) (0 m1) winding1\n"));
+        assert!(xfmr.contains("A2 (p2 n2) (m1 m2) winding2\n"));
+        assert!(xfmr.contains("Acore (m2 0) core\n"));
+        let (h, b) = core.bh_table(4);
+        assert_eq!(h.len(), 11);
+        assert_eq!((h[5], b[5]), (0.0, 0.0));
+        assert!(h.windows(2).all(|w| w[0] < w[1]));
+    }
+}
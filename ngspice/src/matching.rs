@@ -0,0 +1,335 @@
+// Copyright 2022 Andrew Morrow.
+// matching.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Designs L, Pi, and T impedance matching networks.
+
+use crate::filter::Placement;
+use crate::{Error, NgSpice};
+use num_complex::Complex64;
+use std::f64::consts::PI;
+use std::fmt::Write;
+
+/// The shape of a matching network.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Topology {
+    /// Two elements. The loaded Q is fixed by the impedance ratio.
+    L,
+    /// Shunt, series, shunt, with the given loaded Q. The Q must exceed that of an L network
+    /// between the same impedances.
+    Pi { q: f64 },
+    /// Series, shunt, series, with the given loaded Q. The Q must exceed that of an L network
+    /// between the same impedances.
+    T { q: f64 },
+}
+
+/// A lumped reactive component.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Component {
+    /// In henries.
+    Inductor(f64),
+    /// In farads.
+    Capacitor(f64),
+}
+
+/// One element of a matching network.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatchElement {
+    pub placement: Placement,
+    pub component: Component,
+}
+
+/// A matching network, listed from the source end.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchingNetwork {
+    /// In Hz.
+    pub frequency: f64,
+    pub elements: Vec<MatchElement>,
+}
+
+/// A series reactance in ohms or a shunt susceptance in siemens, before it is turned into
+/// a component.
+type Immittance = (Placement, f64);
+
+/// Solves an L network that presents `target` to the source while terminated by `load`, with
+/// the shunt element next to the load. Returns both solutions, source side first.
+fn shunt_at_load(target: Complex64, load: Complex64) -> Vec<[Immittance; 2]> {
+    let y = 1.0 / load;
+    let (g, b) = (y.re, y.im);
+    let disc = g / target.re - g * g;
+    if disc < 0.0 || target.re <= 0.0 {
+        return Vec::new();
+    }
+    [disc.sqrt(), -disc.sqrt()]
+        .iter()
+        .map(|&total| {
+            let z = 1.0 / Complex64::new(g, total);
+            [
+                (Placement::Series, target.im - z.im),
+                (Placement::Shunt, total - b),
+            ]
+        })
+        .collect()
+}
+
+/// Solves an L network that presents `target` to the source while terminated by `load`, with
+/// the series element next to the load. Returns both solutions, source side first.
+fn series_at_load(target: Complex64, load: Complex64) -> Vec<[Immittance; 2]> {
+    let y = 1.0 / target;
+    let disc = load.re / y.re - load.re * load.re;
+    if disc < 0.0 || y.re <= 0.0 {
+        return Vec::new();
+    }
+    [disc.sqrt(), -disc.sqrt()]
+        .iter()
+        .map(|&total| {
+            let yl = 1.0 / Complex64::new(load.re, total);
+            [
+                (Placement::Shunt, y.im - yl.im),
+                (Placement::Series, total - load.im),
+            ]
+        })
+        .collect()
+}
+
+/// Reverses an L network designed from the load's point of view so it reads from the source.
+fn mirror(half: [Immittance; 2]) -> [Immittance; 2] {
+    [half[1], half[0]]
+}
+
+/// Combines adjacent elements of the same placement, which add as reactances in series or
+/// susceptances in shunt.
+fn merge(elements: Vec<Immittance>) -> Vec<Immittance> {
+    let mut out: Vec<Immittance> = Vec::new();
+    for (placement, x) in elements {
+        match out.last_mut() {
+            Some(last) if last.0 == placement => last.1 += x,
+            _ => out.push((placement, x)),
+        }
+    }
+    out
+}
+
+/// Designs every network of the given topology that matches `source` to `load` at
+/// `frequency` Hz, i.e. that presents the complex conjugate of `source` to the source.
+///
+/// Returns an empty list if the topology cannot match the impedances, e.g. when the Q of a Pi
+/// or T network is too low.
+pub fn design(
+    source: Complex64,
+    load: Complex64,
+    frequency: f64,
+    topology: Topology,
+) -> Vec<MatchingNetwork> {
+    let target = source.conj();
+    let solutions: Vec<Vec<Immittance>> = match topology {
+        Topology::L => shunt_at_load(target, load)
+            .into_iter()
+            .chain(series_at_load(target, load))
+            .map(|x| x.to_vec())
+            .collect(),
+        Topology::Pi { q } => {
+            // through a virtual resistance below both terminations
+            let rv = source.re.max(load.re) / (q * q + 1.0);
+            let v = Complex64::new(rv, 0.0);
+            let mut result = Vec::new();
+            for load_half in shunt_at_load(v, load) {
+                for source_half in shunt_at_load(v, source) {
+                    let mut all = mirror(source_half).to_vec();
+                    all.extend(load_half);
+                    result.push(merge(all));
+                }
+            }
+            result
+        }
+        Topology::T { q } => {
+            // through a virtual resistance above both terminations
+            let rv = source.re.min(load.re) * (q * q + 1.0);
+            let v = Complex64::new(rv, 0.0);
+            let mut result = Vec::new();
+            for load_half in series_at_load(v, load) {
+                for source_half in series_at_load(v, source) {
+                    let mut all = mirror(source_half).to_vec();
+                    all.extend(load_half);
+                    result.push(merge(all));
+                }
+            }
+            result
+        }
+    };
+    let w = 2.0 * PI * frequency;
+    solutions
+        .into_iter()
+        .map(|elements| MatchingNetwork {
+            frequency,
+            elements: elements
+                .into_iter()
+                .filter(|&(_, x)| x.abs() > 1e-12)
+                .map(|(placement, x)| MatchElement {
+                    placement,
+                    component: match placement {
+                        Placement::Series if x > 0.0 => Component::Inductor(x / w),
+                        Placement::Series => Component::Capacitor(-1.0 / (w * x)),
+                        Placement::Shunt if x > 0.0 => Component::Capacitor(x / w),
+                        Placement::Shunt => Component::Inductor(-1.0 / (w * x)),
+                    },
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+impl Component {
+    fn impedance(&self, w: f64) -> Complex64 {
+        match *self {
+            Component::Inductor(l) => Complex64::new(0.0, w * l),
+            Component::Capacitor(c) => Complex64::new(0.0, -1.0 / (w * c)),
+        }
+    }
+}
+
+/// Renders a resistance in series with a reactance from `a` to `b`, as seen at angular
+/// frequency `w`.
+fn impedance_lines(out: &mut String, name: &str, a: &str, b: &str, z: Complex64, w: f64) {
+    if z.im == 0.0 {
+        writeln!(out, "R{} {} {} {:e}", name, a, b, z.re).unwrap();
+        return;
+    }
+    writeln!(out, "R{} {} {}_x {:e}", name, a, name, z.re).unwrap();
+    if z.im > 0.0 {
+        writeln!(out, "L{} {}_x {} {:e}", name, name, b, z.im / w).unwrap();
+    } else {
+        writeln!(out, "C{} {}_x {} {:e}", name, name, b, -1.0 / (w * z.im)).unwrap();
+    }
+}
+
+impl MatchingNetwork {
+    /// The impedance looking into the network when terminated by `load`, at the design
+    /// frequency.
+    pub fn input_impedance(&self, load: Complex64) -> Complex64 {
+        let w = 2.0 * PI * self.frequency;
+        let mut z = load;
+        for element in self.elements.iter().rev() {
+            let x = element.component.impedance(w);
+            z = match element.placement {
+                Placement::Series => z + x,
+                Placement::Shunt => 1.0 / (1.0 / z + 1.0 / x),
+            };
+        }
+        z
+    }
+
+    /// Renders the network's elements between nodes `in` and `out`.
+    pub fn netlist(&self) -> String {
+        let mut out = String::new();
+        let series = self
+            .elements
+            .iter()
+            .filter(|e| e.placement == Placement::Series)
+            .count();
+        if series == 0 {
+            out.push_str("Vlink in out DC 0\n");
+        }
+        let mut node = "in".to_owned();
+        let mut seen = 0;
+        for (k, element) in self.elements.iter().enumerate() {
+            let (a, b) = match element.placement {
+                Placement::Shunt => (node.clone(), "0".to_owned()),
+                Placement::Series => {
+                    seen += 1;
+                    let next = if seen == series {
+                        "out".to_owned()
+                    } else {
+                        format!("n{}", k)
+                    };
+                    (std::mem::replace(&mut node, next.clone()), next)
+                }
+            };
+            match element.component {
+                Component::Inductor(l) => writeln!(out, "L{} {} {} {:e}", k, a, b, l),
+                Component::Capacitor(c) => writeln!(out, "C{} {} {} {:e}", k, a, b, c),
+            }
+            .unwrap();
+        }
+        out
+    }
+
+    /// Simulates the network between `source` and `load` with an AC analysis at the design
+    /// frequency and returns the simulated input impedance and return loss in dB.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the simulation fails or its results are missing.
+    pub fn verify(&self, source: Complex64, load: Complex64) -> Result<(Complex64, f64), Error> {
+        let w = 2.0 * PI * self.frequency;
+        let mut deck = String::from("* matching network\nVsrc src 0 AC 1\n");
+        impedance_lines(&mut deck, "src", "src", "in", source, w);
+        deck.push_str(&self.netlist());
+        impedance_lines(&mut deck, "load", "out", "0", load, w);
+        deck.push_str(".end\n");
+        let cmd = format!("ac lin 1 {:e} {:e}", self.frequency, self.frequency);
+        let sim = NgSpice::simulate(&deck, &cmd)?;
+        let value = |name: &str| {
+            sim.vectors
+                .get(name)
+                .and_then(|v| v.values.complex())
+                .and_then(|x| x.first().copied())
+                .ok_or_else(|| Error::MissingVector(name.to_owned()))
+        };
+        // ngSPICE reports source current flowing into the positive terminal
+        let zin = value("in")? / -value("vsrc#branch")?;
+        let gamma = (zin - source.conj()) / (zin + source);
+        Ok((zin, -20.0 * gamma.norm().log10()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_impedances() {
+        let source = Complex64::new(50.0, 0.0);
+        let load = Complex64::new(10.0, -15.0);
+        for topology in [Topology::L, Topology::Pi { q: 5.0 }, Topology::T { q: 5.0 }] {
+            let networks = design(source, load, 100e6, topology);
+            assert!(!networks.is_empty(), "{:?}", topology);
+            for n in &networks {
+                let zin = n.input_impedance(load);
+                assert!((zin - source.conj()).norm() < 1e-6, "{:?}", topology);
+            }
+        }
+        // the load's parallel resistance is below 50 ohms, so only series-at-load works
+        assert_eq!(design(source, load, 100e6, Topology::L).len(), 2);
+        assert!(design(source, load, 100e6, Topology::Pi { q: 0.5 }).is_empty());
+
+        let pi = &design(
+            source,
+            Complex64::new(50.0, 0.0),
+            1e6,
+            Topology::Pi { q: 2.0 },
+        )[0];
+        let placements: Vec<Placement> = pi.elements.iter().map(|e| e.placement).collect();
+        assert_eq!(
+            placements,
+            vec![Placement::Shunt, Placement::Series, Placement::Shunt]
+        );
+        let lines: Vec<String> = pi.netlist().lines().map(str::to_owned).collect();
+        assert!(lines[0].contains(" in 0 "));
+        assert!(lines[1].contains(" in out "));
+        assert!(lines[2].contains(" out 0 "));
+    }
+}
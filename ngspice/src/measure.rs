@@ -0,0 +1,162 @@
+// Copyright 2022 Andrew Morrow.
+// measure.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! `.meas` statements attached to simulations, with their results parsed out of ngSPICE's
+//! output.
+
+use crate::circuit::logical_lines;
+use crate::control::parse_measurements;
+use crate::session::Session;
+use crate::{Error, NgSpice, Simulation};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+/// One measurement, such as a rise time between a `TRIG` and a `TARG` crossing.
+///
+/// ```
+/// use ngspice::measure::Measurement;
+/// let m = Measurement::new("tran", "rise_time", "TRIG v(out) VAL=0.1 RISE=1 TARG v(out) VAL=0.9 RISE=1");
+/// assert_eq!(m.to_string(), ".meas tran rise_time TRIG v(out) VAL=0.1 RISE=1 TARG v(out) VAL=0.9 RISE=1");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Measurement {
+    /// The analysis the measurement applies to: `tran`, `ac`, `dc` or `sp`.
+    pub analysis: String,
+    /// The measurement's name, lowercased as ngSPICE reports it.
+    pub name: String,
+    /// Everything after the name, e.g. `MAX v(out)` or `TRIG ... TARG ...`.
+    pub spec: String,
+}
+
+impl Measurement {
+    pub fn new(analysis: &str, name: &str, spec: &str) -> Self {
+        Measurement {
+            analysis: analysis.to_ascii_lowercase(),
+            name: name.to_ascii_lowercase(),
+            spec: spec.trim().to_owned(),
+        }
+    }
+
+    /// Parses a `.meas`/`.measure` card or a `meas` command.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let keyword = words.next()?.trim_start_matches('.').to_ascii_lowercase();
+        if keyword != "meas" && keyword != "measure" {
+            return None;
+        }
+        let analysis = words.next()?;
+        let name = words.next()?;
+        Some(Measurement::new(
+            analysis,
+            name,
+            &words.collect::<Vec<_>>().join(" "),
+        ))
+    }
+
+    /// The interactive form, for a [`Session`] after the analysis has run.
+    pub fn command(&self) -> String {
+        format!("meas {} {} {}", self.analysis, self.name, self.spec)
+    }
+}
+
+impl Display for Measurement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, ".meas {} {} {}", self.analysis, self.name, self.spec)
+    }
+}
+
+/// Every `.meas` card already in `circuit`.
+pub fn measurements(circuit: &str) -> Vec<Measurement> {
+    logical_lines(circuit)
+        .iter()
+        .filter(|l| l.trim_start().starts_with('.'))
+        .filter_map(|l| Measurement::parse(l))
+        .collect()
+}
+
+/// Adds `cards` to `circuit` just before its `.end`, which they must precede.
+pub fn attach(circuit: &str, cards: &[Measurement]) -> String {
+    let mut lines: Vec<String> = circuit.lines().map(str::to_owned).collect();
+    let end = lines
+        .iter()
+        .rposition(|l| l.trim().eq_ignore_ascii_case(".end"))
+        .unwrap_or(lines.len());
+    lines.splice(end..end, cards.iter().map(Measurement::to_string));
+    lines.join("\n")
+}
+
+impl NgSpice {
+    /// Like [`NgSpice::simulate`], but with `cards` added to the circuit. Returns the results
+    /// of those measurements and of any `.meas` cards already in the circuit, by name.
+    /// Measurements that ngSPICE could not evaluate, e.g. because a crossing never happened,
+    /// are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`NgSpice::simulate`].
+    pub fn simulate_measured(
+        circuit: &str,
+        command: &str,
+        cards: &[Measurement],
+    ) -> Result<(Simulation, HashMap<String, f64>), Error> {
+        let circuit = attach(circuit, cards);
+        let names: Vec<String> = measurements(&circuit).into_iter().map(|m| m.name).collect();
+        let sim = NgSpice::simulate(&circuit, command)?;
+        let results = parse_measurements(&sim.stdout, &names);
+        Ok((sim, results))
+    }
+}
+
+impl Session {
+    /// Runs a measurement on the current plot, e.g. after [`Session::run`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if ngSPICE rejects the command, or [`Error::MissingVector`] if the
+    /// measurement could not be evaluated.
+    pub fn measure(&mut self, measurement: &Measurement) -> Result<f64, Error> {
+        self.command(&measurement.command())?;
+        let names = [measurement.name.clone()];
+        parse_measurements(&self.stdout(), &names)
+            .remove(&measurement.name)
+            .ok_or_else(|| Error::MissingVector(measurement.name.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attaches_measurements() {
+        let deck = "* t\nV1 in 0 PULSE(0 1 0 1n)\nR1 in out 1k\nC1 out 0 1n\n\
+                    .meas tran vmax MAX v(out)\n.end";
+        let rise = Measurement::new(
+            "TRAN",
+            "Rise",
+            "TRIG v(out) VAL=0.1 RISE=1 TARG v(out) VAL=0.9 RISE=1",
+        );
+        let deck = attach(deck, std::slice::from_ref(&rise));
+        assert!(deck.ends_with(&format!("{}\n.end", rise)));
+        let found = measurements(&deck);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0], Measurement::new("tran", "vmax", "MAX v(out)"));
+        assert_eq!(found[1], rise);
+        assert_eq!(rise.command(), format!("meas tran rise {}", rise.spec));
+        assert!(Measurement::parse("R1 a b 1k").is_none());
+    }
+}
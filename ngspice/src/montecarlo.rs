@@ -0,0 +1,443 @@
+// Copyright 2022 Andrew Morrow.
+// montecarlo.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Monte Carlo analysis over toleranced `.param` values.
+
+use crate::campaign::{Campaign, Tags};
+use crate::cancel::CancellationToken;
+use crate::circuit::Circuit;
+use crate::random::Rng;
+use crate::stats::normal_cdf;
+use crate::Error;
+use std::collections::BTreeMap;
+
+/// How a toleranced value is distributed between its limits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Distribution {
+    /// Equally likely anywhere within the tolerance.
+    Uniform,
+    /// Normally distributed, with the tolerance at 3σ. Samples are not truncated.
+    Gaussian,
+}
+
+/// A nominal value and how far it may deviate, e.g. a 1% resistor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerance {
+    pub nominal: f64,
+    /// The allowed deviation as a fraction of `nominal`, e.g. `0.01` for ±1%.
+    pub relative: f64,
+    pub distribution: Distribution,
+}
+
+impl Tolerance {
+    pub fn uniform(nominal: f64, relative: f64) -> Self {
+        Tolerance {
+            nominal,
+            relative,
+            distribution: Distribution::Uniform,
+        }
+    }
+
+    pub fn gaussian(nominal: f64, relative: f64) -> Self {
+        Tolerance {
+            nominal,
+            relative,
+            distribution: Distribution::Gaussian,
+        }
+    }
+
+    /// The smallest value within tolerance.
+    pub fn min(&self) -> f64 {
+        self.nominal - (self.nominal * self.relative).abs()
+    }
+
+    /// The largest value within tolerance.
+    pub fn max(&self) -> f64 {
+        self.nominal + (self.nominal * self.relative).abs()
+    }
+
+    /// The value at `z` standard deviations of a standard normal variable. A uniform
+    /// tolerance maps `z` through the normal CDF onto its range.
+    pub fn at(&self, z: f64) -> f64 {
+        let deviation = match self.distribution {
+            Distribution::Uniform => 2.0 * normal_cdf(z) - 1.0,
+            Distribution::Gaussian => z / 3.0,
+        };
+        self.nominal * (1.0 + self.relative * deviation)
+    }
+
+    /// Draws a random value.
+    pub fn sample(&self, rng: &mut Rng) -> f64 {
+        let deviation = match self.distribution {
+            Distribution::Uniform => 2.0 * rng.uniform() - 1.0,
+            Distribution::Gaussian => rng.normal() / 3.0,
+        };
+        self.nominal * (1.0 + self.relative * deviation)
+    }
+}
+
+/// The local variation of one device instance's parameter, e.g. the threshold voltage of one
+/// transistor in a differential pair.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mismatch {
+    pub nominal: f64,
+    /// The standard deviation, in the parameter's units.
+    pub sigma: f64,
+}
+
+impl Mismatch {
+    pub fn new(nominal: f64, sigma: f64) -> Self {
+        Mismatch { nominal, sigma }
+    }
+
+    /// A mismatch that follows Pelgrom's law, σ = A / √(W·L), for a device of width `w` and
+    /// length `l` and a process coefficient `a`, e.g. in V·m for threshold voltage.
+    pub fn pelgrom(nominal: f64, a: f64, w: f64, l: f64) -> Self {
+        Mismatch::new(nominal, a / (w * l).sqrt())
+    }
+}
+
+/// Which kinds of variation a Monte Carlo run samples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// Only the global parameters added with [`MonteCarlo::param`]; mismatched parameters are
+    /// held at their nominal values.
+    Global,
+    /// Only the mismatched parameters added with [`MonteCarlo::mismatch`], as for offset
+    /// statistics at a fixed process corner.
+    Local,
+    Both,
+}
+
+/// Runs a circuit many times with its toleranced parameters randomized.
+///
+/// Circuits refer to the parameters as `{name}`. Each run defines them with `.param` cards
+/// placed after the title, replacing any existing definitions of the same names.
+///
+/// Global parameters model process variation and take one value per run for the whole
+/// circuit. Mismatched parameters model local variation: each device instance has its own
+/// parameter, drawn independently unless [`MonteCarlo::correlate`] pairs it with another.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MonteCarlo {
+    pub parameters: BTreeMap<String, Tolerance>,
+    pub mismatch: BTreeMap<String, Mismatch>,
+    /// Pairs of mismatched parameters and the correlation between them.
+    pub correlations: Vec<(String, String, f64)>,
+    pub scope: Scope,
+    pub runs: usize,
+    pub seed: u64,
+}
+
+impl MonteCarlo {
+    pub fn new(runs: usize, seed: u64) -> Self {
+        MonteCarlo {
+            parameters: BTreeMap::new(),
+            mismatch: BTreeMap::new(),
+            correlations: Vec::new(),
+            scope: Scope::Both,
+            runs,
+            seed,
+        }
+    }
+
+    /// Adds or replaces a toleranced parameter.
+    pub fn param(mut self, name: &str, tolerance: Tolerance) -> Self {
+        self.parameters.insert(name.to_ascii_lowercase(), tolerance);
+        self
+    }
+
+    /// Adds or replaces several toleranced parameters.
+    pub fn params<I: IntoIterator<Item = (String, Tolerance)>>(mut self, params: I) -> Self {
+        for (name, tolerance) in params {
+            self = self.param(&name, tolerance);
+        }
+        self
+    }
+
+    /// Adds or replaces a mismatched parameter of one device instance. It replaces any global
+    /// parameter of the same name.
+    pub fn mismatch(mut self, name: &str, mismatch: Mismatch) -> Self {
+        self.mismatch.insert(name.to_ascii_lowercase(), mismatch);
+        self
+    }
+
+    /// Correlates two mismatched parameters, e.g. with a negative `correlation` for devices
+    /// whose gradients cancel in a common-centroid layout.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `correlation` is not between -1 and 1, or if `second` is already the second
+    /// parameter of another pair.
+    pub fn correlate(mut self, first: &str, second: &str, correlation: f64) -> Self {
+        assert!(
+            (-1.0..=1.0).contains(&correlation),
+            "correlation must be between -1 and 1"
+        );
+        let (first, second) = (first.to_ascii_lowercase(), second.to_ascii_lowercase());
+        assert!(
+            !self.correlations.iter().any(|(_, b, _)| *b == second),
+            "{} is already correlated",
+            second
+        );
+        self.correlations.push((first, second, correlation));
+        self
+    }
+
+    /// Chooses which kinds of variation to sample.
+    pub fn scope(mut self, scope: Scope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// The parameter values for one run. Each run has its own random stream, so any run can
+    /// be reproduced on its own, and a run draws the same values whatever its scope.
+    pub fn sample(&self, run: usize) -> BTreeMap<String, f64> {
+        let mut rng = Rng::new(self.seed ^ (run as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let global: Vec<f64> = self
+            .parameters
+            .values()
+            .map(|tol| tol.sample(&mut rng))
+            .collect();
+        let local: Vec<f64> = self.mismatch.keys().map(|_| rng.normal()).collect();
+        self.assemble(&global, &local)
+    }
+
+    /// The number of independent random variables behind each sample: one per global
+    /// parameter, then one per mismatched parameter.
+    pub fn dimensions(&self) -> usize {
+        self.parameters.len() + self.mismatch.len()
+    }
+
+    /// The parameter values at a point `z` of independent standard normal variables, one per
+    /// dimension. Uniform tolerances map `z` through the normal CDF, so every parameter is at
+    /// its nominal value when `z` is zero.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `z` does not have [`MonteCarlo::dimensions`] entries.
+    pub fn values_at(&self, z: &[f64]) -> BTreeMap<String, f64> {
+        assert_eq!(z.len(), self.dimensions(), "one variable per dimension");
+        let (global, local) = z.split_at(self.parameters.len());
+        let global: Vec<f64> = self
+            .parameters
+            .values()
+            .zip(global)
+            .map(|(tol, &z)| tol.at(z))
+            .collect();
+        self.assemble(&global, local)
+    }
+
+    /// Combines sampled global values with standard normal draws for each mismatched
+    /// parameter, applying the scope and correlations.
+    fn assemble(&self, global: &[f64], local: &[f64]) -> BTreeMap<String, f64> {
+        let mut values: BTreeMap<String, f64> = self
+            .parameters
+            .iter()
+            .zip(global)
+            .map(|((name, tol), &value)| {
+                let value = match self.scope {
+                    Scope::Local => tol.nominal,
+                    _ => value,
+                };
+                (name.clone(), value)
+            })
+            .collect();
+        let mut z: BTreeMap<&str, f64> = self
+            .mismatch
+            .keys()
+            .zip(local)
+            .map(|(name, &z)| (name.as_str(), z))
+            .collect();
+        for (first, second, rho) in &self.correlations {
+            if let (Some(&a), Some(&b)) = (z.get(first.as_str()), z.get(second.as_str())) {
+                z.insert(second, rho * a + (1.0 - rho * rho).sqrt() * b);
+            }
+        }
+        for (name, m) in &self.mismatch {
+            let deviation = match self.scope {
+                Scope::Global => 0.0,
+                _ => m.sigma * z[name.as_str()],
+            };
+            values.insert(name.clone(), m.nominal + deviation);
+        }
+        values
+    }
+
+    /// Runs every sample and collects the results, tagged with `run` and each parameter value.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first simulation error.
+    pub fn run(&self, circuit: &str, command: &str) -> Result<Campaign, Error> {
+        self.run_each(circuit, |campaign, tags, circuit| {
+            campaign.simulate(tags, circuit, command)
+        })
+    }
+
+    /// Like [`MonteCarlo::run`], but stops the sweep when `token` is cancelled. Each run gets
+    /// its own child of `token`, so cancelling the token halts the run in progress and skips
+    /// the rest.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first simulation error, or [`Error::Cancelled`] with the partial results of
+    /// the interrupted run.
+    pub fn run_cancellable(
+        &self,
+        circuit: &str,
+        command: &str,
+        token: &CancellationToken,
+    ) -> Result<Campaign, Error> {
+        self.run_each(circuit, |campaign, tags, circuit| {
+            campaign.simulate_cancellable(tags, circuit, command, token)
+        })
+    }
+
+    fn run_each<F>(&self, circuit: &str, mut simulate: F) -> Result<Campaign, Error>
+    where
+        F: FnMut(&mut Campaign, Tags, &str) -> Result<usize, Error>,
+    {
+        let mut campaign = Campaign::new("monte carlo");
+        for run in 0..self.runs {
+            let (tags, values) = self.point(run);
+            simulate(&mut campaign, tags, &apply_params(circuit, &values))?;
+        }
+        Ok(campaign)
+    }
+
+    /// The tags and parameter values of one run.
+    pub(crate) fn point(&self, run: usize) -> (Tags, BTreeMap<String, f64>) {
+        let values = self.sample(run);
+        let mut tags = Tags::new().with("run", run as f64);
+        for (name, &value) in &values {
+            tags = tags.with(name, value);
+        }
+        (tags, values)
+    }
+}
+
+impl MonteCarlo {
+    /// Like [`MonteCarlo::run`], but also varies every element annotated with
+    /// [`Circuit::set_tolerance`], as parameters named `tol_<element>`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first simulation error.
+    pub fn run_circuit(&self, circuit: &Circuit, command: &str) -> Result<Campaign, Error> {
+        let (circuit, params) = circuit.toleranced();
+        self.clone()
+            .params(params)
+            .run(&circuit.to_string(), command)
+    }
+}
+
+/// Defines `values` as `.param` cards after the title of `circuit`, removing any existing
+/// definitions of the same names.
+pub fn apply_params(circuit: &str, values: &BTreeMap<String, f64>) -> String {
+    let mut lines = circuit.lines();
+    let mut out: Vec<String> = lines.next().map(str::to_owned).into_iter().collect();
+    for (name, value) in values {
+        out.push(format!(".param {}={:e}", name, value));
+    }
+    for line in lines {
+        let mut words = line.split_whitespace();
+        if !words
+            .next()
+            .is_some_and(|w| w.eq_ignore_ascii_case(".param"))
+        {
+            out.push(line.to_owned());
+            continue;
+        }
+        let kept: Vec<&str> = words
+            .filter(|w| {
+                let name = w.split('=').next().unwrap_or("").to_ascii_lowercase();
+                !values.contains_key(&name)
+            })
+            .collect();
+        if !kept.is_empty() {
+            out.push(format!(".param {}", kept.join(" ")));
+        }
+    }
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_reproducibly() {
+        let mc = MonteCarlo::new(100, 1)
+            .param("R1", Tolerance::uniform(1e3, 0.01))
+            .param("vref", Tolerance::gaussian(1.2, 0.03));
+        assert_eq!(mc.sample(5), mc.sample(5));
+        assert_ne!(mc.sample(5), mc.sample(6));
+        for run in 0..mc.runs {
+            let r = mc.sample(run)["r1"];
+            assert!((990.0..=1010.0).contains(&r));
+        }
+        let values = mc.sample(0);
+        let circuit = apply_params("* title\n.param r1=1k gain=2\nR1 a b {r1}\n.end", &values);
+        let lines: Vec<&str> = circuit.lines().collect();
+        assert_eq!(lines[0], "* title");
+        assert!(lines[1].starts_with(".param r1="));
+        assert!(lines[2].starts_with(".param vref="));
+        assert_eq!(lines[3], ".param gain=2");
+        assert_eq!(lines[4], "R1 a b {r1}");
+    }
+
+    #[test]
+    fn samples_correlated_mismatch() {
+        let mc = MonteCarlo::new(2000, 7)
+            .param("vdd", Tolerance::uniform(1.8, 0.1))
+            .mismatch("vth1", Mismatch::pelgrom(0.4, 5e-9, 1e-6, 1e-6))
+            .mismatch("vth2", Mismatch::new(0.4, 5e-3))
+            .correlate("vth1", "vth2", -0.8);
+        let samples: Vec<_> = (0..mc.runs).map(|run| mc.sample(run)).collect();
+        let (a, b): (Vec<f64>, Vec<f64>) = samples
+            .iter()
+            .map(|s| (s["vth1"] - 0.4, s["vth2"] - 0.4))
+            .unzip();
+        let n = a.len() as f64;
+        let var = |x: &[f64]| x.iter().map(|v| v * v).sum::<f64>() / n;
+        let cov = a.iter().zip(&b).map(|(x, y)| x * y).sum::<f64>() / n;
+        assert!((var(&a).sqrt() - 5e-3).abs() < 5e-4);
+        assert!((cov / (var(&a) * var(&b)).sqrt() + 0.8).abs() < 0.05);
+
+        let local = mc.clone().scope(Scope::Local);
+        assert_eq!(local.sample(3)["vdd"], 1.8);
+        assert_eq!(local.sample(3)["vth2"], mc.sample(3)["vth2"]);
+        assert_eq!(mc.clone().scope(Scope::Global).sample(3)["vth1"], 0.4);
+    }
+
+    #[test]
+    fn parameterizes_element_tolerances() -> Result<(), Error> {
+        let mut circuit = Circuit::parse("* t\nV1 in 0 DC 5\nR1 in out 4.7k\nC1 out 0 100nF\n.end");
+        circuit.set_tolerance("R1", 0.01, Distribution::Uniform)?;
+        circuit.set_tolerance("c1", 0.1, Distribution::Gaussian)?;
+        assert!(circuit
+            .set_tolerance("V1", 0.01, Distribution::Uniform)
+            .is_err());
+        assert!((circuit.tolerances()["c1"].nominal - 100e-9).abs() < 1e-20);
+        let (deck, params) = circuit.toleranced();
+        assert_eq!(params["tol_r1"], Tolerance::uniform(4.7e3, 0.01));
+        let deck = deck.to_string();
+        assert!(deck.contains("R1 in out {tol_r1}\n"));
+        assert!(deck.contains("C1 out 0 {tol_c1}\n"));
+        Ok(())
+    }
+}
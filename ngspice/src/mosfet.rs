@@ -0,0 +1,170 @@
+// Copyright 2022 Andrew Morrow.
+// mosfet.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Power MOSFET subcircuits built from datasheet values.
+
+use std::fmt::Write;
+
+/// The thermal voltage at 27 °C.
+const THERMAL_VOLTAGE: f64 = 0.025_865;
+
+/// The intrinsic body diode's datasheet values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BodyDiode {
+    /// The forward voltage at [`BodyDiode::current`].
+    pub forward_voltage: f64,
+    pub current: f64,
+    /// The reverse recovery time, in seconds. Used as the diode's transit time, which
+    /// approximates recovery only roughly.
+    pub recovery_time: f64,
+}
+
+/// A power MOSFET described by the values found on the first page of its datasheet.
+///
+/// The channel is a level 1 NMOS fitted to the on-resistance. `Cgd` and `Cds` follow a
+/// junction law `C(v) = C(vref) sqrt((1 + vref/vj) / (1 + v/vj))`, which reproduces the
+/// Miller plateau of the gate charge curve and the fall of `Coss` with voltage.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PowerMosfet {
+    pub rds_on: f64,
+    /// The gate-source voltage at which [`PowerMosfet::rds_on`] is specified.
+    pub rds_on_vgs: f64,
+    pub threshold: f64,
+    /// The internal gate resistance, in ohms.
+    pub gate_resistance: f64,
+    /// Input capacitance, Cgs + Cgd, in farads.
+    pub ciss: f64,
+    /// Reverse transfer capacitance, Cgd, in farads.
+    pub crss: f64,
+    /// Output capacitance, Cds + Cgd, in farads.
+    pub coss: f64,
+    /// The drain-source voltage at which the capacitances are specified.
+    pub capacitance_vds: f64,
+    /// The junction potential of the capacitance law, in volts; 0.5 to 1 is typical.
+    pub junction_potential: f64,
+    pub body_diode: BodyDiode,
+}
+
+impl PowerMosfet {
+    /// The level 1 transconductance parameter KP, for W = L, matching the on-resistance.
+    pub fn kp(&self) -> f64 {
+        1.0 / (self.rds_on * (self.rds_on_vgs - self.threshold))
+    }
+
+    /// Scales a capacitance specified at `capacitance_vds` to zero volts.
+    fn zero_bias(&self, c: f64) -> f64 {
+        c * (1.0 + self.capacitance_vds / self.junction_potential).sqrt()
+    }
+
+    /// The charge on a junction-law capacitance at `v`, zero at zero volts.
+    fn charge(&self, c: f64, v: f64) -> f64 {
+        let vj = self.junction_potential;
+        2.0 * self.zero_bias(c) * vj * ((1.0 + v / vj).sqrt() - 1.0)
+    }
+
+    /// The gate-drain charge needed to swing the drain from `vds` to zero: the length of the
+    /// Miller plateau, in coulombs.
+    pub fn miller_charge(&self, vds: f64) -> f64 {
+        self.charge(self.crss, vds)
+    }
+
+    /// Approximately the total gate charge to drive the gate to `vgs` while switching `vds`,
+    /// in coulombs: the plateau plus charging `Ciss` to `vgs`.
+    pub fn gate_charge(&self, vgs: f64, vds: f64) -> f64 {
+        self.ciss * vgs + self.miller_charge(vds)
+    }
+
+    /// Renders the MOSFET as a subcircuit with ports `d`, `g`, and `s`.
+    pub fn subcircuit(&self, name: &str) -> String {
+        let mut out = format!(".subckt {} d g s\n", name);
+        writeln!(out, "Rg g gi {:e}", self.gate_resistance).unwrap();
+        out.push_str("M1 d gi s s channel\n");
+        writeln!(
+            out,
+            ".model channel NMOS (LEVEL=1 VTO={:e} KP={:e})",
+            self.threshold,
+            self.kp()
+        )
+        .unwrap();
+        writeln!(out, "Cgs gi s {:e}", self.ciss - self.crss).unwrap();
+        // charge-based capacitors conserve charge as the voltage swings; the max() keeps the
+        // square root real when the junction is forward biased
+        let vj = self.junction_potential;
+        let capacitor = |out: &mut String, line: &str, c: f64, v: &str| {
+            writeln!(
+                out,
+                "{} I=ddt({:e}*(sqrt(1+max({},{:e})/{:e})-1))",
+                line,
+                2.0 * self.zero_bias(c) * vj,
+                v,
+                -0.9 * vj,
+                vj
+            )
+            .unwrap();
+        };
+        capacitor(&mut out, "Bcgd d gi", self.crss, "v(d,gi)");
+        capacitor(&mut out, "Bcds d s", self.coss - self.crss, "v(d,s)");
+        let diode = &self.body_diode;
+        writeln!(out, "Dbody s d body").unwrap();
+        writeln!(
+            out,
+            ".model body D (IS={:e} N=1 TT={:e})",
+            diode.current / (diode.forward_voltage / THERMAL_VOLTAGE).exp_m1(),
+            diode.recovery_time
+        )
+        .unwrap();
+        writeln!(out, ".ends {}", name).unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_datasheet() {
+        let fet = PowerMosfet {
+            rds_on: 10e-3,
+            rds_on_vgs: 10.0,
+            threshold: 3.0,
+            gate_resistance: 1.5,
+            ciss: 2e-9,
+            crss: 100e-12,
+            coss: 400e-12,
+            capacitance_vds: 25.0,
+            junction_potential: 1.0,
+            body_diode: BodyDiode {
+                forward_voltage: 0.9,
+                current: 10.0,
+                recovery_time: 50e-9,
+            },
+        };
+        // in the deep triode region Rds = 1 / (KP (Vgs - Vth))
+        assert!((1.0 / (fet.kp() * 7.0) - 10e-3).abs() < 1e-12);
+        // the junction law reproduces the datasheet capacitance at its voltage
+        let h = 1e-6;
+        let c25 = (fet.miller_charge(25.0 + h) - fet.miller_charge(25.0 - h)) / (2.0 * h);
+        assert!((c25 - 100e-12).abs() < 1e-15);
+        assert!(fet.gate_charge(10.0, 48.0) > fet.ciss * 10.0);
+        let subckt = fet.subcircuit("irf");
+        assert!(subckt.starts_with(".subckt irf d g s\nRg g gi 1.5e0\nM1 d gi s s channel\n"));
+        assert!(subckt.contains("Cgs gi s 1.9e-9\n"));
+        assert!(subckt.contains("Bcgd d gi I=ddt("));
+        assert!(subckt.contains("Dbody s d body\n"));
+    }
+}
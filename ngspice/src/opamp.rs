@@ -0,0 +1,118 @@
+// Copyright 2022 Andrew Morrow.
+// opamp.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Behavioral op-amp macromodels built from datasheet parameters.
+
+use std::f64::consts::PI;
+
+/// Datasheet parameters of an op-amp.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OpAmp {
+    /// Gain-bandwidth product, in Hz.
+    pub gbw: f64,
+    /// In V/s.
+    pub slew_rate: f64,
+    /// Open-loop DC gain, in V/V.
+    pub open_loop_gain: f64,
+    /// In ohms.
+    pub output_resistance: f64,
+    /// Differential input resistance, in ohms.
+    pub input_resistance: f64,
+    /// In volts.
+    pub input_offset: f64,
+    /// How close the output can swing to each rail, in volts. Zero for rail-to-rail outputs.
+    pub headroom: f64,
+}
+
+impl Default for OpAmp {
+    /// A generic general-purpose op-amp, similar to a TL07x.
+    fn default() -> Self {
+        OpAmp {
+            gbw: 3e6,
+            slew_rate: 13e6,
+            open_loop_gain: 2e5,
+            output_resistance: 100.0,
+            input_resistance: 1e12,
+            input_offset: 0.0,
+            headroom: 1.5,
+        }
+    }
+}
+
+impl OpAmp {
+    /// The internal compensation capacitance. Only its ratio to the other derived values
+    /// matters.
+    const COMPENSATION: f64 = 1e-9;
+
+    /// Renders the macromodel as a subcircuit with ports `inp inn vcc vee out`.
+    ///
+    /// A current-limited transconductance charges the compensation capacitor, which sets the
+    /// dominant pole and slew rate. A behavioral buffer clamps the result to the rails and
+    /// drives the output resistance. Internal nodes are referenced to ground, so supply
+    /// current is not modeled.
+    pub fn subcircuit(&self, name: &str) -> String {
+        let c = Self::COMPENSATION;
+        let gm = 2.0 * PI * self.gbw * c;
+        let r = self.open_loop_gain / gm;
+        let imax = self.slew_rate * c;
+        format!(
+            ".subckt {name} inp inn vcc vee out
+Rin inp inn {rin:e}
+Bgm 0 int I=max(-{imax:e}, min({imax:e}, {gm:e}*(v(inp,inn)+{vos:e})))
+Rgm int 0 {r:e}
+Cgm int 0 {c:e}
+Bout buf 0 V=max(v(vee)+{h:e}, min(v(vcc)-{h:e}, v(int)))
+Rout buf out {rout:e}
+.ends {name}
+",
+            name = name,
+            rin = self.input_resistance,
+            imax = imax,
+            gm = gm,
+            vos = self.input_offset,
+            r = r,
+            c = c,
+            h = self.headroom,
+            rout = self.output_resistance
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_internal_values() {
+        let model = OpAmp {
+            gbw: 1e6,
+            slew_rate: 1e6,
+            open_loop_gain: 1e5,
+            headroom: 0.0,
+            ..OpAmp::default()
+        };
+        let subckt = model.subcircuit("oa");
+        assert!(subckt.starts_with(".subckt oa inp inn vcc vee out\n"));
+        // slew rate = imax / C
+        assert!(subckt.contains("I=max(-1e-3, min(1e-3, "));
+        // GBW = gm / (2 pi C), Aol = gm R
+        let gm = 2.0 * PI * 1e-3;
+        assert!(subckt.contains(&format!("{:e}*(v(inp,inn)+0e0)", gm)));
+        assert!(subckt.contains(&format!("Rgm int 0 {:e}\n", 1e5 / gm)));
+        assert!(subckt.ends_with(".ends oa\n"));
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright 2022 Andrew Morrow.
+// options.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Typed simulator options, so a misspelled option name is a compile error rather than a
+//! setting ngSPICE silently ignores.
+
+use crate::temperature::strip_temperature;
+use crate::{Error, NgSpice, Simulation};
+use std::fmt::{self, Formatter};
+
+/// The numerical integration method for transient analyses.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Integration {
+    Trapezoidal,
+    Gear,
+}
+
+/// Options written to a `.options` card. `None` leaves ngSPICE's default.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimOptions {
+    /// Relative error tolerance.
+    pub reltol: Option<f64>,
+    /// Absolute current error tolerance, in A.
+    pub abstol: Option<f64>,
+    /// Absolute voltage error tolerance, in V.
+    pub vntol: Option<f64>,
+    /// Absolute charge error tolerance, in C.
+    pub chgtol: Option<f64>,
+    /// Transient truncation error overestimation factor.
+    pub trtol: Option<f64>,
+    /// The minimum conductance added across every junction, in S.
+    pub gmin: Option<f64>,
+    /// The DC iteration limit.
+    pub itl1: Option<u32>,
+    /// The DC transfer curve iteration limit.
+    pub itl2: Option<u32>,
+    /// The iteration limit at each transient timepoint.
+    pub itl4: Option<u32>,
+    pub method: Option<Integration>,
+    /// The maximum order of Gear integration, 2 to 6.
+    pub maxord: Option<u32>,
+    /// The circuit temperature, in °C.
+    pub temp: Option<f64>,
+    /// The temperature model parameters were measured at, in °C.
+    pub tnom: Option<f64>,
+    /// The number of gmin stepping steps tried when the operating point fails to converge.
+    pub gminsteps: Option<u32>,
+    /// The number of source stepping steps.
+    pub srcsteps: Option<u32>,
+}
+
+impl SimOptions {
+    /// Every option that is set, as `name=value` pairs in the order of the fields.
+    pub fn pairs(&self) -> Vec<(&'static str, String)> {
+        let real = |name, value: Option<f64>| value.map(|v| (name, format!("{:e}", v)));
+        let int = |name, value: Option<u32>| value.map(|v| (name, v.to_string()));
+        let method = self.method.map(|m| {
+            let value = match m {
+                Integration::Trapezoidal => "trap",
+                Integration::Gear => "gear",
+            };
+            ("method", value.to_owned())
+        });
+        [
+            real("reltol", self.reltol),
+            real("abstol", self.abstol),
+            real("vntol", self.vntol),
+            real("chgtol", self.chgtol),
+            real("trtol", self.trtol),
+            real("gmin", self.gmin),
+            int("itl1", self.itl1),
+            int("itl2", self.itl2),
+            int("itl4", self.itl4),
+            method,
+            int("maxord", self.maxord),
+            real("temp", self.temp),
+            real("tnom", self.tnom),
+            int("gminsteps", self.gminsteps),
+            int("srcsteps", self.srcsteps),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// Returns `circuit` with these options in a `.options` card after the title. Any
+    /// setting of the same options already in the circuit is removed, including a `.temp`
+    /// card if `temp` is set.
+    ///
+    /// Existing options are only recognized when written as `name=value`, without spaces.
+    pub fn apply(&self, circuit: &str) -> String {
+        let pairs = self.pairs();
+        if pairs.is_empty() {
+            return circuit.to_owned();
+        }
+        let names: Vec<&str> = pairs.iter().map(|(n, _)| *n).collect();
+        let mut lines = circuit.lines();
+        let mut out: Vec<String> = lines.next().map(str::to_owned).into_iter().collect();
+        out.push(self.to_string());
+        for line in lines {
+            let line = if self.temp.is_some() {
+                strip_temperature(line)
+            } else {
+                Some(line.to_owned())
+            };
+            out.extend(line.and_then(|l| remove_options(&l, &names)));
+        }
+        out.join("\n")
+    }
+}
+
+impl fmt::Display for SimOptions {
+    /// Formats the options as a `.options` card, or nothing if no option is set.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let pairs = self.pairs();
+        if pairs.is_empty() {
+            return Ok(());
+        }
+        f.write_str(".options")?;
+        for (name, value) in pairs {
+            write!(f, " {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes the named options from a `.option` card. Returns `None` if nothing is left of the
+/// card, and any other line unchanged.
+pub(crate) fn remove_options(line: &str, names: &[&str]) -> Option<String> {
+    let mut words = line.split_whitespace();
+    let card = words.next().unwrap_or("").to_ascii_lowercase();
+    if !matches!(card.as_str(), ".option" | ".options" | ".opt") {
+        return Some(line.to_owned());
+    }
+    let kept: Vec<&str> = words
+        .filter(|w| {
+            let name = w.split('=').next().unwrap_or("").to_ascii_lowercase();
+            !w.contains('=') || !names.contains(&name.as_str())
+        })
+        .collect();
+    if kept.is_empty() {
+        None
+    } else {
+        Some(format!("{} {}", card, kept.join(" ")))
+    }
+}
+
+impl NgSpice {
+    /// Like [`NgSpice::simulate`], but with `options` applied to the circuit as described in
+    /// [`SimOptions::apply`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`NgSpice::simulate`].
+    pub fn simulate_with_options(
+        circuit: &str,
+        command: &str,
+        options: &SimOptions,
+    ) -> Result<Simulation, Error> {
+        NgSpice::simulate(&options.apply(circuit), command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_and_replaces_options() {
+        let options = SimOptions {
+            reltol: Some(1e-4),
+            itl4: Some(50),
+            method: Some(Integration::Gear),
+            temp: Some(85.0),
+            ..SimOptions::default()
+        };
+        assert_eq!(
+            options.to_string(),
+            ".options reltol=1e-4 itl4=50 method=gear temp=8.5e1"
+        );
+        let deck = "* osc\n.temp 27\n.option RELTOL=1e-3 abstol=1e-12\n.options method=trap\nR1 a 0 1k\n.end";
+        assert_eq!(
+            options.apply(deck),
+            "* osc\n.options reltol=1e-4 itl4=50 method=gear temp=8.5e1\n.option abstol=1e-12\nR1 a 0 1k\n.end"
+        );
+        assert_eq!(SimOptions::default().apply(deck), deck);
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2022 Andrew Morrow.
+// opto.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Behavioral models of LEDs, photodiodes, and optocouplers.
+
+use std::fmt::Write;
+
+/// An LED, modeled as a diode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Led {
+    /// The saturation current, in amps.
+    pub saturation_current: f64,
+    /// The emission coefficient; typically 2 to 4 for visible and infrared LEDs.
+    pub emission: f64,
+    pub series_resistance: f64,
+    /// The zero-bias junction capacitance, in farads.
+    pub capacitance: f64,
+}
+
+impl Led {
+    /// Fits the saturation current to a datasheet forward voltage at a forward current, at
+    /// 27 °C.
+    pub fn from_forward_voltage(
+        voltage: f64,
+        current: f64,
+        emission: f64,
+        series_resistance: f64,
+        capacitance: f64,
+    ) -> Self {
+        const THERMAL_VOLTAGE: f64 = 0.025_865;
+        let junction = voltage - current * series_resistance;
+        Led {
+            saturation_current: current / (junction / (emission * THERMAL_VOLTAGE)).exp_m1(),
+            emission,
+            series_resistance,
+            capacitance,
+        }
+    }
+
+    /// A `.model` card for the diode.
+    pub fn model(&self, name: &str) -> String {
+        format!(
+            ".model {} D (IS={:e} N={:e} RS={:e} CJO={:e})",
+            name, self.saturation_current, self.emission, self.series_resistance, self.capacitance
+        )
+    }
+}
+
+/// A photodiode. Optical power is given as the voltage of a control node, 1 V meaning 1 W.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Photodiode {
+    /// In amps per watt.
+    pub responsivity: f64,
+    /// The reverse current in the dark, in amps.
+    pub dark_current: f64,
+    /// The junction capacitance, in farads.
+    pub capacitance: f64,
+    pub shunt_resistance: f64,
+}
+
+impl Photodiode {
+    /// Renders the photodiode as a subcircuit with ports `anode`, `cathode`, and `light`.
+    pub fn subcircuit(&self, name: &str) -> String {
+        let mut out = format!(".subckt {} anode cathode light\n", name);
+        // photocurrent flows from cathode to anode inside the device
+        writeln!(out, "Gph cathode anode light 0 {:e}", self.responsivity).unwrap();
+        writeln!(out, "Idark cathode anode DC {:e}", self.dark_current).unwrap();
+        writeln!(out, "Dj anode cathode dj").unwrap();
+        writeln!(
+            out,
+            ".model dj D (IS={:e} CJO={:e})",
+            self.dark_current, self.capacitance
+        )
+        .unwrap();
+        writeln!(out, "Rsh anode cathode {:e}", self.shunt_resistance).unwrap();
+        out.push_str("Rlight light 0 1e12\n");
+        writeln!(out, ".ends {}", name).unwrap();
+        out
+    }
+}
+
+/// An optocoupler with a phototransistor output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Optocoupler {
+    pub led: Led,
+    /// The current transfer ratio, collector current over LED current.
+    pub ctr: f64,
+    /// The time constant of the output's response to a change in LED current, in seconds.
+    pub response_time: f64,
+    /// The collector-emitter voltage below which the output saturates, in volts.
+    pub saturation_voltage: f64,
+    /// The collector-emitter capacitance, in farads.
+    pub output_capacitance: f64,
+}
+
+impl Optocoupler {
+    /// Renders the optocoupler as a subcircuit with ports `anode`, `cathode`, `collector`,
+    /// and `emitter`.
+    pub fn subcircuit(&self, name: &str) -> String {
+        let mut out = format!(".subckt {} anode cathode collector emitter\n", name);
+        out.push_str("Dled anode sense led\n");
+        writeln!(out, "{}", self.led.model("led")).unwrap();
+        out.push_str("Vsense sense cathode DC 0\n");
+        // a 1 ohm, tau farad RC lags the LED current
+        out.push_str("Blag lag0 0 V=i(Vsense)\n");
+        out.push_str("Rlag lag0 lag 1\n");
+        writeln!(out, "Clag lag 0 {:e}", self.response_time).unwrap();
+        writeln!(
+            out,
+            "Bce collector emitter I={:e}*max(v(lag), 0)*tanh(max(v(collector,emitter), 0)/{:e})",
+            self.ctr, self.saturation_voltage
+        )
+        .unwrap();
+        writeln!(out, "Cce collector emitter {:e}", self.output_capacitance).unwrap();
+        writeln!(out, ".ends {}", name).unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_optocoupler() {
+        let led = Led::from_forward_voltage(1.2, 10e-3, 2.0, 1.0, 20e-12);
+        let vt: f64 = 0.025_865;
+        let i = led.saturation_current * ((1.2 - 10e-3) / (2.0 * vt)).exp_m1();
+        assert!((i - 10e-3).abs() < 1e-12);
+        let opto = Optocoupler {
+            led,
+            ctr: 1.0,
+            response_time: 5e-6,
+            saturation_voltage: 0.2,
+            output_capacitance: 10e-12,
+        };
+        let subckt = opto.subcircuit("pc817");
+        assert!(subckt.contains("Clag lag 0 5e-6\n"));
+        assert!(subckt.contains(
+            "Bce collector emitter I=1e0*max(v(lag), 0)*tanh(max(v(collector,emitter), 0)/2e-1)\n"
+        ));
+        let pd = Photodiode {
+            responsivity: 0.5,
+            dark_current: 1e-9,
+            capacitance: 10e-12,
+            shunt_resistance: 1e9,
+        };
+        assert!(pd
+            .subcircuit("pd")
+            .contains("Gph cathode anode light 0 5e-1\n"));
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright 2022 Andrew Morrow.
+// overlay.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Aligns one vector across the runs of a campaign for side-by-side review.
+
+use crate::campaign::{Campaign, Tags};
+use crate::control::vector_name;
+use crate::format::NumericFormat;
+use crate::gnuplot::Gnuplot;
+use crate::kernels::{interpolate_all, magnitude_db};
+use crate::{DataType, Error, VectorValues};
+use std::fmt::Write as _;
+
+/// One vector from every run of a campaign, resampled onto a common scale.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Overlay {
+    /// The vector expression, e.g. `v(out)`.
+    pub vector: String,
+    pub scale_type: DataType,
+    pub datatype: DataType,
+    /// The first run's scale, which every trace is resampled onto.
+    pub scale: Vec<f64>,
+    /// One label per run, made from its tags.
+    pub labels: Vec<String>,
+    /// One trace per run. Complex vectors are converted to magnitude in dB.
+    pub traces: Vec<Vec<f64>>,
+}
+
+/// Describes a run by its tags, e.g. `corner=ss temp=125`.
+fn label(tags: &Tags, run: usize) -> String {
+    let parts: Vec<String> = tags.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    if parts.is_empty() {
+        format!("run {}", run)
+    } else {
+        parts.join(" ")
+    }
+}
+
+impl Campaign {
+    /// Collects the named vector from every run.
+    ///
+    /// Runs rarely share time points, because ngSPICE adapts its steps, so every trace is
+    /// interpolated linearly onto the first run's scale.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingScale`] or [`Error::MissingVector`] if any run lacks the scale
+    /// or the vector, or if the vector's length differs from its run's scale.
+    pub fn overlay(&self, vector: &str) -> Result<Overlay, Error> {
+        let name = vector_name(vector);
+        let mut overlay = Overlay {
+            vector: vector.to_owned(),
+            scale_type: DataType::Unknown,
+            datatype: DataType::Unknown,
+            scale: Vec::new(),
+            labels: Vec::new(),
+            traces: Vec::new(),
+        };
+        for (i, run) in self.runs().iter().enumerate() {
+            let sim = &run.simulation;
+            let scale = sim.scale_values().ok_or(Error::MissingScale)?;
+            let info = sim
+                .vectors
+                .get(&name)
+                .ok_or_else(|| Error::MissingVector(vector.to_owned()))?;
+            let values: Vec<f64> = match &info.values {
+                VectorValues::Real(x) => x.to_vec(),
+                VectorValues::Complex(x) => magnitude_db(x),
+            };
+            if scale.is_empty() {
+                return Err(Error::MissingScale);
+            }
+            // every trace needs a value at every point of the scale
+            if values.len() != scale.len() {
+                return Err(Error::MissingVector(vector.to_owned()));
+            }
+            if i == 0 {
+                overlay.scale = scale.clone();
+                overlay.datatype = info.datatype.clone();
+                overlay.scale_type = sim
+                    .scale_vector()
+                    .map_or(DataType::Unknown, |(s, _)| sim.vectors[s].datatype.clone());
+            }
+            let trace = if scale == overlay.scale {
+                values
+            } else {
+                interpolate_all(&scale, &values, &overlay.scale)
+            };
+            overlay.labels.push(label(&run.tags, i));
+            overlay.traces.push(trace);
+        }
+        Ok(overlay)
+    }
+}
+
+impl Overlay {
+    /// Renders the overlay as CSV with a header row: the scale, then one column per run.
+    pub fn csv(&self) -> String {
+        self.csv_with(&NumericFormat::default())
+    }
+
+    /// Like [`Overlay::csv`], with numbers written in `format`.
+    pub fn csv_with(&self, format: &NumericFormat) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('"', "\"\""));
+        let mut out = quote(self.scale_type.quantity());
+        for l in &self.labels {
+            write!(out, ",{}", quote(l)).unwrap();
+        }
+        out.push('\n');
+        for (row, x) in self.scale.iter().enumerate() {
+            out.push_str(&format.format(*x));
+            for trace in &self.traces {
+                out.push(',');
+                out.push_str(&format.format(trace[row]));
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Converts the overlay into a gnuplot table with one line per run.
+    pub fn to_gnuplot(&self) -> Gnuplot {
+        self.to_gnuplot_with(&NumericFormat::default())
+    }
+
+    /// Like [`Overlay::to_gnuplot`], with numbers written in `format`.
+    pub fn to_gnuplot_with(&self, format: &NumericFormat) -> Gnuplot {
+        let mut data = format!("# {} {}\n", self.scale_type.quantity(), self.vector);
+        for (row, x) in self.scale.iter().enumerate() {
+            data.push_str(&format.format(*x));
+            for trace in &self.traces {
+                data.push(' ');
+                data.push_str(&format.format(trace[row]));
+            }
+            data.push('\n');
+        }
+        Gnuplot {
+            data,
+            x_label: self.scale_type.axis_label(),
+            y_label: self.datatype.axis_label(),
+            log_x: self.scale_type == DataType::Frequency,
+            titles: self.labels.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Simulation, VectorInfo};
+
+    fn run(time: Vec<f64>, out: Vec<f64>) -> Simulation {
+        let mut sim = Simulation::default();
+        for (name, datatype, values) in [
+            ("time", DataType::Time, time),
+            ("out", DataType::Voltage, out),
+        ] {
+            let info = VectorInfo {
+                datatype,
+                values: VectorValues::Real(values.into()),
+                scale: None,
+            };
+            sim.vectors.insert(name.to_owned(), info);
+        }
+        sim
+    }
+
+    #[test]
+    fn aligns_runs() -> Result<(), Error> {
+        let mut campaign = Campaign::new("corners");
+        campaign.add(
+            Tags::new().with("corner", "tt"),
+            run(vec![0.0, 1.0, 2.0], vec![0.0, 1.0, 2.0]),
+        );
+        campaign.add(Tags::new(), run(vec![0.0, 2.0], vec![0.0, 4.0]));
+        let overlay = campaign.overlay("v(out)")?;
+        assert_eq!(overlay.labels, vec!["corner=tt", "run 1"]);
+        assert_eq!(overlay.traces[1], vec![0.0, 2.0, 4.0]);
+        assert_eq!(
+            overlay.csv(),
+            "\"Time\",\"corner=tt\",\"run 1\"\n0e0,0e0,0e0\n1e0,1e0,2e0\n2e0,2e0,4e0\n"
+        );
+        let plot = overlay.to_gnuplot();
+        assert_eq!(plot.y_label, "Voltage (V)");
+        assert!(plot
+            .script("o.dat")
+            .contains("using 1:3 with lines title \"run 1\""));
+        assert!(campaign.overlay("v(missing)").is_err());
+        campaign.add(Tags::new(), run(vec![0.0, 1.0, 2.0], vec![0.0, 1.0]));
+        assert!(matches!(
+            campaign.overlay("v(out)"),
+            Err(Error::MissingVector(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+// Copyright 2022 Andrew Morrow.
+// pdn.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Power-delivery-network impedance checks against a target impedance mask.
+
+use crate::analysis::AcCommand;
+use crate::circuit::Circuit;
+use crate::format::NumericFormat;
+use crate::Error;
+use std::fmt::{self, Formatter, Write as _};
+
+/// The highest permitted impedance magnitude at each frequency, as `(frequency, ohms)`
+/// points in increasing frequency. Between points the limit is interpolated on log-log axes;
+/// outside them it holds the nearest point's value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImpedanceMask {
+    pub points: Vec<(f64, f64)>,
+}
+
+impl ImpedanceMask {
+    pub fn new(points: Vec<(f64, f64)>) -> Self {
+        ImpedanceMask { points }
+    }
+
+    /// The classic flat target impedance, `voltage * ripple / step_current`, up to
+    /// `bandwidth`. This keeps the worst-case load step from moving the rail by more than
+    /// `ripple`, a fraction of `voltage`. Above `bandwidth` the mask rises at 20 dB/decade,
+    /// where on-die decoupling is expected to take over.
+    pub fn target(voltage: f64, ripple: f64, step_current: f64, bandwidth: f64) -> Self {
+        let z = voltage * ripple / step_current;
+        ImpedanceMask::new(vec![(bandwidth, z), (bandwidth * 1e3, z * 1e3)])
+    }
+
+    /// The impedance limit at `frequency`.
+    pub fn limit_at(&self, frequency: f64) -> f64 {
+        let (first, last) = match (self.points.first(), self.points.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return f64::INFINITY,
+        };
+        if frequency <= first.0 {
+            return first.1;
+        }
+        if frequency >= last.0 {
+            return last.1;
+        }
+        let k = self.points.partition_point(|&(f, _)| f < frequency);
+        let ((f0, z0), (f1, z1)) = (self.points[k - 1], self.points[k]);
+        let t = (frequency / f0).ln() / (f1 / f0).ln();
+        z0 * (z1 / z0).powf(t)
+    }
+
+    /// Compares an impedance curve against the mask.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `frequency` and `impedance` have different lengths.
+    pub fn check(&self, frequency: &[f64], impedance: &[f64]) -> PdnReport {
+        assert_eq!(
+            frequency.len(),
+            impedance.len(),
+            "one impedance per frequency"
+        );
+        let margins: Vec<f64> = frequency
+            .iter()
+            .zip(impedance)
+            .map(|(&f, &z)| 20.0 * (self.limit_at(f) / z.abs()).log10())
+            .collect();
+        let mut violations = Vec::new();
+        let mut band: Option<MaskViolation> = None;
+        for (k, &margin) in margins.iter().enumerate() {
+            if margin >= 0.0 {
+                violations.extend(band.take());
+                continue;
+            }
+            let point = MaskViolation {
+                start: frequency[k],
+                stop: frequency[k],
+                worst_frequency: frequency[k],
+                impedance: impedance[k].abs(),
+                limit: self.limit_at(frequency[k]),
+                margin,
+            };
+            band = Some(match band {
+                Some(b) if b.margin <= margin => MaskViolation {
+                    stop: frequency[k],
+                    ..b
+                },
+                Some(b) => MaskViolation {
+                    start: b.start,
+                    ..point
+                },
+                None => point,
+            });
+        }
+        violations.extend(band);
+        PdnReport {
+            frequency: frequency.to_vec(),
+            impedance: impedance.iter().map(|z| z.abs()).collect(),
+            margins,
+            violations,
+        }
+    }
+
+    /// Measures the impedance of `circuit` looking into `node` with an AC analysis, and
+    /// compares it against the mask. Supplies in the circuit should be DC sources, which AC
+    /// analysis treats as shorts, so the result is the impedance the load sees.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if `node` is ground, or the errors of
+    /// [`crate::NgSpice::simulate`].
+    pub fn verify(
+        &self,
+        circuit: &Circuit,
+        node: &str,
+        ac: &AcCommand,
+    ) -> Result<PdnReport, Error> {
+        if node == "0" || node.eq_ignore_ascii_case("gnd") {
+            return Err(Error::InvalidArgument(
+                "PDN impedance cannot be measured at ground".to_owned(),
+            ));
+        }
+        // a 1 A source makes the node voltage equal to the impedance
+        let mut patch = circuit.patch();
+        patch.add(&format!("Ipdn_probe 0 {} DC 0 AC 1", node))?;
+        let (deck, _) = patch.finish();
+        let result = ac.run(&deck.to_string())?;
+        let z: Vec<f64> = result
+            .response(&node.to_ascii_lowercase())?
+            .iter()
+            .map(|z| z.norm())
+            .collect();
+        Ok(self.check(result.frequency(), &z))
+    }
+}
+
+/// A band of frequencies where the impedance exceeds the mask.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaskViolation {
+    /// The first frequency in the band, in hertz.
+    pub start: f64,
+    /// The last frequency in the band, in hertz.
+    pub stop: f64,
+    /// Where the impedance is furthest above the mask.
+    pub worst_frequency: f64,
+    /// The impedance at `worst_frequency`, in ohms.
+    pub impedance: f64,
+    /// The mask at `worst_frequency`, in ohms.
+    pub limit: f64,
+    /// How far below the mask the impedance is at `worst_frequency`, in dB. Always negative.
+    pub margin: f64,
+}
+
+/// An impedance curve checked against an [`ImpedanceMask`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PdnReport {
+    /// In hertz.
+    pub frequency: Vec<f64>,
+    /// The impedance magnitude at each frequency, in ohms.
+    pub impedance: Vec<f64>,
+    /// The margin below the mask at each frequency, in dB. Negative where it is exceeded.
+    pub margins: Vec<f64>,
+    pub violations: Vec<MaskViolation>,
+}
+
+impl PdnReport {
+    pub fn passed(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// The smallest margin at any frequency, in dB, and where it occurs.
+    pub fn worst_margin(&self) -> Option<(f64, f64)> {
+        self.margins
+            .iter()
+            .zip(&self.frequency)
+            .min_by(|a, b| a.0.total_cmp(b.0))
+            .map(|(&m, &f)| (f, m))
+    }
+
+    /// Like the [`Display`](fmt::Display) output, with numbers written in `format`. Margins
+    /// in dB keep one decimal place.
+    pub fn to_string_with(&self, format: &NumericFormat) -> String {
+        let n = |x: f64| format.format(x);
+        let mut out = String::new();
+        if let Some((freq, margin)) = self.worst_margin() {
+            writeln!(out, "worst margin {:.1} dB at {} Hz", margin, n(freq)).unwrap();
+        }
+        for v in &self.violations {
+            writeln!(
+                out,
+                "{} to {} Hz: {} ohm at {} Hz exceeds {} ohm by {:.1} dB",
+                n(v.start),
+                n(v.stop),
+                n(v.impedance),
+                n(v.worst_frequency),
+                n(v.limit),
+                -v.margin
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+impl fmt::Display for PdnReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_string_with(&NumericFormat::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_impedance_mask() {
+        let mask = ImpedanceMask::target(1.0, 0.05, 5.0, 1e6);
+        assert!((mask.limit_at(1e3) - 0.01).abs() < 1e-12);
+        assert!((mask.limit_at(1e7) - 0.1).abs() < 1e-9);
+        let frequency = [1e3, 1e4, 1e5, 2e5, 1e6, 1e7];
+        let impedance = [0.005, 0.02, 0.04, 0.015, 0.005, 0.05];
+        let report = mask.check(&frequency, &impedance);
+        assert!(!report.passed());
+        assert_eq!(report.violations.len(), 1);
+        let band = &report.violations[0];
+        assert_eq!(
+            (band.start, band.stop, band.worst_frequency),
+            (1e4, 2e5, 1e5)
+        );
+        assert!((band.margin + 20.0 * 4f64.log10()).abs() < 1e-9);
+        assert_eq!(report.worst_margin().map(|(f, _)| f), Some(1e5));
+        assert!(mask.check(&[1e3], &[0.001]).passed());
+    }
+}
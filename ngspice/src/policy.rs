@@ -0,0 +1,198 @@
+// Copyright 2022 Andrew Morrow.
+// policy.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Controls which ngSPICE commands may be run.
+
+use crate::{Error, NgSpice};
+use once_cell::sync::OnceCell;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// Commands that are denied by default, because they can end the process, touch the
+/// filesystem or the shell, or start background threads that corrupt the shared instance.
+const DEFAULT_DENIED: &[&str] = &[
+    "quit",
+    "exit",
+    "shell",
+    "source",
+    "cd",
+    "alias",
+    "bg_run",
+    "bg_halt",
+    "bg_resume",
+];
+
+/// Analysis and inspection commands allowed by [`CommandPolicy::strict`].
+const STRICT_ALLOWED: &[&str] = &[
+    "op", "ac", "dc", "tran", "noise", "sens", "disto", "pz", "tf", "run", "meas", "let", "print",
+    "alter", "altermod", "show", "showmod", "echo", "set", "unset", "option", "setplot", "destroy",
+];
+
+/// A policy deciding which ngSPICE commands may run, both as simulation commands and inside
+/// `.control` blocks of a circuit.
+///
+/// The policy matches the first word of each command, ignoring case.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CommandPolicy {
+    denied: HashSet<String>,
+    /// If set, only these commands may run.
+    allowed: Option<HashSet<String>>,
+}
+
+impl Default for CommandPolicy {
+    /// Denies commands that would break the shared ngSPICE instance and allows everything else.
+    fn default() -> Self {
+        CommandPolicy {
+            denied: DEFAULT_DENIED.iter().map(|&s| s.to_owned()).collect(),
+            allowed: None,
+        }
+    }
+}
+
+impl CommandPolicy {
+    /// Allows every command, including ones that can break the shared instance.
+    pub fn permissive() -> Self {
+        CommandPolicy {
+            denied: HashSet::new(),
+            allowed: None,
+        }
+    }
+
+    /// Allows only analysis and inspection commands. Suitable for untrusted decks.
+    pub fn strict() -> Self {
+        CommandPolicy {
+            allowed: Some(STRICT_ALLOWED.iter().map(|&s| s.to_owned()).collect()),
+            ..CommandPolicy::default()
+        }
+    }
+
+    /// Allows a command, removing it from the deny list if necessary.
+    pub fn allow(mut self, command: &str) -> Self {
+        let command = command.to_ascii_lowercase();
+        self.denied.remove(&command);
+        if let Some(allowed) = &mut self.allowed {
+            allowed.insert(command);
+        }
+        self
+    }
+
+    /// Denies a command.
+    pub fn deny(mut self, command: &str) -> Self {
+        let command = command.to_ascii_lowercase();
+        if let Some(allowed) = &mut self.allowed {
+            allowed.remove(&command);
+        }
+        self.denied.insert(command);
+        self
+    }
+
+    /// Returns true if the policy permits `command`.
+    pub fn permits(&self, command: &str) -> bool {
+        // ngSPICE accepts several commands on one line separated by semicolons
+        command.split(';').all(|cmd| {
+            let word = match cmd.split_whitespace().next() {
+                Some(x) => x.to_ascii_lowercase(),
+                None => return true,
+            };
+            !self.denied.contains(&word) && self.allowed.as_ref().is_none_or(|a| a.contains(&word))
+        })
+    }
+
+    /// Checks a single command.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ForbiddenCommand`] if the policy does not permit the command.
+    pub fn check(&self, command: &str) -> Result<(), Error> {
+        if self.permits(command) {
+            Ok(())
+        } else {
+            Err(Error::ForbiddenCommand(command.trim().to_owned()))
+        }
+    }
+
+    /// Checks every command inside the `.control` blocks of a circuit.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ForbiddenCommand`] for the first command that is not permitted.
+    pub fn check_circuit(&self, circuit: &str) -> Result<(), Error> {
+        let mut in_control = false;
+        for line in circuit.lines() {
+            let word = line
+                .split_whitespace()
+                .next()
+                .unwrap_or("")
+                .to_ascii_lowercase();
+            match word.as_str() {
+                ".control" => in_control = true,
+                ".endc" => in_control = false,
+                _ if in_control && !word.starts_with('*') => self.check(line)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+static POLICY: OnceCell<Mutex<CommandPolicy>> = OnceCell::new();
+
+fn policy() -> &'static Mutex<CommandPolicy> {
+    POLICY.get_or_init(|| Mutex::new(CommandPolicy::default()))
+}
+
+impl NgSpice {
+    /// Replaces the policy applied to every subsequent simulation.
+    pub fn set_command_policy(policy: CommandPolicy) {
+        *self::policy().lock().unwrap_or_else(|e| e.into_inner()) = policy;
+    }
+
+    /// Returns a copy of the current command policy.
+    pub fn command_policy() -> CommandPolicy {
+        policy().lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies() {
+        let default = CommandPolicy::default();
+        assert!(default.permits("tran 1u 1m"));
+        assert!(!default.permits("QUIT"));
+        assert!(!default.permits("print v(1); shell rm -rf /"));
+        assert!(default.clone().allow("shell").permits("shell ls"));
+
+        let strict = CommandPolicy::strict();
+        assert!(strict.permits("ac dec 10 1 1meg"));
+        assert!(!strict.permits("wrdata out.txt v(1)"));
+        assert!(strict
+            .clone()
+            .allow("wrdata")
+            .permits("wrdata out.txt v(1)"));
+        assert!(!strict.deny("tran").permits("tran 1u 1m"));
+
+        let circuit = "* title\nR1 a 0 1k\n.control\nop\nshell ls\n.endc\n.end";
+        assert!(matches!(
+            default.check_circuit(circuit),
+            Err(Error::ForbiddenCommand(cmd)) if cmd == "shell ls"
+        ));
+        assert!(CommandPolicy::permissive().check_circuit(circuit).is_ok());
+    }
+}
@@ -0,0 +1,206 @@
+// Copyright 2022 Andrew Morrow.
+// pool.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! An optional pool of vector buffers, reused when results are copied out of ngSPICE.
+
+use crate::{NgSpice, Simulation, VectorValues};
+use num_complex::Complex64;
+use once_cell::sync::OnceCell;
+use std::sync::{Arc, Mutex};
+
+/// Spare buffers for vector data. Results copied out of ngSPICE take the smallest spare buffer
+/// that fits, and [`NgSpice::recycle`] returns a finished simulation's buffers, so a long
+/// sweep stops allocating once the pool has warmed up.
+#[derive(Debug, Default)]
+pub struct BufferPool {
+    real: Vec<Vec<f64>>,
+    complex: Vec<Vec<Complex64>>,
+    /// The most buffers of each kind the pool keeps.
+    max_buffers: usize,
+}
+
+impl BufferPool {
+    pub fn new(max_buffers: usize) -> Self {
+        BufferPool {
+            max_buffers,
+            ..BufferPool::default()
+        }
+    }
+
+    /// The number of spare buffers held.
+    pub fn len(&self) -> usize {
+        self.real.len() + self.complex.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies `values` into a spare buffer, or a new one if none fits.
+    pub fn copy_real(&mut self, values: &[f64]) -> Vec<f64> {
+        let mut buf = take(&mut self.real, values.len());
+        buf.extend_from_slice(values);
+        buf
+    }
+
+    /// Copies `values` into a spare buffer, or a new one if none fits.
+    pub fn copy_complex(&mut self, values: &[Complex64]) -> Vec<Complex64> {
+        let mut buf = take(&mut self.complex, values.len());
+        buf.extend_from_slice(values);
+        buf
+    }
+
+    /// Keeps the buffer behind `values`, unless another clone still shares it.
+    pub fn give(&mut self, values: VectorValues) {
+        match values {
+            VectorValues::Real(x) => {
+                if let Ok(x) = Arc::try_unwrap(x) {
+                    keep(&mut self.real, x, self.max_buffers);
+                }
+            }
+            VectorValues::Complex(x) => {
+                if let Ok(x) = Arc::try_unwrap(x) {
+                    keep(&mut self.complex, x, self.max_buffers);
+                }
+            }
+        }
+    }
+
+    /// Keeps every buffer of `sim` not shared with a clone.
+    pub fn recycle(&mut self, sim: Simulation) {
+        for (_, info) in sim.vectors {
+            self.give(info.values);
+        }
+    }
+}
+
+/// Takes the smallest buffer with room for `len` values, emptied.
+fn take<T>(spares: &mut Vec<Vec<T>>, len: usize) -> Vec<T> {
+    let best = spares
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| b.capacity() >= len)
+        .min_by_key(|(_, b)| b.capacity())
+        .map(|(k, _)| k);
+    match best {
+        Some(k) => {
+            let mut buf = spares.swap_remove(k);
+            buf.clear();
+            buf
+        }
+        None => Vec::with_capacity(len),
+    }
+}
+
+/// Keeps `buf`, dropping the smallest spare if the pool is full.
+fn keep<T>(spares: &mut Vec<Vec<T>>, buf: Vec<T>, max_buffers: usize) {
+    if max_buffers == 0 {
+        return;
+    }
+    if spares.len() >= max_buffers {
+        let smallest = spares
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, b)| b.capacity())
+            .map(|(k, b)| (k, b.capacity()));
+        match smallest {
+            Some((k, capacity)) if capacity < buf.capacity() => {
+                spares.swap_remove(k);
+            }
+            _ => return,
+        }
+    }
+    spares.push(buf);
+}
+
+static POOL: OnceCell<Mutex<Option<BufferPool>>> = OnceCell::new();
+
+fn pool() -> &'static Mutex<Option<BufferPool>> {
+    POOL.get_or_init(|| Mutex::new(None))
+}
+
+/// Copies real values out of ngSPICE, through the pool if it is enabled.
+pub(crate) fn copy_real(values: &[f64]) -> Vec<f64> {
+    match pool().lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        Some(pool) => pool.copy_real(values),
+        None => values.to_owned(),
+    }
+}
+
+/// Copies complex values out of ngSPICE, through the pool if it is enabled.
+pub(crate) fn copy_complex(values: &[Complex64]) -> Vec<Complex64> {
+    match pool().lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        Some(pool) => pool.copy_complex(values),
+        None => values.to_owned(),
+    }
+}
+
+impl NgSpice {
+    /// Starts reusing vector buffers for every subsequent simulation, keeping up to
+    /// `max_buffers` spare buffers of each kind. Replaces any existing pool.
+    pub fn enable_buffer_pool(max_buffers: usize) {
+        *pool().lock().unwrap_or_else(|e| e.into_inner()) = Some(BufferPool::new(max_buffers));
+    }
+
+    /// Stops reusing buffers and frees every spare one.
+    pub fn disable_buffer_pool() {
+        *pool().lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    /// Returns a finished simulation's buffers to the pool, so the next simulation can reuse
+    /// them. Drops `sim` as usual if the pool is disabled.
+    pub fn recycle(sim: Simulation) {
+        if let Some(pool) = pool().lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            pool.recycle(sim);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, VectorInfo};
+
+    #[test]
+    fn reuses_buffers() {
+        let mut pool = BufferPool::new(2);
+        let first = pool.copy_real(&[1.0; 100]);
+        let ptr = first.as_ptr();
+        let mut sim = Simulation::default();
+        let info = |values: Vec<f64>| VectorInfo {
+            datatype: DataType::Voltage,
+            values: values.into(),
+            scale: None,
+        };
+        sim.vectors.insert("a".to_owned(), info(first));
+        let shared = sim.clone();
+        pool.recycle(sim);
+        // the clone still holds the buffer
+        assert!(pool.is_empty());
+        pool.recycle(shared);
+        assert_eq!(pool.len(), 1);
+        let reused = pool.copy_real(&[2.0; 50]);
+        assert_eq!(reused.as_ptr(), ptr);
+        assert_eq!(reused, vec![2.0; 50]);
+        assert!(pool.is_empty());
+        for len in [10, 20, 30] {
+            pool.give(vec![0.0; len].into());
+        }
+        assert_eq!(pool.len(), 2);
+        assert!(pool.copy_real(&[0.0; 15]).capacity() >= 20);
+    }
+}
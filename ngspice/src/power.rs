@@ -0,0 +1,435 @@
+// Copyright 2022 Andrew Morrow.
+// power.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Canned power-supply tests: soft-start, inrush, and load and line steps.
+
+use crate::format::NumericFormat;
+use crate::{Error, NgSpice};
+use std::f64::consts::PI;
+use std::fmt::{self, Formatter, Write};
+
+/// The shape of a supply ramp from 0 V to its final voltage.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RampProfile {
+    /// A straight line over the ramp time.
+    Linear,
+    /// A raised-cosine S-curve, which starts and ends with zero slope.
+    SCurve,
+    /// An RC-style charge with the given time constant, reaching its final voltage at the end
+    /// of the ramp time.
+    Exponential { tau: f64 },
+}
+
+impl RampProfile {
+    /// The fraction of the final voltage reached at `x`, the fraction of the ramp time elapsed.
+    pub fn fraction(&self, x: f64) -> f64 {
+        let x = x.clamp(0.0, 1.0);
+        match *self {
+            RampProfile::Linear => x,
+            RampProfile::SCurve => 0.5 - 0.5 * (PI * x).cos(),
+            RampProfile::Exponential { tau } => {
+                // normalize so the ramp still ends exactly on its final voltage
+                (1.0 - (-x / tau).exp()) / (1.0 - (-1.0 / tau).exp())
+            }
+        }
+    }
+}
+
+/// A measurement that exceeded its limit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    pub measurement: String,
+    /// The measured value. Infinite if the measured event never happened.
+    pub value: f64,
+    pub limit: f64,
+}
+
+impl Violation {
+    /// Like the [`Display`](fmt::Display) output, with numbers written in `format`.
+    pub fn to_string_with(&self, format: &NumericFormat) -> String {
+        format!(
+            "{} = {} exceeds limit {}",
+            self.measurement,
+            format.format(self.value),
+            format.format(self.limit)
+        )
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_string_with(&NumericFormat::default()))
+    }
+}
+
+/// Describes how to ramp a supply and what to check afterwards.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SoftStartTest {
+    /// The supply node driven by the ramp. The source is added automatically and must not
+    /// already be present.
+    pub supply: String,
+    /// The final supply voltage.
+    pub voltage: f64,
+    /// How long the supply takes to reach its final voltage, in seconds.
+    pub ramp_time: f64,
+    pub profile: RampProfile,
+    /// The regulated output node.
+    pub output: String,
+    /// The output voltage the regulator should settle to.
+    pub target: f64,
+    /// The allowed output error as a fraction of `target`, e.g. `0.02` for 2%.
+    pub tolerance: f64,
+    /// How long to simulate, in seconds.
+    pub stop: f64,
+    /// The largest allowed supply current, in amps.
+    pub max_inrush: Option<f64>,
+    /// The latest allowed time to regulation, in seconds.
+    pub max_regulation_time: Option<f64>,
+}
+
+/// The results of a [`SoftStartTest`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SoftStartReport {
+    /// The largest current drawn from the supply, in amps.
+    pub peak_inrush: f64,
+    /// When the peak current was drawn, in seconds.
+    pub peak_inrush_time: f64,
+    /// When the output entered its tolerance band for the last time, or `None` if it was
+    /// outside the band at the end of the simulation.
+    pub regulation_time: Option<f64>,
+    pub violations: Vec<Violation>,
+}
+
+impl SoftStartTest {
+    const RAMP_POINTS: usize = 32;
+
+    fn source_name(&self) -> String {
+        format!("Vsoftstart_{}", self.supply)
+    }
+
+    /// Renders a PWL voltage source that ramps the supply with the configured profile.
+    pub fn stimulus(&self) -> String {
+        let mut out = format!("{} {} 0 PWL(0 0", self.source_name(), self.supply);
+        for k in 1..=Self::RAMP_POINTS {
+            let x = k as f64 / Self::RAMP_POINTS as f64;
+            write!(
+                out,
+                " {:e} {:e}",
+                x * self.ramp_time,
+                self.voltage * self.profile.fraction(x)
+            )
+            .unwrap();
+        }
+        out.push_str(")\n");
+        out
+    }
+
+    /// Measures inrush and time to regulation from the waveforms of a run of
+    /// [`SoftStartTest::stimulus`], and checks them against the limits.
+    ///
+    /// `supply_current` is the branch current of the ramp source, which ngSPICE reports as
+    /// negative while the source delivers power.
+    pub fn extract(&self, time: &[f64], supply_current: &[f64], output: &[f64]) -> SoftStartReport {
+        let mut report = SoftStartReport::default();
+        for (&t, &i) in time.iter().zip(supply_current) {
+            if -i > report.peak_inrush {
+                report.peak_inrush = -i;
+                report.peak_inrush_time = t;
+            }
+        }
+        let band = (self.target * self.tolerance).abs();
+        let inside = |v: f64| (v - self.target).abs() <= band;
+        report.regulation_time = match output.iter().rposition(|&v| !inside(v)) {
+            None => time.first().copied(),
+            Some(last) if last + 1 < output.len() => Some(time[last + 1]),
+            Some(_) => None,
+        };
+        if let Some(limit) = self.max_inrush {
+            if report.peak_inrush > limit {
+                report.violations.push(Violation {
+                    measurement: "peak_inrush".to_owned(),
+                    value: report.peak_inrush,
+                    limit,
+                });
+            }
+        }
+        if let Some(limit) = self.max_regulation_time {
+            let value = report.regulation_time.unwrap_or(f64::INFINITY);
+            if value > limit {
+                report.violations.push(Violation {
+                    measurement: "regulation_time".to_owned(),
+                    value,
+                    limit,
+                });
+            }
+        }
+        report
+    }
+}
+
+/// What a [`StepTest`] steps.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    /// A current sink from `node` to ground stepping between `from` and `to` amps.
+    Load { node: String, from: f64, to: f64 },
+    /// A supply voltage on `node` stepping between `from` and `to` volts.
+    Line { node: String, from: f64, to: f64 },
+}
+
+/// Describes a transient load or line step test of a regulator.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepTest {
+    pub step: Step,
+    /// When to step, in seconds. The first step goes to `to`, the next back to `from`, and so
+    /// on.
+    pub times: Vec<f64>,
+    /// How long each step takes, in seconds.
+    pub edge: f64,
+    /// The regulated output node.
+    pub output: String,
+    /// The output voltage the regulator should hold.
+    pub target: f64,
+    /// The allowed output error as a fraction of `target`, e.g. `0.01` for 1%.
+    pub tolerance: f64,
+    /// How long to simulate, in seconds.
+    pub stop: f64,
+}
+
+/// The output's response to one step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StepResponse {
+    /// When the step started, in seconds.
+    pub time: f64,
+    /// The largest error from the target before the next step, in volts. Positive for
+    /// overshoot and negative for undershoot.
+    pub deviation: f64,
+    /// How long after the step the output entered its tolerance band for the last time, or
+    /// `None` if it was outside the band when the next step started.
+    pub recovery_time: Option<f64>,
+}
+
+impl StepTest {
+    fn steps_in_order(&self) -> bool {
+        self.times.windows(2).all(|w| w[0] <= w[1])
+    }
+
+    fn source_name(&self) -> String {
+        match &self.step {
+            Step::Load { node, .. } => format!("Istep_{}", node),
+            Step::Line { node, .. } => format!("Vstep_{}", node),
+        }
+    }
+
+    /// Renders a PWL source that applies the steps.
+    pub fn stimulus(&self) -> String {
+        let (node, from, to) = match &self.step {
+            Step::Load { node, from, to } | Step::Line { node, from, to } => (node, *from, *to),
+        };
+        let mut out = format!("{} {} 0 PWL(0 {:e}", self.source_name(), node, from);
+        let mut level = from;
+        for &t in &self.times {
+            let next = if level == from { to } else { from };
+            write!(out, " {:e} {:e} {:e} {:e}", t, level, t + self.edge, next).unwrap();
+            level = next;
+        }
+        out.push_str(")\n");
+        out
+    }
+
+    /// Measures the output's deviation and recovery after each step.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `time` and `output` have different lengths, or if [`StepTest::times`]
+    /// decreases anywhere.
+    pub fn extract(&self, time: &[f64], output: &[f64]) -> Vec<StepResponse> {
+        assert_eq!(
+            time.len(),
+            output.len(),
+            "time and output must have the same length"
+        );
+        assert!(self.steps_in_order(), "step times must not decrease");
+        let band = (self.target * self.tolerance).abs();
+        let mut result = Vec::new();
+        for (k, &start) in self.times.iter().enumerate() {
+            let end = self.times.get(k + 1).copied().unwrap_or(f64::INFINITY);
+            let lo = time.partition_point(|&t| t < start);
+            let hi = time.partition_point(|&t| t < end);
+            let window = &output[lo..hi];
+            let deviation = window
+                .iter()
+                .map(|&v| v - self.target)
+                .fold(0.0, |a: f64, e| if e.abs() > a.abs() { e } else { a });
+            let recovery_time = match window.iter().rposition(|&v| (v - self.target).abs() > band) {
+                None => Some(0.0),
+                Some(last) if lo + last + 1 < hi => Some(time[lo + last + 1] - start),
+                Some(_) => None,
+            };
+            result.push(StepResponse {
+                time: start,
+                deviation,
+                recovery_time,
+            });
+        }
+        result
+    }
+}
+
+impl NgSpice {
+    /// Ramps a supply into a regulator and measures inrush current and time to regulation.
+    ///
+    /// `dut` holds the netlist lines for the regulator and its load, without a title or `.end`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the simulation fails or the output or supply current is missing.
+    pub fn soft_start(dut: &str, test: &SoftStartTest) -> Result<SoftStartReport, Error> {
+        let circuit = format!(
+            "* soft-start\n{}\n{}.end\n",
+            dut.trim_end(),
+            test.stimulus()
+        );
+        let step = (test.ramp_time / 200.0).min(test.stop / 1000.0);
+        let sim = NgSpice::simulate(&circuit, &format!("tran {:e} {:e}", step, test.stop))?;
+        let (_, time) = sim.scale_vector().ok_or(Error::MissingScale)?;
+        let time = time.real().ok_or(Error::MissingScale)?;
+        let current = sim.real_vector(&format!(
+            "{}#branch",
+            test.source_name().to_ascii_lowercase()
+        ))?;
+        let output = sim.real_vector(&test.output)?;
+        Ok(test.extract(time, current, output))
+    }
+
+    /// Applies load or line steps to a regulator and measures its response to each.
+    ///
+    /// `dut` holds the netlist lines for the regulator, its supply, and its load, without a
+    /// title or `.end`. The step source is added automatically; for line steps it replaces the
+    /// supply, which must not already be present.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidArgument`] if the step times decrease, or an error if the
+    /// simulation fails or the output is missing.
+    pub fn step_response(dut: &str, test: &StepTest) -> Result<Vec<StepResponse>, Error> {
+        if !test.steps_in_order() {
+            return Err(Error::InvalidArgument(
+                "step times must not decrease".to_owned(),
+            ));
+        }
+        let circuit = format!(
+            "* step response\n{}\n{}.end\n",
+            dut.trim_end(),
+            test.stimulus()
+        );
+        let step = (test.edge / 10.0).min(test.stop / 1000.0);
+        let sim = NgSpice::simulate(&circuit, &format!("tran {:e} {:e}", step, test.stop))?;
+        let (_, time) = sim.scale_vector().ok_or(Error::MissingScale)?;
+        let time = time.real().ok_or(Error::MissingScale)?;
+        let output = sim.real_vector(&test.output)?;
+        if output.len() != time.len() {
+            return Err(Error::MissingVector(test.output.clone()));
+        }
+        Ok(test.extract(time, output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_inrush_and_regulation() {
+        let test = SoftStartTest {
+            supply: "vin".to_owned(),
+            voltage: 12.0,
+            ramp_time: 1e-3,
+            profile: RampProfile::Linear,
+            output: "out".to_owned(),
+            target: 5.0,
+            tolerance: 0.02,
+            stop: 5e-3,
+            max_inrush: Some(1.0),
+            max_regulation_time: Some(1e-3),
+        };
+        assert!(test
+            .stimulus()
+            .starts_with("Vsoftstart_vin vin 0 PWL(0 0 3.125e-5 3.75e-1"));
+        assert!((RampProfile::SCurve.fraction(0.5) - 0.5).abs() < 1e-12);
+        assert!((RampProfile::Exponential { tau: 0.2 }.fraction(1.0) - 1.0).abs() < 1e-12);
+
+        let time: Vec<f64> = (0..=50).map(|i| i as f64 * 1e-4).collect();
+        // the output charges up and regulates from 2 ms on
+        let output: Vec<f64> = time.iter().map(|&t| (t / 2e-3).min(1.0) * 5.0).collect();
+        let current: Vec<f64> = time
+            .iter()
+            .map(|&t| if t == 5e-4 { -2.0 } else { -0.1 })
+            .collect();
+        let report = test.extract(&time, &current, &output);
+        assert_eq!(report.peak_inrush, 2.0);
+        assert_eq!(report.peak_inrush_time, 5e-4);
+        assert!((report.regulation_time.unwrap() - 2e-3).abs() < 1e-3 * 0.15);
+        assert_eq!(report.violations.len(), 2);
+        assert_eq!(report.violations[0].measurement, "peak_inrush");
+    }
+
+    #[test]
+    fn measures_load_steps() {
+        let test = StepTest {
+            step: Step::Load {
+                node: "out".to_owned(),
+                from: 0.1,
+                to: 1.0,
+            },
+            times: vec![1.0, 3.0],
+            edge: 0.01,
+            output: "out".to_owned(),
+            target: 5.0,
+            tolerance: 0.01,
+            stop: 5.0,
+        };
+        assert_eq!(
+            test.stimulus(),
+            "Istep_out out 0 PWL(0 1e-1 1e0 1e-1 1.01e0 1e0 3e0 1e0 3.01e0 1e-1)\n"
+        );
+        // droops by 0.5 V and recovers after 0.5 s; overshoots and never recovers
+        let time: Vec<f64> = (0..=50).map(|i| i as f64 * 0.1).collect();
+        let output: Vec<f64> = time
+            .iter()
+            .map(|&t| match t {
+                t if t < 1.05 => 5.0,
+                t if t < 1.45 => 4.5,
+                t if t < 3.05 => 5.0,
+                _ => 5.2,
+            })
+            .collect();
+        let steps = test.extract(&time, &output);
+        assert_eq!(steps.len(), 2);
+        assert!((steps[0].deviation + 0.5).abs() < 1e-12);
+        assert!((steps[0].recovery_time.unwrap() - 0.5).abs() < 1e-9);
+        assert!((steps[1].deviation - 0.2).abs() < 1e-12);
+        assert_eq!(steps[1].recovery_time, None);
+        let unordered = StepTest {
+            times: vec![3.0, 1.0],
+            ..test
+        };
+        assert!(matches!(
+            NgSpice::step_response("", &unordered),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
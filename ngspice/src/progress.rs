@@ -0,0 +1,141 @@
+// Copyright 2022 Andrew Morrow.
+// progress.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Reports how far an analysis has progressed while it runs.
+
+use crate::background::{self, Options};
+use crate::circuit::parse_number;
+use crate::{Error, NgSpice, Simulation};
+use ngspice_sys::*;
+use std::os::raw::c_int;
+use std::sync::Mutex;
+
+/// A progress callback and the stop time it measures against.
+struct Reporter {
+    callback: Box<dyn FnMut(Progress) + Send>,
+    stop: Option<f64>,
+}
+
+static REPORTER: Mutex<Option<Reporter>> = Mutex::new(None);
+
+/// How far a simulation has got, passed to [`NgSpice::simulate_with_progress`]'s callback.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress {
+    /// The value of the scale at the latest point, e.g. the simulated time.
+    pub position: f64,
+    /// Where the scale will end, if the command states it, e.g. a transient's stop time.
+    pub stop: Option<f64>,
+}
+
+impl Progress {
+    /// The fraction of the analysis completed, from 0 to 1, if the stop is known.
+    pub fn fraction(&self) -> Option<f64> {
+        self.stop
+            .filter(|&s| s > 0.0)
+            .map(|s| (self.position / s).clamp(0.0, 1.0))
+    }
+}
+
+/// The stop time of a `tran` command, e.g. `1e-3` for `tran 1u 1m`.
+pub(crate) fn stop_time(command: &str) -> Option<f64> {
+    let mut words = command.split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case("tran") {
+        return None;
+    }
+    words.nth(1).and_then(parse_number)
+}
+
+pub(crate) fn attach(callback: Box<dyn FnMut(Progress) + Send>, command: &str) {
+    *REPORTER.lock().unwrap_or_else(|e| e.into_inner()) = Some(Reporter {
+        callback,
+        stop: stop_time(command),
+    });
+}
+
+pub(crate) fn detach() {
+    *REPORTER.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Passes the scale value of a point from ngSPICE's data callback to the reporter, if any.
+///
+/// # Safety
+///
+/// `data` must be the valid pointer ngSPICE passed to the callback, holding `count` vectors.
+pub(crate) unsafe fn report(data: pvecvaluesall, count: c_int) {
+    let mut reporter = REPORTER.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(reporter) = reporter.as_mut() else {
+        return;
+    };
+    for k in 0..count.min((*data).veccount) as usize {
+        let v = *(*data).vecsa.add(k);
+        if (*v).is_scale {
+            (reporter.callback)(Progress {
+                position: (*v).creal,
+                stop: reporter.stop,
+            });
+            return;
+        }
+    }
+}
+
+impl NgSpice {
+    /// Like [`NgSpice::simulate`], but calls `progress` with the position of every point as
+    /// it is computed.
+    ///
+    /// The simulation runs on ngSPICE's background thread, where `progress` is called; this
+    /// function blocks until it finishes.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`NgSpice::simulate`].
+    pub fn simulate_with_progress<F>(
+        circuit: &str,
+        command: &str,
+        progress: F,
+    ) -> Result<Simulation, Error>
+    where
+        F: FnMut(Progress) + Send + 'static,
+    {
+        background::spawn(
+            circuit,
+            command,
+            Options {
+                progress: Some(Box::new(progress)),
+                ..Options::default()
+            },
+        )?
+        .join()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_against_stop_time() {
+        assert_eq!(stop_time("tran 1u 2m"), Some(2e-3));
+        assert_eq!(stop_time("TRAN 1n 10u 0 1n uic"), Some(10e-6));
+        assert_eq!(stop_time("ac dec 10 1 1meg"), None);
+        let p = Progress {
+            position: 0.5e-3,
+            stop: Some(2e-3),
+        };
+        assert_eq!(p.fraction(), Some(0.25));
+        assert_eq!(Progress { stop: None, ..p }.fraction(), None);
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright 2022 Andrew Morrow.
+// rails.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Netlist fragments for realistic supply rails and voltage references.
+
+use crate::montecarlo::Tolerance;
+use std::fmt::Write;
+
+/// A decoupling capacitor with its parasitics.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Decoupling {
+    /// In farads. A zero-farad entry is left out of the netlist, since without its capacitor
+    /// the branch would short or load the rail.
+    pub capacitance: f64,
+    /// Equivalent series resistance, in ohms.
+    pub esr: f64,
+    /// Equivalent series inductance, in henries.
+    pub esl: f64,
+}
+
+/// A supply: a DC source, optionally ramped up, behind a series impedance and decoupled at
+/// its load.
+///
+/// The voltage is toleranced. [`SupplyRail::netlist`] defines it with a `.param` card named
+/// by [`SupplyRail::parameter`], which [`crate::montecarlo::MonteCarlo`] replaces with sampled
+/// values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SupplyRail {
+    /// Used to name the rail's elements and internal nodes.
+    pub name: String,
+    /// The node the load connects to.
+    pub node: String,
+    pub voltage: Tolerance,
+    /// How long the source takes to ramp from 0 V, in seconds. `None` starts at full voltage.
+    pub ramp: Option<f64>,
+    /// Series resistance, in ohms.
+    pub resistance: f64,
+    /// Series inductance, in henries.
+    pub inductance: f64,
+    pub decoupling: Vec<Decoupling>,
+}
+
+/// Writes a series chain of elements from `from` to `to`, skipping zero-valued ones. If every
+/// element is zero, `from` and `to` are linked directly, so the chain must not end at ground.
+fn series(out: &mut String, prefix: &str, from: &str, to: &str, elements: &[(char, f64)]) {
+    let present: Vec<&(char, f64)> = elements.iter().filter(|(_, v)| *v != 0.0).collect();
+    if present.is_empty() {
+        // a zero-ohm link keeps `from` and `to` distinct nodes
+        writeln!(out, "V{}_link {} {} DC 0", prefix, from, to).unwrap();
+        return;
+    }
+    let mut node = from.to_owned();
+    for (k, &&(kind, value)) in present.iter().enumerate() {
+        let next = if k + 1 == present.len() {
+            to.to_owned()
+        } else {
+            format!("{}_{}", prefix, k + 1)
+        };
+        writeln!(
+            out,
+            "{}{}_{} {} {} {:e}",
+            kind, prefix, k, node, next, value
+        )
+        .unwrap();
+        node = next;
+    }
+}
+
+impl SupplyRail {
+    /// An ideal rail: no ramp, series impedance, or decoupling.
+    pub fn new(name: &str, node: &str, voltage: Tolerance) -> Self {
+        SupplyRail {
+            name: name.to_owned(),
+            node: node.to_owned(),
+            voltage,
+            ramp: None,
+            resistance: 0.0,
+            inductance: 0.0,
+            decoupling: Vec::new(),
+        }
+    }
+
+    pub fn ramp(mut self, time: f64) -> Self {
+        self.ramp = Some(time);
+        self
+    }
+
+    pub fn series(mut self, resistance: f64, inductance: f64) -> Self {
+        self.resistance = resistance;
+        self.inductance = inductance;
+        self
+    }
+
+    pub fn decouple(mut self, capacitance: f64, esr: f64, esl: f64) -> Self {
+        self.decoupling.push(Decoupling {
+            capacitance,
+            esr,
+            esl,
+        });
+        self
+    }
+
+    /// The name of the `.param` holding the rail voltage.
+    pub fn parameter(&self) -> String {
+        format!("vrail_{}", self.name).to_ascii_lowercase()
+    }
+
+    /// The rail's toleranced parameters, for [`crate::montecarlo::MonteCarlo::params`].
+    pub fn tolerances(&self) -> Vec<(String, Tolerance)> {
+        vec![(self.parameter(), self.voltage)]
+    }
+
+    /// Renders the rail's netlist lines.
+    pub fn netlist(&self) -> String {
+        let param = self.parameter();
+        let src = format!("{}_src", self.name);
+        let mut out = format!(".param {}={:e}\n", param, self.voltage.nominal);
+        match self.ramp {
+            Some(t) => writeln!(
+                out,
+                "V{} {} 0 PWL(0 0 {:e} {{{}}})",
+                self.name, src, t, param
+            ),
+            None => writeln!(out, "V{} {} 0 DC {{{}}}", self.name, src, param),
+        }
+        .unwrap();
+        series(
+            &mut out,
+            &format!("{}_s", self.name),
+            &src,
+            &self.node,
+            &[('R', self.resistance), ('L', self.inductance)],
+        );
+        for (k, cap) in self
+            .decoupling
+            .iter()
+            .enumerate()
+            .filter(|(_, cap)| cap.capacitance != 0.0)
+        {
+            series(
+                &mut out,
+                &format!("{}_d{}", self.name, k),
+                &self.node,
+                "0",
+                &[('C', cap.capacitance), ('R', cap.esr), ('L', cap.esl)],
+            );
+        }
+        out
+    }
+}
+
+/// A voltage reference with a toleranced initial accuracy and an output resistance.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    pub name: String,
+    pub node: String,
+    pub voltage: Tolerance,
+    /// In ohms.
+    pub output_resistance: f64,
+}
+
+impl Reference {
+    pub fn new(name: &str, node: &str, voltage: Tolerance, output_resistance: f64) -> Self {
+        Reference {
+            name: name.to_owned(),
+            node: node.to_owned(),
+            voltage,
+            output_resistance,
+        }
+    }
+
+    /// The name of the `.param` holding the reference voltage.
+    pub fn parameter(&self) -> String {
+        format!("vref_{}", self.name).to_ascii_lowercase()
+    }
+
+    /// The reference's toleranced parameters, for [`crate::montecarlo::MonteCarlo::params`].
+    pub fn tolerances(&self) -> Vec<(String, Tolerance)> {
+        vec![(self.parameter(), self.voltage)]
+    }
+
+    /// Renders the reference's netlist lines.
+    pub fn netlist(&self) -> String {
+        let param = self.parameter();
+        let int = format!("{}_int", self.name);
+        let mut out = format!(".param {}={:e}\n", param, self.voltage.nominal);
+        writeln!(out, "V{} {} 0 DC {{{}}}", self.name, int, param).unwrap();
+        series(
+            &mut out,
+            &format!("{}_o", self.name),
+            &int,
+            &self.node,
+            &[('R', self.output_resistance)],
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_rail() {
+        let rail = SupplyRail::new("vdd", "vdd", Tolerance::uniform(3.3, 0.05))
+            .ramp(1e-3)
+            .series(0.1, 0.0)
+            .decouple(0.0, 0.0, 0.0)
+            .decouple(1e-6, 0.01, 1e-9);
+        assert_eq!(
+            rail.netlist(),
+            ".param vrail_vdd=3.3e0
+Vvdd vdd_src 0 PWL(0 0 1e-3 {vrail_vdd})
+Rvdd_s_0 vdd_src vdd 1e-1
+Cvdd_d1_0 vdd vdd_d1_1 1e-6
+Rvdd_d1_1 vdd_d1_1 vdd_d1_2 1e-2
+Lvdd_d1_2 vdd_d1_2 0 1e-9
+"
+        );
+        assert_eq!(rail.tolerances()[0].0, "vrail_vdd");
+        let vref = Reference::new("ref", "vref", Tolerance::gaussian(1.2, 0.001), 0.0);
+        assert_eq!(
+            vref.netlist(),
+            ".param vref_ref=1.2e0\nVref ref_int 0 DC {vref_ref}\nVref_o_link ref_int vref DC 0\n"
+        );
+    }
+}
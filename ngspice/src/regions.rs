@@ -0,0 +1,290 @@
+// Copyright 2022 Andrew Morrow.
+// regions.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Classifies the operating region of each MOSFET after an operating point analysis.
+
+use crate::circuit::{Card, Circuit};
+use crate::format::NumericFormat;
+use crate::{Error, NgSpice, Simulation};
+use std::collections::BTreeMap;
+use std::fmt::{self, Formatter, Write as _};
+
+/// The square-law operating region of a MOSFET.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Region {
+    /// The gate-source voltage is below threshold.
+    Cutoff,
+    /// The channel is not pinched off: `vds < vgs - vth`.
+    Triode,
+    Saturation,
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Region::Cutoff => "cutoff",
+            Region::Triode => "triode",
+            Region::Saturation => "saturation",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Channel {
+    N,
+    P,
+}
+
+/// Classifies a device from its terminal voltages and threshold, all signed as ngSPICE
+/// reports them, i.e. negative when a PMOS device is on.
+pub fn classify(channel: Channel, vgs: f64, vds: f64, vth: f64) -> Region {
+    let sign = match channel {
+        Channel::N => 1.0,
+        Channel::P => -1.0,
+    };
+    let overdrive = sign * (vgs - vth);
+    if overdrive <= 0.0 {
+        Region::Cutoff
+    } else if sign * vds < overdrive {
+        Region::Triode
+    } else {
+        Region::Saturation
+    }
+}
+
+/// The bias of one MOSFET at the operating point.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceBias {
+    /// The element name, in lowercase.
+    pub device: String,
+    pub channel: Channel,
+    pub vgs: f64,
+    pub vds: f64,
+    pub vth: f64,
+    pub region: Region,
+}
+
+impl DeviceBias {
+    /// How far the gate is driven past threshold, positive for both channel types.
+    pub fn overdrive(&self) -> f64 {
+        match self.channel {
+            Channel::N => self.vgs - self.vth,
+            Channel::P => self.vth - self.vgs,
+        }
+    }
+
+    /// How far the device is from the edge between triode and saturation, positive in
+    /// saturation and negative in triode.
+    pub fn saturation_margin(&self) -> f64 {
+        let vds = match self.channel {
+            Channel::N => self.vds,
+            Channel::P => -self.vds,
+        };
+        vds - self.overdrive()
+    }
+}
+
+/// The operating region of every top-level MOSFET in a circuit.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RegionReport {
+    /// One entry per device, in netlist order.
+    pub devices: Vec<DeviceBias>,
+}
+
+impl RegionReport {
+    pub fn device(&self, name: &str) -> Option<&DeviceBias> {
+        self.devices
+            .iter()
+            .find(|d| d.device.eq_ignore_ascii_case(name))
+    }
+
+    /// The devices that are not in their expected region. `expected` maps lowercase device
+    /// names to regions; devices it does not mention are expected to be in saturation, as most
+    /// devices in an analog design are.
+    pub fn unexpected<'a>(
+        &'a self,
+        expected: &'a BTreeMap<String, Region>,
+    ) -> impl Iterator<Item = &'a DeviceBias> {
+        self.devices.iter().filter(move |d| {
+            d.region
+                != expected
+                    .get(&d.device)
+                    .copied()
+                    .unwrap_or(Region::Saturation)
+        })
+    }
+}
+
+impl RegionReport {
+    /// Writes one line per device, with voltages written by `number`.
+    fn write(&self, out: &mut impl fmt::Write, number: impl Fn(f64) -> String) -> fmt::Result {
+        for d in &self.devices {
+            writeln!(
+                out,
+                "{}: {} (vgs={} vds={} vth={})",
+                d.device,
+                d.region,
+                number(d.vgs),
+                number(d.vds),
+                number(d.vth)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Like the [`Display`](fmt::Display) output, with voltages written in `format` instead of
+    /// to three decimal places.
+    pub fn to_string_with(&self, format: &NumericFormat) -> String {
+        let mut out = String::new();
+        self.write(&mut out, |x| format.format(x)).unwrap();
+        out
+    }
+}
+
+impl fmt::Display for RegionReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.write(f, |x| format!("{:.3}", x))
+    }
+}
+
+impl NgSpice {
+    /// Runs an operating point analysis and classifies every top-level MOSFET as in cutoff,
+    /// triode, or saturation, from ngSPICE's `vgs`, `vds`, and `vth` device quantities.
+    ///
+    /// The channel type comes from the device's `.model` card, or if the model is not in the
+    /// circuit, e.g. because it is in an included library, from the sign of the threshold.
+    /// Devices inside subcircuits are not classified.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from [`NgSpice::simulate`], or [`Error::MissingVector`] if a device
+    /// model does not report the needed quantities.
+    pub fn operating_regions(circuit: &Circuit) -> Result<RegionReport, Error> {
+        let devices = mosfets(circuit);
+        if devices.is_empty() {
+            return Ok(RegionReport::default());
+        }
+        let mut deck = circuit.clone();
+        let mut save = String::from(".save all");
+        for (name, _) in &devices {
+            for q in ["vgs", "vds", "vth"] {
+                save.push_str(&format!(" @{}[{}]", name, q));
+            }
+        }
+        deck.cards.push(Card::Directive(save));
+        let sim = NgSpice::simulate(&deck.to_string(), "op")?;
+        report(&devices, &sim)
+    }
+}
+
+/// The lowercase name and, if its model is in the circuit, the channel of each MOSFET.
+fn mosfets(circuit: &Circuit) -> Vec<(String, Option<Channel>)> {
+    let mut models = BTreeMap::new();
+    for model in circuit.models() {
+        let channel = match model.kind.to_ascii_lowercase().as_str() {
+            "nmos" => Channel::N,
+            "pmos" => Channel::P,
+            _ => continue,
+        };
+        models.insert(model.name.to_ascii_lowercase(), channel);
+    }
+    circuit
+        .elements()
+        .filter(|e| e.kind() == 'M')
+        .map(|e| {
+            let model = e.value().unwrap_or("").to_ascii_lowercase();
+            (e.name.to_ascii_lowercase(), models.get(&model).copied())
+        })
+        .collect()
+}
+
+fn report(devices: &[(String, Option<Channel>)], sim: &Simulation) -> Result<RegionReport, Error> {
+    let mut report = RegionReport::default();
+    for (name, channel) in devices {
+        let quantity = |q: &str| -> Result<f64, Error> {
+            let vector = format!("@{}[{}]", name, q);
+            sim.real_vector(&vector)?
+                .first()
+                .copied()
+                .ok_or(Error::MissingVector(vector))
+        };
+        let (vgs, vds, vth) = (quantity("vgs")?, quantity("vds")?, quantity("vth")?);
+        let channel = channel.unwrap_or(if vth < 0.0 { Channel::P } else { Channel::N });
+        report.devices.push(DeviceBias {
+            device: name.clone(),
+            channel,
+            vgs,
+            vds,
+            vth,
+            region: classify(channel, vgs, vds, vth),
+        });
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, VectorInfo, VectorValues};
+
+    #[test]
+    fn classifies_devices() {
+        let circuit = Circuit::parse(
+            "* pair
+M1 d1 g s 0 nch w=1u l=1u
+M2 d2 g s vdd pch w=1u l=1u
+M3 d3 0 s 0 nch
+.model nch nmos level=1
+.model pch pmos(level=1)
+.end",
+        );
+        let devices = mosfets(&circuit);
+        assert_eq!(devices[1], ("m2".to_owned(), Some(Channel::P)));
+        let mut sim = Simulation::default();
+        for (name, value) in [
+            ("@m1[vgs]", 1.0),
+            ("@m1[vds]", 0.2),
+            ("@m1[vth]", 0.5),
+            ("@m2[vgs]", -1.0),
+            ("@m2[vds]", -1.0),
+            ("@m2[vth]", -0.5),
+            ("@m3[vgs]", 0.3),
+            ("@m3[vds]", 1.0),
+            ("@m3[vth]", 0.5),
+        ] {
+            let info = VectorInfo {
+                datatype: DataType::Voltage,
+                values: VectorValues::Real(vec![value].into()),
+                scale: None,
+            };
+            sim.vectors.insert(name.to_owned(), info);
+        }
+        let report = report(&devices, &sim).unwrap();
+        let regions: Vec<Region> = report.devices.iter().map(|d| d.region).collect();
+        assert_eq!(
+            regions,
+            vec![Region::Triode, Region::Saturation, Region::Cutoff]
+        );
+        assert!((report.devices[1].saturation_margin() - 0.5).abs() < 1e-12);
+        let expected = BTreeMap::from([("m3".to_owned(), Region::Cutoff)]);
+        let odd: Vec<&str> = report
+            .unexpected(&expected)
+            .map(|d| d.device.as_str())
+            .collect();
+        assert_eq!(odd, vec!["m1"]);
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright 2022 Andrew Morrow.
+// session.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Exclusive, multi-command access to ngSPICE.
+
+use crate::limits::{Budget, ResourceLimits};
+use crate::value::SpiceValue;
+use crate::{extract_plot, Error, NgSpice, Simulation};
+use ngspice_sys::*;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::pin::Pin;
+use std::sync::MutexGuard;
+
+/// Exclusive access to the shared ngSPICE instance across several commands.
+///
+/// Unlike [`NgSpice::simulate`], a session keeps the loaded circuit and every plot ngSPICE
+/// produces (`tran1`, `ac2`, ...) until they are destroyed or the session ends, so results from
+/// before and after a change can be compared. Other threads block until the session is
+/// dropped.
+///
+/// Hooks registered with [`NgSpice::on_pre_load`] and [`NgSpice::on_post_extract`] do not run
+/// in sessions.
+pub struct Session {
+    handle: MutexGuard<'static, Pin<Box<NgSpice>>>,
+}
+
+impl NgSpice {
+    /// Starts a session, blocking until no other simulation or session is running.
+    ///
+    /// # Panics
+    ///
+    /// Panics if ngSPICE encountered a fatal error on another thread.
+    pub fn session() -> Session {
+        let mut handle = NgSpice::shared().lock().expect(
+            "ngSPICE mutex was poisoned, meaning ngSPICE encountered a fatal error on another thread",
+        );
+        handle.as_mut().stdout().truncate(0);
+        handle.as_mut().stderr().truncate(0);
+        Session { handle }
+    }
+}
+
+impl Session {
+    /// Parses a new circuit, replacing the previously loaded one. Existing plots are kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the circuit fails validation or ngSPICE cannot parse it.
+    pub fn load_circuit(&mut self, circuit: &str) -> Result<(), Error> {
+        NgSpice::check_circuit(circuit)?;
+        self.handle.as_mut().load_circuit(circuit)
+    }
+
+    /// Runs an arbitrary ngSPICE command, subject to the current command policy.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command is not permitted or ngSPICE rejects it.
+    pub fn command(&mut self, cmd: &str) -> Result<(), Error> {
+        NgSpice::check_command(cmd)?;
+        self.handle.as_mut().command(cmd)
+    }
+
+    /// Runs an analysis command and returns the name of the plot it created.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the command is not permitted or ngSPICE rejects it.
+    pub fn run(&mut self, cmd: &str) -> Result<String, Error> {
+        self.command(cmd)?;
+        Ok(self.current_plot())
+    }
+
+    /// The name of the current plot, e.g. `tran1`.
+    pub fn current_plot(&self) -> String {
+        unsafe { CStr::from_ptr(ngSpice_CurPlot()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// The names of every plot ngSPICE is holding, most recent first.
+    pub fn plots(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        unsafe {
+            let mut name = ngSpice_AllPlots() as *const *mut c_char;
+            if name.is_null() {
+                return result;
+            }
+            while !(*name).is_null() {
+                result.push(CStr::from_ptr(*name).to_string_lossy().into_owned());
+                name = name.add(1);
+            }
+        }
+        result
+    }
+
+    /// Copies every vector of the named plot out of ngSPICE.
+    ///
+    /// The returned simulation includes all ngSPICE output produced by the session so far.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingVector`] if there is no such plot.
+    pub fn plot(&mut self, name: &str) -> Result<Simulation, Error> {
+        if !self.plots().iter().any(|p| p == name) {
+            return Err(Error::MissingVector(name.to_owned()));
+        }
+        let plot = CString::new(name).map_err(|_| Error::InvalidStringEncoding)?;
+        let limits = ResourceLimits::default();
+        let (mut sim, _) = unsafe { extract_plot(plot.as_ptr(), &mut Budget::new(&limits)) };
+        sim.stdout = self.handle.as_mut().stdout().clone();
+        sim.stderr = self.handle.as_mut().stderr().clone();
+        Ok(sim)
+    }
+
+    /// Discards the output collected so far, so the next extracted plot holds only what
+    /// follows.
+    pub(crate) fn clear_output(&mut self) {
+        self.handle.as_mut().stdout().truncate(0);
+        self.handle.as_mut().stderr().truncate(0);
+    }
+
+    /// All ngSPICE output to stdout during the session so far.
+    pub(crate) fn stdout(&mut self) -> String {
+        self.handle.as_mut().stdout().clone()
+    }
+
+    /// Changes the value of an element in the loaded circuit, as with `alter R1=10k`, without
+    /// reloading it. The change applies to every later analysis in the session.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingElement`] if the circuit has no such element.
+    pub fn alter(&mut self, element: &str, value: impl Into<SpiceValue>) -> Result<(), Error> {
+        check_name(element)?;
+        let cmd = format!("alter {}={}", element, value.into());
+        self.alter_command(element, &cmd)
+    }
+
+    /// Changes a parameter of an element in the loaded circuit, as with `alter M1 w=2u`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingElement`] if the circuit has no such element.
+    pub fn alter_parameter(
+        &mut self,
+        element: &str,
+        parameter: &str,
+        value: impl Into<SpiceValue>,
+    ) -> Result<(), Error> {
+        check_name(element)?;
+        check_name(parameter)?;
+        let cmd = format!("alter {} {}={}", element, parameter, value.into());
+        self.alter_command(element, &cmd)
+    }
+
+    /// Changes a parameter of a model in the loaded circuit, as with `altermod nmos vth0=0.4`.
+    /// Every element using the model is affected.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingElement`] if the circuit has no such model.
+    pub fn altermod(
+        &mut self,
+        model: &str,
+        parameter: &str,
+        value: impl Into<SpiceValue>,
+    ) -> Result<(), Error> {
+        check_name(model)?;
+        check_name(parameter)?;
+        let cmd = format!("altermod {} {}={}", model, parameter, value.into());
+        self.alter_command(model, &cmd)
+    }
+
+    /// Runs an `alter` or `altermod` command, which ngSPICE reports failing only on stderr.
+    fn alter_command(&mut self, name: &str, cmd: &str) -> Result<(), Error> {
+        let seen = self.handle.as_mut().stderr().len();
+        self.command(cmd)?;
+        let new = self.handle.as_mut().stderr()[seen..].to_ascii_lowercase();
+        if new.contains("no such") || new.contains("error") {
+            return Err(Error::MissingElement(name.to_owned()));
+        }
+        Ok(())
+    }
+
+    /// Frees a plot and all of its vectors.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `name` is not a single plot name, or if ngSPICE cannot destroy the
+    /// plot.
+    pub fn destroy(&mut self, name: &str) -> Result<(), Error> {
+        if name.as_bytes().contains(&0) {
+            return Err(Error::InvalidStringEncoding);
+        }
+        // bypass the command policy, which destroying plots cannot harm, but only for one plot;
+        // a separator would smuggle further commands past it
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == ';') {
+            return Err(Error::MissingVector(name.to_owned()));
+        }
+        self.handle.as_mut().command(&format!("destroy {}", name))
+    }
+}
+
+/// Rejects names that would change the meaning of an `alter` command.
+fn check_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '=') {
+        return Err(Error::MissingElement(name.to_owned()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, NgSpice};
+
+    #[test]
+    fn keeps_plots_across_commands() -> Result<(), Error> {
+        let mut session = NgSpice::session();
+        session.load_circuit(
+            ".title divider
+V1 in 0 dc(10)
+R1 in out 1k
+R2 out 0 1k
+.end",
+        )?;
+        let before = session.run("op")?;
+        session.alter("R2", 3e3)?;
+        let after = session.run("op")?;
+        assert_ne!(before, after);
+        let v_before = session.plot(&before)?.real_vector("out")?[0];
+        let v_after = session.plot(&after)?.real_vector("out")?[0];
+        assert!((v_before - 5.0).abs() < 1e-6);
+        assert!((v_after - 7.5).abs() < 1e-6);
+        assert!(matches!(
+            session.alter("R9", 1.0),
+            Err(Error::MissingElement(_))
+        ));
+        assert!(session.altermod("bad name", "vth0", 0.4).is_err());
+        assert!(matches!(
+            session.destroy("tran1; shell true"),
+            Err(Error::MissingVector(_))
+        ));
+        session.destroy(&before)?;
+        assert!(!session.plots().contains(&before));
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+// Copyright 2022 Andrew Morrow.
+// soa.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Safe-operating-area checks for power devices over transient results.
+
+use crate::control::vector_name;
+use crate::{Error, Simulation};
+
+/// A pulsed SOA boundary: the largest current at each voltage for pulses no longer than
+/// `duration`, as read off a datasheet's SOA chart.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SoaCurve {
+    /// The longest pulse this boundary permits, in seconds. Use infinity for the DC boundary.
+    pub duration: f64,
+    /// `(voltage, current)` points in increasing voltage. The boundary is interpolated on
+    /// log-log axes, held at the first current below the first point, and closed above the
+    /// last point.
+    pub points: Vec<(f64, f64)>,
+}
+
+impl SoaCurve {
+    pub fn new(duration: f64, points: Vec<(f64, f64)>) -> Self {
+        SoaCurve { duration, points }
+    }
+
+    /// The largest permitted current at `voltage`.
+    pub fn current_at(&self, voltage: f64) -> f64 {
+        let (first, last) = match (self.points.first(), self.points.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return f64::INFINITY,
+        };
+        if voltage <= first.0 {
+            return first.1;
+        }
+        if voltage > last.0 {
+            return 0.0;
+        }
+        let k = self.points.partition_point(|&(v, _)| v < voltage);
+        let ((v0, i0), (v1, i1)) = (self.points[k - 1], self.points[k]);
+        let t = (voltage / v0).ln() / (v1 / v0).ln();
+        i0 * (i1 / i0).powf(t)
+    }
+}
+
+/// A device's safe operating area. The voltage and current are vector expressions such as
+/// `v(d,s)` and `i(vsense)`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SafeOperatingArea {
+    pub device: String,
+    pub voltage: String,
+    pub current: String,
+    pub max_voltage: Option<f64>,
+    pub max_current: Option<f64>,
+    pub max_power: Option<f64>,
+    pub curves: Vec<SoaCurve>,
+}
+
+/// Which part of the safe operating area was left.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SoaLimit {
+    Voltage,
+    Current,
+    Power,
+    /// The [`SoaCurve`] with this duration.
+    Curve(f64),
+}
+
+/// One excursion outside the safe operating area.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SoaViolation {
+    pub device: String,
+    pub limit: SoaLimit,
+    /// When the excursion began, in seconds.
+    pub start: f64,
+    /// When the excursion ended, or the end of the run if it never did.
+    pub end: f64,
+    /// The time of the sample furthest beyond the limit.
+    pub peak_time: f64,
+    /// The magnitude of the voltage, current or power at the peak. For curves, the current.
+    pub peak: f64,
+    /// The limit at the peak.
+    pub allowed: f64,
+}
+
+impl SafeOperatingArea {
+    pub fn new(device: &str, voltage: &str, current: &str) -> Self {
+        SafeOperatingArea {
+            device: device.to_owned(),
+            voltage: voltage.to_owned(),
+            current: current.to_owned(),
+            max_voltage: None,
+            max_current: None,
+            max_power: None,
+            curves: Vec::new(),
+        }
+    }
+
+    pub fn max_voltage(mut self, limit: f64) -> Self {
+        self.max_voltage = Some(limit);
+        self
+    }
+
+    pub fn max_current(mut self, limit: f64) -> Self {
+        self.max_current = Some(limit);
+        self
+    }
+
+    pub fn max_power(mut self, limit: f64) -> Self {
+        self.max_power = Some(limit);
+        self
+    }
+
+    pub fn curve(mut self, curve: SoaCurve) -> Self {
+        self.curves.push(curve);
+        self
+    }
+
+    /// Finds every excursion outside the safe operating area in a transient run. Excursions
+    /// beyond a curve are only reported if they last longer than its duration.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingScale`] if `sim` has no time vector, or
+    /// [`Error::MissingVector`] if the voltage or current is missing.
+    pub fn check(&self, sim: &Simulation) -> Result<Vec<SoaViolation>, Error> {
+        let time = sim.scale_values().ok_or(Error::MissingScale)?;
+        let voltage = resolve(sim, &self.voltage, time.len())?;
+        let current = resolve(sim, &self.current, time.len())?;
+        let v: Vec<f64> = voltage.iter().map(|x| x.abs()).collect();
+        let i: Vec<f64> = current.iter().map(|x| x.abs()).collect();
+        let p: Vec<f64> = v.iter().zip(&i).map(|(v, i)| v * i).collect();
+        let mut out = Vec::new();
+        let mut scan = |limit: SoaLimit, values: &[f64], allowed: &dyn Fn(usize) -> f64| {
+            for (start, end, peak) in excursions(values, allowed) {
+                let duration = time[end] - time[start];
+                if let SoaLimit::Curve(d) = limit {
+                    if duration <= d {
+                        continue;
+                    }
+                }
+                out.push(SoaViolation {
+                    device: self.device.clone(),
+                    limit,
+                    start: time[start],
+                    end: time[end],
+                    peak_time: time[peak],
+                    peak: values[peak],
+                    allowed: allowed(peak),
+                });
+            }
+        };
+        if let Some(max) = self.max_voltage {
+            scan(SoaLimit::Voltage, &v, &|_| max);
+        }
+        if let Some(max) = self.max_current {
+            scan(SoaLimit::Current, &i, &|_| max);
+        }
+        if let Some(max) = self.max_power {
+            scan(SoaLimit::Power, &p, &|_| max);
+        }
+        for curve in &self.curves {
+            scan(SoaLimit::Curve(curve.duration), &i, &|k| {
+                curve.current_at(v[k])
+            });
+        }
+        out.sort_by(|a, b| a.start.total_cmp(&b.start));
+        Ok(out)
+    }
+}
+
+/// Checks every device in `areas`, returning all violations in time order.
+///
+/// # Errors
+///
+/// Returns the first error from [`SafeOperatingArea::check`].
+pub fn check_all(
+    areas: &[SafeOperatingArea],
+    sim: &Simulation,
+) -> Result<Vec<SoaViolation>, Error> {
+    let mut out = Vec::new();
+    for area in areas {
+        out.extend(area.check(sim)?);
+    }
+    out.sort_by(|a, b| a.start.total_cmp(&b.start));
+    Ok(out)
+}
+
+/// The values of a vector expression, including differential voltages like `v(d,s)`.
+fn resolve(sim: &Simulation, expr: &str, len: usize) -> Result<Vec<f64>, Error> {
+    let missing = || Error::MissingVector(expr.to_owned());
+    let lower = expr.trim().to_ascii_lowercase();
+    let values = match lower
+        .strip_prefix("v(")
+        .and_then(|x| x.strip_suffix(')'))
+        .and_then(|x| x.split_once(','))
+    {
+        Some((pos, neg)) => {
+            let node = |n: &str| match n.trim() {
+                "0" | "gnd" => Ok(vec![0.0; len]),
+                n => sim.real_vector(n).map(<[f64]>::to_vec),
+            };
+            let (pos, neg) = (node(pos)?, node(neg)?);
+            if pos.len() != neg.len() {
+                return Err(missing());
+            }
+            pos.iter().zip(&neg).map(|(p, n)| p - n).collect()
+        }
+        None => sim.real_vector(&vector_name(expr))?.to_vec(),
+    };
+    if values.len() == len {
+        Ok(values)
+    } else {
+        Err(missing())
+    }
+}
+
+/// The `(start, end, peak)` sample indices of every run of samples above `allowed`. `end`
+/// is the first sample back within the limit, or the last sample.
+fn excursions(values: &[f64], allowed: &dyn Fn(usize) -> f64) -> Vec<(usize, usize, usize)> {
+    let mut out = Vec::new();
+    let mut run: Option<(usize, usize)> = None;
+    for (k, &x) in values.iter().enumerate() {
+        let over = x - allowed(k);
+        if over > 0.0 {
+            run = match run {
+                Some((start, peak)) if values[peak] - allowed(peak) >= over => Some((start, peak)),
+                Some((start, _)) => Some((start, k)),
+                None => Some((k, k)),
+            };
+        } else if let Some((start, peak)) = run.take() {
+            out.push((start, k, peak));
+        }
+    }
+    if let Some((start, peak)) = run {
+        out.push((start, values.len() - 1, peak));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, VectorInfo, VectorValues};
+
+    #[test]
+    fn flags_soa_excursions() -> Result<(), Error> {
+        let mut sim = Simulation::default();
+        let time = vec![0.0, 1e-6, 2e-6, 3e-6, 4e-6, 5e-6, 6e-6, 7e-6];
+        for (name, datatype, values) in [
+            ("time", DataType::Time, time),
+            (
+                "d",
+                DataType::Voltage,
+                vec![10.0, 50.0, 50.0, 50.0, 10.0, 90.0, 10.0, 10.0],
+            ),
+            ("s", DataType::Voltage, vec![0.0; 8]),
+            (
+                "vs#branch",
+                DataType::Current,
+                vec![1.0, 3.0, 3.0, 3.0, 1.0, 1.0, 1.0, 1.0],
+            ),
+        ] {
+            let values = VectorValues::Real(values.into());
+            sim.vectors.insert(
+                name.to_owned(),
+                VectorInfo {
+                    datatype,
+                    values,
+                    scale: None,
+                },
+            );
+        }
+        let curve = SoaCurve::new(2.5e-6, vec![(10.0, 10.0), (100.0, 1.0)]);
+        assert!((curve.current_at(50.0) - 10.0 * 0.2f64.powf(1.0)).abs() < 1e-9);
+        assert_eq!(curve.current_at(5.0), 10.0);
+        assert_eq!(curve.current_at(200.0), 0.0);
+        let soa = SafeOperatingArea::new("q1", "v(d,s)", "i(vs)")
+            .max_voltage(80.0)
+            .max_power(100.0)
+            .curve(curve);
+        let violations = soa.check(&sim)?;
+        let limits: Vec<SoaLimit> = violations.iter().map(|v| v.limit).collect();
+        // 150 W and 3 A at 50 V for 3 us, beyond the 2.5 us curve; then a 90 V spike
+        assert_eq!(
+            limits,
+            [SoaLimit::Power, SoaLimit::Curve(2.5e-6), SoaLimit::Voltage]
+        );
+        assert_eq!((violations[0].start, violations[0].end), (1e-6, 4e-6));
+        assert_eq!(violations[0].peak, 150.0);
+        assert_eq!(violations[2].peak_time, 5e-6);
+        let short = SafeOperatingArea::new("q1", "v(d,0)", "i(vs)")
+            .curve(SoaCurve::new(1e-3, vec![(10.0, 10.0), (100.0, 1.0)]));
+        assert!(check_all(&[short], &sim)?.is_empty());
+        Ok(())
+    }
+}
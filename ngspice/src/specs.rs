@@ -0,0 +1,261 @@
+// Copyright 2022 Andrew Morrow.
+// specs.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Named limits on measurements, checked against runs and campaigns.
+
+use crate::campaign::{Campaign, Run};
+use crate::format::NumericFormat;
+use std::collections::BTreeMap;
+use std::fmt::{self, Formatter};
+
+/// The allowed range of one measurement. Either bound may be absent.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limit {
+    pub min: Option<f64>,
+    /// The typical value, for reports only.
+    pub typ: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl Limit {
+    pub fn between(min: f64, max: f64) -> Self {
+        Limit {
+            min: Some(min),
+            max: Some(max),
+            ..Limit::default()
+        }
+    }
+
+    pub fn at_least(min: f64) -> Self {
+        Limit {
+            min: Some(min),
+            ..Limit::default()
+        }
+    }
+
+    pub fn at_most(max: f64) -> Self {
+        Limit {
+            max: Some(max),
+            ..Limit::default()
+        }
+    }
+
+    /// Sets the typical value.
+    pub fn typ(mut self, typ: f64) -> Self {
+        self.typ = Some(typ);
+        self
+    }
+
+    /// The distance from `value` to the nearest bound: positive inside the limit, negative
+    /// outside. Infinite if there are no bounds.
+    pub fn margin(&self, value: f64) -> f64 {
+        let above_min = self.min.map_or(f64::INFINITY, |m| value - m);
+        let below_max = self.max.map_or(f64::INFINITY, |m| m - value);
+        above_min.min(below_max)
+    }
+}
+
+impl Limit {
+    /// Writes the bounds as `[min, max]`, with `-` for a missing bound.
+    fn write(&self, out: &mut impl fmt::Write, number: &impl Fn(f64) -> String) -> fmt::Result {
+        let bound = |b: Option<f64>| b.map_or("-".to_owned(), number);
+        write!(out, "[{}, {}]", bound(self.min), bound(self.max))
+    }
+
+    /// Like the [`Display`](fmt::Display) output, with numbers written in `format`.
+    pub fn to_string_with(&self, format: &NumericFormat) -> String {
+        let mut out = String::new();
+        self.write(&mut out, &|x| format.format(x)).unwrap();
+        out
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.write(f, &|x: f64| x.to_string())
+    }
+}
+
+/// The outcome of checking one measurement.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Fail,
+    /// The run has no such measurement.
+    Missing,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpecResult {
+    pub name: String,
+    pub limit: Limit,
+    pub value: Option<f64>,
+    pub status: Status,
+}
+
+/// Every spec checked against one set of measurements.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpecReport {
+    pub results: Vec<SpecResult>,
+}
+
+impl SpecReport {
+    /// Whether every spec passed. Missing measurements count as failures.
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.status == Status::Pass)
+    }
+
+    /// The specs that did not pass.
+    pub fn failures(&self) -> impl Iterator<Item = &SpecResult> {
+        self.results.iter().filter(|r| r.status != Status::Pass)
+    }
+
+    /// Panics with the report unless every spec passed, for use in tests and CI.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any spec failed or is missing.
+    pub fn assert_passed(&self) {
+        assert!(self.passed(), "specs failed:\n{}", self);
+    }
+
+    /// Writes one line per spec, with numbers written by `number`.
+    fn write(&self, out: &mut impl fmt::Write, number: &impl Fn(f64) -> String) -> fmt::Result {
+        for r in &self.results {
+            let status = match r.status {
+                Status::Pass => "PASS",
+                Status::Fail => "FAIL",
+                Status::Missing => "MISSING",
+            };
+            let value = r.value.map_or("-".to_owned(), number);
+            write!(out, "{:<8} {} = {} ", status, r.name, value)?;
+            r.limit.write(out, number)?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    /// Like the [`Display`](fmt::Display) output, with numbers written in `format`.
+    pub fn to_string_with(&self, format: &NumericFormat) -> String {
+        let mut out = String::new();
+        self.write(&mut out, &|x| format.format(x)).unwrap();
+        out
+    }
+}
+
+impl fmt::Display for SpecReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.write(f, &|x: f64| x.to_string())
+    }
+}
+
+/// Named limits on measurements, e.g. `gain` between 19 and 21.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Specs {
+    pub limits: BTreeMap<String, Limit>,
+}
+
+impl Specs {
+    pub fn new() -> Self {
+        Specs::default()
+    }
+
+    /// Adds or replaces a limit.
+    pub fn with(mut self, name: &str, limit: Limit) -> Self {
+        self.limits.insert(name.to_owned(), limit);
+        self
+    }
+
+    /// Checks every limit against `measurements`.
+    pub fn check(&self, measurements: &BTreeMap<String, f64>) -> SpecReport {
+        let results = self
+            .limits
+            .iter()
+            .map(|(name, &limit)| {
+                let value = measurements.get(name).copied();
+                let status = match value {
+                    None => Status::Missing,
+                    Some(v) if limit.margin(v) >= 0.0 => Status::Pass,
+                    Some(_) => Status::Fail,
+                };
+                SpecResult {
+                    name: name.clone(),
+                    limit,
+                    value,
+                    status,
+                }
+            })
+            .collect();
+        SpecReport { results }
+    }
+
+    /// Checks every limit against a run's measurements.
+    pub fn check_run(&self, run: &Run) -> SpecReport {
+        self.check(&run.measurements)
+    }
+
+    /// Checks every run of a campaign, in order.
+    pub fn check_campaign(&self, campaign: &Campaign) -> Vec<SpecReport> {
+        campaign.runs().iter().map(|r| self.check_run(r)).collect()
+    }
+
+    /// The fraction of a campaign's runs that pass every spec. Zero for an empty campaign.
+    pub fn yield_fraction(&self, campaign: &Campaign) -> f64 {
+        let reports = self.check_campaign(campaign);
+        if reports.is_empty() {
+            return 0.0;
+        }
+        reports.iter().filter(|r| r.passed()).count() as f64 / reports.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::campaign::Tags;
+    use crate::Simulation;
+
+    #[test]
+    fn checks_limits() {
+        let specs = Specs::new()
+            .with("gain", Limit::between(19.0, 21.0).typ(20.0))
+            .with("iq", Limit::at_most(1e-3));
+        let mut campaign = Campaign::new("yield");
+        for (gain, iq) in [(20.0, 0.5e-3), (22.0, 0.5e-3), (20.5, 2e-3), (19.0, 1e-3)] {
+            let run = campaign.add(Tags::new(), Simulation::default());
+            let m = &mut campaign.runs_mut()[run].measurements;
+            m.insert("gain".to_owned(), gain);
+            m.insert("iq".to_owned(), iq);
+        }
+        campaign.add(Tags::new(), Simulation::default());
+        let reports = specs.check_campaign(&campaign);
+        assert!(reports[0].passed());
+        assert_eq!(reports[1].failures().next().unwrap().name, "gain");
+        assert!(reports[3].passed());
+        assert_eq!(reports[4].results[0].status, Status::Missing);
+        assert_eq!(specs.yield_fraction(&campaign), 0.4);
+        assert_eq!(Limit::between(1.0, 3.0).margin(2.5), 0.5);
+        assert_eq!(
+            reports[1].to_string(),
+            "FAIL     gain = 22 [19, 21]\nPASS     iq = 0.0005 [-, 0.001]\n"
+        );
+        assert_eq!(
+            reports[1].to_string_with(&NumericFormat::engineering(3)),
+            "FAIL     gain = 22.0e0 [19.0e0, 21.0e0]\nPASS     iq = 500e-6 [-, 1.00e-3]\n"
+        );
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright 2022 Andrew Morrow.
+// spectrum.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Spectral analysis of transient captures: FFT, THD, SNR, SNDR, SFDR, and ENOB.
+
+use crate::kernels::{interpolate_all, multiply};
+use crate::{Error, Simulation};
+use num_complex::Complex64;
+use std::f64::consts::PI;
+
+/// A window applied to samples before the FFT.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Window {
+    /// No windowing. Only suitable for coherently sampled signals.
+    Rectangular,
+    Hann,
+    /// 4-term Blackman-Harris, with sidelobes below -92 dB.
+    BlackmanHarris,
+}
+
+impl Window {
+    /// The `n` window coefficients. Windows are symmetric about `n / 2`, so only half are
+    /// computed.
+    fn coefficients(self, n: usize) -> Vec<f64> {
+        let mut out: Vec<f64> = (0..=n / 2).map(|i| self.coefficient(i, n)).collect();
+        out.resize(n, 0.0);
+        for i in n / 2 + 1..n {
+            out[i] = out[n - i];
+        }
+        out
+    }
+
+    fn coefficient(self, i: usize, n: usize) -> f64 {
+        let x = 2.0 * PI * i as f64 / n as f64;
+        match self {
+            Window::Rectangular => 1.0,
+            Window::Hann => 0.5 - 0.5 * x.cos(),
+            Window::BlackmanHarris => {
+                0.35875 - 0.48829 * x.cos() + 0.14128 * (2.0 * x).cos() - 0.01168 * (3.0 * x).cos()
+            }
+        }
+    }
+
+    /// The number of bins on each side of a tone that hold its leaked energy.
+    fn leakage_bins(self) -> usize {
+        match self {
+            Window::Rectangular => 0,
+            Window::Hann => 2,
+            Window::BlackmanHarris => 4,
+        }
+    }
+}
+
+/// Computes the discrete Fourier transform of `input`.
+///
+/// Power-of-two lengths use a radix-2 FFT. Other lengths fall back to a direct O(n²) DFT.
+pub fn fft(input: &[Complex64]) -> Vec<Complex64> {
+    let n = input.len();
+    if n <= 1 {
+        return input.to_vec();
+    }
+    if !n.is_power_of_two() {
+        return (0..n)
+            .map(|k| {
+                input
+                    .iter()
+                    .enumerate()
+                    .map(|(j, x)| {
+                        x * Complex64::from_polar(1.0, -2.0 * PI * (j * k) as f64 / n as f64)
+                    })
+                    .sum()
+            })
+            .collect();
+    }
+    let bits = n.trailing_zeros();
+    let mut out: Vec<Complex64> = (0..n)
+        .map(|i| input[i.reverse_bits() >> (usize::BITS - bits)])
+        .collect();
+    let mut len = 2;
+    while len <= n {
+        let step = Complex64::from_polar(1.0, -2.0 * PI / len as f64);
+        for start in (0..n).step_by(len) {
+            let mut w = Complex64::new(1.0, 0.0);
+            for k in 0..len / 2 {
+                let a = out[start + k];
+                let b = out[start + k + len / 2] * w;
+                out[start + k] = a + b;
+                out[start + k + len / 2] = a - b;
+                w *= step;
+            }
+        }
+        len <<= 1;
+    }
+    out
+}
+
+/// Computes the inverse discrete Fourier transform of `input`, scaled by `1/n` so that
+/// `ifft(&fft(x))` returns `x`.
+pub fn ifft(input: &[Complex64]) -> Vec<Complex64> {
+    // conjugating around the forward transform inverts it
+    let conj: Vec<Complex64> = input.iter().map(|c| c.conj()).collect();
+    let scale = 1.0 / input.len().max(1) as f64;
+    fft(&conj).iter().map(|c| c.conj() * scale).collect()
+}
+
+/// Returns the one-sided power spectrum of real samples, one value per bin from DC to Nyquist.
+pub fn power_spectrum(samples: &[f64], window: Window) -> Vec<f64> {
+    let n = samples.len();
+    let windowed: Vec<Complex64> = multiply(samples, &window.coefficients(n))
+        .into_iter()
+        .map(|x| Complex64::new(x, 0.0))
+        .collect();
+    fft(&windowed)
+        .iter()
+        .take(n / 2 + 1)
+        .map(|c| c.norm_sqr())
+        .collect()
+}
+
+/// Picks the tone frequency closest to `target` that completes a prime number of cycles in
+/// `points` samples, so the capture is coherent and needs no window.
+pub fn coherent_frequency(sample_rate: f64, points: usize, target: f64) -> f64 {
+    let ideal = (target * points as f64 / sample_rate).round().max(1.0) as usize;
+    let is_prime = |m: usize| {
+        m >= 2
+            && (2..)
+                .take_while(|d| d * d <= m)
+                .all(|d| !m.is_multiple_of(d))
+    };
+    let cycles = (0..points)
+        .flat_map(|d| [ideal.saturating_sub(d), ideal + d])
+        .find(|&m| m < points / 2 && is_prime(m))
+        .unwrap_or(1);
+    cycles as f64 * sample_rate / points as f64
+}
+
+/// Resamples a non-uniformly sampled waveform onto `points` uniform samples starting at `start`.
+pub fn resample(
+    time: &[f64],
+    values: &[f64],
+    start: f64,
+    sample_rate: f64,
+    points: usize,
+) -> Vec<f64> {
+    let at: Vec<f64> = (0..points)
+        .map(|i| start + i as f64 / sample_rate)
+        .collect();
+    interpolate_all(time, values, &at)
+}
+
+/// Spectral performance metrics for a single-tone capture. All ratios are in dB.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpectralMetrics {
+    /// Frequency of the fundamental tone, in Hz.
+    pub fundamental: f64,
+    /// Signal-to-noise ratio, excluding harmonics.
+    pub snr: f64,
+    /// Signal-to-noise-and-distortion ratio.
+    pub sndr: f64,
+    /// Spurious-free dynamic range: the fundamental relative to the largest other tone.
+    pub sfdr: f64,
+    /// Total harmonic distortion, relative to the fundamental (negative for small distortion).
+    pub thd: f64,
+    /// Effective number of bits, derived from the SNDR.
+    pub enob: f64,
+}
+
+/// Computes single-tone spectral metrics from uniformly sampled data.
+///
+/// The fundamental is the largest non-DC bin. Harmonics 2 through `harmonics` are folded back
+/// into the first Nyquist zone.
+///
+/// # Panics
+///
+/// Panics if `samples` is empty.
+pub fn spectral_metrics(
+    samples: &[f64],
+    sample_rate: f64,
+    window: Window,
+    harmonics: usize,
+) -> SpectralMetrics {
+    assert!(!samples.is_empty(), "cannot analyze an empty capture");
+    let spectrum = power_spectrum(samples, window);
+    let n = samples.len();
+    let spread = window.leakage_bins();
+    let bins = spectrum.len();
+    let fund = (spread + 1..bins)
+        .max_by(|&a, &b| spectrum[a].total_cmp(&spectrum[b]))
+        .unwrap_or(1);
+    let band = |center: usize| center.saturating_sub(spread)..(center + spread + 1).min(bins);
+    let mut claimed = vec![false; bins];
+    let claim = |center: usize, claimed: &mut Vec<bool>| -> f64 {
+        band(center)
+            .filter(|&i| !std::mem::replace(&mut claimed[i], true))
+            .map(|i| spectrum[i])
+            .sum()
+    };
+    claim(0, &mut claimed);
+    let signal = claim(fund, &mut claimed);
+    let mut distortion = 0.0;
+    let mut largest_spur = 0.0f64;
+    for h in 2..=harmonics {
+        let alias = (h * fund) % n;
+        let bin = if alias > n / 2 { n - alias } else { alias };
+        let power = claim(bin, &mut claimed);
+        distortion += power;
+        largest_spur = largest_spur.max(power);
+    }
+    let noise: f64 = (0..bins)
+        .filter(|&i| !claimed[i])
+        .map(|i| spectrum[i])
+        .sum();
+    // any unclaimed bin is also a spur candidate
+    for center in 0..bins {
+        let power: f64 = band(center)
+            .filter(|&i| !claimed[i])
+            .map(|i| spectrum[i])
+            .sum();
+        largest_spur = largest_spur.max(power);
+    }
+    let db = |x: f64| 10.0 * x.log10();
+    let sndr = db(signal / (noise + distortion));
+    SpectralMetrics {
+        fundamental: fund as f64 * sample_rate / n as f64,
+        snr: db(signal / noise),
+        sndr,
+        sfdr: db(signal / largest_spur),
+        thd: db(distortion / signal),
+        enob: (sndr - 1.76) / 6.02,
+    }
+}
+
+impl Simulation {
+    /// Resamples the named vector and computes its spectral metrics.
+    ///
+    /// The capture starts at `start` seconds and covers `points` samples at `sample_rate`. Use
+    /// [`coherent_frequency`] to pick the stimulus frequency so the capture is coherent.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vector or the time scale is missing, or
+    /// [`Error::InvalidArgument`] if `points` is zero.
+    pub fn spectral_metrics(
+        &self,
+        vector: &str,
+        start: f64,
+        sample_rate: f64,
+        points: usize,
+        window: Window,
+    ) -> Result<SpectralMetrics, Error> {
+        if points == 0 {
+            return Err(Error::InvalidArgument(
+                "a spectrum needs at least one point".to_owned(),
+            ));
+        }
+        let (_, time) = self.scale_vector().ok_or(Error::MissingScale)?;
+        let time = time.real().ok_or(Error::MissingScale)?;
+        let values = self.real_vector(vector)?;
+        let samples = resample(time, values, start, sample_rate, points);
+        Ok(spectral_metrics(&samples, sample_rate, window, 5))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_coherent_tone() {
+        let n = 1024;
+        let fs = 1024.0;
+        let f = coherent_frequency(fs, n, 50.0);
+        assert_eq!(f, 47.0);
+        let samples: Vec<f64> = (0..n)
+            .map(|i| {
+                let t = i as f64 / fs;
+                (2.0 * PI * f * t).sin()
+                    + 0.01 * (2.0 * PI * 2.0 * f * t).sin()
+                    + 0.001 * (2.0 * PI * 300.0 * t).sin()
+            })
+            .collect();
+        let m = spectral_metrics(&samples, fs, Window::Rectangular, 5);
+        assert_eq!(m.fundamental, 47.0);
+        assert!((m.thd + 40.0).abs() < 1e-6);
+        assert!((m.sfdr - 40.0).abs() < 1e-6);
+        assert!((m.snr - 60.0).abs() < 1e-6);
+        assert!(m.sndr < 40.0 && m.sndr > 39.9);
+        assert!(matches!(
+            Simulation::default().spectral_metrics("v(out)", 0.0, fs, 0, Window::Rectangular),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn fft_matches_dft() {
+        let input: Vec<Complex64> = (0..8).map(|i| Complex64::new(i as f64, 0.0)).collect();
+        let fast = fft(&input);
+        let slow: Vec<Complex64> = (0..8)
+            .map(|k| {
+                input
+                    .iter()
+                    .enumerate()
+                    .map(|(j, x)| x * Complex64::from_polar(1.0, -2.0 * PI * (j * k) as f64 / 8.0))
+                    .sum()
+            })
+            .collect();
+        for (a, b) in fast.iter().zip(&slow) {
+            assert!((a - b).norm() < 1e-9);
+        }
+        for (a, b) in ifft(&fast).iter().zip(&input) {
+            assert!((a - b).norm() < 1e-9);
+        }
+    }
+}
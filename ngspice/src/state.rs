@@ -0,0 +1,107 @@
+// Copyright 2022 Andrew Morrow.
+// state.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Snapshots of a transient simulation's state at one instant.
+
+use crate::waveform::interpolate;
+use crate::{DataType, Error, Simulation, VectorValues};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Every node voltage and branch current at one instant.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    /// In seconds.
+    pub time: f64,
+    /// Node voltages by node name.
+    pub voltages: BTreeMap<String, f64>,
+    /// Branch currents by vector name, e.g. `v1#branch`.
+    pub currents: BTreeMap<String, f64>,
+}
+
+impl Snapshot {
+    /// Renders the node voltages as an `.ic` card, so a later run can start from this state.
+    pub fn to_ic(&self) -> String {
+        let mut out = String::from(".ic");
+        for (node, v) in &self.voltages {
+            write!(out, " v({})={:e}", node, v).unwrap();
+        }
+        out
+    }
+}
+
+impl Simulation {
+    /// Interpolates every real voltage and current vector at time `t`. Times outside the
+    /// simulation are clamped to its first or last point.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingScale`] if the simulation has no real time scale.
+    pub fn state_at(&self, t: f64) -> Result<Snapshot, Error> {
+        let (scale_name, scale) = self.scale_vector().ok_or(Error::MissingScale)?;
+        let time = scale.real().ok_or(Error::MissingScale)?;
+        if self.vectors[scale_name].datatype != DataType::Time || time.is_empty() {
+            return Err(Error::MissingScale);
+        }
+        let mut snapshot = Snapshot {
+            time: t,
+            ..Snapshot::default()
+        };
+        for (name, info) in &self.vectors {
+            let values = match &info.values {
+                VectorValues::Real(x) if x.len() == time.len() => x,
+                _ => continue,
+            };
+            let target = match info.datatype {
+                DataType::Voltage => &mut snapshot.voltages,
+                DataType::Current => &mut snapshot.currents,
+                _ => continue,
+            };
+            target.insert(name.clone(), interpolate(time, values, t));
+        }
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DataType, Simulation, VectorInfo, VectorValues};
+
+    #[test]
+    fn interpolates_state() {
+        let mut sim = Simulation::default();
+        for (name, datatype, values) in [
+            ("time", DataType::Time, vec![0.0, 1.0, 2.0]),
+            ("out", DataType::Voltage, vec![0.0, 2.0, 4.0]),
+            ("v1#branch", DataType::Current, vec![0.0, -1.0, -1.0]),
+        ] {
+            sim.vectors.insert(
+                name.to_owned(),
+                VectorInfo {
+                    datatype,
+                    values: VectorValues::Real(values.into()),
+                    scale: None,
+                },
+            );
+        }
+        let state = sim.state_at(0.5).unwrap();
+        assert_eq!(state.voltages["out"], 1.0);
+        assert_eq!(state.currents["v1#branch"], -0.5);
+        assert!(!state.voltages.contains_key("time"));
+        assert_eq!(state.to_ic(), ".ic v(out)=1e0");
+    }
+}
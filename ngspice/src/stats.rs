@@ -0,0 +1,283 @@
+// Copyright 2022 Andrew Morrow.
+// stats.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Histograms and kernel density estimates over measurements and waveforms.
+
+use crate::{Error, Simulation};
+use std::f64::consts::PI;
+
+/// The probability that a standard normal variable is at most `x`.
+///
+/// Accurate to a relative error of about 1e-7, including far into either tail.
+pub fn normal_cdf(x: f64) -> f64 {
+    // the complementary error function, from Numerical Recipes' Chebyshev fit
+    let z = x.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = [
+        -1.265_512_23,
+        1.000_023_68,
+        0.374_091_96,
+        0.096_784_18,
+        -0.186_288_06,
+        0.278_868_07,
+        -1.135_203_98,
+        1.488_515_87,
+        -0.822_152_23,
+        0.170_872_77,
+    ]
+    .iter()
+    .rev()
+    .fold(0.0, |acc, c| acc * t + c);
+    let erfc = t * (-z * z + poly).exp();
+    if x >= 0.0 {
+        1.0 - 0.5 * erfc
+    } else {
+        0.5 * erfc
+    }
+}
+
+/// The value that a standard normal variable is at most with probability `p`, the inverse of
+/// [`normal_cdf`].
+///
+/// Uses Acklam's rational approximation, with a relative error below 1.2e-9.
+///
+/// # Panics
+///
+/// Panics if `p` is not strictly between 0 and 1.
+pub fn normal_quantile(p: f64) -> f64 {
+    assert!(p > 0.0 && p < 1.0, "probability must be between 0 and 1");
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    let horner = |coefficients: &[f64], x: f64| coefficients.iter().fold(0.0, |acc, c| acc * x + c);
+    let tail = |q: f64| horner(&C, q) / (horner(&D, q) * q + 1.0);
+    if p < 0.024_25 {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - 0.024_25 {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        q * horner(&A, r) / (horner(&B, r) * r + 1.0)
+    }
+}
+
+/// A histogram with equal-width bins.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    /// Bin edges in ascending order. There is one more edge than there are bins.
+    pub edges: Vec<f64>,
+    /// The total weight in each bin. For unweighted data this is the number of values.
+    pub counts: Vec<f64>,
+}
+
+impl Histogram {
+    /// Builds a histogram of `values` with `bins` equal-width bins spanning their full range.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bins` is zero.
+    pub fn new(values: &[f64], bins: usize) -> Self {
+        let weights = vec![1.0; values.len()];
+        Histogram::weighted(values, &weights, bins)
+    }
+
+    /// Builds a histogram where each value contributes its weight instead of 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bins` is zero or if `values` and `weights` have different lengths.
+    pub fn weighted(values: &[f64], weights: &[f64], bins: usize) -> Self {
+        assert!(bins > 0, "a histogram needs at least one bin");
+        assert_eq!(values.len(), weights.len(), "every value needs a weight");
+        let lo = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let hi = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let (lo, hi) = if values.is_empty() {
+            (0.0, 1.0)
+        } else if lo == hi {
+            (lo - 0.5, hi + 0.5)
+        } else {
+            (lo, hi)
+        };
+        let width = (hi - lo) / bins as f64;
+        let edges = (0..=bins).map(|i| lo + i as f64 * width).collect();
+        let mut counts = vec![0.0; bins];
+        for (&v, &w) in values.iter().zip(weights) {
+            let idx = (((v - lo) / width) as usize).min(bins - 1);
+            counts[idx] += w;
+        }
+        Histogram { edges, counts }
+    }
+
+    /// The midpoint of each bin.
+    pub fn centers(&self) -> Vec<f64> {
+        self.edges.windows(2).map(|e| 0.5 * (e[0] + e[1])).collect()
+    }
+
+    /// The counts normalized so the histogram integrates to 1.
+    pub fn density(&self) -> Vec<f64> {
+        let total: f64 = self.counts.iter().sum();
+        self.counts
+            .iter()
+            .zip(self.edges.windows(2))
+            .map(|(&c, e)| {
+                if total > 0.0 {
+                    c / (total * (e[1] - e[0]))
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+}
+
+/// Builds a histogram of the amplitudes a waveform spends time at.
+///
+/// Each sample is weighted by half of the time intervals on either side of it, so the
+/// non-uniform time steps chosen by ngSPICE do not bias the distribution.
+pub fn amplitude_histogram(time: &[f64], values: &[f64], bins: usize) -> Histogram {
+    let n = time.len();
+    let weights: Vec<f64> = (0..n)
+        .map(|i| {
+            let before = if i > 0 { time[i] - time[i - 1] } else { 0.0 };
+            let after = if i + 1 < n {
+                time[i + 1] - time[i]
+            } else {
+                0.0
+            };
+            0.5 * (before + after)
+        })
+        .collect();
+    Histogram::weighted(values, &weights, bins)
+}
+
+/// A probability density sampled at evenly spaced points.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Density {
+    pub x: Vec<f64>,
+    pub y: Vec<f64>,
+}
+
+/// Estimates the probability density of `values` with a Gaussian kernel.
+///
+/// The bandwidth follows Silverman's rule of thumb. The density is evaluated at `points`
+/// evenly spaced points extending three bandwidths past the data on each side.
+pub fn kde(values: &[f64], points: usize) -> Density {
+    let n = values.len() as f64;
+    if values.is_empty() || points == 0 {
+        return Density {
+            x: Vec::new(),
+            y: Vec::new(),
+        };
+    }
+    let mean = values.iter().sum::<f64>() / n;
+    let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    let bandwidth = match 1.06 * var.sqrt() * n.powf(-0.2) {
+        h if h > 0.0 => h,
+        _ => 1.0,
+    };
+    let lo = values.iter().copied().fold(f64::INFINITY, f64::min) - 3.0 * bandwidth;
+    let hi = values.iter().copied().fold(f64::NEG_INFINITY, f64::max) + 3.0 * bandwidth;
+    let step = if points > 1 {
+        (hi - lo) / (points - 1) as f64
+    } else {
+        0.0
+    };
+    let norm = 1.0 / (n * bandwidth * (2.0 * PI).sqrt());
+    let x: Vec<f64> = (0..points).map(|i| lo + i as f64 * step).collect();
+    let y = x
+        .iter()
+        .map(|&x| {
+            norm * values
+                .iter()
+                .map(|v| (-0.5 * ((x - v) / bandwidth).powi(2)).exp())
+                .sum::<f64>()
+        })
+        .collect();
+    Density { x, y }
+}
+
+impl Simulation {
+    /// Builds a time-weighted amplitude histogram of the named vector.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vector or the time scale is missing.
+    pub fn amplitude_histogram(&self, vector: &str, bins: usize) -> Result<Histogram, Error> {
+        let (_, time) = self.scale_vector().ok_or(Error::MissingScale)?;
+        let time = time.real().ok_or(Error::MissingScale)?;
+        Ok(amplitude_histogram(time, self.real_vector(vector)?, bins))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histograms_and_density() {
+        let h = Histogram::new(&[0.0, 1.0, 1.0, 2.0, 4.0], 4);
+        assert_eq!(h.edges, vec![0.0, 1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(h.counts, vec![1.0, 2.0, 1.0, 1.0]);
+        assert_eq!(h.centers()[0], 0.5);
+        assert!((h.density().iter().sum::<f64>() - 1.0).abs() < 1e-12);
+
+        // each sample is weighted by half the time steps around it
+        let h = amplitude_histogram(&[0.0, 1.0, 10.0], &[0.0, 1.0, 1.0], 2);
+        assert_eq!(h.counts, vec![0.5, 9.5]);
+
+        let d = kde(&[0.0, 0.0, 1.0, 1.0], 101);
+        let area: f64 = d.y.iter().sum::<f64>() * (d.x[1] - d.x[0]);
+        assert!((area - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn normal_tails() {
+        assert!((normal_cdf(0.0) - 0.5).abs() < 1e-7);
+        // P(Z > 6) = 9.8659e-10
+        assert!((normal_cdf(-6.0) / 9.8659e-10 - 1.0).abs() < 1e-4);
+        for x in [-6.0, -2.0, 0.3, 3.0] {
+            assert!((normal_quantile(normal_cdf(x)) - x).abs() < 1e-6);
+        }
+    }
+}
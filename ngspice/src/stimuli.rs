@@ -0,0 +1,319 @@
+// Copyright 2022 Andrew Morrow.
+// stimuli.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Reusable test signals, rendered as PWL or behavioral sources.
+
+use crate::random::Rng;
+use crate::spectrum::ifft;
+use num_complex::Complex64;
+use std::f64::consts::PI;
+use std::fmt::Write;
+
+/// A test signal that can be rendered as an ngSPICE voltage or current source.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stimulus {
+    /// Piecewise-linear `(time, value)` points, in ascending time order.
+    Pwl(Vec<(f64, f64)>),
+    /// An expression in `time`, rendered as a B-source.
+    Behavioral(String),
+}
+
+/// One sine in a [`Stimulus::multitone`] signal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tone {
+    /// In Hz.
+    pub frequency: f64,
+    /// Peak amplitude.
+    pub amplitude: f64,
+    /// In degrees.
+    pub phase: f64,
+}
+
+/// Returns the output of a maximal-length linear feedback shift register, seeded with all
+/// ones. The sequence repeats every `2^order - 1` bits.
+///
+/// # Panics
+///
+/// Panics unless `order` is 7, 9, 11, 15, 20, 23, or 31.
+pub fn prbs_bits(order: u32, count: usize) -> Vec<bool> {
+    // the second feedback tap of the standard PRBS polynomials x^order + x^tap + 1
+    let tap = match order {
+        7 => 6,
+        9 => 5,
+        11 => 9,
+        15 => 14,
+        20 => 3,
+        23 => 18,
+        31 => 28,
+        _ => panic!("unsupported PRBS order {}", order),
+    };
+    let mut state: u32 = (1 << order) - 1;
+    (0..count)
+        .map(|_| {
+            let bit = ((state >> (order - 1)) ^ (state >> (tap - 1))) & 1;
+            state = ((state << 1) | bit) & ((1 << order) - 1);
+            bit == 1
+        })
+        .collect()
+}
+
+impl Stimulus {
+    /// A pseudo-random bit sequence of `bits` bits, each lasting `bit_time` seconds, switching
+    /// between `low` and `high` with an `edge` second transition.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is not supported by [`prbs_bits`].
+    pub fn prbs(order: u32, bits: usize, bit_time: f64, low: f64, high: f64, edge: f64) -> Self {
+        let level = |b: bool| if b { high } else { low };
+        let sequence = prbs_bits(order, bits);
+        let mut points = Vec::with_capacity(2 * bits);
+        for (k, &bit) in sequence.iter().enumerate() {
+            let t = k as f64 * bit_time;
+            if k == 0 {
+                points.push((0.0, level(bit)));
+            } else if bit != sequence[k - 1] {
+                points.push((t, level(!bit)));
+                points.push((t + edge, level(bit)));
+            }
+        }
+        let end = bits as f64 * bit_time;
+        if let Some(&(_, last)) = points.last() {
+            points.push((end, last));
+        }
+        Stimulus::Pwl(points)
+    }
+
+    /// A linear frequency sweep from `f0` to `f1` Hz over `duration` seconds. The sweep
+    /// continues at the same rate after `duration`.
+    pub fn chirp(f0: f64, f1: f64, duration: f64, amplitude: f64, offset: f64) -> Self {
+        let rate = (f1 - f0) / (2.0 * duration);
+        Stimulus::Behavioral(format!(
+            "{:e}+{:e}*sin({:e}*({:e}*time+{:e}*time*time))",
+            offset,
+            amplitude,
+            2.0 * PI,
+            f0,
+            rate
+        ))
+    }
+
+    /// The sum of several sines around `offset`.
+    pub fn multitone(tones: &[Tone], offset: f64) -> Self {
+        let mut expr = format!("{:e}", offset);
+        for tone in tones {
+            write!(
+                expr,
+                "+{:e}*sin({:e}*time+{:e})",
+                tone.amplitude,
+                2.0 * PI * tone.frequency,
+                tone.phase.to_radians()
+            )
+            .unwrap();
+        }
+        Stimulus::Behavioral(expr)
+    }
+
+    /// A PWM signal with one period per entry in `duties`, each the fraction of the period
+    /// spent at `high`. Edges take `edge` seconds and are not counted in the duty cycle.
+    pub fn pwm(period: f64, duties: &[f64], low: f64, high: f64, edge: f64) -> Self {
+        let mut points = vec![(0.0, low)];
+        for (k, &duty) in duties.iter().enumerate() {
+            let start = k as f64 * period;
+            let on = duty.clamp(0.0, 1.0) * period;
+            if on <= 0.0 {
+                continue;
+            }
+            let off = (start + on + edge).min(start + period - edge);
+            points.push((start, low));
+            points.push((start + edge, high));
+            points.push((off, high));
+            points.push((off + edge, low));
+        }
+        points.push((duties.len() as f64 * period, low));
+        points.dedup_by(|b, a| a.0 == b.0 && a.1 == b.1);
+        Stimulus::Pwl(points)
+    }
+
+    fn render(&self, prefix: char, quantity: char, name: &str, pos: &str, neg: &str) -> String {
+        match self {
+            Stimulus::Pwl(points) => {
+                let mut out = format!("{}{} {} {} PWL(", prefix, name, pos, neg);
+                for (k, (t, v)) in points.iter().enumerate() {
+                    if k > 0 {
+                        out.push(' ');
+                    }
+                    write!(out, "{:e} {:e}", t, v).unwrap();
+                }
+                out.push_str(")\n");
+                out
+            }
+            Stimulus::Behavioral(expr) => {
+                format!("B{} {} {} {}={}\n", name, pos, neg, quantity, expr)
+            }
+        }
+    }
+
+    /// Renders a voltage source from `pos` to `neg`.
+    ///
+    /// `name` is the instance name without its type letter: `V` is prepended for PWL signals
+    /// and `B` for behavioral ones.
+    pub fn voltage_source(&self, name: &str, pos: &str, neg: &str) -> String {
+        self.render('V', 'V', name, pos, neg)
+    }
+
+    /// Renders a current source driving current from `pos` through the source to `neg`.
+    ///
+    /// `name` is the instance name without its type letter: `I` is prepended for PWL signals
+    /// and `B` for behavioral ones.
+    pub fn current_source(&self, name: &str, pos: &str, neg: &str) -> String {
+        self.render('I', 'I', name, pos, neg)
+    }
+}
+
+/// The shape of a [`Noise`] signal's power spectral density between its band edges.
+#[derive(Clone, Copy, Debug)]
+pub enum NoiseSpectrum {
+    /// Flat.
+    White,
+    /// Falling as 1/f.
+    Pink,
+    /// Any relative density, given as a function of frequency in Hz.
+    Custom(fn(f64) -> f64),
+}
+
+/// Band-limited Gaussian noise synthesized from a seed.
+///
+/// Unlike ngSPICE's `TRNOISE`, the same seed produces the same trace on every run and
+/// platform, and the spectrum can be any shape.
+#[derive(Clone, Copy, Debug)]
+pub struct Noise {
+    /// The RMS value of the trace.
+    pub rms: f64,
+    /// The lowest frequency with noise, in Hz.
+    pub f_min: f64,
+    /// The highest frequency with noise, in Hz. The trace is sampled at four times this rate.
+    pub f_max: f64,
+    /// In seconds.
+    pub duration: f64,
+    pub spectrum: NoiseSpectrum,
+    pub seed: u64,
+}
+
+impl Noise {
+    /// The sample interval and the samples of the trace, starting at time 0.
+    pub fn samples(&self) -> (f64, Vec<f64>) {
+        let wanted = (4.0 * self.f_max * self.duration).ceil().max(2.0) as usize;
+        let n = wanted.next_power_of_two();
+        let dt = self.duration / (wanted - 1) as f64;
+        let df = 1.0 / (n as f64 * dt);
+        let mut rng = Rng::new(self.seed);
+        let mut bins = vec![Complex64::new(0.0, 0.0); n];
+        for k in 1..n / 2 {
+            let f = k as f64 * df;
+            if f < self.f_min || f > self.f_max {
+                continue;
+            }
+            let density = match self.spectrum {
+                NoiseSpectrum::White => 1.0,
+                NoiseSpectrum::Pink => 1.0 / f,
+                NoiseSpectrum::Custom(shape) => shape(f).max(0.0),
+            };
+            // Gaussian real and imaginary parts give Gaussian samples in time
+            bins[k] = Complex64::new(rng.normal(), rng.normal()) * density.sqrt();
+            bins[n - k] = bins[k].conj();
+        }
+        // the scale doesn't matter here, since the trace is normalized to the requested rms
+        let mut trace: Vec<f64> = ifft(&bins).iter().take(wanted).map(|c| c.re).collect();
+        let rms = (trace.iter().map(|x| x * x).sum::<f64>() / trace.len() as f64).sqrt();
+        if rms > 0.0 {
+            trace.iter_mut().for_each(|x| *x *= self.rms / rms);
+        }
+        (dt, trace)
+    }
+
+    /// The trace as a PWL stimulus.
+    pub fn stimulus(&self) -> Stimulus {
+        let (dt, samples) = self.samples();
+        Stimulus::Pwl(
+            samples
+                .into_iter()
+                .enumerate()
+                .map(|(k, v)| (k as f64 * dt, v))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_signals() {
+        let bits = prbs_bits(7, 254);
+        assert_eq!(bits[..127], bits[127..]);
+        assert_eq!(bits[..127].iter().filter(|&&b| b).count(), 64);
+
+        let pwm = Stimulus::pwm(1.0, &[0.5, 0.0], 0.0, 1.0, 0.1);
+        assert_eq!(
+            pwm.voltage_source("pwm", "g", "0"),
+            "Vpwm g 0 PWL(0e0 0e0 1e-1 1e0 6e-1 1e0 7e-1 0e0 2e0 0e0)\n"
+        );
+        let tone = Stimulus::multitone(
+            &[Tone {
+                frequency: 1.0,
+                amplitude: 2.0,
+                phase: 0.0,
+            }],
+            0.5,
+        );
+        assert_eq!(
+            tone.current_source("tone", "a", "b"),
+            format!("Btone a b I=5e-1+2e0*sin({:e}*time+0e0)\n", 2.0 * PI)
+        );
+        match Stimulus::prbs(7, 16, 1e-9, 0.0, 1.0, 1e-10) {
+            Stimulus::Pwl(points) => {
+                assert_eq!(points[0], (0.0, if bits[0] { 1.0 } else { 0.0 }));
+                assert_eq!(points.last().unwrap().0, 16e-9);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn synthesizes_reproducible_noise() {
+        let noise = Noise {
+            rms: 0.1,
+            f_min: 10.0,
+            f_max: 1000.0,
+            duration: 1.0,
+            spectrum: NoiseSpectrum::Pink,
+            seed: 7,
+        };
+        let (dt, samples) = noise.samples();
+        assert_eq!(samples.len(), 4000);
+        assert!((dt * 3999.0 - 1.0).abs() < 1e-12);
+        let rms = (samples.iter().map(|x| x * x).sum::<f64>() / samples.len() as f64).sqrt();
+        assert!((rms - 0.1).abs() < 1e-9);
+        assert_eq!(noise.samples(), (dt, samples));
+        assert_ne!(
+            Noise { seed: 8, ..noise }.samples().1[0],
+            noise.samples().1[0]
+        );
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright 2022 Andrew Morrow.
+// stream.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Delivers simulation data point by point while ngSPICE is still running.
+
+use crate::background::{self, Options, SimulationHandle};
+use crate::progress;
+use crate::{DataType, Error, NgSpice};
+use ngspice_sys::*;
+use num_complex::Complex64;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::os::raw::{c_int, c_void};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+/// Where ngSPICE's callbacks send events, if a streaming run is in progress.
+static STREAM: Mutex<Option<Sender<StreamEvent>>> = Mutex::new(None);
+
+/// Something that happened during a streaming run, in the order ngSPICE reported it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamEvent {
+    /// An analysis is starting. Its points follow.
+    Plan(SimulationPlan),
+    Point(DataPoint),
+}
+
+/// A vector that an analysis will produce.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedVector {
+    /// The vector's position in the plot, from 0.
+    pub index: usize,
+    /// The lowercase name, as used in [`DataPoint::values`].
+    pub name: String,
+    pub datatype: DataType,
+    pub complex: bool,
+}
+
+/// The vectors of an analysis, sent before its first point so consumers can allocate
+/// buffers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulationPlan {
+    /// The plot name, e.g. `tran1`.
+    pub plot: String,
+    pub title: String,
+    pub date: String,
+    /// The analysis type, e.g. `Transient Analysis`.
+    pub analysis: String,
+    pub vectors: Vec<PlannedVector>,
+}
+
+/// The value of every vector at one point of an analysis.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DataPoint {
+    /// The position of the point within the analysis, from 0.
+    pub index: usize,
+    /// Values by lowercase vector name, e.g. `time` or `v(out)`. Real vectors have a zero
+    /// imaginary part.
+    pub values: HashMap<String, Complex64>,
+    /// The name of the scale vector, e.g. `time`, if ngSPICE marked one.
+    pub scale: Option<String>,
+}
+
+impl DataPoint {
+    /// The real part of the named value.
+    pub fn real(&self, name: &str) -> Option<f64> {
+        self.values.get(name).map(|c| c.re)
+    }
+
+    /// The value of the scale, e.g. the time of a transient point.
+    pub fn scale_value(&self) -> Option<f64> {
+        self.scale.as_deref().and_then(|s| self.real(s))
+    }
+}
+
+pub(crate) fn attach(sender: Sender<StreamEvent>) {
+    *STREAM.lock().unwrap_or_else(|e| e.into_inner()) = Some(sender);
+}
+
+pub(crate) fn detach() {
+    *STREAM.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// ngSPICE calls this once per simulated point.
+pub(crate) extern "C" fn send_data(
+    data: pvecvaluesall,
+    count: c_int,
+    _: c_int,
+    _: *mut c_void,
+) -> c_int {
+    unsafe { progress::report(data, count) };
+    let mut stream = STREAM.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(sender) = stream.as_ref() else {
+        return 0;
+    };
+    let point = unsafe { data_point(data, count) };
+    if sender.send(StreamEvent::Point(point)).is_err() {
+        // nobody is listening any more; stop building points
+        *stream = None;
+    }
+    0
+}
+
+unsafe fn string(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(s).to_string_lossy().into_owned()
+}
+
+/// ngSPICE calls this once per analysis, before its first point.
+pub(crate) extern "C" fn send_init_data(info: pvecinfoall, _: c_int, _: *mut c_void) -> c_int {
+    let mut stream = STREAM.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(sender) = stream.as_ref() else {
+        return 0;
+    };
+    let plan = unsafe {
+        simulation_plan(info, |name| {
+            // the vector already exists in the new plot, so its type can be looked up
+            let details = ngGet_Vec_Info(name);
+            if details.is_null() {
+                DataType::Unknown
+            } else {
+                DataType::from((*details).v_type as u32)
+            }
+        })
+    };
+    if sender.send(StreamEvent::Plan(plan)).is_err() {
+        *stream = None;
+    }
+    0
+}
+
+/// Copies the first `count` values ngSPICE sent for one point.
+unsafe fn data_point(data: pvecvaluesall, count: c_int) -> DataPoint {
+    let mut point = DataPoint {
+        index: (*data).vecindex as usize,
+        ..DataPoint::default()
+    };
+    for k in 0..count.min((*data).veccount) as usize {
+        let v = *(*data).vecsa.add(k);
+        let name = CStr::from_ptr((*v).name)
+            .to_string_lossy()
+            .to_ascii_lowercase();
+        if (*v).is_scale {
+            point.scale = Some(name.clone());
+        }
+        point
+            .values
+            .insert(name, Complex64::new((*v).creal, (*v).cimag));
+    }
+    point
+}
+
+/// Describes the vectors of a new analysis, looking up each vector's type by name.
+unsafe fn simulation_plan(
+    info: pvecinfoall,
+    datatype: impl Fn(*mut c_char) -> DataType,
+) -> SimulationPlan {
+    let vectors = (0..(*info).veccount as usize)
+        .map(|k| {
+            let v = *(*info).vecs.add(k);
+            PlannedVector {
+                index: (*v).number as usize,
+                name: string((*v).vecname).to_ascii_lowercase(),
+                datatype: datatype((*v).vecname),
+                complex: !(*v).is_real,
+            }
+        })
+        .collect();
+    SimulationPlan {
+        plot: string((*info).name),
+        title: string((*info).title),
+        date: string((*info).date),
+        analysis: string((*info).type_),
+        vectors,
+    }
+}
+
+impl NgSpice {
+    /// Like [`NgSpice::simulate_async`], but also returns a channel that receives every point
+    /// as ngSPICE computes it, each analysis's points preceded by its [`SimulationPlan`]. The
+    /// channel closes when the simulation finishes.
+    ///
+    /// Dropping the receiver does not stop the simulation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error immediately if the circuit or command fails validation.
+    pub fn simulate_streaming(
+        circuit: &str,
+        command: &str,
+    ) -> Result<(SimulationHandle, Receiver<StreamEvent>), Error> {
+        let (sender, receiver) = mpsc::channel();
+        let handle = background::spawn(
+            circuit,
+            command,
+            Options {
+                stream: Some(sender),
+                ..Options::default()
+            },
+        )?;
+        Ok((handle, receiver))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    #[test]
+    fn streams_points() {
+        // the callbacks' conversions, without the shared stream other runs send to
+        let names = [
+            CString::new("time").unwrap(),
+            CString::new("V(out)").unwrap(),
+        ];
+        let mut values: Vec<vecvalues> = names
+            .iter()
+            .zip([(1e-3, true), (2.5, false)])
+            .map(|(name, (x, is_scale))| vecvalues {
+                name: name.as_ptr() as *mut _,
+                creal: x,
+                cimag: 0.0,
+                is_scale,
+                is_complex: false,
+            })
+            .collect();
+        let mut pointers: Vec<pvecvalues> = values.iter_mut().map(|v| v as *mut _).collect();
+        let mut all = vecvaluesall {
+            veccount: 2,
+            vecindex: 7,
+            vecsa: pointers.as_mut_ptr(),
+        };
+        let plot = CString::new("tran1").unwrap();
+        let mut infos: Vec<vecinfo> = names
+            .iter()
+            .enumerate()
+            .map(|(k, name)| vecinfo {
+                number: k as c_int,
+                vecname: name.as_ptr() as *mut _,
+                is_real: true,
+                pdvec: std::ptr::null_mut(),
+                pdvecscale: std::ptr::null_mut(),
+            })
+            .collect();
+        let mut info_pointers: Vec<pvecinfo> = infos.iter_mut().map(|v| v as *mut _).collect();
+        let mut init = vecinfoall {
+            name: plot.as_ptr() as *mut _,
+            title: std::ptr::null_mut(),
+            date: std::ptr::null_mut(),
+            type_: std::ptr::null_mut(),
+            veccount: 2,
+            vecs: info_pointers.as_mut_ptr(),
+        };
+        let plan = unsafe { simulation_plan(&mut init, |_| DataType::Voltage) };
+        assert_eq!(plan.plot, "tran1");
+        assert_eq!(plan.vectors[1].name, "v(out)");
+        assert_eq!(plan.vectors[1].datatype, DataType::Voltage);
+        assert!(!plan.vectors[1].complex);
+        let point = unsafe { data_point(&mut all, 2) };
+        assert_eq!(point.index, 7);
+        assert_eq!(point.scale_value(), Some(1e-3));
+        assert_eq!(point.real("v(out)"), Some(2.5));
+    }
+}
@@ -0,0 +1,286 @@
+// Copyright 2022 Andrew Morrow.
+// stress.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Screening transient waveforms against device stress ratings.
+
+use crate::control::vector_name;
+use crate::format::NumericFormat;
+use crate::waveform::{derivative, smooth};
+use crate::{Error, Simulation};
+use std::fmt::{self, Formatter, Write as _};
+
+/// Absolute and slew-rate ratings for one vector, e.g. a MOSFET's drain voltage and dv/dt.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rating {
+    /// A vector expression such as `v(drain)` or `i(vds)`.
+    pub vector: String,
+    /// The largest permitted magnitude.
+    pub max_abs: Option<f64>,
+    /// The largest permitted magnitude of the derivative, in units per second.
+    pub max_slew: Option<f64>,
+    /// The moving-average window applied before differentiating, in seconds. Without one,
+    /// single-step edges at breakpoints can report slew rates the device never sees.
+    pub smoothing: Option<f64>,
+}
+
+impl Rating {
+    pub fn new(vector: &str) -> Self {
+        Rating {
+            vector: vector.to_owned(),
+            max_abs: None,
+            max_slew: None,
+            smoothing: None,
+        }
+    }
+
+    pub fn max_abs(mut self, limit: f64) -> Self {
+        self.max_abs = Some(limit);
+        self
+    }
+
+    pub fn max_slew(mut self, limit: f64) -> Self {
+        self.max_slew = Some(limit);
+        self
+    }
+
+    pub fn smoothing(mut self, window: f64) -> Self {
+        self.smoothing = Some(window);
+        self
+    }
+}
+
+/// Which rating a violation exceeded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stress {
+    Absolute,
+    Slew,
+}
+
+/// The peak of one excursion beyond a rating. An excursion lasts from the sample where the
+/// rating is first exceeded until the waveform returns within it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    pub vector: String,
+    pub stress: Stress,
+    /// The time of the peak, in seconds.
+    pub time: f64,
+    /// The value or slew rate at the peak.
+    pub value: f64,
+    pub limit: f64,
+}
+
+impl Violation {
+    /// How far the peak exceeded the rating, e.g. `1.2` for 20% over.
+    pub fn severity(&self) -> f64 {
+        self.value.abs() / self.limit
+    }
+}
+
+/// Every violation found by [`Screen::check`], worst first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StressReport {
+    pub violations: Vec<Violation>,
+}
+
+impl StressReport {
+    pub fn passed(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// The `n` most severe violations.
+    pub fn worst(&self, n: usize) -> &[Violation] {
+        &self.violations[..n.min(self.violations.len())]
+    }
+
+    /// Like the [`Display`](fmt::Display) output, with numbers written in `format`.
+    /// Percentages stay whole numbers.
+    pub fn to_string_with(&self, format: &NumericFormat) -> String {
+        let mut out = String::new();
+        for v in &self.violations {
+            let stress = match v.stress {
+                Stress::Absolute => "abs",
+                Stress::Slew => "slew",
+            };
+            writeln!(
+                out,
+                "{} {} = {} at {} s (limit {}, {:.0}%)",
+                v.vector,
+                stress,
+                format.format(v.value),
+                format.format(v.time),
+                format.format(v.limit),
+                v.severity() * 100.0
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+impl fmt::Display for StressReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_string_with(&NumericFormat::default()))
+    }
+}
+
+/// A set of ratings to check transient results against.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Screen {
+    pub ratings: Vec<Rating>,
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Screen::default()
+    }
+
+    pub fn rate(mut self, rating: Rating) -> Self {
+        self.ratings.push(rating);
+        self
+    }
+
+    /// Finds every excursion beyond a rating in `sim`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingScale`] if `sim` has no time vector, or
+    /// [`Error::MissingVector`] if a rated vector is missing.
+    pub fn check(&self, sim: &Simulation) -> Result<StressReport, Error> {
+        let time = sim.scale_values().ok_or(Error::MissingScale)?;
+        let mut violations = Vec::new();
+        for rating in &self.ratings {
+            let values = sim.real_vector(&vector_name(&rating.vector))?;
+            if values.len() != time.len() {
+                return Err(Error::MissingVector(rating.vector.clone()));
+            }
+            if let Some(limit) = rating.max_abs {
+                excursions(
+                    &rating.vector,
+                    Stress::Absolute,
+                    &time,
+                    values,
+                    limit,
+                    &mut violations,
+                );
+            }
+            if let Some(limit) = rating.max_slew {
+                let slew = match rating.smoothing {
+                    Some(window) => derivative(&time, &smooth(&time, values, window)),
+                    None => derivative(&time, values),
+                };
+                excursions(
+                    &rating.vector,
+                    Stress::Slew,
+                    &time,
+                    &slew,
+                    limit,
+                    &mut violations,
+                );
+            }
+        }
+        violations.sort_by(|a, b| b.severity().total_cmp(&a.severity()));
+        Ok(StressReport { violations })
+    }
+}
+
+/// Pushes the peak of every run of samples whose magnitude exceeds `limit`.
+fn excursions(
+    vector: &str,
+    stress: Stress,
+    time: &[f64],
+    values: &[f64],
+    limit: f64,
+    out: &mut Vec<Violation>,
+) {
+    let mut peak: Option<usize> = None;
+    for (i, v) in values.iter().enumerate() {
+        if v.abs() > limit {
+            if peak.is_none_or(|p| v.abs() > values[p].abs()) {
+                peak = Some(i);
+            }
+            continue;
+        }
+        if let Some(p) = peak.take() {
+            out.push(Violation {
+                vector: vector.to_owned(),
+                stress,
+                time: time[p],
+                value: values[p],
+                limit,
+            });
+        }
+    }
+    if let Some(p) = peak {
+        out.push(Violation {
+            vector: vector.to_owned(),
+            stress,
+            time: time[p],
+            value: values[p],
+            limit,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, VectorInfo, VectorValues};
+
+    #[test]
+    fn reports_worst_excursions() -> Result<(), Error> {
+        let mut sim = Simulation::default();
+        let time: Vec<f64> = (0..10).map(|i| i as f64 * 1e-6).collect();
+        let drain = vec![0.0, 10.0, 45.0, 30.0, 0.0, 0.0, -42.0, 0.0, 0.0, 0.0];
+        for (name, datatype, values) in [
+            ("time", DataType::Time, time),
+            ("drain", DataType::Voltage, drain),
+        ] {
+            let values = VectorValues::Real(values.into());
+            sim.vectors.insert(
+                name.to_owned(),
+                VectorInfo {
+                    datatype,
+                    values,
+                    scale: None,
+                },
+            );
+        }
+        let report = Screen::new()
+            .rate(Rating::new("v(drain)").max_abs(40.0).max_slew(15e6))
+            .check(&sim)?;
+        assert!(!report.passed());
+        let abs: Vec<&Violation> = report
+            .violations
+            .iter()
+            .filter(|v| v.stress == Stress::Absolute)
+            .collect();
+        assert_eq!(abs.len(), 2);
+        assert_eq!((abs[0].time, abs[0].value), (2e-6, 45.0));
+        assert_eq!((abs[1].time, abs[1].value), (6e-6, -42.0));
+        assert_eq!(report.violations[0].stress, Stress::Slew);
+        assert_eq!(report.worst(1).len(), 1);
+        let relaxed = Screen::new()
+            .rate(Rating::new("drain").max_abs(50.0))
+            .check(&sim)?;
+        assert!(relaxed.passed());
+        assert!(Screen::new()
+            .rate(Rating::new("v(gate)"))
+            .check(&sim)
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright 2022 Andrew Morrow.
+// telemetry.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Metrics about every simulation, for services that run this crate for a long time.
+//!
+//! With the `metrics` feature enabled, simulations report through the [`metrics`] crate
+//! facade, so any exporter (e.g. Prometheus) installed by the application picks them up.
+//! Without it, nothing is recorded. The metrics are:
+//!
+//! | Name | Kind | Labels |
+//! |------|------|--------|
+//! | [`SIMULATIONS`] | counter | `outcome`: `ok` or an [`error_class`] |
+//! | [`DURATION`] | histogram, in seconds | `outcome` |
+//! | [`POINTS`] | counter | |
+//!
+//! [`metrics`]: https://docs.rs/metrics
+
+use crate::{Error, Simulation};
+use std::time::Duration;
+
+/// Simulations run, successful or not.
+pub const SIMULATIONS: &str = "ngspice_simulations_total";
+/// How long each simulation took, from validation to extracted results.
+pub const DURATION: &str = "ngspice_simulation_duration_seconds";
+/// Points produced by successful simulations, i.e. the length of their longest vector.
+pub const POINTS: &str = "ngspice_points_total";
+
+/// A short, stable name for the kind of an error, for use as a metric label.
+pub fn error_class(error: &Error) -> &'static str {
+    match error {
+        Error::InvalidStringEncoding => "invalid_string_encoding",
+        Error::InvalidCircuit(_) => "invalid_circuit",
+        Error::InvalidNetlist(_) => "invalid_netlist",
+        Error::MissingVector(_) => "missing_vector",
+        Error::ForbiddenCommand(_) => "forbidden_command",
+        Error::ResourceLimit { .. } => "resource_limit",
+        Error::Cancelled { .. } => "cancelled",
+        Error::Timeout { .. } => "timeout",
+        Error::MissingScale => "missing_scale",
+        Error::MissingElement(_) => "missing_element",
+        Error::DuplicateDefinition { .. } => "duplicate_definition",
+        Error::InvalidValue(_) => "invalid_value",
+        Error::InvalidArgument(_) => "invalid_argument",
+        Error::UnitMismatch { .. } => "unit_mismatch",
+        Error::Include { .. } => "include",
+        Error::Io(_) => "io",
+        #[cfg(feature = "sqlite")]
+        Error::Database(_) => "database",
+        Error::Unknown(_) => "unknown",
+    }
+}
+
+/// The number of points in a simulation's longest vector.
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+fn points(sim: &Simulation) -> usize {
+    sim.vectors
+        .values()
+        .map(|v| match (v.values.real(), v.values.complex()) {
+            (Some(x), _) => x.len(),
+            (_, Some(x)) => x.len(),
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Records the outcome of one simulation.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn record(result: &Result<Simulation, Error>, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        let outcome = match result {
+            Ok(_) => "ok",
+            Err(e) => error_class(e),
+        };
+        ::metrics::counter!(SIMULATIONS, "outcome" => outcome).increment(1);
+        ::metrics::histogram!(DURATION, "outcome" => outcome).record(elapsed.as_secs_f64());
+        if let Ok(sim) = result {
+            ::metrics::counter!(POINTS).increment(points(sim) as u64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, VectorInfo};
+
+    #[test]
+    fn classifies_and_counts() {
+        assert_eq!(error_class(&Error::Timeout { partial: None }), "timeout");
+        assert_eq!(error_class(&Error::MissingScale), "missing_scale");
+        let mut sim = Simulation::default();
+        for (name, len) in [("time", 5), ("out", 5), ("op", 1)] {
+            let values = vec![0.0; len].into();
+            let datatype = DataType::Voltage;
+            sim.vectors.insert(
+                name.to_owned(),
+                VectorInfo {
+                    datatype,
+                    values,
+                    scale: None,
+                },
+            );
+        }
+        assert_eq!(points(&sim), 5);
+        record(&Ok(sim), Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright 2022 Andrew Morrow.
+// temperature.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Sweeps the circuit temperature, re-running an analysis at each temperature.
+
+use crate::campaign::{Campaign, Tags};
+use crate::options::remove_options;
+use crate::{Error, NgSpice, Simulation};
+
+/// Removes any temperature setting from a `.temp` or `.option` card. Returns `None` if
+/// nothing is left of the card, and the card unchanged if it sets no temperature.
+pub(crate) fn strip_temperature(line: &str) -> Option<String> {
+    let is_temp = line
+        .split_whitespace()
+        .next()
+        .is_some_and(|w| w.eq_ignore_ascii_case(".temp"));
+    if is_temp {
+        None
+    } else {
+        remove_options(line, &["temp"])
+    }
+}
+
+/// Sets the temperature of `circuit` in °C with a `.temp` card after the title, removing any
+/// `.temp` card or `temp=` option it already has.
+pub fn with_temperature(circuit: &str, celsius: f64) -> String {
+    let mut lines = circuit.lines();
+    let mut out: Vec<String> = lines.next().map(str::to_owned).into_iter().collect();
+    out.push(format!(".temp {}", celsius));
+    out.extend(lines.filter_map(strip_temperature));
+    out.join("\n")
+}
+
+/// Runs an analysis at each of a list of temperatures.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemperatureSweep {
+    /// In °C, in the order they are run.
+    pub temperatures: Vec<f64>,
+}
+
+impl TemperatureSweep {
+    pub fn new(temperatures: &[f64]) -> Self {
+        TemperatureSweep {
+            temperatures: temperatures.to_vec(),
+        }
+    }
+
+    /// Every temperature from `start` to `stop` inclusive, `step` apart.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `step` is zero or does not move from `start` towards `stop`.
+    pub fn linear(start: f64, stop: f64, step: f64) -> Self {
+        assert!(
+            step != 0.0 && (stop - start) * step >= 0.0,
+            "step must move from start towards stop"
+        );
+        let count = ((stop - start) / step + 1e-9).floor() as usize + 1;
+        TemperatureSweep {
+            temperatures: (0..count).map(|i| start + i as f64 * step).collect(),
+        }
+    }
+
+    /// Runs `command` at every temperature.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first simulation error.
+    pub fn run(&self, circuit: &str, command: &str) -> Result<TemperatureResults, Error> {
+        let runs = self
+            .temperatures
+            .iter()
+            .map(|&t| {
+                Ok((
+                    t,
+                    NgSpice::simulate(&with_temperature(circuit, t), command)?,
+                ))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(TemperatureResults { runs })
+    }
+}
+
+/// The results of a [`TemperatureSweep`], keyed by temperature.
+#[derive(Clone, Debug, Default)]
+pub struct TemperatureResults {
+    /// Each temperature in °C and its simulation, in the order they were run.
+    pub runs: Vec<(f64, Simulation)>,
+}
+
+impl TemperatureResults {
+    pub fn temperatures(&self) -> Vec<f64> {
+        self.runs.iter().map(|(t, _)| *t).collect()
+    }
+
+    /// The simulation at a temperature, to within a millikelvin.
+    pub fn get(&self, celsius: f64) -> Option<&Simulation> {
+        self.runs
+            .iter()
+            .find(|(t, _)| (t - celsius).abs() < 1e-3)
+            .map(|(_, sim)| sim)
+    }
+
+    /// Evaluates `metric` at every temperature, e.g. to plot a reference voltage against
+    /// temperature.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error from `metric`.
+    pub fn trend<F>(&self, mut metric: F) -> Result<Vec<(f64, f64)>, Error>
+    where
+        F: FnMut(&Simulation) -> Result<f64, Error>,
+    {
+        self.runs
+            .iter()
+            .map(|(t, sim)| Ok((*t, metric(sim)?)))
+            .collect()
+    }
+
+    /// Converts the results into a campaign with each run tagged `temp`.
+    pub fn into_campaign(self, name: &str) -> Campaign {
+        let mut campaign = Campaign::new(name);
+        for (t, sim) in self.runs {
+            campaign.add(Tags::new().with("temp", t), sim);
+        }
+        campaign
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_temperature_settings() {
+        let deck =
+            "* bandgap\n.temp 27\n.option reltol=1e-4 TEMP=50\n.options temp=0\nR1 a 0 1k\n.end";
+        assert_eq!(
+            with_temperature(deck, -40.0),
+            "* bandgap\n.temp -40\n.option reltol=1e-4\nR1 a 0 1k\n.end"
+        );
+        assert_eq!(
+            TemperatureSweep::linear(-40.0, 125.0, 55.0).temperatures,
+            vec![-40.0, 15.0, 70.0, 125.0]
+        );
+        let results = TemperatureResults {
+            runs: vec![(25.0, Simulation::default())],
+        };
+        assert!(results.get(25.0).is_some() && results.get(26.0).is_none());
+        let campaign = results.into_campaign("temps");
+        assert_eq!(campaign.runs()[0].tags.number("temp"), Some(25.0));
+    }
+}
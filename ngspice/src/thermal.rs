@@ -0,0 +1,154 @@
+// Copyright 2022 Andrew Morrow.
+// thermal.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Generates RC subcircuits from thermal models for electro-thermal simulation.
+//!
+//! Temperatures are modeled as voltages in °C and heat flow as current in W, so a thermal
+//! resistance in K/W becomes a resistor and a heat capacity in J/K becomes a capacitor.
+
+use std::fmt::Write;
+
+/// One RC pair of a thermal model, as listed in a device datasheet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThermalStage {
+    /// In K/W.
+    pub resistance: f64,
+    /// In J/K.
+    pub capacitance: f64,
+}
+
+/// A thermal model from junction to ambient.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ThermalNetwork {
+    /// Parallel RC pairs in series. Datasheets usually give this form because it fits a
+    /// measured transient thermal impedance curve directly, but its internal nodes have no
+    /// physical meaning.
+    Foster(Vec<ThermalStage>),
+    /// Series resistors with a capacitor from each node to ambient, junction first. Each node
+    /// corresponds to a physical layer such as die, die attach, or case.
+    Cauer(Vec<ThermalStage>),
+}
+
+impl ThermalNetwork {
+    fn stages(&self) -> &[ThermalStage] {
+        match self {
+            ThermalNetwork::Foster(s) | ThermalNetwork::Cauer(s) => s,
+        }
+    }
+
+    /// The steady-state thermal resistance from junction to ambient, in K/W.
+    pub fn resistance(&self) -> f64 {
+        self.stages().iter().map(|s| s.resistance).sum()
+    }
+
+    /// The transient thermal impedance of a Foster network after a power step of `t` seconds,
+    /// in K/W. Returns `None` for Cauer networks.
+    pub fn foster_impedance(&self, t: f64) -> Option<f64> {
+        match self {
+            ThermalNetwork::Foster(stages) => Some(
+                stages
+                    .iter()
+                    .map(|s| s.resistance * (1.0 - (-t / (s.resistance * s.capacitance)).exp()))
+                    .sum(),
+            ),
+            ThermalNetwork::Cauer(_) => None,
+        }
+    }
+
+    /// Renders the network as a subcircuit with ports `tj` (junction) and `tamb` (ambient).
+    pub fn subcircuit(&self, name: &str) -> String {
+        let mut out = format!(".subckt {} tj tamb\n", name);
+        let stages = self.stages();
+        for (k, s) in stages.iter().enumerate() {
+            let a = if k == 0 {
+                "tj".to_owned()
+            } else {
+                format!("n{}", k)
+            };
+            let b = if k + 1 == stages.len() {
+                "tamb".to_owned()
+            } else {
+                format!("n{}", k + 1)
+            };
+            writeln!(out, "R{} {} {} {:e}", k, a, b, s.resistance).unwrap();
+            match self {
+                ThermalNetwork::Foster(_) => {
+                    writeln!(out, "C{} {} {} {:e}", k, a, b, s.capacitance).unwrap()
+                }
+                ThermalNetwork::Cauer(_) => {
+                    writeln!(out, "C{} {} tamb {:e}", k, a, s.capacitance).unwrap()
+                }
+            }
+        }
+        writeln!(out, ".ends {}", name).unwrap();
+        out
+    }
+}
+
+/// Renders the lines that heat a thermal subcircuit with a device's power dissipation.
+///
+/// `power` is a B-source expression for the dissipated power, e.g. `v(d,s)*i(vsense)`. The
+/// junction temperature is then available as `v(<name>_tj)`, relative to ground, with the
+/// ambient held at `ambient` °C.
+pub fn electro_thermal(name: &str, subcircuit: &str, power: &str, ambient: f64) -> String {
+    let tj = format!("{}_tj", name);
+    let tamb = format!("{}_tamb", name);
+    format!(
+        "X{name}_th {tj} {tamb} {subcircuit}\nV{name}_amb {tamb} 0 DC {ambient:e}\nB{name}_heat {tamb} {tj} I={power}\n",
+        name = name,
+        tj = tj,
+        tamb = tamb,
+        subcircuit = subcircuit,
+        ambient = ambient,
+        power = power
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_networks() {
+        let stages = vec![
+            ThermalStage {
+                resistance: 0.5,
+                capacitance: 1e-3,
+            },
+            ThermalStage {
+                resistance: 1.5,
+                capacitance: 1e-1,
+            },
+        ];
+        let foster = ThermalNetwork::Foster(stages.clone());
+        assert_eq!(foster.resistance(), 2.0);
+        assert_eq!(foster.foster_impedance(0.0), Some(0.0));
+        assert!((foster.foster_impedance(1e3).unwrap() - 2.0).abs() < 1e-12);
+        assert_eq!(
+            foster.subcircuit("zth"),
+            ".subckt zth tj tamb\nR0 tj n1 5e-1\nC0 tj n1 1e-3\nR1 n1 tamb 1.5e0\nC1 n1 tamb 1e-1\n.ends zth\n"
+        );
+        let cauer = ThermalNetwork::Cauer(stages);
+        assert!(cauer.subcircuit("zth").contains("C1 n1 tamb 1e-1\n"));
+        assert!(cauer.subcircuit("zth").contains("C0 tj tamb 1e-3\n"));
+        assert_eq!(cauer.foster_impedance(1.0), None);
+        assert_eq!(
+            electro_thermal("q1", "zth", "v(d,s)*i(vs)", 25.0),
+            "Xq1_th q1_tj q1_tamb zth\nVq1_amb q1_tamb 0 DC 2.5e1\nBq1_heat q1_tamb q1_tj I=v(d,s)*i(vs)\n"
+        );
+    }
+}
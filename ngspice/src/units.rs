@@ -0,0 +1,204 @@
+// Copyright 2022 Andrew Morrow.
+// units.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Typed vector accessors, which check that a vector holds the requested quantity before
+//! handing out its values.
+
+use crate::control::vector_name;
+use crate::kernels::magnitude_db;
+use crate::{DataType, Error, Simulation, VectorValues};
+
+/// A unit that vector values can be read in with [`Simulation::get`].
+pub trait Unit {
+    /// The unit symbol, e.g. `V`.
+    const SYMBOL: &'static str;
+
+    /// Converts a vector of `datatype` to this unit, or returns `None` if it holds a
+    /// different quantity.
+    fn convert(datatype: &DataType, values: &VectorValues) -> Option<Vec<f64>>;
+}
+
+/// The real values of a vector. ngSPICE stores some real quantities, such as the frequency
+/// of an AC analysis, as complex numbers with no imaginary part.
+fn real(values: &VectorValues) -> Option<Vec<f64>> {
+    match values {
+        VectorValues::Real(x) => Some(x.to_vec()),
+        VectorValues::Complex(x) if x.iter().all(|c| c.im == 0.0) => {
+            Some(x.iter().map(|c| c.re).collect())
+        }
+        VectorValues::Complex(_) => None,
+    }
+}
+
+macro_rules! real_unit {
+    ($(#[$doc:meta])* $name:ident, $symbol:literal, $($datatype:ident)|+) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        pub struct $name;
+
+        impl Unit for $name {
+            const SYMBOL: &'static str = $symbol;
+
+            fn convert(datatype: &DataType, values: &VectorValues) -> Option<Vec<f64>> {
+                match datatype {
+                    $(DataType::$datatype)|+ => real(values),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+real_unit!(Volts, "V", Voltage);
+real_unit!(Amps, "A", Current);
+real_unit!(Seconds, "s", Time);
+real_unit!(Hertz, "Hz", Frequency);
+real_unit!(
+    /// Resistances, and impedances with no reactive part.
+    Ohms,
+    "Ω",
+    Resistance | Impedance
+);
+real_unit!(Watts, "W", Power);
+real_unit!(Farads, "F", Capacitance);
+real_unit!(Coulombs, "C", Charge);
+real_unit!(Celsius, "°C", Temperature);
+
+/// Magnitudes in dB: `db()` vectors as they are, and voltages and currents as 20·log10 of
+/// their magnitude.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Decibels;
+
+impl Unit for Decibels {
+    const SYMBOL: &'static str = "dB";
+
+    fn convert(datatype: &DataType, values: &VectorValues) -> Option<Vec<f64>> {
+        match (datatype, values) {
+            (DataType::Decibel, _) => real(values),
+            (DataType::Voltage | DataType::Current, VectorValues::Complex(x)) => {
+                Some(magnitude_db(x))
+            }
+            (DataType::Voltage | DataType::Current, VectorValues::Real(x)) => {
+                Some(x.iter().map(|v| 20.0 * v.abs().log10()).collect())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Phases in radians: `ph()` vectors as they are, and the argument of complex voltages and
+/// currents.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Radians;
+
+impl Unit for Radians {
+    const SYMBOL: &'static str = "rad";
+
+    fn convert(datatype: &DataType, values: &VectorValues) -> Option<Vec<f64>> {
+        match (datatype, values) {
+            (DataType::Phase, _) => real(values),
+            (DataType::Voltage | DataType::Current, VectorValues::Complex(x)) => {
+                Some(x.iter().map(|c| c.arg()).collect())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Phases in degrees, converted from the same vectors as [`Radians`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Degrees;
+
+impl Unit for Degrees {
+    const SYMBOL: &'static str = "°";
+
+    fn convert(datatype: &DataType, values: &VectorValues) -> Option<Vec<f64>> {
+        Radians::convert(datatype, values).map(|x| x.into_iter().map(f64::to_degrees).collect())
+    }
+}
+
+impl Simulation {
+    /// Returns the named vector in unit `U`, e.g. `sim.get::<Volts>("v(out)")`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MissingVector`] if there is no such vector, or [`Error::UnitMismatch`]
+    /// if it holds a quantity that cannot be expressed in `U`.
+    pub fn get<U: Unit>(&self, name: &str) -> Result<Vec<f64>, Error> {
+        let key = vector_name(name);
+        let info = self
+            .vectors
+            .get(&key)
+            .ok_or_else(|| Error::MissingVector(name.to_owned()))?;
+        U::convert(&info.datatype, &info.values).ok_or_else(|| Error::UnitMismatch {
+            vector: name.to_owned(),
+            unit: U::SYMBOL,
+            datatype: info.datatype.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VectorInfo;
+    use num_complex::Complex64;
+
+    #[test]
+    fn checks_and_converts_units() {
+        let mut sim = Simulation::default();
+        let mut add = |name: &str, datatype, values| {
+            let info = VectorInfo {
+                datatype,
+                values,
+                scale: None,
+            };
+            sim.vectors.insert(name.to_owned(), info);
+        };
+        add(
+            "out",
+            DataType::Voltage,
+            vec![Complex64::new(0.0, 10.0)].into(),
+        );
+        add(
+            "frequency",
+            DataType::Frequency,
+            vec![Complex64::new(1e3, 0.0)].into(),
+        );
+        add("vdd#branch", DataType::Current, vec![-1e-3].into());
+        assert_eq!(sim.get::<Decibels>("v(out)").unwrap(), [20.0]);
+        assert_eq!(sim.get::<Degrees>("out").unwrap(), [90.0]);
+        assert_eq!(sim.get::<Hertz>("frequency").unwrap(), [1e3]);
+        assert_eq!(sim.get::<Amps>("i(vdd)").unwrap(), [-1e-3]);
+        assert!(matches!(
+            sim.get::<Volts>("i(vdd)"),
+            Err(Error::UnitMismatch {
+                unit: "V",
+                datatype: DataType::Current,
+                ..
+            })
+        ));
+        assert!(matches!(
+            sim.get::<Volts>("out"),
+            Err(Error::UnitMismatch { .. })
+        ));
+        assert!(matches!(
+            sim.get::<Volts>("v(x)"),
+            Err(Error::MissingVector(_))
+        ));
+    }
+}
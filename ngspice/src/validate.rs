@@ -0,0 +1,502 @@
+// Copyright 2022 Andrew Morrow.
+// validate.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Checks for SPICE netlist constraints that ngSPICE does not report on its own.
+
+use crate::circuit::{parse_number, Element};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Formatter};
+
+/// The longest physical netlist line that will be passed to ngSPICE.
+///
+/// ngSPICE copies tokens into fixed-size buffers in several places, so very long lines can be
+/// truncated without any error being reported.
+pub const MAX_LINE_LENGTH: usize = 1024;
+
+/// A single problem found in a netlist.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    /// The 1-based line number where the problem was found.
+    pub line: usize,
+    pub kind: DiagnosticKind,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DiagnosticKind {
+    /// The first line looks like an element, but ngSPICE always treats the first line as the
+    /// title, so the element would be silently dropped.
+    ElementInTitle,
+    /// The line is longer than [`MAX_LINE_LENGTH`].
+    LineTooLong { length: usize },
+    /// An element with the same name was already defined in the same scope.
+    DuplicateElement { name: String, first_line: usize },
+    /// There is no `.end` card. ngSPICE reads to the end of the deck anyway. Reported on the
+    /// last line.
+    MissingEnd,
+    /// No top-level element connects to ground (`0` or `gnd`), so no node voltage is defined.
+    /// Reported on the title line. `external` is set if the deck reads other files, which may
+    /// ground it, and makes this only a warning.
+    NoGround { external: bool },
+    /// A device refers to a model that is defined nowhere in the netlist.
+    UndefinedModel { element: String, model: String },
+    /// A subcircuit instance refers to a subcircuit that is defined nowhere in the netlist.
+    UndefinedSubcircuit { element: String, subcircuit: String },
+    /// A directive such as `.ic` or `.save` refers to a node that no top-level element
+    /// connects to.
+    UndefinedNode { node: String },
+    /// A `.control` block runs a background command, which would race with the background
+    /// thread this crate manages.
+    BackgroundCommand { command: String },
+}
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    /// The netlist is rejected before it reaches ngSPICE.
+    Error,
+    /// The netlist is likely wrong, but ngSPICE can still run it.
+    Warning,
+}
+
+impl DiagnosticKind {
+    pub fn severity(&self) -> Severity {
+        match self {
+            DiagnosticKind::MissingEnd
+            | DiagnosticKind::NoGround { external: true }
+            | DiagnosticKind::UndefinedNode { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.kind.severity() == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            DiagnosticKind::ElementInTitle => f.write_str(
+                "the first line is always the title, but this looks like an element definition",
+            ),
+            DiagnosticKind::LineTooLong { length } => write!(
+                f,
+                "line is {} characters long; the maximum is {}",
+                length, MAX_LINE_LENGTH
+            ),
+            DiagnosticKind::DuplicateElement { name, first_line } => write!(
+                f,
+                "element {} was already defined on line {}",
+                name, first_line
+            ),
+            DiagnosticKind::MissingEnd => f.write_str("the netlist has no .end card"),
+            DiagnosticKind::NoGround { .. } => {
+                f.write_str("no element connects to ground (node 0)")
+            }
+            DiagnosticKind::UndefinedModel { element, model } => {
+                write!(f, "{} uses undefined model {}", element, model)
+            }
+            DiagnosticKind::UndefinedSubcircuit {
+                element,
+                subcircuit,
+            } => write!(
+                f,
+                "{} instantiates undefined subcircuit {}",
+                element, subcircuit
+            ),
+            DiagnosticKind::UndefinedNode { node } => {
+                write!(f, "no element connects to node {}", node)
+            }
+            DiagnosticKind::BackgroundCommand { command } => write!(
+                f,
+                "background command {} in a .control block would race with the library",
+                command
+            ),
+        }
+    }
+}
+
+/// Checks a netlist for problems that ngSPICE would silently ignore or mangle, or that make
+/// the circuit unsolvable.
+///
+/// Returns all problems found, in line order. An empty Vec means the netlist passed. Only
+/// diagnostics with [`Severity::Error`] stop [`NgSpice::simulate`](crate::NgSpice::simulate).
+pub fn check_netlist(circuit: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = check_lines(circuit);
+    diagnostics.extend(check_references(circuit));
+    // stable, so diagnostics on the same line keep the order of the checks
+    diagnostics.sort_by_key(|d| d.line);
+    diagnostics
+}
+
+/// Line length, title, and duplicate element checks.
+fn check_lines(circuit: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    // Element names are scoped to the enclosing subcircuit, so keep a stack of name tables
+    let mut scopes: Vec<HashMap<String, usize>> = vec![HashMap::new()];
+    let mut in_control = false;
+    for (idx, line) in circuit.lines().enumerate() {
+        let line_no = idx + 1;
+        let length = line.chars().count();
+        if length > MAX_LINE_LENGTH {
+            diagnostics.push(Diagnostic {
+                line: line_no,
+                kind: DiagnosticKind::LineTooLong { length },
+            });
+        }
+        if idx == 0 {
+            if looks_like_element(line) {
+                diagnostics.push(Diagnostic {
+                    line: line_no,
+                    kind: DiagnosticKind::ElementInTitle,
+                });
+            }
+            continue;
+        }
+        let trimmed = line.trim_start();
+        let first = match trimmed.split_whitespace().next() {
+            Some(x) => x.to_ascii_lowercase(),
+            None => continue,
+        };
+        if in_control {
+            in_control = first != ".endc";
+            continue;
+        }
+        match first.as_str() {
+            ".control" => in_control = true,
+            ".subckt" => scopes.push(HashMap::new()),
+            ".ends" if scopes.len() > 1 => {
+                scopes.pop();
+            }
+            _ if is_element_name(&first) => {
+                let scope = scopes.last_mut().expect("scope stack is never empty");
+                if let Some(&first_line) = scope.get(&first) {
+                    diagnostics.push(Diagnostic {
+                        line: line_no,
+                        kind: DiagnosticKind::DuplicateElement {
+                            name: trimmed.split_whitespace().next().unwrap().to_owned(),
+                            first_line,
+                        },
+                    });
+                } else {
+                    scope.insert(first, line_no);
+                }
+            }
+            _ => {}
+        }
+    }
+    diagnostics
+}
+
+/// Joins `+` continuation lines onto the line before them, keeping the 1-based number of each
+/// logical line's first physical line.
+fn numbered_lines(circuit: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (idx, line) in circuit.lines().enumerate() {
+        match (line.trim_start().strip_prefix('+'), lines.last_mut()) {
+            (Some(rest), Some((_, last))) if idx > 1 => {
+                last.push(' ');
+                last.push_str(rest.trim());
+            }
+            _ => lines.push((idx + 1, line.trim().to_owned())),
+        }
+    }
+    lines
+}
+
+/// The model a device refers to, for device types that require one, given the names of the
+/// models defined so far.
+fn required_model<'a>(element: &'a Element, models: &BTreeSet<String>) -> Option<&'a str> {
+    let plain = |i: usize| {
+        element
+            .params
+            .get(i)
+            .map(String::as_str)
+            .filter(|p| !p.contains(['=', '{']))
+    };
+    match element.kind() {
+        'D' | 'J' | 'Z' | 'S' | 'O' => plain(0),
+        // MOSFETs may have up to three nodes beyond the four the parser takes
+        'M' => (0..4)
+            .filter_map(plain)
+            .find(|p| models.contains(&p.to_ascii_lowercase()))
+            .or(plain(0)),
+        // the first parameter is the substrate node if there is one, and the model otherwise;
+        // a defined model, a positional area or OFF all rule the substrate out
+        'Q' => {
+            let first = plain(0);
+            if first.is_some_and(|p| models.contains(&p.to_ascii_lowercase())) {
+                return first;
+            }
+            plain(1)
+                .filter(|p| {
+                    !p.contains('(') && parse_number(p).is_none() && !p.eq_ignore_ascii_case("off")
+                })
+                .or(first)
+        }
+        _ => None,
+    }
+}
+
+/// Ground, `.end`, and undefined model, subcircuit and node checks.
+fn check_references(circuit: &str) -> Vec<Diagnostic> {
+    let lines = numbered_lines(circuit);
+    let mut diagnostics = Vec::new();
+    let mut models = BTreeSet::new();
+    let mut subcircuits = BTreeSet::new();
+    let mut devices: Vec<(usize, Element, usize)> = Vec::new();
+    let mut node_refs: Vec<(usize, String)> = Vec::new();
+    let mut external = false;
+    let mut has_end = false;
+    let mut has_elements = false;
+    let mut grounded = false;
+    let mut nodes = BTreeSet::new();
+    let mut depth = 0;
+    let mut in_control = false;
+    for (line_no, line) in lines.iter().skip(1) {
+        let first = line
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        if in_control {
+            in_control = first != ".endc";
+            if first.starts_with("bg_") {
+                diagnostics.push(Diagnostic {
+                    line: *line_no,
+                    kind: DiagnosticKind::BackgroundCommand { command: first },
+                });
+            }
+            continue;
+        }
+        let second = || line.split_whitespace().nth(1).map(str::to_ascii_lowercase);
+        match first.as_str() {
+            "" => {}
+            ".control" => in_control = true,
+            ".end" => {
+                has_end = true;
+                break;
+            }
+            ".subckt" => {
+                depth += 1;
+                subcircuits.extend(second());
+            }
+            ".ends" => depth -= 1,
+            ".model" => models.extend(second()),
+            ".include" | ".inc" | ".lib" => external = true,
+            ".ic" | ".nodeset" | ".save" | ".print" | ".plot" | ".probe" if depth == 0 => {
+                let lower = line.to_ascii_lowercase();
+                for reference in lower.split("v(").skip(1) {
+                    if let Some((inside, _)) = reference.split_once(')') {
+                        node_refs
+                            .extend(inside.split(',').map(|n| (*line_no, n.trim().to_owned())));
+                    }
+                }
+            }
+            _ if first.starts_with(['.', '*']) => {}
+            _ => {
+                if let Some(element) = Element::parse(line) {
+                    if depth == 0 {
+                        has_elements = true;
+                        for node in &element.nodes {
+                            let node = node.to_ascii_lowercase();
+                            grounded |= node == "0" || node == "gnd";
+                            nodes.insert(node);
+                        }
+                    }
+                    devices.push((*line_no, element, depth));
+                }
+            }
+        }
+    }
+    if has_elements && !grounded {
+        diagnostics.push(Diagnostic {
+            line: 1,
+            kind: DiagnosticKind::NoGround { external },
+        });
+    }
+    // definitions may come from files ngSPICE reads itself
+    if !external {
+        for (line, element, _) in &devices {
+            let kind = if element.kind() == 'X' {
+                element
+                    .value()
+                    .filter(|s| !subcircuits.contains(&s.to_ascii_lowercase()))
+                    .map(|s| DiagnosticKind::UndefinedSubcircuit {
+                        element: element.name.clone(),
+                        subcircuit: s.to_owned(),
+                    })
+            } else {
+                required_model(element, &models)
+                    .filter(|m| !models.contains(&m.to_ascii_lowercase()))
+                    .map(|m| DiagnosticKind::UndefinedModel {
+                        element: element.name.clone(),
+                        model: m.to_owned(),
+                    })
+            };
+            if let Some(kind) = kind {
+                diagnostics.push(Diagnostic { line: *line, kind });
+            }
+        }
+    }
+    for (line, node) in node_refs {
+        // hierarchical nodes like x1.out are inside subcircuit instances
+        if !node.is_empty() && !node.contains('.') && !nodes.contains(&node) {
+            diagnostics.push(Diagnostic {
+                line,
+                kind: DiagnosticKind::UndefinedNode { node },
+            });
+        }
+    }
+    if !has_end {
+        diagnostics.push(Diagnostic {
+            line: circuit.lines().count().max(1),
+            kind: DiagnosticKind::MissingEnd,
+        });
+    }
+    diagnostics
+}
+
+/// Returns true if the token could be an element name, e.g. `R1` or `xamp`.
+pub(crate) fn is_element_name(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_ascii_alphabetic())
+}
+
+/// A heuristic for whether a title line is really a two-terminal element like `R1 a b 10k`.
+fn looks_like_element(line: &str) -> bool {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    if tokens.len() < 4 {
+        return false;
+    }
+    let prefix = tokens[0].chars().next().unwrap().to_ascii_lowercase();
+    if !"rclvi".contains(prefix) || tokens[0].len() < 2 {
+        return false;
+    }
+    let value = tokens[3].to_ascii_lowercase();
+    value.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        || value.starts_with("dc")
+        || value.contains('(')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_netlist_problems() {
+        let long = format!("* {}", "x".repeat(MAX_LINE_LENGTH));
+        let circuit = format!(
+            "R1 a b 10k
+R1 a 0 1k
+C1 a 0 1u
+.subckt amp in out
+R1 in out 1k
+.ends
+{}
+.end",
+            long
+        );
+        let diags = check_netlist(&circuit);
+        assert_eq!(
+            diags,
+            vec![
+                Diagnostic {
+                    line: 1,
+                    kind: DiagnosticKind::ElementInTitle
+                },
+                Diagnostic {
+                    line: 7,
+                    kind: DiagnosticKind::LineTooLong {
+                        length: MAX_LINE_LENGTH + 2
+                    }
+                },
+            ]
+        );
+        let circuit = ".title ok\nR1 a b 1k\nr1 b 0 1k\n.end";
+        assert_eq!(
+            check_netlist(circuit),
+            vec![Diagnostic {
+                line: 3,
+                kind: DiagnosticKind::DuplicateElement {
+                    name: "r1".to_owned(),
+                    first_line: 2
+                }
+            }]
+        );
+
+        let circuit = "* refs\nV1 in gnd DC 1\nD1 in out dmod\nQ1 c b e sub qmod\n\
+                       X1 in out amp\n.subckt amp a y\nM1 y a 0 0 nch\n.ends\n\
+                       .model QMOD npn\nQ2 c b e qmod 2\nQ3 c b e qmod OFF\n.ic v(out)=0 v(x1.y)=0 v(missing)=1\n\
+                       .control\nbg_run\n.endc";
+        let kinds: Vec<(usize, DiagnosticKind)> = check_netlist(circuit)
+            .into_iter()
+            .map(|d| (d.line, d.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    3,
+                    DiagnosticKind::UndefinedModel {
+                        element: "D1".to_owned(),
+                        model: "dmod".to_owned()
+                    }
+                ),
+                (
+                    7,
+                    DiagnosticKind::UndefinedModel {
+                        element: "M1".to_owned(),
+                        model: "nch".to_owned()
+                    }
+                ),
+                (
+                    12,
+                    DiagnosticKind::UndefinedNode {
+                        node: "missing".to_owned()
+                    }
+                ),
+                (
+                    14,
+                    DiagnosticKind::BackgroundCommand {
+                        command: "bg_run".to_owned()
+                    }
+                ),
+                (15, DiagnosticKind::MissingEnd),
+            ]
+        );
+        let floating = check_netlist("* f\nR1 a b 1k\n.include models.lib\nD1 a b dx\n.end");
+        assert_eq!(floating.len(), 1);
+        assert_eq!(
+            floating[0].kind,
+            DiagnosticKind::NoGround { external: true }
+        );
+        assert!(!floating[0].is_error());
+        let floating = check_netlist("* f\nR1 a b 1k\n.end");
+        assert_eq!(
+            floating[0].kind,
+            DiagnosticKind::NoGround { external: false }
+        );
+        assert!(floating[0].is_error());
+
+        // a five-node SOI MOSFET, whose model follows the body contact
+        let soi = check_netlist("* soi\nM1 d g 0 b t nsoi w=1u\n.model nsoi nmos level=10\n.end");
+        assert!(soi.is_empty(), "{:?}", soi);
+    }
+}
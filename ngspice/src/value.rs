@@ -0,0 +1,131 @@
+// Copyright 2022 Andrew Morrow.
+// value.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Component values written with SPICE scale factors, e.g. `10k` or `4.7u`.
+
+use crate::circuit::parse_number;
+use crate::format::{Notation, NumericFormat};
+use crate::Error;
+use std::fmt::{self, Formatter};
+use std::str::FromStr;
+
+/// A number that parses and formats with SPICE scale factors.
+///
+/// SPICE scale factors ignore case, so `1M` is one *milli*, not one mega; mega is `meg`.
+/// Formatting always writes lowercase factors, with `meg` for 10⁶, and enough digits to read
+/// back as exactly the same `f64`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct SpiceValue(pub f64);
+
+const FACTORS: [(i32, &str); 10] = [
+    (12, "t"),
+    (9, "g"),
+    (6, "meg"),
+    (3, "k"),
+    (0, ""),
+    (-3, "m"),
+    (-6, "u"),
+    (-9, "n"),
+    (-12, "p"),
+    (-15, "f"),
+];
+
+impl SpiceValue {
+    pub fn value(self) -> f64 {
+        self.0
+    }
+}
+
+impl From<f64> for SpiceValue {
+    fn from(value: f64) -> Self {
+        SpiceValue(value)
+    }
+}
+
+impl From<SpiceValue> for f64 {
+    fn from(value: SpiceValue) -> Self {
+        value.0
+    }
+}
+
+impl FromStr for SpiceValue {
+    type Err = Error;
+
+    /// Parses a number as ngSPICE does: `2meg`, `4.7u`, `100nF` and `1e-3` are accepted, and
+    /// letters after the scale factor, such as units, are ignored.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
+        let valid = s.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '.' | '-' | '+'));
+        parse_number(s)
+            .filter(|_| valid)
+            .map(SpiceValue)
+            .ok_or_else(|| Error::InvalidValue(s.to_owned()))
+    }
+}
+
+impl fmt::Display for SpiceValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0 == 0.0 {
+            return f.write_str("0");
+        }
+        let engineering = NumericFormat {
+            notation: Notation::Engineering,
+            ..NumericFormat::default()
+        }
+        .format(self.0);
+        let Some((mantissa, exponent)) = engineering.split_once('e') else {
+            // not finite
+            return f.write_str(&engineering);
+        };
+        let exponent: i32 = exponent.parse().map_err(|_| fmt::Error)?;
+        match FACTORS.iter().find(|(e, _)| *e == exponent) {
+            Some((_, factor)) => write!(f, "{}{}", mantissa, factor),
+            None => write!(f, "{:e}", self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_scale_factors() {
+        for (text, value) in [
+            ("10k", 10e3),
+            ("4.7u", 4.7e-6),
+            ("2meg", 2e6),
+            ("1m", 1e-3),
+            ("-220n", -220e-9),
+            ("0", 0.0),
+            ("1.5", 1.5),
+            ("1e-18", 1e-18),
+        ] {
+            let parsed: SpiceValue = text.parse().unwrap();
+            assert_eq!(parsed.value(), value, "{}", text);
+            assert_eq!(SpiceValue(value).to_string(), text);
+        }
+        assert_eq!("1M".parse::<SpiceValue>().unwrap().value(), 1e-3);
+        assert_eq!("100nF".parse::<SpiceValue>().unwrap().value(), 100e-9);
+        let third = SpiceValue(1.0 / 3.0);
+        assert_eq!(third.to_string().parse::<SpiceValue>().unwrap(), third);
+        assert!(matches!(
+            "k10".parse::<SpiceValue>(),
+            Err(Error::InvalidValue(_))
+        ));
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright 2022 Andrew Morrow.
+// warmup.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Paying ngSPICE's start-up costs ahead of the first simulation that matters.
+
+use crate::{Error, NgSpice};
+use once_cell::sync::OnceCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A circuit small enough to solve instantly, which still exercises loading, analysis and
+/// plot management.
+const WARM_UP_CIRCUIT: &str = "* warm up\nV1 1 0 DC 1\nR1 1 0 1k\n.end";
+
+/// Code models and model libraries to load once, up front.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Preload {
+    /// XSPICE code model libraries (`.cm` files), loaded with the `codemodel` command.
+    pub codemodels: Vec<PathBuf>,
+    /// Model libraries, read into memory and added to every subsequent circuit.
+    pub libraries: Vec<PathBuf>,
+}
+
+impl Preload {
+    pub fn new() -> Self {
+        Preload::default()
+    }
+
+    pub fn codemodel<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.codemodels.push(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn library<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.libraries.push(path.as_ref().to_owned());
+        self
+    }
+}
+
+static LIBRARIES: OnceCell<Mutex<String>> = OnceCell::new();
+
+fn libraries() -> &'static Mutex<String> {
+    LIBRARIES.get_or_init(|| Mutex::new(String::new()))
+}
+
+/// Adds the preloaded model libraries to `circuit`.
+pub(crate) fn add_libraries(circuit: &mut String) {
+    let libraries = libraries().lock().unwrap_or_else(|e| e.into_inner());
+    if !libraries.is_empty() {
+        insert_after_title(circuit, &libraries);
+    }
+}
+
+pub(crate) fn insert_after_title(circuit: &mut String, text: &str) {
+    match circuit.find('\n') {
+        Some(k) => circuit.insert_str(k + 1, text),
+        None => {
+            circuit.push('\n');
+            circuit.push_str(text);
+        }
+    }
+}
+
+impl NgSpice {
+    /// Initializes ngSPICE and runs a trivial simulation, so that the first real simulation
+    /// does not pay for loading the shared library, its initialization, or its first
+    /// allocations. Blocks until no other simulation is running.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the warm-up simulation fails, which means ngSPICE is unusable.
+    pub fn warm_up() -> Result<(), Error> {
+        let mut session = NgSpice::session();
+        session.load_circuit(WARM_UP_CIRCUIT)?;
+        let plot = session.run("op")?;
+        session.destroy(&plot)
+    }
+
+    /// Warms up ngSPICE, loads `preload`'s code models, and reads its model libraries into
+    /// memory. The libraries are then added to every circuit run by [`NgSpice::simulate`] and
+    /// the functions built on it, replacing any libraries preloaded before.
+    ///
+    /// Preloading saves the file reads and code model loading on every run; ngSPICE still
+    /// parses the library models with each circuit.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Io`] if a library cannot be read, or an error if ngSPICE rejects a
+    /// code model or the warm-up simulation.
+    pub fn preload(preload: &Preload) -> Result<(), Error> {
+        let mut text = String::new();
+        for path in &preload.libraries {
+            text.push_str(&fs::read_to_string(path)?);
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+        }
+        {
+            let mut session = NgSpice::session();
+            for path in &preload.codemodels {
+                session.command(&format!("codemodel {}", path.display()))?;
+            }
+        }
+        NgSpice::warm_up()?;
+        *libraries().lock().unwrap_or_else(|e| e.into_inner()) = text;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_preloaded_libraries() {
+        let mut circuit = "* t\nR1 a 0 1k\n.end".to_owned();
+        insert_after_title(&mut circuit, ".model dmod d is=1e-14\n");
+        assert_eq!(circuit, "* t\n.model dmod d is=1e-14\nR1 a 0 1k\n.end");
+        let mut bare = "* t".to_owned();
+        insert_after_title(&mut bare, ".model x d\n");
+        assert_eq!(bare, "* t\n.model x d\n");
+        let preload = Preload::new().codemodel("a.cm").library("lib.mod");
+        assert_eq!(preload.libraries, [PathBuf::from("lib.mod")]);
+    }
+}
@@ -0,0 +1,381 @@
+// Copyright 2022 Andrew Morrow.
+// waveform.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Numeric helpers for waveforms sampled on a non-uniform scale such as ngSPICE's time vector.
+
+use crate::kernels::interpolate_all;
+use crate::{Error, Simulation};
+
+/// Linearly interpolates the waveform `(x, y)` at `at`.
+///
+/// `x` must be sorted in ascending order. Values of `at` outside the range of `x` are clamped
+/// to the first or last value of `y`.
+///
+/// # Panics
+///
+/// Panics if `x` and `y` have different lengths or are empty.
+pub fn interpolate(x: &[f64], y: &[f64], at: f64) -> f64 {
+    assert_eq!(x.len(), y.len(), "x and y must have the same length");
+    assert!(!x.is_empty(), "cannot interpolate an empty waveform");
+    // index of the first sample strictly after `at`
+    let idx = x.partition_point(|&v| v <= at);
+    if idx == 0 {
+        return y[0];
+    }
+    if idx == x.len() {
+        return y[x.len() - 1];
+    }
+    let (x0, x1) = (x[idx - 1], x[idx]);
+    let (y0, y1) = (y[idx - 1], y[idx]);
+    if x1 == x0 {
+        return y1;
+    }
+    y0 + (y1 - y0) * (at - x0) / (x1 - x0)
+}
+
+/// Returns the indices of local maxima. A flat top counts once, at its first sample.
+pub fn peaks(y: &[f64]) -> Vec<usize> {
+    extrema(y, |a, b| a > b)
+}
+
+/// Returns the indices of local minima. A flat bottom counts once, at its first sample.
+pub fn troughs(y: &[f64]) -> Vec<usize> {
+    extrema(y, |a, b| a < b)
+}
+
+fn extrema(y: &[f64], beyond: impl Fn(f64, f64) -> bool) -> Vec<usize> {
+    let mut result = Vec::new();
+    let mut i = 1;
+    while i + 1 < y.len() {
+        if beyond(y[i], y[i - 1]) {
+            // skip over a plateau to see where the signal goes next
+            let mut j = i;
+            while j + 1 < y.len() && y[j + 1] == y[i] {
+                j += 1;
+            }
+            if j + 1 < y.len() && beyond(y[i], y[j + 1]) {
+                result.push(i);
+            }
+            i = j + 1;
+        } else {
+            i += 1;
+        }
+    }
+    result
+}
+
+/// Upper and lower envelopes of a waveform, sampled on the waveform's own scale.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Envelope {
+    pub upper: Vec<f64>,
+    pub lower: Vec<f64>,
+}
+
+/// Computes the envelopes of `(x, y)` by interpolating linearly between its peaks and troughs.
+///
+/// If the waveform has no peaks (or no troughs), that envelope is the waveform itself.
+pub fn envelope(x: &[f64], y: &[f64]) -> Envelope {
+    let trace = |idx: Vec<usize>| -> Vec<f64> {
+        if idx.is_empty() {
+            return y.to_vec();
+        }
+        let px: Vec<f64> = idx.iter().map(|&i| x[i]).collect();
+        let py: Vec<f64> = idx.iter().map(|&i| y[i]).collect();
+        interpolate_all(&px, &py, x)
+    };
+    Envelope {
+        upper: trace(peaks(y)),
+        lower: trace(troughs(y)),
+    }
+}
+
+/// Ripple measured over a steady-state window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ripple {
+    /// The difference between the largest and smallest sample in the window.
+    pub peak_to_peak: f64,
+    /// The average distance between the upper and lower envelopes in the window.
+    pub envelope: f64,
+    /// The average value in the window, weighted by time.
+    pub mean: f64,
+    /// The RMS deviation from the mean, weighted by time.
+    pub rms: f64,
+}
+
+/// Measures the ripple of `(x, y)` between `start` and `stop`.
+///
+/// Returns `None` if fewer than two samples fall inside the window.
+pub fn ripple(x: &[f64], y: &[f64], start: f64, stop: f64) -> Option<Ripple> {
+    let lo = x.partition_point(|&t| t < start);
+    let hi = x.partition_point(|&t| t <= stop);
+    if hi < lo + 2 {
+        return None;
+    }
+    let (xw, yw) = (&x[lo..hi], &y[lo..hi]);
+    let env = envelope(xw, yw);
+    let span = xw[xw.len() - 1] - xw[0];
+    // trapezoidal averages respect ngSPICE's non-uniform time steps
+    let average = |f: &dyn Fn(usize) -> f64| -> f64 {
+        if span == 0.0 {
+            return (0..xw.len()).map(f).sum::<f64>() / xw.len() as f64;
+        }
+        (1..xw.len())
+            .map(|i| 0.5 * (f(i) + f(i - 1)) * (xw[i] - xw[i - 1]))
+            .sum::<f64>()
+            / span
+    };
+    let mean = average(&|i| yw[i]);
+    let rms = average(&|i| (yw[i] - mean).powi(2)).sqrt();
+    let max = yw.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let min = yw.iter().copied().fold(f64::INFINITY, f64::min);
+    Some(Ripple {
+        peak_to_peak: max - min,
+        envelope: average(&|i| env.upper[i] - env.lower[i]),
+        mean,
+        rms,
+    })
+}
+
+/// Differentiates `(x, y)` with second-order finite differences that account for uneven
+/// steps, using one-sided differences at the ends. Repeated `x` values, which ngSPICE emits at
+/// breakpoints, take the derivative of the neighbouring step.
+///
+/// # Panics
+///
+/// Panics if `x` and `y` have different lengths.
+pub fn derivative(x: &[f64], y: &[f64]) -> Vec<f64> {
+    assert_eq!(x.len(), y.len(), "x and y must have the same length");
+    let n = x.len();
+    let slope = |a: usize, b: usize| {
+        let dx = x[b] - x[a];
+        if dx == 0.0 {
+            None
+        } else {
+            Some((y[b] - y[a]) / dx)
+        }
+    };
+    let mut result: Vec<Option<f64>> = (0..n)
+        .map(|i| {
+            if n < 2 {
+                return Some(0.0);
+            }
+            if i == 0 {
+                return slope(0, 1);
+            }
+            if i == n - 1 {
+                return slope(n - 2, n - 1);
+            }
+            let (h1, h2) = (x[i] - x[i - 1], x[i + 1] - x[i]);
+            if h1 == 0.0 || h2 == 0.0 {
+                return slope(i - 1, i).or_else(|| slope(i, i + 1));
+            }
+            Some(
+                (h1 * h1 * y[i + 1] - h2 * h2 * y[i - 1] + (h2 * h2 - h1 * h1) * y[i])
+                    / (h1 * h2 * (h1 + h2)),
+            )
+        })
+        .collect();
+    // fill any points that were surrounded by repeats from their neighbours
+    for i in 1..n {
+        if result[i].is_none() {
+            result[i] = result[i - 1];
+        }
+    }
+    for i in (0..n.saturating_sub(1)).rev() {
+        if result[i].is_none() {
+            result[i] = result[i + 1];
+        }
+    }
+    result.into_iter().map(|d| d.unwrap_or(0.0)).collect()
+}
+
+/// The running integral of `(x, y)` from its first sample, by the trapezoidal rule.
+///
+/// # Panics
+///
+/// Panics if `x` and `y` have different lengths.
+pub fn integral(x: &[f64], y: &[f64]) -> Vec<f64> {
+    assert_eq!(x.len(), y.len(), "x and y must have the same length");
+    let mut total = 0.0;
+    let mut result = Vec::with_capacity(x.len());
+    for i in 0..x.len() {
+        if i > 0 {
+            total += 0.5 * (y[i] + y[i - 1]) * (x[i] - x[i - 1]);
+        }
+        result.push(total);
+    }
+    result
+}
+
+/// The running integral evaluated at any `at` within the waveform, integrating the linear
+/// interpolation exactly.
+fn integral_at(x: &[f64], y: &[f64], running: &[f64], at: f64) -> f64 {
+    let idx = x.partition_point(|&v| v <= at);
+    if idx == 0 {
+        return 0.0;
+    }
+    let k = idx - 1;
+    running[k] + 0.5 * (y[k] + interpolate(x, y, at)) * (at - x[k])
+}
+
+/// The integral of `(x, y)` between `start` and `stop`, clamped to the waveform's range. Use
+/// it for charge from a current or energy from a power.
+///
+/// # Panics
+///
+/// Panics if `x` and `y` have different lengths or are empty.
+pub fn integrate(x: &[f64], y: &[f64], start: f64, stop: f64) -> f64 {
+    let running = integral(x, y);
+    let (lo, hi) = (x[0], x[x.len() - 1]);
+    integral_at(x, y, &running, stop.clamp(lo, hi))
+        - integral_at(x, y, &running, start.clamp(lo, hi))
+}
+
+/// Smooths `(x, y)` with a moving average `window` wide in `x`, centred on each sample and
+/// shortened at the ends. Averaging over `x` rather than over samples keeps uneven steps from
+/// skewing the result.
+///
+/// # Panics
+///
+/// Panics if `x` and `y` have different lengths.
+pub fn smooth(x: &[f64], y: &[f64], window: f64) -> Vec<f64> {
+    assert_eq!(x.len(), y.len(), "x and y must have the same length");
+    if x.is_empty() || window <= 0.0 {
+        return y.to_vec();
+    }
+    let running = integral(x, y);
+    let (lo, hi) = (x[0], x[x.len() - 1]);
+    x.iter()
+        .zip(y)
+        .map(|(&t, &v)| {
+            let a = (t - window / 2.0).max(lo);
+            let b = (t + window / 2.0).min(hi);
+            if b <= a {
+                return v;
+            }
+            (integral_at(x, y, &running, b) - integral_at(x, y, &running, a)) / (b - a)
+        })
+        .collect()
+}
+
+impl Simulation {
+    /// The derivative of the named vector against its scale, e.g. dv/dt. If `smoothing` is
+    /// given, the vector is first smoothed with a moving average that wide, which suppresses
+    /// the noise that differentiation amplifies.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vector or the scale is missing.
+    pub fn derivative(&self, vector: &str, smoothing: Option<f64>) -> Result<Vec<f64>, Error> {
+        let (_, scale) = self.scale_vector().ok_or(Error::MissingScale)?;
+        let scale = scale.real().ok_or(Error::MissingScale)?;
+        let values = self.real_vector(vector)?;
+        Ok(match smoothing {
+            Some(window) => derivative(scale, &smooth(scale, values, window)),
+            None => derivative(scale, values),
+        })
+    }
+
+    /// The integral of the named vector between `start` and `stop` on its scale, e.g. the
+    /// charge delivered by a current.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vector or the scale is missing, or if either is empty.
+    pub fn integrate(&self, vector: &str, start: f64, stop: f64) -> Result<f64, Error> {
+        let (_, scale) = self.scale_vector().ok_or(Error::MissingScale)?;
+        let scale = scale.real().ok_or(Error::MissingScale)?;
+        let values = self.real_vector(vector)?;
+        if values.is_empty() || values.len() != scale.len() {
+            return Err(Error::MissingVector(vector.to_owned()));
+        }
+        Ok(integrate(scale, values, start, stop))
+    }
+
+    /// Measures the ripple of the named vector between `start` and `stop` on its scale.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vector or the scale is missing, or [`Error::MissingVector`] if
+    /// the window holds fewer than two samples.
+    pub fn ripple(&self, vector: &str, start: f64, stop: f64) -> Result<Ripple, Error> {
+        let (_, scale) = self.scale_vector().ok_or(Error::MissingScale)?;
+        let scale = scale.real().ok_or(Error::MissingScale)?;
+        ripple(scale, self.real_vector(vector)?, start, stop)
+            .ok_or_else(|| Error::MissingVector(vector.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_linearly() {
+        let x = [0.0, 1.0, 3.0];
+        let y = [0.0, 2.0, 6.0];
+        assert_eq!(interpolate(&x, &y, 0.5), 1.0);
+        assert_eq!(interpolate(&x, &y, 2.0), 4.0);
+        assert_eq!(interpolate(&x, &y, -1.0), 0.0);
+        assert_eq!(interpolate(&x, &y, 5.0), 6.0);
+        assert_eq!(interpolate(&x, &y, 1.0), 2.0);
+    }
+
+    #[test]
+    fn measures_ripple() {
+        // a 1 V ripple riding on 5 V
+        let x: Vec<f64> = (0..=400).map(|i| i as f64 * 0.01).collect();
+        let y: Vec<f64> = x
+            .iter()
+            .map(|&t| 5.0 + 0.5 * (2.0 * std::f64::consts::PI * t).sin())
+            .collect();
+        assert_eq!(peaks(&y).len(), 4);
+        assert_eq!(troughs(&y).len(), 4);
+        assert_eq!(peaks(&[0.0, 1.0, 1.0, 0.0, 2.0, 2.0]), vec![1]);
+        let r = ripple(&x, &y, 1.0, 3.0).unwrap();
+        assert!((r.peak_to_peak - 1.0).abs() < 1e-9);
+        assert!((r.envelope - 1.0).abs() < 0.05);
+        assert!((r.mean - 5.0).abs() < 1e-3);
+        assert!((r.rms - 0.5 / 2f64.sqrt()).abs() < 1e-3);
+        assert!(ripple(&x, &y, 10.0, 11.0).is_none());
+    }
+
+    #[test]
+    fn differentiates_and_integrates() {
+        // uneven steps, with a repeated breakpoint at 0.3
+        let x = [0.0, 0.1, 0.3, 0.3, 0.35, 0.6, 1.0];
+        let y: Vec<f64> = x.iter().map(|&t| t * t).collect();
+        let dy = derivative(&x, &y);
+        // central differences are exact for quadratics
+        assert!((dy[1] - 0.2).abs() < 1e-12);
+        assert!((dy[5] - 1.2).abs() < 1e-12);
+        assert!(dy.iter().all(|d| d.is_finite()));
+        assert_eq!(
+            integral(&[0.0, 1.0, 3.0], &[1.0, 1.0, 1.0]),
+            vec![0.0, 1.0, 3.0]
+        );
+        let ramp = [0.0, 2.0];
+        assert!((integrate(&ramp, &ramp, 0.5, 1.5) - 1.0).abs() < 1e-12);
+        assert!((integrate(&ramp, &ramp, -1.0, 5.0) - 2.0).abs() < 1e-12);
+        let noisy: Vec<f64> = (0..=100)
+            .map(|i| if i % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+        let t: Vec<f64> = (0..=100).map(|i| i as f64).collect();
+        let smoothed = smooth(&t, &noisy, 10.0);
+        assert!(smoothed[50].abs() < 0.2);
+    }
+}
@@ -0,0 +1,58 @@
+// Copyright 2022 Andrew Morrow.
+// xlsx.rs
+// ngspice
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Excel workbook export. Requires the `xlsx` feature.
+
+use crate::campaign::{Campaign, TagValue};
+use rust_xlsxwriter::{Format, Workbook, XlsxError};
+use std::path::Path;
+
+impl Campaign {
+    /// Writes the campaign's [`table`](Campaign::table) to an `.xlsx` workbook.
+    ///
+    /// The workbook has a single sheet with a bold header row, followed by one row per run.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the workbook cannot be written.
+    pub fn write_xlsx<P: AsRef<Path>>(&self, path: P) -> Result<(), XlsxError> {
+        let table = self.table();
+        let mut workbook = Workbook::new();
+        let sheet = workbook.add_worksheet();
+        sheet.set_name("Measurements")?;
+        let bold = Format::new().set_bold();
+        for (col, name) in table.columns.iter().enumerate() {
+            sheet.write_string_with_format(0, col as u16, name, &bold)?;
+        }
+        for (row, cells) in table.rows.iter().enumerate() {
+            let row = row as u32 + 1;
+            for (col, cell) in cells.iter().enumerate() {
+                let col = col as u16;
+                match cell {
+                    Some(TagValue::Number(x)) => {
+                        sheet.write_number(row, col, *x)?;
+                    }
+                    Some(TagValue::Text(x)) => {
+                        sheet.write_string(row, col, x)?;
+                    }
+                    None => {}
+                }
+            }
+        }
+        workbook.save(path.as_ref())
+    }
+}